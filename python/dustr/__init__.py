@@ -7,6 +7,7 @@ import sys
 import warnings
 
 try:
+    from dustr._dustr import __version__  # noqa: F401
     from dustr._dustr import main as rust_main
 except ImportError as e:
     print(f"Error: Failed to import Rust extension: {e}", file=sys.stderr)
@@ -14,18 +15,41 @@ except ImportError as e:
     sys.exit(1)
 
 
-def main():
-    """Main entry point - delegates to Rust implementation"""
+def _complain(decorated, plain, quiet_errors):
+    """Print an error on stderr: the decorative text, or the plain one
+    (if any) with quiet_errors"""
+    message = plain if quiet_errors else decorated
+    if message is not None:
+        print(message, file=sys.stderr)
+
+
+def main(quiet_errors=False):
+    """Main entry point - delegates to Rust implementation. With
+    quiet_errors (or --quiet-errors), errors are reported as plain
+    "dustr: ..." lines."""
     try:
-        rust_main(sys.argv[1:])
+        rust_main(sys.argv[1:], quiet_errors=quiet_errors)
     except KeyboardInterrupt:
-        print("\nThe Dustr was shot by the user !")
+        _complain("\nThe Dustr was shot by the user !", None, quiet_errors)
         warnings.filterwarnings("ignore")
         sys.exit(1)
+    except PermissionError as e:
+        _complain(
+            f"The Ducky has no permission to read this directory !\n{e.strerror}",
+            f"dustr: {e.strerror}",
+            quiet_errors,
+        )
+        sys.exit(1)
+    except (FileNotFoundError, NotADirectoryError) as e:
+        print(f"dustr: {e.strerror}", file=sys.stderr)
+        sys.exit(1)
     except Exception as e:  # pylint: disable=broad-except
-        print("Sorry, the Dustr was eaten by the Python !\nReason:", sys.exc_info()[0])
-        if isinstance(e, SystemExit):
-            raise
+        _complain(
+            f"Sorry, the Dustr was eaten by the Python !\nReason: {type(e)}",
+            f"dustr: {e}",
+            quiet_errors,
+        )
+        sys.exit(1)
 
 
 if __name__ == "__main__":
@@ -0,0 +1,326 @@
+//! Shell completion scripts (`dustr completions bash|zsh|fish`).
+//!
+//! The scripts are generated from the clap definition of the command line,
+//! so every option, its enum values and its value hint (directories for
+//! `--exclude`, files for `--output`) stay in sync with the parser.
+
+use clap::{Arg, ArgAction, Command, ValueHint};
+
+/// Shells a completion script can be written for
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// What an option looks like to a completion script
+struct OptionSpec {
+    long: Option<String>,
+    /// Visible long aliases, such as `--mtime` for `--time`
+    aliases: Vec<String>,
+    short: Option<char>,
+    /// First sentence of the help text
+    help: String,
+    /// Name of the value, for options that take one
+    value_name: Option<String>,
+    /// Allowed values, for enum options
+    values: Vec<String>,
+    hint: ValueHint,
+    /// Whether the option may be given more than once
+    repeatable: bool,
+}
+
+impl OptionSpec {
+    fn new(arg: &Arg) -> Self {
+        let help = arg.get_help().map(|h| h.to_string()).unwrap_or_default();
+        let help = match help.find(". ") {
+            Some(end) => help[..end].to_string(),
+            None => help.trim_end_matches('.').to_string(),
+        };
+        let takes_value = matches!(arg.get_action(), ArgAction::Set | ArgAction::Append);
+        OptionSpec {
+            long: arg.get_long().map(str::to_string),
+            aliases: arg
+                .get_visible_aliases()
+                .into_iter()
+                .flatten()
+                .map(str::to_string)
+                .collect(),
+            short: arg.get_short(),
+            help,
+            value_name: takes_value.then(|| {
+                arg.get_value_names()
+                    .and_then(|names| names.first())
+                    .map_or_else(|| arg.get_id().to_string(), |name| name.to_string())
+            }),
+            values: arg
+                .get_possible_values()
+                .iter()
+                .filter(|value| !value.is_hide_set())
+                .map(|value| value.get_name().to_string())
+                .collect(),
+            hint: arg.get_value_hint(),
+            repeatable: matches!(arg.get_action(), ArgAction::Append | ArgAction::Count),
+        }
+    }
+
+    /// Every spelling of the option: `-x`, `--long` and its aliases
+    fn spellings(&self) -> Vec<String> {
+        let short = self.short.map(|s| format!("-{}", s));
+        let longs = self.long.iter().chain(&self.aliases);
+        short
+            .into_iter()
+            .chain(longs.map(|l| format!("--{}", l)))
+            .collect()
+    }
+}
+
+/// A subcommand and the values of its single positional argument
+struct SubcommandSpec {
+    name: String,
+    help: String,
+    values: Vec<String>,
+}
+
+/// The options and subcommands of `cmd`, including `--help` and `--version`
+fn specs(cmd: &Command) -> (Vec<OptionSpec>, Vec<SubcommandSpec>) {
+    let mut cmd = cmd.clone();
+    cmd.build();
+    let options = cmd
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+        .map(OptionSpec::new)
+        .collect();
+    let subcommands = cmd
+        .get_subcommands()
+        .map(|sub| SubcommandSpec {
+            name: sub.get_name().to_string(),
+            help: sub.get_about().map(|h| h.to_string()).unwrap_or_default(),
+            values: sub
+                .get_positionals()
+                .flat_map(|arg| arg.get_possible_values())
+                .map(|value| value.get_name().to_string())
+                .collect(),
+        })
+        .collect();
+    (options, subcommands)
+}
+
+/// The completion script for `cmd`, registered under its name
+pub fn generate(cmd: &Command, shell: Shell) -> String {
+    let bin = cmd.get_name();
+    let (options, subcommands) = specs(cmd);
+    match shell {
+        Shell::Bash => bash(bin, &options, &subcommands),
+        Shell::Zsh => zsh(bin, &options, &subcommands),
+        Shell::Fish => fish(bin, &options, &subcommands),
+    }
+}
+
+/// Name of the completion function for `bin`
+fn function_name(bin: &str) -> String {
+    format!(
+        "_{}",
+        bin.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    )
+}
+
+fn bash(bin: &str, options: &[OptionSpec], subcommands: &[SubcommandSpec]) -> String {
+    let func = function_name(bin);
+    let mut out = format!(
+        "{func}() {{\n    \
+         local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"\n    \
+         local words=\" ${{COMP_WORDS[*]:1:COMP_CWORD-1}} \"\n    \
+         COMPREPLY=()\n"
+    );
+    for sub in subcommands {
+        out.push_str(&format!(
+            "    if [[ \"$words\" == *\" {} \"* ]]; then\n        \
+             COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n        \
+             return\n    \
+             fi\n",
+            sub.name,
+            sub.values.join(" ")
+        ));
+    }
+    out.push_str("    case \"$prev\" in\n");
+    for opt in options.iter().filter(|opt| opt.value_name.is_some()) {
+        let reply = if !opt.values.is_empty() {
+            format!(
+                "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                opt.values.join(" ")
+            )
+        } else {
+            match opt.hint {
+                ValueHint::DirPath => "COMPREPLY=($(compgen -d -- \"$cur\"))".to_string(),
+                ValueHint::FilePath | ValueHint::AnyPath => {
+                    "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string()
+                }
+                _ => ":".to_string(),
+            }
+        };
+        out.push_str(&format!(
+            "        {})\n            {}\n            return\n            ;;\n",
+            opt.spellings().join("|"),
+            reply
+        ));
+    }
+    let flags: Vec<String> = options.iter().flat_map(OptionSpec::spellings).collect();
+    let commands: Vec<&str> = subcommands.iter().map(|sub| sub.name.as_str()).collect();
+    out.push_str(&format!(
+        "    esac\n    \
+         if [[ \"$cur\" == -* ]]; then\n        \
+         COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n    \
+         else\n        \
+         COMPREPLY=($(compgen -d -W \"{}\" -- \"$cur\"))\n    \
+         fi\n\
+         }}\n\
+         complete -o filenames -F {} {}\n",
+        flags.join(" "),
+        commands.join(" "),
+        func,
+        bin
+    ));
+    out
+}
+
+/// Escape text for a `[description]` inside a single-quoted zsh spec
+fn zsh_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh(bin: &str, options: &[OptionSpec], subcommands: &[SubcommandSpec]) -> String {
+    let func = function_name(bin);
+    let mut out = format!("#compdef {bin}\n\n{func}() {{\n    local state\n");
+    for sub in subcommands {
+        out.push_str(&format!(
+            "    if (( ${{words[(I){}]}} && ${{words[(I){}]}} < CURRENT )); then\n        \
+             _values shell {}\n        \
+             return\n    \
+             fi\n",
+            sub.name,
+            sub.name,
+            sub.values.join(" ")
+        ));
+    }
+    out.push_str("    _arguments -s -S \\\n");
+    for opt in options {
+        let spellings = opt.spellings();
+        let group = if opt.repeatable {
+            "*".to_string()
+        } else if spellings.len() > 1 {
+            format!("({})", spellings.join(" "))
+        } else {
+            String::new()
+        };
+        let value = match &opt.value_name {
+            None => String::new(),
+            Some(name) => {
+                let action = if !opt.values.is_empty() {
+                    format!("({})", opt.values.join(" "))
+                } else {
+                    match opt.hint {
+                        ValueHint::DirPath => "_directories".to_string(),
+                        ValueHint::FilePath | ValueHint::AnyPath => "_files".to_string(),
+                        _ => " ".to_string(),
+                    }
+                };
+                format!(":{}:{}", name, action)
+            }
+        };
+        for spelling in &spellings {
+            let separator = match (&opt.value_name, spelling.starts_with("--")) {
+                (None, _) => "",
+                (Some(_), true) => "=",
+                (Some(_), false) => "+",
+            };
+            out.push_str(&format!(
+                "        '{}{}{}[{}]{}' \\\n",
+                group,
+                spelling,
+                separator,
+                zsh_escape(&opt.help),
+                value
+            ));
+        }
+    }
+    let commands: Vec<String> = subcommands
+        .iter()
+        .map(|sub| {
+            let help = zsh_escape(&sub.help)
+                .replace('"', "\\\"")
+                .replace('$', "\\$")
+                .replace('`', "\\`");
+            format!("{}\\:\"{}\"", sub.name, help)
+        })
+        .collect();
+    out.push_str(&format!(
+        "        '1: :->first' && return\n    \
+         case $state in\n        \
+         first)\n            \
+         _alternative 'directories:directory:_directories' \
+         'commands:command:(({}))'\n            \
+         ;;\n    \
+         esac\n\
+         }}\n\n\
+         {} \"$@\"\n",
+        commands.join(" "),
+        func
+    ));
+    out
+}
+
+/// Quote text for fish
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish(bin: &str, options: &[OptionSpec], subcommands: &[SubcommandSpec]) -> String {
+    let commands: Vec<&str> = subcommands.iter().map(|sub| sub.name.as_str()).collect();
+    let mut out = format!("complete -c {bin} -f\n");
+    for sub in subcommands {
+        out.push_str(&format!(
+            "complete -c {} -n __fish_use_subcommand -a {} -d {}\n\
+             complete -c {} -n '__fish_seen_subcommand_from {}' -a {}\n",
+            bin,
+            sub.name,
+            fish_quote(&sub.help),
+            bin,
+            sub.name,
+            fish_quote(&sub.values.join(" "))
+        ));
+    }
+    out.push_str(&format!(
+        "complete -c {} -n 'not __fish_seen_subcommand_from {}' -a '(__fish_complete_directories)'\n",
+        bin,
+        commands.join(" ")
+    ));
+    for opt in options {
+        let mut line = format!("complete -c {}", bin);
+        if let Some(short) = opt.short {
+            line.push_str(&format!(" -s {}", short));
+        }
+        for long in opt.long.iter().chain(&opt.aliases) {
+            line.push_str(&format!(" -l {}", long));
+        }
+        if opt.value_name.is_some() {
+            if !opt.values.is_empty() {
+                line.push_str(&format!(" -x -a {}", fish_quote(&opt.values.join(" "))));
+            } else {
+                match opt.hint {
+                    ValueHint::DirPath => line.push_str(" -x -a '(__fish_complete_directories)'"),
+                    ValueHint::FilePath | ValueHint::AnyPath => line.push_str(" -r -F"),
+                    _ => line.push_str(" -x"),
+                }
+            }
+        }
+        line.push_str(&format!(" -d {}\n", fish_quote(&opt.help)));
+        out.push_str(&line);
+    }
+    out
+}
@@ -0,0 +1,406 @@
+//! Default options from a config file and the `DUSTR_OPTS` environment
+//! variable.
+//!
+//! The config file is `$DUSTR_CONFIG`, or `dustr/config.toml` in
+//! `$XDG_CONFIG_HOME` (`~/.config` by default). Its keys are long option
+//! names, with `-` or `_`:
+//!
+//! ```toml
+//! sort = "size"
+//! color = "always"
+//! nogrouping = true
+//! exclude = [".git", "node_modules"]
+//! verbose = 2
+//! ```
+//!
+//! `DUSTR_OPTS` holds flags as on the command line, e.g. `--sort name -g`.
+//! Both become arguments placed before the explicit ones, config file
+//! first, so the command line takes precedence; list options such as
+//! `--exclude` accumulate instead. A default that conflicts with a later
+//! option, such as `--si` in `DUSTR_OPTS` and `--binary` on the command
+//! line, is dropped. `--no-config` ignores both.
+
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Command};
+
+use crate::limits::{parse_key, parse_string, strip_comment};
+
+/// Default arguments gathered from the config file and `DUSTR_OPTS`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Defaults {
+    /// The config file that was read, if any
+    pub file: Option<PathBuf>,
+    /// Arguments from the config file
+    pub file_args: Vec<String>,
+    /// Arguments from `DUSTR_OPTS`
+    pub env_args: Vec<String>,
+}
+
+impl Defaults {
+    /// Read the config file and `DUSTR_OPTS`. A missing default config file
+    /// is fine; a missing `$DUSTR_CONFIG` is not.
+    pub fn load(cmd: &Command) -> Result<Self, String> {
+        let mut defaults = Defaults::default();
+        let explicit = env::var_os("DUSTR_CONFIG").filter(|path| !path.is_empty());
+        let path = explicit.clone().map(PathBuf::from).or_else(|| {
+            env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+                .map(|dir| dir.join("dustr").join("config.toml"))
+        });
+        if let Some(path) = path {
+            match fs::read_to_string(&path) {
+                Ok(text) => {
+                    defaults.file_args = parse_config(&text, cmd)
+                        .map_err(|msg| format!("{}:{}", path.display(), msg))?;
+                    defaults.file = Some(path);
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound && explicit.is_none() => {}
+                Err(e) => {
+                    return Err(format!(
+                        "Cannot read config file '{}': {}",
+                        path.display(),
+                        e
+                    ))
+                }
+            }
+        }
+        if let Ok(opts) = env::var("DUSTR_OPTS") {
+            defaults.env_args = split_args(&opts).map_err(|msg| format!("DUSTR_OPTS: {}", msg))?;
+        }
+        Ok(defaults)
+    }
+
+    /// The arguments to parse: `program`, the defaults and then `args`.
+    /// The defaults are left out when `args` contain `--no-config`, and so
+    /// is each default that conflicts with a later argument (see
+    /// `drop_conflicting`).
+    pub fn with_args(
+        cmd: &Command,
+        program: &str,
+        args: Vec<OsString>,
+    ) -> Result<(Vec<OsString>, Self), String> {
+        let no_config = args
+            .iter()
+            .take_while(|arg| *arg != "--")
+            .any(|arg| arg == "--no-config");
+        let defaults = if no_config {
+            Defaults::default()
+        } else {
+            Defaults::load(cmd)?
+        };
+        let later: Vec<OsString> = drop_conflicting(cmd, &defaults.env_args, &args)
+            .into_iter()
+            .map(OsString::from)
+            .chain(args)
+            .collect();
+        let merged = std::iter::once(OsString::from(program))
+            .chain(
+                drop_conflicting(cmd, &defaults.file_args, &later)
+                    .into_iter()
+                    .map(OsString::from),
+            )
+            .chain(later)
+            .collect();
+        Ok((merged, defaults))
+    }
+
+    /// Print the effective settings for `--dump-config`: where the defaults
+    /// came from, then every option given anywhere, in config file syntax
+    pub fn dump(&self, out: &mut dyn Write, cmd: &Command, matches: &ArgMatches) -> io::Result<()> {
+        match &self.file {
+            Some(path) => writeln!(out, "# Config file: {}", path.display())?,
+            None => writeln!(out, "# Config file: none")?,
+        }
+        if !self.env_args.is_empty() {
+            writeln!(out, "# DUSTR_OPTS: {}", self.env_args.join(" "))?;
+        }
+        for arg in cmd.get_arguments() {
+            let (Some(long), id) = (arg.get_long(), arg.get_id().as_str()) else {
+                continue;
+            };
+            if matches!(long, "no-config" | "dump-config")
+                || matches.value_source(id) != Some(ValueSource::CommandLine)
+            {
+                continue;
+            }
+            let value = match arg.get_action() {
+                ArgAction::SetTrue | ArgAction::SetFalse => "true".to_string(),
+                ArgAction::Count => matches.get_count(id).to_string(),
+                action => {
+                    let values: Vec<String> = matches
+                        .get_raw(id)
+                        .into_iter()
+                        .flatten()
+                        .map(|v| toml_value(&v.to_string_lossy()))
+                        .collect();
+                    match (action, values.as_slice()) {
+                        (ArgAction::Append, _) => format!("[{}]", values.join(", ")),
+                        (_, [value]) => value.clone(),
+                        _ => continue,
+                    }
+                }
+            };
+            writeln!(out, "{} = {}", long, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The ids of the options given in `args`
+fn given_ids<T: Into<OsString>>(
+    cmd: &Command,
+    args: impl IntoIterator<Item = T>,
+) -> HashSet<String> {
+    let args = std::iter::once(OsString::from("dustr")).chain(args.into_iter().map(Into::into));
+    let Ok(matches) = cmd.clone().ignore_errors(true).try_get_matches_from(args) else {
+        return HashSet::new();
+    };
+    matches
+        .ids()
+        .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::CommandLine))
+        .map(|id| id.to_string())
+        .collect()
+}
+
+/// Leave out the `defaults` that conflict with an option given in `later`,
+/// such as `--si` from `DUSTR_OPTS` with `--binary` on the command line, so
+/// the later arguments win instead of failing. Each default option is
+/// kept or dropped with the values that follow it.
+pub fn drop_conflicting(cmd: &Command, defaults: &[String], later: &[OsString]) -> Vec<String> {
+    if defaults.is_empty() {
+        return Vec::new();
+    }
+    let mut cmd = cmd.clone();
+    cmd.build();
+    let given = given_ids(&cmd, later);
+    let mut conflicting = HashSet::new();
+    for arg in cmd.get_arguments() {
+        for other in cmd.get_arg_conflicts_with(arg) {
+            let (a, b) = (arg.get_id().as_str(), other.get_id().as_str());
+            if given.contains(a) {
+                conflicting.insert(b);
+            }
+            if given.contains(b) {
+                conflicting.insert(a);
+            }
+        }
+    }
+    let mut options: Vec<Vec<String>> = Vec::new();
+    for arg in defaults {
+        match options.last_mut() {
+            Some(option) if !arg.starts_with('-') => option.push(arg.clone()),
+            _ => options.push(vec![arg.clone()]),
+        }
+    }
+    options
+        .into_iter()
+        .filter(|option| {
+            given_ids(&cmd, option)
+                .iter()
+                .all(|id| !conflicting.contains(id.as_str()))
+        })
+        .flatten()
+        .collect()
+}
+
+/// A value in config file syntax: numbers bare, anything else quoted
+fn toml_value(value: &str) -> String {
+    if value.parse::<f64>().is_ok_and(f64::is_finite) {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// A scalar value in the config file
+fn parse_scalar(value: &str) -> Result<(String, &str), String> {
+    if value.starts_with('"') || value.starts_with('\'') {
+        return parse_string(value);
+    }
+    let end = value
+        .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+        .unwrap_or(value.len());
+    let (scalar, rest) = value.split_at(end);
+    if scalar.is_empty() {
+        return Err(format!("expected a value, found {:?}", value));
+    }
+    Ok((scalar.to_string(), rest))
+}
+
+/// Parse a value: a string, number or boolean, or an array of those
+fn parse_value(value: &str) -> Result<Vec<String>, String> {
+    let Some(mut rest) = value.strip_prefix('[') else {
+        let (scalar, rest) = parse_scalar(value)?;
+        if !rest.trim().is_empty() {
+            return Err(format!("unexpected {:?} after value", rest.trim()));
+        }
+        return Ok(vec![scalar]);
+    };
+    let mut items = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix(']') {
+            if !after.trim().is_empty() {
+                return Err(format!("unexpected {:?} after array", after.trim()));
+            }
+            return Ok(items);
+        }
+        let (item, after) = parse_scalar(rest)?;
+        items.push(item);
+        rest = after.trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest);
+        if rest.is_empty() {
+            return Err("unterminated array (arrays must fit on one line)".to_string());
+        }
+    }
+}
+
+/// Turn the contents of a config file into command line arguments for
+/// `cmd`, checking every key and value. Errors are prefixed with the
+/// 1-based line number.
+pub fn parse_config(text: &str, cmd: &Command) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for (lineno, raw) in text.lines().enumerate() {
+        let err = |msg: String| format!("{}: {}", lineno + 1, msg);
+        let line = strip_comment(raw).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(err("tables are not supported".to_string()));
+        }
+        let (key, rest) = parse_key(line).map_err(err)?;
+        let key_err = |msg: String| err(format!("key '{}': {}", key, msg));
+        let value = rest
+            .trim_start()
+            .strip_prefix('=')
+            .ok_or_else(|| err(format!("expected '=' after {:?}", key)))?
+            .trim();
+        let long = key.replace('_', "-");
+        let arg = cmd
+            .get_arguments()
+            .find(|arg| {
+                arg.get_long() == Some(long.as_str())
+                    || arg
+                        .get_visible_aliases()
+                        .is_some_and(|aliases| aliases.contains(&long.as_str()))
+            })
+            .filter(|_| {
+                !matches!(
+                    long.as_str(),
+                    "no-config" | "dump-config" | "help" | "version"
+                )
+            })
+            .ok_or_else(|| key_err("unknown option".to_string()))?;
+        let values = parse_value(value).map_err(key_err)?;
+        let flag = format!("--{}", long);
+        match (arg.get_action(), values.as_slice()) {
+            (ArgAction::SetTrue | ArgAction::SetFalse, [value]) => match value.as_str() {
+                "true" => args.push(flag),
+                "false" => {}
+                _ => return Err(key_err(format!("expected true or false, not {:?}", value))),
+            },
+            (ArgAction::Count, [value]) => {
+                let count = match value.as_str() {
+                    "true" => 1,
+                    "false" => 0,
+                    n => n
+                        .parse::<u8>()
+                        .map_err(|_| key_err(format!("expected a count, not {:?}", n)))?,
+                };
+                args.extend(std::iter::repeat_n(flag, count.into()));
+            }
+            (ArgAction::Set | ArgAction::Append, values) => {
+                if values.len() != 1 && !matches!(arg.get_action(), ArgAction::Append) {
+                    return Err(key_err("expected a single value, not an array".to_string()));
+                }
+                for value in values {
+                    let arg = format!("{}={}", flag, value);
+                    check_value(cmd, &arg).map_err(key_err)?;
+                    args.push(arg);
+                }
+            }
+            _ => return Err(key_err("expected a single value, not an array".to_string())),
+        }
+    }
+    Ok(args)
+}
+
+/// Check a single `--option=value` argument against `cmd`, reporting only
+/// invalid values: conflicts and requirements are checked once all
+/// arguments are merged
+fn check_value(cmd: &Command, arg: &str) -> Result<(), String> {
+    use clap::error::ErrorKind;
+    match cmd.clone().try_get_matches_from(["dustr", arg]) {
+        Err(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::InvalidValue | ErrorKind::ValueValidation
+            ) =>
+        {
+            let message = e.to_string();
+            Err(message
+                .lines()
+                .map(str::trim)
+                .take_while(|line| !line.is_empty())
+                .map(|line| line.trim_start_matches("error: "))
+                .collect::<Vec<_>>()
+                .join(" "))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Split `DUSTR_OPTS` into arguments like a shell would: on whitespace,
+/// with 'single' and "double" quotes and backslash escapes
+pub fn split_args(s: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => args.extend(current.take()),
+            '\'' => {
+                let word = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated ' quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let word = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => word.push(c),
+                            None => return Err("unterminated \" quote".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated \" quote".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    current.get_or_insert_with(String::new).push(c);
+                }
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+    Ok(args)
+}
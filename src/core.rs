@@ -2,53 +2,267 @@ use jwalk::WalkDir as JWalkDir;
 use parking_lot::Mutex;
 use rayon::prelude::*;
 use signal_hook::consts::SIGINT;
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fs;
-use std::io::{self, Write};
-use std::os::unix::fs::MetadataExt;
-use std::path::Path;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-/// Error type for core dustr operations
+use crate::diagnostics;
+use crate::duplicates::{DuplicateCandidates, DuplicateSet};
+use crate::empty_dirs::{EmptyEntries, EmptyFinder};
+use crate::glob::GlobSet;
+use crate::junk::{Junk, JunkPatterns};
+use crate::limits::Limits;
+use crate::treemap::{NodeBuilder, TreeNode};
+
+/// Error type for core dustr operations.
+/// I/O variants carry a message and the OS errno when one is known.
 #[derive(Debug)]
 pub enum DustrError {
-    NotFound(String),
-    PermissionDenied(String),
-    OsError(String),
+    NotFound(String, Option<i32>),
+    PermissionDenied(String, Option<i32>),
+    NotADirectory(String, Option<i32>),
+    OsError(String, Option<i32>),
     Cancelled,
+    /// The reader of the output went away, e.g. `dustr | head`
+    BrokenPipe,
+    /// The scan hit `--timeout` or `--max-files`; carries what was gathered
+    /// from the top-level entries that were completed
+    Truncated(Box<ScanResult>),
+    /// Entries over their `--limits` size with `--strict-limits` set
+    LimitsExceeded(usize),
+}
+
+impl DustrError {
+    /// Classify an I/O error by its kind, keeping its errno
+    pub fn from_io(msg: String, e: &io::Error) -> Self {
+        let errno = e.raw_os_error();
+        match e.kind() {
+            io::ErrorKind::NotFound => DustrError::NotFound(msg, errno),
+            io::ErrorKind::PermissionDenied => DustrError::PermissionDenied(msg, errno),
+            io::ErrorKind::NotADirectory => DustrError::NotADirectory(msg, errno),
+            io::ErrorKind::BrokenPipe => DustrError::BrokenPipe,
+            _ => DustrError::OsError(msg, errno),
+        }
+    }
+
+    /// The OS errno associated with this error, if any
+    pub fn errno(&self) -> Option<i32> {
+        match self {
+            DustrError::NotFound(_, errno)
+            | DustrError::PermissionDenied(_, errno)
+            | DustrError::NotADirectory(_, errno)
+            | DustrError::OsError(_, errno) => *errno,
+            DustrError::BrokenPipe => Some(libc::EPIPE),
+            DustrError::Cancelled | DustrError::Truncated(_) | DustrError::LimitsExceeded(_) => {
+                None
+            }
+        }
+    }
 }
 
 impl std::fmt::Display for DustrError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            DustrError::NotFound(msg) => write!(f, "{}", msg),
-            DustrError::PermissionDenied(msg) => write!(f, "{}", msg),
-            DustrError::OsError(msg) => write!(f, "{}", msg),
+            DustrError::NotFound(msg, _) => write!(f, "{}", msg),
+            DustrError::PermissionDenied(msg, _) => write!(f, "{}", msg),
+            DustrError::NotADirectory(msg, _) => write!(f, "{}", msg),
+            DustrError::OsError(msg, _) => write!(f, "{}", msg),
             DustrError::Cancelled => write!(f, "Cancelled"),
+            DustrError::BrokenPipe => write!(f, "Broken pipe"),
+            DustrError::Truncated(scan) => match scan.truncated {
+                Some(reason) => write!(f, "Scan truncated ({})", reason),
+                None => write!(f, "Scan truncated"),
+            },
+            DustrError::LimitsExceeded(n) => {
+                let plural = if *n == 1 { "entry is" } else { "entries are" };
+                write!(f, "{} {} over the size limit", n, plural)
+            }
         }
     }
 }
 
 impl std::error::Error for DustrError {}
 
+impl From<io::Error> for DustrError {
+    fn from(e: io::Error) -> Self {
+        DustrError::from_io(e.to_string(), &e)
+    }
+}
+
+/// Why a scan stopped before covering everything
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Truncation {
+    /// `--timeout` ran out after this many seconds
+    Timeout(u64),
+    /// More than `--max-files` files were found
+    MaxFiles(u64),
+}
+
+impl std::fmt::Display for Truncation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Truncation::Timeout(secs) => write!(f, "timeout after {}s", secs),
+            Truncation::MaxFiles(max) => write!(f, "more than {} files", max),
+        }
+    }
+}
+
+/// Version of the dustr crate
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Target triple dustr was built for
+pub const TARGET: &str = env!("DUSTR_TARGET");
+
+/// `rustc --version` of the compiler that built dustr
+pub const RUSTC_VERSION: &str = env!("DUSTR_RUSTC_VERSION");
+
+/// Cargo profile of the build ("debug" or "release")
+pub const PROFILE: &str = env!("DUSTR_PROFILE");
+
+/// Version shown by `--version`, with the build information
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("DUSTR_TARGET"),
+    ", ",
+    env!("DUSTR_RUSTC_VERSION"),
+    ")"
+);
+
+/// Version of the structured report formats (JSON, Markdown, HTML), raised
+/// on changes that break readers of older reports
+pub const FORMAT_VERSION: u32 = 1;
+
 /// Shared CLI arguments (used by both the binary and the Python entry point)
 #[derive(clap::Parser, Debug)]
-#[command(about = "Show disk usage statistics", long_about = None)]
+#[command(
+    about = "Show disk usage statistics",
+    long_about = None,
+    version = LONG_VERSION,
+    args_override_self = true,
+    disable_help_subcommand = true
+)]
 pub struct Cli {
     /// Directory to analyze
-    #[arg(default_value = ".")]
+    #[arg(default_value = ".", value_hint = clap::ValueHint::DirPath)]
     pub dirname: String,
 
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+
+    /// Size each of the NUL-separated paths read from FILE ("-" for stdin)
+    /// as a whole instead of DIRNAME's entries, e.g. with
+    /// `find ... -print0 | dustr --files0-from -`
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["dirname", "watch"], value_hint = clap::ValueHint::FilePath)]
+    pub files0_from: Option<String>,
+
+    /// Ignore the config file and DUSTR_OPTS
+    #[arg(long)]
+    pub no_config: bool,
+
+    /// Print the settings merged from the config file, DUSTR_OPTS and the
+    /// command line, then exit
+    #[arg(long)]
+    pub dump_config: bool,
+
+    /// Report errors as plain `dustr: ...` lines on stderr, without the
+    /// decorative messages of the Python entry point
+    #[arg(long)]
+    pub quiet_errors: bool,
+
+    #[command(flatten)]
+    pub opts: Options,
+}
+
+/// Commands run instead of a scan
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum CliCommand {
+    /// Print a shell completion script
+    ///
+    /// For example: dustr completions bash > /etc/bash_completion.d/dustr
+    Completions {
+        /// Shell to write the script for
+        shell: crate::completions::Shell,
+    },
+}
+
+/// Options controlling scanning and output
+#[derive(clap::Args, Debug, Clone)]
+pub struct Options {
     /// Count inodes instead of disk usage
     #[arg(short, long)]
     pub inodes: bool,
 
+    /// Show sizes and inode counts side by side (both come from one scan)
+    #[arg(long, conflicts_with = "inodes")]
+    pub both: bool,
+
+    /// Add a KB column with the plain kilobyte count next to the
+    /// human-readable size
+    #[arg(long)]
+    pub show_raw: bool,
+
+    /// Add a Bytes column with the exact byte count next to the
+    /// human-readable size
+    #[arg(long)]
+    pub exact: bool,
+
+    /// With --inodes, count only directories (files count as 0)
+    #[arg(long, requires = "inodes")]
+    pub dirs_only: bool,
+
+    /// Show only directories (and symlinks to them) as rows; hidden files
+    /// still count towards the total with --total-all
+    #[arg(long)]
+    pub only_dirs: bool,
+
+    /// With --only-dirs, include the hidden files in the total and the
+    /// percentages
+    #[arg(long, requires = "only_dirs")]
+    pub total_all: bool,
+
+    /// Show only the N largest entries, in the chosen order; the others
+    /// are summed up in a note below the table
+    #[arg(long, value_name = "N")]
+    pub top: Option<usize>,
+
+    /// With --top, show the entries left out as a single "<other>" row
+    /// that is sorted like any other entry
+    #[arg(long, requires = "top")]
+    pub aggregate_remainder: bool,
+
+    /// Start the table with a "." row for the whole directory, at 100%,
+    /// and scale the other bars to it rather than to the largest entry
+    #[arg(long)]
+    pub show_root: bool,
+
+    /// Size directories from their immediate children's apparent sizes
+    /// instead of walking them: instant, but the totals are approximate
+    #[arg(long)]
+    pub shallow: bool,
+
+    /// Leave out empty (zero-byte) files: they are neither listed nor
+    /// counted, also with --inodes
+    #[arg(long)]
+    pub skip_empty: bool,
+
     /// Don't use thousand separators
     #[arg(short = 'g', long)]
     pub nogrouping: bool,
 
-    /// Don't append file type indicators
+    /// Separator placed between digit groups in counts (default: from the
+    /// LC_ALL, LC_NUMERIC or LANG locale)
+    #[arg(long, value_name = "CHAR", conflicts_with = "nogrouping")]
+    pub group_separator: Option<char>,
+
+    /// Hide the Type column (and the JSON `type` field)
     #[arg(short = 'f', long = "noF")]
     pub no_f: bool,
 
@@ -56,467 +270,4659 @@ pub struct Cli {
     #[arg(short, long)]
     pub json: bool,
 
+    /// Output results in Prometheus text exposition format
+    #[arg(long, conflicts_with = "json")]
+    pub prometheus: bool,
+
+    /// Report format: the terminal table, a Markdown table, an HTML page,
+    /// or du-compatible `SIZE<TAB>PATH` lines
+    #[arg(long, value_enum, default_value_t = ReportFormat::Table, conflicts_with_all = ["json", "prometheus"])]
+    pub format: ReportFormat,
+
+    /// With --format du, end each record with NUL instead of a newline
+    #[arg(short = '0', long)]
+    pub null: bool,
+
+    /// Write the report to FILE instead of stdout
+    #[arg(short, long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    pub output: Option<String>,
+
+    /// Also write an SVG treemap of the directory tree to FILE
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    pub treemap: Option<String>,
+
+    /// Number of directory levels shown in the treemap
+    #[arg(long, value_name = "N", default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+    pub treemap_depth: u64,
+
+    /// Number of decimal places shown for sizes
+    #[arg(long, default_value_t = 1, value_name = "N")]
+    pub precision: usize,
+
+    /// Show sizes in powers of 1000 (kB, MB, GB)
+    #[arg(long, conflicts_with = "binary")]
+    pub si: bool,
+
+    /// Show sizes in powers of 1024 (KiB, MiB, GiB)
+    #[arg(long)]
+    pub binary: bool,
+
+    /// Show every size as a whole number of SIZE blocks, rounded up (e.g. K,
+    /// M, G, MB, 4K); defaults to $DU_BLOCK_SIZE or $BLOCK_SIZE
+    #[arg(short = 'B', long, value_name = "SIZE", value_parser = BlockSize::parse, conflicts_with_all = ["si", "binary"])]
+    pub block_size: Option<BlockSize>,
+
+    /// Show every size in the same unit (K, M, G or T) instead of scaling
+    /// each to its own, so the column can be compared at a glance; follows
+    /// --si and --binary
+    #[arg(long, value_enum, ignore_case = true, conflicts_with = "block_size")]
+    pub unit: Option<SizeUnit>,
+
+    /// Sort entries by size, name, or modification time
+    #[arg(long, value_enum, default_value_t = SortKey::Size)]
+    pub sort: SortKey,
+
+    /// Show the newest modification time found in each entry
+    #[arg(long, visible_alias = "mtime")]
+    pub time: bool,
+
+    /// How modification times are shown
+    #[arg(long, value_enum, default_value_t = TimeStyle::Iso, requires = "time")]
+    pub time_style: TimeStyle,
+
+    /// Histogram bar scale; log keeps small entries visible next to huge ones
+    #[arg(long, value_enum, default_value_t = HistogramScale::Linear)]
+    pub scale: HistogramScale,
+
+    /// Draw bars for each entry's size, or for the running share of the
+    /// total counted from the largest entry down (a Pareto view)
+    #[arg(long, value_enum, default_value_t = HistogramStyle::PerEntry)]
+    pub histogram_style: HistogramStyle,
+
+    /// Draw no histogram bar for entries below PCT percent of the total;
+    /// by default every nonempty entry gets at least one mark
+    #[arg(long, value_name = "PCT", default_value_t = 0.0)]
+    pub min_mark_percent: f64,
+
+    /// Color histogram bars by share of the total and highlight entries
+    /// over their limit: auto (when writing to a terminal), always or
+    /// never. NO_COLOR disables color unless CLICOLOR_FORCE is set.
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Explain the colors of the histogram bars below the table (only
+    /// printed when color is on)
+    #[arg(long)]
+    pub legend: bool,
+
+    /// Mark the table rows of entries whose name matches GLOB (e.g.
+    /// node_modules) with a leading '>', or in bold when color is on
+    /// (repeatable)
+    #[arg(long, value_name = "GLOB")]
+    pub highlight: Vec<String>,
+
+    /// Characters used to draw histogram and progress bars
+    #[arg(long, value_enum, default_value_t = BarStyle::Ascii)]
+    pub bar_style: BarStyle,
+
+    /// Draw bars with CHAR instead, e.g. '=' (non-ASCII characters fall back
+    /// to ASCII when the locale is not UTF-8)
+    #[arg(long, value_name = "CHAR", conflicts_with = "bar_style")]
+    pub bar_char: Option<char>,
+
+    /// When sorting by name, compare digit runs numerically and ignore case
+    /// and accents (so "log.2" comes before "log.10")
+    #[arg(long)]
+    pub natural: bool,
+
+    /// When sorting by name, compare names byte by byte, so uppercase comes
+    /// before lowercase ("README" before "notes"), instead of ignoring case
+    #[arg(long, conflicts_with = "natural")]
+    pub case_sensitive: bool,
+
+    /// Count the size of files that symlinks point to (without descending into
+    /// linked directories); a target reached more than once is counted once
+    #[arg(long)]
+    pub count_symlink_size: bool,
+
+    /// Add the sizes of extended attributes (and so of macOS resource
+    /// forks) to the files and directories carrying them (Linux and macOS)
+    #[arg(long)]
+    pub xattrs: bool,
+
+    /// Enter each directory once, skipping any reached again through a
+    /// bind mount or other filesystem cycle (costs memory per directory)
+    #[arg(long)]
+    pub detect_cycles: bool,
+
+    /// Leave out the blocks allocated to directories themselves, which are
+    /// counted by default as `du` does, and sum file contents only
+    #[arg(
+        long = "no-dir-entries",
+        action = clap::ArgAction::SetFalse,
+        overrides_with = "include_dir_overhead_compat"
+    )]
+    pub include_dir_overhead: bool,
+
+    /// The former opt-in spelling, still accepted on the command line and
+    /// in config files; like the default, counts directory blocks, and the
+    /// last of it and --no-dir-entries wins
+    #[arg(
+        long = "include-dir-overhead",
+        hide = true,
+        overrides_with = "include_dir_overhead"
+    )]
+    pub include_dir_overhead_compat: bool,
+
+    /// List symlinks whose targets don't exist
+    #[arg(long)]
+    pub check_links: bool,
+
+    /// Only count files last modified more than AGE ago (e.g. 30d, 6m, 1y)
+    /// or before a date (YYYY-MM-DD)
+    #[arg(long, value_name = "AGE", value_parser = AgeSpec::parse)]
+    pub older_than: Option<AgeSpec>,
+
+    /// Only count files last modified less than AGE ago (e.g. 30d, 6m, 1y)
+    /// or on or after a date (YYYY-MM-DD)
+    #[arg(long, value_name = "AGE", value_parser = AgeSpec::parse)]
+    pub newer_than: Option<AgeSpec>,
+
+    /// Process top-level entries as they are read instead of listing them
+    /// first, bounding memory for huge directories (progress shows a spinner)
+    #[arg(long)]
+    pub streaming: bool,
+
+    /// Add a summary of counts and sizes by entry type (files, directories,
+    /// symlinks, special files)
+    #[arg(long)]
+    pub summary: bool,
+
+    /// Add the distribution of file sizes: how many regular files, and how
+    /// many bytes, fall into each size bucket
+    #[arg(long)]
+    pub size_histogram: bool,
+
+    /// Upper bounds of the --size-histogram buckets, comma-separated (e.g.
+    /// 1K,1M,1G); a last bucket holds the larger files
+    #[arg(
+        long,
+        value_name = "SIZES",
+        value_delimiter = ',',
+        value_parser = parse_size_arg,
+        requires = "size_histogram"
+    )]
+    pub buckets: Vec<u64>,
+
+    /// Add statistics of the regular-file sizes: count, total, mean,
+    /// median, 90th and 99th percentiles and maximum
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Add the usage at each depth below DIRNAME: the space and files of
+    /// the top-level entries (depth 1), of their children (depth 2), etc.
+    #[arg(long)]
+    pub by_depth: bool,
+
+    /// Show at most N --by-depth rows, the last one (N+) holding the deeper
+    /// levels
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "by_depth"
+    )]
+    pub max_depth_rows: u64,
+
+    /// Add the N directories anywhere in the tree with the most direct
+    /// children, e.g. a runaway temporary directory
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub inode_hogs: Option<u64>,
+
+    /// Add the sets of identical regular files, with the space each set
+    /// wastes; hard links to one file are not copies
+    #[arg(long)]
+    pub duplicates: bool,
+
+    /// Smallest file --duplicates hashes (default 1M); smaller files are
+    /// never read
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = "1M",
+        value_parser = parse_size_arg,
+        requires = "duplicates"
+    )]
+    pub dup_min_size: u64,
+
+    /// Add the zero-byte files and the directories holding nothing but
+    /// empty directories
+    #[arg(long)]
+    pub find_empty: bool,
+
+    /// Show at most N paths in each --find-empty list of the table (JSON
+    /// has them all)
+    #[arg(long, value_name = "N", default_value_t = 20, requires = "find_empty")]
+    pub find_empty_limit: usize,
+
+    /// Add the files left behind that are safe to reclaim: core dumps,
+    /// temporary files, editor backup and swap files, .DS_Store and crash
+    /// dumps, with totals per pattern and the largest matches
+    #[arg(long)]
+    pub find_junk: bool,
+
+    /// Also count files whose name matches GLOB as --find-junk junk
+    /// (repeatable)
+    #[arg(long, value_name = "GLOB", requires = "find_junk")]
+    pub junk_pattern: Vec<String>,
+
+    /// Show at most N of the largest --find-junk matches in the table
+    /// (JSON has them all)
+    #[arg(long, value_name = "N", default_value_t = 20, requires = "find_junk")]
+    pub find_junk_limit: usize,
+
+    /// Add the regular files modified less than AGE ago (e.g. 1h, 2d) or
+    /// on or after a date (YYYY-MM-DD) that are at least --recent-min-size
+    /// large, largest first; with --watch, files new since the previous
+    /// refresh are marked with +
+    #[arg(long, value_name = "AGE", value_parser = AgeSpec::parse)]
+    pub recent: Option<AgeSpec>,
+
+    /// Smallest file --recent lists (e.g. 100M)
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = "1M",
+        value_parser = parse_size_arg,
+        requires = "recent"
+    )]
+    pub recent_min_size: u64,
+
+    /// Show at most N --recent files in the table (JSON has them all)
+    #[arg(long, value_name = "N", default_value_t = 20, requires = "recent")]
+    pub recent_limit: usize,
+
+    /// Walk and hash with N threads instead of one per CPU
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub threads: Option<u64>,
+
+    /// Show the device, size and free space of the filesystem holding
+    /// DIRNAME above the table, like df
+    #[arg(long)]
+    pub free: bool,
+
+    /// Check for Ctrl+C (and update the verbose status) every N entries;
+    /// lower it for slow network filesystems, raise it for fast local ones
+    #[arg(long, value_name = "N", default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    pub check_interval: u64,
+
+    /// Annotate entries with their usage against size limits read from a
+    /// TOML FILE mapping glob patterns to sizes (e.g. "project-*" = "500G")
+    #[arg(
+        long,
+        value_name = "FILE",
+        value_parser = Limits::load,
+        value_hint = clap::ValueHint::FilePath
+    )]
+    pub limits: Option<Limits>,
+
+    /// Exit with an error when any entry is over its limit
+    #[arg(long, requires = "limits")]
+    pub strict_limits: bool,
+
+    /// Skip files and directories whose name (or, for patterns containing a
+    /// "/", whose path relative to DIRECTORY) matches the glob PATTERN;
+    /// may be repeated
+    #[arg(long, value_name = "PATTERN", value_hint = clap::ValueHint::DirPath)]
+    pub exclude: Vec<String>,
+
+    /// Read exclude patterns from FILE, one per line; blank lines and lines
+    /// starting with "#" are ignored
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    pub exclude_from: Option<String>,
+
+    /// Only list entries using at least SIZE on disk (e.g. 500K, 1M, 1.5G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size_arg)]
+    pub min_size: Option<u64>,
+
+    /// Only list entries using at most SIZE on disk (e.g. 500K, 1M, 1.5G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size_arg)]
+    pub max_size: Option<u64>,
+
+    /// Only count files whose name (or, for patterns containing a "/", whose
+    /// path relative to DIRECTORY) matches the glob PATTERN; directories are
+    /// still traversed. May be repeated
+    #[arg(long, value_name = "PATTERN")]
+    pub include: Vec<String>,
+
+    /// Show the average size of the files within each entry
+    #[arg(long)]
+    pub avg: bool,
+
+    /// Shorten names longer than N characters with an ellipsis; 0 never
+    /// shortens. By default names are
+    /// fitted to the terminal width when writing to a terminal
+    #[arg(long, value_name = "N")]
+    pub max_name_width: Option<usize>,
+
+    /// Show a running total percentage down the sorted list
+    #[arg(long)]
+    pub cumulative: bool,
+
+    /// Prune well-known build and cache directories (node_modules, .git,
+    /// target, __pycache__, .venv, .tox, .gradle, DerivedData, .mypy_cache,
+    /// .pytest_cache, .cache) from the entries and report their combined
+    /// size as a single "build/cache artifacts" line
+    #[arg(long)]
+    pub prune_known: bool,
+
+    /// Add NAME to the --prune-known directory names; may be repeated
+    #[arg(long, value_name = "NAME", requires = "prune_known")]
+    pub prune_add: Vec<String>,
+
+    /// Remove NAME from the --prune-known directory names; may be repeated
+    #[arg(long, value_name = "NAME", requires = "prune_known")]
+    pub prune_remove: Vec<String>,
+
+    /// With --prune-known, don't compute the size of pruned directories
+    #[arg(long, requires = "prune_known")]
+    pub prune_skip: bool,
+
+    /// Re-scan every N seconds, clearing the screen between runs, until Ctrl+C
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub watch: Option<u64>,
+
     /// Cross mount boundaries (by default stays on the same filesystem)
     #[arg(short = 'x', long)]
     pub cross_mounts: bool,
 
-    /// Show directories being traversed
-    #[arg(short, long)]
-    pub verbose: bool,
+    /// Stop the scan after SECS seconds, e.g. on a stalled network mount,
+    /// and report the entries completed so far
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout: Option<u64>,
+
+    /// Stop the scan once more than N files have been found, and report
+    /// the entries completed so far
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_files: Option<u64>,
+
+    /// Fail on the first path that cannot be read instead of warning about
+    /// it and leaving it out of the sizes
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Show directories being traversed, debug diagnostics and per-entry
+    /// timings; repeat (-vv) to trace every directory entered
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Don't print warnings about unreadable paths
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print how long the scan and report took to stderr afterwards
+    #[arg(long)]
+    pub timing: bool,
 
     /// Live-update statistics table during traversal
     #[arg(short, long)]
     pub live: bool,
+
+    /// Don't draw the progress bar on stderr during the scan
+    #[arg(long)]
+    pub no_progress: bool,
 }
 
-/// Calculate directory sizes for all items in a directory (parallel version)
-pub fn calculate_directory_sizes(
-    path: &str,
-    use_inodes: bool,
-    cross_mounts: bool,
-    verbose: bool,
-    live: bool,
-) -> Result<HashMap<String, u64>, DustrError> {
-    let base_path = Path::new(path);
+/// Key used to order entries in the output
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// Ascending by size (largest last)
+    Size,
+    /// Alphabetically by name, ignoring case unless `--case-sensitive`
+    Name,
+    /// Ascending by newest modification time (most recent last)
+    Time,
+}
 
-    if !base_path.exists() {
-        return Err(DustrError::NotFound(format!(
-            "Directory not found: {}",
-            path
-        )));
-    }
+/// Format used for the modification time column
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeStyle {
+    /// ISO-8601 timestamp in UTC, e.g. 2024-05-01T12:30:00Z
+    Iso,
+    /// Age relative to now, e.g. "3 days ago"
+    Relative,
+}
 
-    if !base_path.is_dir() {
-        return Err(DustrError::OsError(format!("Not a directory: {}", path)));
+/// Fixed unit for `--unit`
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeUnit {
+    /// Kilobytes
+    #[value(name = "K")]
+    K,
+    /// Megabytes
+    #[value(name = "M")]
+    M,
+    /// Gigabytes
+    #[value(name = "G")]
+    G,
+    /// Terabytes
+    #[value(name = "T")]
+    T,
+}
+
+impl SizeUnit {
+    /// Power of the unit's base, from 1 for K to 4 for T
+    fn power(self) -> usize {
+        self as usize + 1
     }
+}
 
-    let entries = match fs::read_dir(base_path) {
-        Ok(entries) => entries,
-        Err(e) => match e.kind() {
-            io::ErrorKind::NotFound => {
-                return Err(DustrError::NotFound(format!(
-                    "Directory not found: {}",
-                    path
-                )));
-            }
-            io::ErrorKind::PermissionDenied => {
-                return Err(DustrError::PermissionDenied(format!(
-                    "Permission denied: {}",
-                    e
-                )));
-            }
-            _ => {
-                return Err(DustrError::OsError(format!(
-                    "Cannot read directory '{}': {}",
-                    path, e
-                )));
-            }
-        },
-    };
+/// How histogram bar lengths relate to entry sizes
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistogramScale {
+    /// Bars proportional to size
+    Linear,
+    /// Bars proportional to log(size)
+    Log,
+}
 
-    // Collect entries first to get count
-    let entries_vec: Vec<_> = entries.flatten().collect();
-    let total_entries = entries_vec.len();
+/// What the histogram bars show
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistogramStyle {
+    /// Each entry's own size
+    PerEntry,
+    /// The share of the total taken by the entry and all larger ones
+    Cumulative,
+}
 
-    // Compute the base directory device id once for mount boundary checks
-    let base_dev = if !cross_mounts {
-        match fs::metadata(base_path) {
-            Ok(m) => Some(m.dev()),
-            Err(e) => {
-                return Err(DustrError::OsError(format!(
-                    "Cannot read metadata for '{}': {}",
-                    path, e
-                )));
-            }
-        }
-    } else {
-        None
-    };
+/// Layout of the report written by `print_disk_usage`
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// Plain-text table with a histogram
+    Table,
+    /// GitHub-flavored Markdown table
+    Markdown,
+    /// Standalone HTML page with a sortable table
+    Html,
+    /// `du -k`-style `SIZE<TAB>PATH` lines for scripts
+    Du,
+}
 
-    // Shared state for progress and cancellation
-    let progress = Arc::new(AtomicUsize::new(0));
-    let cancelled = Arc::new(AtomicBool::new(false));
-    let results: Arc<Mutex<HashMap<String, u64>>> = Arc::new(Mutex::new(HashMap::new()));
-    let current_entry: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
+/// When to use colored output
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal
+    Auto,
+    /// Always color
+    Always,
+    /// Never color
+    Never,
+}
 
-    // Register OS signal handler to set cancelled flag directly on Ctrl+C.
-    let signal_id = match signal_hook::flag::register(SIGINT, cancelled.clone()) {
-        Ok(id) => Some(id),
-        Err(e) => {
-            if verbose {
-                eprintln!(
-                    "Warning: failed to register SIGINT handler: {}. \
-                     Ctrl+C responsiveness may be reduced.",
-                    e
-                );
-            }
-            None
-        }
-    };
+/// How histogram and progress bars are drawn
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarStyle {
+    /// Plain ASCII characters
+    Ascii,
+    /// Unicode block characters with eighth-cell precision (falls back to
+    /// ASCII when the locale is not UTF-8)
+    #[value(alias = "unicode")]
+    Blocks,
+}
 
-    // Spawn live display thread if requested
-    let live_last_lines = Arc::new(AtomicUsize::new(0));
-    let live_display = if live {
-        let results_for_display = results.clone();
-        let cancelled_for_display = cancelled.clone();
-        let progress_for_display = progress.clone();
-        let last_lines_for_display = live_last_lines.clone();
-        let dirname = path.to_string();
-        Some(std::thread::spawn(move || {
-            let mut last_lines = 0usize;
-            loop {
-                std::thread::sleep(std::time::Duration::from_millis(500));
-                if cancelled_for_display.load(Ordering::Relaxed) {
-                    break;
-                }
-                let snapshot: Vec<(String, u64)> = {
-                    let r = results_for_display.lock();
-                    r.iter().map(|(k, v)| (k.clone(), *v)).collect()
+/// A point in time given either as an age relative to now ("30d", "6m",
+/// "1y") or as a calendar date ("2023-01-31", midnight UTC)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgeSpec {
+    /// The value as the user wrote it, used in report headers
+    pub text: String,
+    cutoff: AgeCutoff,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AgeCutoff {
+    /// Seconds before now
+    Age(i64),
+    /// Absolute epoch seconds
+    Date(i64),
+}
+
+impl AgeSpec {
+    /// Parse `<N><unit>` with unit s, h, d, w, m (30 days) or y (365 days),
+    /// or an ISO date `YYYY-MM-DD`
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let text = s.trim();
+        let cutoff = match parse_iso_date(text) {
+            Some(epoch) => AgeCutoff::Date(epoch),
+            None => {
+                let split = text
+                    .find(|c: char| !c.is_ascii_digit())
+                    .ok_or_else(|| format!("missing unit in {:?} (use s, h, d, w, m or y)", s))?;
+                let (amount, unit) = text.split_at(split);
+                let amount: i64 = amount.parse().map_err(|_| {
+                    format!("invalid age {:?} (expected e.g. 30d or 2023-01-31)", s)
+                })?;
+                let unit_secs = match unit {
+                    "s" => 1,
+                    "h" => 3_600,
+                    "d" => 86_400,
+                    "w" => 7 * 86_400,
+                    "m" => 30 * 86_400,
+                    "y" => 365 * 86_400,
+                    _ => {
+                        return Err(format!(
+                            "unknown unit {:?} in {:?} (use s, h, d, w, m or y)",
+                            unit, s
+                        ))
+                    }
                 };
-                let current = progress_for_display.load(Ordering::Relaxed);
-                let table = render_stats_table(
-                    &dirname,
-                    &snapshot,
-                    use_inodes,
-                    false,
-                    current,
-                    total_entries,
-                );
-                let bar = format_progress_bar(current, total_entries);
-                // Move cursor up to overwrite previous output, then print
-                if last_lines > 0 {
-                    eprint!("\x1b[{}A\x1b[J", last_lines);
-                }
-                eprintln!("{}{}", table, bar);
-                io::stderr().flush().ok();
-                last_lines = table.lines().count() + 1;
-                last_lines_for_display.store(last_lines, Ordering::Relaxed);
+                AgeCutoff::Age(
+                    amount
+                        .checked_mul(unit_secs)
+                        .ok_or_else(|| format!("age {:?} is too large", s))?,
+                )
             }
-        }))
-    } else {
-        None
-    };
+        };
+        Ok(AgeSpec {
+            text: text.to_string(),
+            cutoff,
+        })
+    }
 
-    // Process entries in parallel
-    entries_vec.par_iter().for_each(|entry| {
-        // Check for cancellation
-        if cancelled.load(Ordering::Relaxed) {
-            return;
+    /// The boundary as epoch seconds, as seen at `now`
+    pub fn cutoff(&self, now: i64) -> i64 {
+        match self.cutoff {
+            AgeCutoff::Age(secs) => now.saturating_sub(secs),
+            AgeCutoff::Date(epoch) => epoch,
         }
+    }
+}
 
-        let file_name = entry.file_name().to_string_lossy().to_string();
-        let file_path = entry.path();
+/// Parse `YYYY-MM-DD` into epoch seconds at midnight UTC
+fn parse_iso_date(s: &str) -> Option<i64> {
+    let mut parts = s.splitn(3, '-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let (year, month, day): (i64, u32, u32) =
+        (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+    if !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    // Reject days past the end of the month, e.g. 2023-02-30
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    Some(days * 86_400)
+}
 
-        if verbose {
-            *current_entry.lock() = file_name.clone();
+impl Options {
+    /// Whether the walk needs to collect modification times
+    pub fn needs_mtime(&self) -> bool {
+        self.time || self.sort == SortKey::Time
+    }
+
+    /// Check constraints between options that clap cannot express
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(min), Some(max)) = (self.min_size, self.max_size) {
+            if min > max {
+                return Err(format!(
+                    "--min-size ({}) is larger than --max-size ({})",
+                    self.format_size(min),
+                    self.format_size(max)
+                ));
+            }
+        }
+        if !(0.0..=100.0).contains(&self.min_mark_percent) {
+            return Err(format!(
+                "--min-mark-percent must be between 0 and 100, not {}",
+                self.min_mark_percent
+            ));
+        }
+        if self.null && self.format != ReportFormat::Du {
+            return Err("--null requires --format du".to_string());
+        }
+        if self.top == Some(0) {
+            return Err("--top must be at least 1".to_string());
         }
+        if self.aggregate_remainder && self.top.is_none() {
+            return Err("--aggregate-remainder requires --top".to_string());
+        }
+        Ok(())
+    }
 
-        let size = if use_inodes {
-            count_inodes(&file_path, &cancelled, base_dev, &current_entry)
+    /// Lowest level of diagnostics printed by the command line tools:
+    /// warnings by default, debug notes with `--verbose`, the directory
+    /// trace with `-vv`, none with `--quiet`
+    pub fn diagnostics_level(&self) -> Option<diagnostics::Level> {
+        if self.quiet {
+            None
+        } else if self.verbose >= 2 {
+            Some(diagnostics::Level::Trace)
+        } else if self.verbose > 0 {
+            Some(diagnostics::Level::Debug)
         } else {
-            calculate_size_kb(&file_path, &cancelled, base_dev, &current_entry)
-        };
+            Some(diagnostics::Level::Warning)
+        }
+    }
 
-        if !cancelled.load(Ordering::Relaxed) {
-            results.lock().insert(file_name, size);
+    /// Fill in the unit settings from `DU_BLOCK_SIZE` or `BLOCK_SIZE` when
+    /// none were given on the command line, as `du` does. The value "si"
+    /// selects `--si`; values that are not valid block sizes are ignored.
+    pub fn apply_block_size_env(&mut self) {
+        if self.block_size.is_some() || self.unit.is_some() || self.si || self.binary {
+            return;
+        }
+        let value = ["DU_BLOCK_SIZE", "BLOCK_SIZE"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok());
+        match value.as_deref() {
+            Some("si") => self.si = true,
+            Some(value) => self.block_size = BlockSize::parse(value).ok(),
+            None => {}
         }
+    }
 
-        // Update progress periodically
-        let current = progress.fetch_add(1, Ordering::Relaxed) + 1;
-        if !live && current.is_multiple_of(10) {
-            let entry_name = if verbose {
-                Some(current_entry.lock().clone())
-            } else {
-                None
-            };
-            print_progress(current, total_entries, entry_name.as_deref());
+    /// The digit grouping for counts: the locale's, with `--group-separator`
+    /// replacing its separator
+    pub fn digit_grouping(&self) -> DigitGrouping {
+        let locale = DigitGrouping::from_env();
+        match self.group_separator {
+            Some(separator) => DigitGrouping {
+                separator,
+                ..locale
+            },
+            None => locale,
         }
-    });
+    }
 
-    // Ensure final progress state is shown after parallel iteration completes
-    if !live {
-        print_progress(total_entries, total_entries, None);
+    /// Format a count with digit grouping, unless `--nogrouping` is set
+    pub fn group_digits(&self, num: u64) -> String {
+        if self.nogrouping {
+            num.to_string()
+        } else {
+            format_with_grouping(num, self.digit_grouping())
+        }
     }
 
-    // Unregister our signal handler now that computation is done
-    if let Some(id) = signal_id {
-        signal_hook::low_level::unregister(id);
+    /// Whether to color output going to a stream that is (or isn't) a
+    /// terminal, honoring the NO_COLOR and CLICOLOR_FORCE conventions.
+    /// Any non-empty NO_COLOR counts, while CLICOLOR_FORCE=0 means unset.
+    pub fn use_color(&self, is_terminal: bool) -> bool {
+        let value = |var| std::env::var_os(var).filter(|v| !v.is_empty());
+        color_enabled(
+            self.color,
+            is_terminal,
+            value("NO_COLOR").is_some(),
+            value("CLICOLOR_FORCE").is_some_and(|v| v != "0"),
+        )
     }
-    // Stop the live display thread
-    let was_cancelled = cancelled.load(Ordering::Relaxed);
-    cancelled.store(true, Ordering::Relaxed);
-    if let Some(handle) = live_display {
+
+    /// An empty `--duplicates` collector with the `--dup-min-size` bound;
+    /// `None` without `--duplicates`
+    pub fn new_duplicate_candidates(&self) -> Option<DuplicateCandidates> {
+        self.duplicates
+            .then(|| DuplicateCandidates::new(self.dup_min_size.saturating_mul(1024)))
+    }
+
+    /// An empty `--treemap` tree; `None` without `--treemap`
+    pub fn new_treemap(&self) -> Option<NodeBuilder> {
+        self.treemap.is_some().then(NodeBuilder::default)
+    }
+
+    /// The `--find-junk` patterns, with those from `--junk-pattern`; `None`
+    /// without `--find-junk`
+    pub fn junk_patterns(&self) -> Option<JunkPatterns> {
+        self.find_junk
+            .then(|| JunkPatterns::new(&self.junk_pattern))
+    }
+
+    /// Run `f` on a pool of `--threads` threads, or on the global pool
+    pub fn with_threads<R: Send>(&self, f: impl FnOnce() -> R + Send) -> Result<R, DustrError> {
+        let Some(threads) = self.threads else {
+            return Ok(f());
+        };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build()
+            .map_err(|e| {
+                DustrError::OsError(format!("Cannot start {} threads: {}", threads, e), None)
+            })?;
+        Ok(pool.install(f))
+    }
+
+    /// Empty `--size-histogram` buckets, with the `--buckets` bounds or the
+    /// default ones; `None` without `--size-histogram`
+    pub fn new_size_histogram(&self) -> Option<SizeHistogram> {
+        self.size_histogram.then(|| {
+            if self.buckets.is_empty() {
+                SizeHistogram::new(&SizeHistogram::DEFAULT_BOUNDS)
+            } else {
+                SizeHistogram::new(&self.buckets)
+            }
+        })
+    }
+
+    /// The glyphs for bars under `--bar-style`/`--bar-char`, using
+    /// `ascii_char` for plain ASCII bars
+    pub fn bar_glyphs(&self, ascii_char: char) -> BarGlyphs {
+        match (self.bar_char, self.bar_style) {
+            (Some(c), _) if c.is_ascii() || unicode_output() => BarGlyphs::Char(c),
+            (None, BarStyle::Blocks) if unicode_output() => BarGlyphs::Blocks,
+            _ => BarGlyphs::Char(ascii_char),
+        }
+    }
+
+    /// Format a size in KB with the selected units and precision
+    pub fn format_size(&self, size_kb: u64) -> String {
+        let bytes = size_kb as f64 * 1024.0;
+        match (&self.block_size, self.unit) {
+            (Some(block), _) => block.format(size_kb),
+            (None, Some(unit)) if self.si => {
+                format_in_unit(bytes, 1000.0, &SI_UNITS, unit.power(), self.precision)
+            }
+            (None, Some(unit)) if self.binary => {
+                format_in_unit(bytes, 1024.0, &BINARY_UNITS, unit.power(), self.precision)
+            }
+            (None, Some(unit)) => format_size_in(size_kb, unit, self.precision),
+            (None, None) if self.si => format_size_si(size_kb, self.precision),
+            (None, None) if self.binary => format_size_binary(size_kb, self.precision),
+            (None, None) => format_size(size_kb, self.precision),
+        }
+    }
+
+    /// Whether an entry of `size_kb` lies within `--min-size`/`--max-size`
+    pub fn size_in_range(&self, size_kb: u64) -> bool {
+        self.min_size.is_none_or(|min| size_kb >= min)
+            && self.max_size.is_none_or(|max| size_kb <= max)
+    }
+
+    /// The exclude and include rules: `--exclude` patterns merged with those
+    /// read from `--exclude-from`, plus the `--include` patterns
+    pub fn path_filter(&self) -> Result<PathFilter, DustrError> {
+        let mut patterns = self.exclude.clone();
+        if let Some(path) = &self.exclude_from {
+            patterns.extend(read_pattern_file(path)?);
+        }
+        let known = if self.prune_known {
+            KNOWN_JUNK_DIRS
+                .iter()
+                .map(|name| name.to_string())
+                .chain(self.prune_add.iter().cloned())
+                .filter(|name| !self.prune_remove.contains(name))
+                .collect()
+        } else {
+            HashSet::new()
+        };
+        Ok(PathFilter {
+            globs: GlobSet::new(patterns),
+            includes: GlobSet::new(&self.include),
+            known,
+        })
+    }
+
+    /// Whether `--older-than` or `--newer-than` restricts the counted files
+    pub fn has_age_filter(&self) -> bool {
+        self.older_than.is_some() || self.newer_than.is_some()
+    }
+
+    /// Whether a file modified at `mtime` passes the age filters.
+    /// Modification times in the future (clock skew) count as age zero.
+    pub fn age_matches(&self, mtime: i64, now: i64) -> bool {
+        let mtime = mtime.min(now);
+        self.older_than
+            .as_ref()
+            .is_none_or(|spec| mtime < spec.cutoff(now))
+            && self
+                .newer_than
+                .as_ref()
+                .is_none_or(|spec| mtime >= spec.cutoff(now))
+    }
+
+    /// Whether `--skip-empty` leaves out the file with metadata `meta`
+    pub fn skips_empty(&self, meta: &fs::Metadata) -> bool {
+        self.skip_empty && meta.is_file() && meta.len() == 0
+    }
+
+    /// Whether `--recent` lists the file with metadata `meta`
+    pub fn is_recent(&self, meta: &fs::Metadata, now: i64) -> bool {
+        self.recent.as_ref().is_some_and(|spec| {
+            meta.mtime() >= spec.cutoff(now)
+                && meta.len() >= self.recent_min_size.saturating_mul(1024)
+        })
+    }
+
+    /// Whether `--highlight` marks the table row of entry `name`
+    pub fn is_highlighted(&self, name: &str) -> bool {
+        self.highlight
+            .iter()
+            .any(|pattern| crate::glob::glob_match(pattern, name))
+    }
+
+    /// Description of the active age filters for report headers
+    pub fn age_filter_note(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if let Some(spec) = &self.older_than {
+            parts.push(format!("older than {}", spec.text));
+        }
+        if let Some(spec) = &self.newer_than {
+            parts.push(format!("newer than {}", spec.text));
+        }
+        if parts.is_empty() {
+            None
+        } else {
+            Some(format!("Counting only files {}", parts.join(" and ")))
+        }
+    }
+}
+
+/// Read glob patterns from a file, one per line, skipping blank lines and
+/// `#` comments
+pub fn read_pattern_file(path: &str) -> Result<Vec<String>, DustrError> {
+    let text = fs::read_to_string(path).map_err(|e| {
+        DustrError::from_io(format!("Cannot read exclude file '{}': {}", path, e), &e)
+    })?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// Build and cache directory names pruned by `--prune-known`
+pub const KNOWN_JUNK_DIRS: &[&str] = &[
+    "node_modules",
+    ".git",
+    "target",
+    "__pycache__",
+    ".venv",
+    ".tox",
+    ".gradle",
+    "DerivedData",
+    ".mypy_cache",
+    ".pytest_cache",
+    ".cache",
+];
+
+/// Exclude and include rules, matched against paths relative to the scan root
+#[derive(Debug, Default)]
+pub struct PathFilter {
+    globs: GlobSet,
+    includes: GlobSet,
+    /// Directory names pruned and aggregated by `--prune-known`
+    known: HashSet<String>,
+}
+
+impl PathFilter {
+    /// Whether any rule can prune entries from the walk
+    pub fn prunes(&self) -> bool {
+        !self.globs.is_empty()
+    }
+
+    /// Whether a directory name is a known build/cache directory
+    pub fn is_known_junk(&self, name: &OsStr) -> bool {
+        !self.known.is_empty() && self.known.contains(name.to_string_lossy().as_ref())
+    }
+
+    /// Whether files are restricted by `--include`
+    pub fn has_includes(&self) -> bool {
+        !self.includes.is_empty()
+    }
+
+    /// Whether an entry (file or directory) is excluded from the walk
+    pub fn excludes(&self, rel_path: &Path) -> bool {
+        self.globs.matches(rel_path)
+    }
+
+    /// Whether a file is counted under the `--include` rules
+    pub fn includes_file(&self, rel_path: &Path) -> bool {
+        !self.has_includes() || self.includes.matches(rel_path)
+    }
+}
+
+/// The setup shared by the walks of a whole tree outside the main scan
+/// (`dump_tree_json`, `find_duplicates`, `find_empty_dirs`): the root,
+/// which must be a directory, the exclude and include rules, the device
+/// to stay on without `cross_mounts`, and a Ctrl+C flag that is set until
+/// the walk is dropped. Hidden entries are walked like any other; the
+/// exclude patterns decide what is left out.
+pub struct TreeWalk<'a> {
+    pub root: &'a Path,
+    pub filter: Arc<PathFilter>,
+    pub base_dev: Option<u64>,
+    pub cancelled: Arc<AtomicBool>,
+    signal_id: Option<signal_hook::SigId>,
+}
+
+impl<'a> TreeWalk<'a> {
+    pub fn new(path: &'a str, opts: &Options) -> Result<Self, DustrError> {
+        let root = Path::new(path);
+        let root_meta = fs::metadata(root).map_err(|e| {
+            DustrError::from_io(format!("Cannot read metadata for '{}': {}", path, e), &e)
+        })?;
+        if !root_meta.is_dir() {
+            return Err(DustrError::NotADirectory(
+                format!("Not a directory: {}", path),
+                Some(libc::ENOTDIR),
+            ));
+        }
+        let filter = Arc::new(opts.path_filter()?);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let signal_id = signal_hook::flag::register(SIGINT, cancelled.clone()).ok();
+        Ok(TreeWalk {
+            root,
+            filter,
+            base_dev: (!opts.cross_mounts).then(|| root_meta.dev()),
+            cancelled,
+            signal_id,
+        })
+    }
+
+    /// A walker over the tree that drops excluded entries before they are
+    /// yielded, so excluded directories are never descended into
+    pub fn walker(&self) -> JWalkDir {
+        let mut walker = JWalkDir::new(self.root).skip_hidden(false);
+        if self.filter.prunes() {
+            let filter = self.filter.clone();
+            let root = self.root.to_path_buf();
+            walker = walker.process_read_dir(move |_, _, _, children| {
+                children.retain(|child| {
+                    child.as_ref().map_or(true, |e| {
+                        let path = e.path();
+                        !filter.excludes(path.strip_prefix(&root).unwrap_or(&path))
+                    })
+                });
+            });
+        }
+        walker
+    }
+
+    /// `path` relative to the root
+    pub fn rel<'p>(&self, path: &'p Path) -> &'p Path {
+        path.strip_prefix(self.root).unwrap_or(path)
+    }
+
+    /// Whether `meta` belongs to another filesystem that is not crossed
+    pub fn other_device(&self, meta: &fs::Metadata) -> bool {
+        self.base_dev.is_some_and(|dev| meta.dev() != dev)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// `Ok` unless Ctrl+C was pressed during the walk
+    pub fn finish(self) -> Result<(), DustrError> {
+        if self.is_cancelled() {
+            Err(DustrError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Drop for TreeWalk<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.signal_id.take() {
+            signal_hook::low_level::unregister(id);
+        }
+    }
+}
+
+/// A relative path joined with `/` separators regardless of platform
+pub(crate) fn slash_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl Default for Options {
+    /// The options in effect when no flags are given on the command line
+    fn default() -> Self {
+        use clap::Parser;
+        Cli::parse_from(["dustr"]).opts
+    }
+}
+
+/// Statistics gathered for a single top-level entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EntryStats {
+    /// Disk usage in kilobytes (allocated blocks, not apparent size)
+    pub size_kb: u64,
+    /// Disk usage in bytes, exact where `size_kb` rounds each file up
+    pub bytes: u64,
+    /// Number of inodes, including the entry itself (only directories
+    /// when `dirs_only` is set)
+    pub inodes: u64,
+    /// Newest modification time (epoch seconds) anywhere in the entry,
+    /// collected when `--time` or `--sort time` is used
+    pub mtime: Option<i64>,
+    /// Size limit in kilobytes from the first matching `--limits` pattern
+    pub limit_kb: Option<u64>,
+    /// Number of files whose size is included in `size_kb`
+    pub files: u64,
+    /// Wall-clock time spent walking the entry
+    pub scan_time: Duration,
+}
+
+impl EntryStats {
+    /// Add the blocks allocated to a file (or directory) to the usage
+    fn add_blocks(&mut self, meta: &fs::Metadata) {
+        self.size_kb += blocks_to_kb(meta);
+        self.bytes += meta.blocks() * 512;
+    }
+
+    /// Add `bytes` of extended attributes to the usage, rounded up to a
+    /// kilobyte once rather than per file
+    fn add_xattrs(&mut self, bytes: u64) {
+        self.size_kb += bytes.div_ceil(1024);
+        self.bytes += bytes;
+    }
+
+    /// The value shown for this entry: inode count or size in KB
+    pub fn value(&self, use_inodes: bool) -> u64 {
+        if use_inodes {
+            self.inodes
+        } else {
+            self.size_kb
+        }
+    }
+
+    /// Average size of the entry's files in kilobytes, if it has any
+    pub fn avg_file_kb(&self) -> Option<f64> {
+        (self.files > 0).then(|| self.size_kb as f64 / self.files as f64)
+    }
+
+    /// Time spent walking the entry, in seconds
+    pub fn scan_seconds(&self) -> f64 {
+        self.scan_time.as_secs_f64()
+    }
+
+    /// Files counted per second of walking, 0 for an instant walk
+    pub fn files_per_second(&self) -> f64 {
+        let secs = self.scan_seconds();
+        if secs > 0.0 {
+            self.files as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// Add the usage of `other` to this entry, keeping the newest mtime.
+    /// The limit is dropped: it belongs to a single entry.
+    pub fn absorb(&mut self, other: &EntryStats) {
+        self.size_kb += other.size_kb;
+        self.bytes += other.bytes;
+        self.inodes += other.inodes;
+        self.files += other.files;
+        self.mtime = self.mtime.max(other.mtime);
+        self.scan_time += other.scan_time;
+        self.limit_kb = None;
+    }
+
+    /// Whether the entry's size exceeds its limit
+    pub fn over_limit(&self) -> bool {
+        self.limit_kb.is_some_and(|limit| self.size_kb > limit)
+    }
+
+    /// Usage against the limit, e.g. "87% of 500.0 GB"
+    pub fn limit_note(&self, opts: &Options) -> Option<String> {
+        self.limit_kb.map(|limit| {
+            let percent = if limit == 0 {
+                100.0
+            } else {
+                100.0 * self.size_kb as f64 / limit as f64
+            };
+            format!("{:.0}% of {}", percent, opts.format_size(limit))
+        })
+    }
+}
+
+/// Count and disk usage of one kind of entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KindTotals {
+    pub count: u64,
+    /// Allocated size of the entries themselves, in kilobytes
+    pub size_kb: u64,
+}
+
+/// Totals split by entry kind, collected with `--summary`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TypeBreakdown {
+    pub files: KindTotals,
+    pub dirs: KindTotals,
+    pub symlinks: KindTotals,
+    /// Sockets, FIFOs and device nodes
+    pub special: KindTotals,
+}
+
+impl TypeBreakdown {
+    /// Add one entry, classified by its (not followed) file type
+    fn record(&mut self, file_type: fs::FileType, meta: &fs::Metadata) {
+        let kind = if file_type.is_file() {
+            &mut self.files
+        } else if file_type.is_dir() {
+            &mut self.dirs
+        } else if file_type.is_symlink() {
+            &mut self.symlinks
+        } else {
+            &mut self.special
+        };
+        kind.count += 1;
+        kind.size_kb += blocks_to_kb(meta);
+    }
+
+    fn merge(&mut self, other: &TypeBreakdown) {
+        for (mine, theirs) in [
+            (&mut self.files, other.files),
+            (&mut self.dirs, other.dirs),
+            (&mut self.symlinks, other.symlinks),
+            (&mut self.special, other.special),
+        ] {
+            mine.count += theirs.count;
+            mine.size_kb += theirs.size_kb;
+        }
+    }
+
+    /// The kinds as `(key, label, totals)`, keys being those used in JSON
+    pub fn kinds(&self) -> [(&'static str, &'static str, KindTotals); 4] {
+        [
+            ("files", "Regular files", self.files),
+            ("directories", "Directories", self.dirs),
+            ("symlinks", "Symlinks", self.symlinks),
+            ("special", "Special files", self.special),
+        ]
+    }
+}
+
+/// Files of up to `le` kilobytes (apparent size), or of any size for the
+/// last bucket of a `SizeHistogram`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeBucket {
+    pub le: Option<u64>,
+    pub count: u64,
+    /// Their combined apparent size
+    pub bytes: u64,
+}
+
+/// Regular files by size, collected with `--size-histogram`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeHistogram {
+    /// In ascending order, the last one without an upper bound
+    pub buckets: Vec<SizeBucket>,
+}
+
+impl SizeHistogram {
+    /// Bucket bounds in KB when `--buckets` is not given: empty files, up
+    /// to 4K, 64K, 1M, 16M, 256M and 4G
+    pub const DEFAULT_BOUNDS: [u64; 7] = [0, 4, 64, 1_000, 16_000, 256_000, 4_000_000];
+
+    /// Empty buckets with upper `bounds` in KB, in any order, plus one for
+    /// the larger files
+    pub fn new(bounds: &[u64]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_unstable();
+        bounds.dedup();
+        let buckets = bounds
+            .into_iter()
+            .map(Some)
+            .chain([None])
+            .map(|le| SizeBucket {
+                le,
+                ..SizeBucket::default()
+            })
+            .collect();
+        SizeHistogram { buckets }
+    }
+
+    /// Add a file of `bytes` bytes
+    pub fn record(&mut self, bytes: u64) {
+        if let Some(bucket) = self
+            .buckets
+            .iter_mut()
+            .find(|b| b.le.is_none_or(|le| bytes <= le.saturating_mul(1024)))
+        {
+            bucket.count += 1;
+            bucket.bytes += bytes;
+        }
+    }
+
+    fn merge(&mut self, other: &SizeHistogram) {
+        for (mine, theirs) in self.buckets.iter_mut().zip(&other.buckets) {
+            mine.count += theirs.count;
+            mine.bytes += theirs.bytes;
+        }
+    }
+}
+
+/// Number of file sizes `FileSizeStats` keeps before it switches to a
+/// sketch
+pub const EXACT_SIZE_LIMIT: usize = 100_000;
+
+/// Relative error of the sketched `FileSizeStats` quantiles
+const SKETCH_ACCURACY: f64 = 0.01;
+
+/// Regular-file size statistics, collected with `--stats`. Sizes are kept
+/// as they are up to `EXACT_SIZE_LIMIT` files, then folded into
+/// logarithmic buckets whose quantiles are within 1% of the true ones, so
+/// memory stays bounded on huge trees.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileSizeStats {
+    pub count: u64,
+    /// Combined apparent size
+    pub total: u64,
+    pub max: u64,
+    /// Every size, until there are too many
+    exact: Vec<u64>,
+    /// Files per bucket once there are too many; bucket 0 holds the empty
+    /// files, bucket `i` sizes up to `gamma^(i - 1)`
+    sketch: Option<BTreeMap<u32, u64>>,
+}
+
+impl FileSizeStats {
+    fn gamma() -> f64 {
+        (1.0 + SKETCH_ACCURACY) / (1.0 - SKETCH_ACCURACY)
+    }
+
+    fn bucket(bytes: u64) -> u32 {
+        match bytes {
+            0 => 0,
+            _ => 1 + ((bytes as f64).ln() / Self::gamma().ln()).ceil() as u32,
+        }
+    }
+
+    /// The size every file in `bucket` is taken to have: the one with
+    /// the least relative error to both ends
+    fn bucket_value(bucket: u32) -> f64 {
+        match bucket {
+            0 => 0.0,
+            _ => 2.0 * Self::gamma().powi(bucket as i32 - 1) / (Self::gamma() + 1.0),
+        }
+    }
+
+    /// Add a file of `bytes` bytes
+    pub fn record(&mut self, bytes: u64) {
+        self.count += 1;
+        self.total += bytes;
+        self.max = self.max.max(bytes);
+        match &mut self.sketch {
+            Some(sketch) => *sketch.entry(Self::bucket(bytes)).or_default() += 1,
+            None => {
+                self.exact.push(bytes);
+                if self.exact.len() > EXACT_SIZE_LIMIT {
+                    self.start_sketch();
+                }
+            }
+        }
+    }
+
+    fn start_sketch(&mut self) {
+        let mut sketch = BTreeMap::new();
+        for bytes in self.exact.drain(..) {
+            *sketch.entry(Self::bucket(bytes)).or_default() += 1;
+        }
+        self.exact.shrink_to_fit();
+        self.sketch = Some(sketch);
+    }
+
+    pub fn merge(&mut self, other: FileSizeStats) {
+        self.count += other.count;
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+        self.exact.extend(other.exact);
+        if self.sketch.is_none() && (other.sketch.is_some() || self.exact.len() > EXACT_SIZE_LIMIT)
+        {
+            self.start_sketch();
+        }
+        if let Some(sketch) = &mut self.sketch {
+            for bytes in self.exact.drain(..) {
+                *sketch.entry(Self::bucket(bytes)).or_default() += 1;
+            }
+            for (bucket, count) in other.sketch.into_iter().flatten() {
+                *sketch.entry(bucket).or_default() += count;
+            }
+        }
+    }
+
+    /// Mean size, 0 without files
+    pub fn mean(&self) -> f64 {
+        match self.count {
+            0 => 0.0,
+            n => self.total as f64 / n as f64,
+        }
+    }
+
+    /// The size at quantile `q` (0.5 for the median) by the nearest-rank
+    /// method, 0 without files. Exact until the sizes are sketched.
+    pub fn quantile(&self, q: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((q * self.count as f64).ceil() as u64).clamp(1, self.count);
+        match &self.sketch {
+            None => {
+                let mut sizes = self.exact.clone();
+                *sizes.select_nth_unstable((rank - 1) as usize).1
+            }
+            Some(sketch) => {
+                let mut seen = 0;
+                for (&bucket, &count) in sketch {
+                    seen += count;
+                    if seen >= rank {
+                        return (Self::bucket_value(bucket).round() as u64).min(self.max);
+                    }
+                }
+                self.max
+            }
+        }
+    }
+}
+
+/// What lives at one depth below the scanned directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DepthLevel {
+    /// 1 for the top-level entries, 2 for their children, and so on
+    pub depth: usize,
+    pub size_kb: u64,
+    pub bytes: u64,
+    /// Number of files whose size is included
+    pub files: u64,
+}
+
+impl DepthLevel {
+    fn absorb(&mut self, other: &DepthLevel) {
+        self.size_kb += other.size_kb;
+        self.bytes += other.bytes;
+        self.files += other.files;
+    }
+}
+
+/// Usage by depth, collected with `--by-depth`. Directories count at their
+/// own depth, so the levels add up to the total of a full walk (pruned
+/// directories and `--shallow` leave the deeper levels out).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DepthUsage {
+    /// Every depth from 1 to the deepest one reached, in order
+    pub levels: Vec<DepthLevel>,
+}
+
+impl DepthUsage {
+    fn level(&mut self, depth: usize) -> &mut DepthLevel {
+        while self.levels.len() < depth {
+            let depth = self.levels.len() + 1;
+            self.levels.push(DepthLevel {
+                depth,
+                ..DepthLevel::default()
+            });
+        }
+        &mut self.levels[depth - 1]
+    }
+
+    /// Add the blocks of an entry at `depth`, and count it as a file if
+    /// `file` is set
+    fn record(&mut self, depth: usize, meta: &fs::Metadata, file: bool) {
+        let level = self.level(depth);
+        level.size_kb += blocks_to_kb(meta);
+        level.bytes += meta.blocks() * 512;
+        level.files += u64::from(file);
+    }
+
+    fn merge(&mut self, other: &DepthUsage) {
+        for theirs in &other.levels {
+            self.level(theirs.depth).absorb(theirs);
+        }
+    }
+
+    /// At most `max_rows` levels, the last of them holding that depth and
+    /// all deeper ones
+    pub fn capped(&self, max_rows: usize) -> Vec<DepthLevel> {
+        let mut rows: Vec<DepthLevel> = self.levels.iter().take(max_rows).copied().collect();
+        if let Some(last) = rows.last_mut().filter(|_| self.levels.len() > max_rows) {
+            for deeper in &self.levels[max_rows..] {
+                last.absorb(deeper);
+            }
+        }
+        rows
+    }
+}
+
+/// A large file modified recently, found with `--recent`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentFile {
+    pub path: String,
+    /// Apparent size
+    pub bytes: u64,
+    pub mtime: i64,
+    /// Not listed by the previous `--watch` refresh
+    pub new: bool,
+}
+
+impl RecentFile {
+    fn new(path: &Path, meta: &fs::Metadata) -> Self {
+        RecentFile {
+            path: path.to_string_lossy().to_string(),
+            bytes: meta.len(),
+            mtime: meta.mtime(),
+            new: false,
+        }
+    }
+}
+
+/// Largest first, then by path
+fn sort_recent(recent: &mut [RecentFile]) {
+    recent.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+}
+
+/// What `--watch` carries from one refresh to the next
+#[derive(Debug, Default)]
+pub struct WatchState {
+    /// The `--recent` files listed last time, once there was a last time
+    recent_seen: Option<HashSet<String>>,
+}
+
+impl WatchState {
+    /// Mark the files that the previous refresh did not list, and remember
+    /// these for the next one. Nothing is marked on the first refresh.
+    pub fn mark_new(&mut self, recent: &mut [RecentFile]) {
+        if let Some(seen) = &self.recent_seen {
+            for file in recent.iter_mut() {
+                file.new = !seen.contains(&file.path);
+            }
+        }
+        self.recent_seen = Some(recent.iter().map(|f| f.path.clone()).collect());
+    }
+}
+
+/// A directory with many entries, found with `--inode-hogs`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InodeHog {
+    pub path: String,
+    /// Number of entries directly inside it
+    pub direct: u64,
+    /// Number of entries anywhere below it
+    pub recursive: u64,
+}
+
+/// The directories with the most direct children, in a heap bounded to
+/// the `--inode-hogs` count
+#[derive(Debug, Default)]
+pub struct InodeHogs {
+    limit: usize,
+    /// Least direct children on top; among equals, the last path
+    heap: BinaryHeap<Reverse<(u64, Reverse<String>, u64)>>,
+}
+
+impl InodeHogs {
+    pub fn new(limit: usize) -> Self {
+        InodeHogs {
+            limit,
+            heap: BinaryHeap::with_capacity(limit.saturating_add(1).min(1024)),
+        }
+    }
+
+    /// Keep `path` while it is among the directories with the most direct
+    /// children
+    pub fn offer(&mut self, path: &Path, direct: u64, recursive: u64) {
+        let full = self.heap.len() >= self.limit;
+        if self.limit == 0 || full && self.heap.peek().is_some_and(|Reverse(min)| direct < min.0) {
+            return;
+        }
+        let path = path.to_string_lossy().to_string();
+        self.heap.push(Reverse((direct, Reverse(path), recursive)));
+        if self.heap.len() > self.limit {
+            self.heap.pop();
+        }
+    }
+
+    /// Offer the directories of one walk, from the number of entries read
+    /// in each of them. Counts are folded into the parent directories
+    /// deepest first, giving the number of entries below each one.
+    fn offer_walk(&mut self, direct: HashMap<Arc<Path>, u64>) {
+        let mut dirs: Vec<(Arc<Path>, u64)> = direct.into_iter().collect();
+        dirs.sort_by_key(|(dir, _)| Reverse(dir.components().count()));
+        let mut below: HashMap<Arc<Path>, u64> = dirs.iter().cloned().collect();
+        for (dir, _) in &dirs {
+            let count = below[dir];
+            if let Some(parent) = dir.parent().and_then(|p| below.get_mut(p)) {
+                *parent += count;
+            }
+        }
+        for (dir, direct) in dirs {
+            self.offer(&dir, direct, below[&dir]);
+        }
+    }
+
+    /// The directories kept, most direct children first, then by path
+    pub fn into_sorted(self) -> Vec<InodeHog> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((direct, Reverse(path), recursive))| InodeHog {
+                path,
+                direct,
+                recursive,
+            })
+            .collect()
+    }
+}
+
+/// Result of scanning the immediate children of a directory
+#[derive(Debug, Default)]
+pub struct ScanResult {
+    pub entries: HashMap<String, EntryStats>,
+    /// Number of paths that could not be read during the walk
+    pub errors: u64,
+    /// Number of symlinks whose target does not exist
+    pub broken_symlinks: u64,
+    /// Number of paths deleted between being listed and being read, which
+    /// are skipped (vanished top-level entries are not listed)
+    pub races_skipped: u64,
+    /// Dangling symlinks as `(link, target)`, collected with `--check-links`
+    pub broken_links: Vec<(String, String)>,
+    /// Totals by entry kind, collected with `--summary`
+    pub types: TypeBreakdown,
+    /// Regular files by size, collected with `--size-histogram`
+    pub size_histogram: Option<SizeHistogram>,
+    /// Regular-file size statistics, collected with `--stats`
+    pub file_size_stats: Option<FileSizeStats>,
+    /// Usage at each depth, collected with `--by-depth`
+    pub by_depth: Option<DepthUsage>,
+    /// Directories with the most entries, collected with `--inode-hogs`
+    pub inode_hogs: Option<Vec<InodeHog>>,
+    /// Large files modified recently, largest first, collected with
+    /// `--recent`
+    pub recent: Option<Vec<RecentFile>>,
+    /// Sets of identical files, collected with `--duplicates`
+    pub duplicates: Option<Vec<DuplicateSet>>,
+    /// Empty files and directories, collected with `--find-empty`
+    pub empty: Option<EmptyEntries>,
+    /// Reclaimable leftovers, largest first, collected with `--find-junk`
+    pub junk: Option<Junk>,
+    /// Usage down to `--treemap-depth` levels, collected with `--treemap`
+    pub treemap: Option<TreeNode>,
+    /// Number of top-level entries left out by filters: the exclude
+    /// patterns, `--skip-empty`, the size range and the entry filter
+    pub filtered: u64,
+    /// Bytes of extended attributes included in the sizes with `--xattrs`
+    pub xattr_bytes: u64,
+    /// Number of directories pruned by `--prune-known`
+    pub pruned_dirs: u64,
+    /// Combined size of the pruned directories (0 with `--prune-skip`)
+    pub pruned_kb: u64,
+    /// Wall-clock time spent scanning
+    pub duration: Duration,
+    /// Set when `--timeout` or `--max-files` stopped the scan early, in
+    /// which case only completed top-level entries are included
+    pub truncated: Option<Truncation>,
+}
+
+impl ScanResult {
+    /// Per-entry values as returned by `calculate_directory_sizes`
+    pub fn values(&self, use_inodes: bool) -> HashMap<String, u64> {
+        self.entries
+            .iter()
+            .map(|(name, stats)| (name.clone(), stats.value(use_inodes)))
+            .collect()
+    }
+
+    /// Per-entry `(size_kb, inodes)` pairs as returned by `calculate_both`
+    pub fn both_values(&self) -> HashMap<String, (u64, u64)> {
+        self.entries
+            .iter()
+            .map(|(name, stats)| (name.clone(), (stats.size_kb, stats.inodes)))
+            .collect()
+    }
+
+    /// Sum of the per-entry values, as returned by `directory_total`
+    pub fn total(&self, use_inodes: bool) -> u64 {
+        self.entries
+            .values()
+            .map(|stats| stats.value(use_inodes))
+            .sum()
+    }
+}
+
+/// Calculate directory sizes for all items in a directory (parallel version)
+pub fn calculate_directory_sizes(
+    path: &str,
+    opts: &Options,
+) -> Result<HashMap<String, u64>, DustrError> {
+    scan_directory(path, opts).map(|scan| scan.values(opts.inodes))
+}
+
+/// Calculate sizes (KB) and inode counts for all items in a directory in a
+/// single traversal
+pub fn calculate_both(
+    path: &str,
+    opts: &Options,
+) -> Result<HashMap<String, (u64, u64)>, DustrError> {
+    scan_directory(path, opts).map(|scan| scan.both_values())
+}
+
+/// How the value of one entry changed between two scans; `None` where the
+/// entry is missing from that scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryDelta {
+    pub name: String,
+    pub old: Option<u64>,
+    pub new: Option<u64>,
+}
+
+impl EntryDelta {
+    /// Change from the old value to the new one, a missing entry counting
+    /// as 0
+    pub fn delta(&self) -> i128 {
+        i128::from(self.new.unwrap_or(0)) - i128::from(self.old.unwrap_or(0))
+    }
+}
+
+/// The entries whose value differs between the `old` and `new` results of
+/// `calculate_directory_sizes` (or were added or removed), largest change
+/// first, then by name
+pub fn compare_values(old: &HashMap<String, u64>, new: &HashMap<String, u64>) -> Vec<EntryDelta> {
+    let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    let mut deltas: Vec<EntryDelta> = names
+        .into_iter()
+        .map(|name| EntryDelta {
+            name: name.clone(),
+            old: old.get(name).copied(),
+            new: new.get(name).copied(),
+        })
+        .filter(|delta| delta.old != delta.new)
+        .collect();
+    deltas.sort_by(|a, b| {
+        b.delta()
+            .abs()
+            .cmp(&a.delta().abs())
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    deltas
+}
+
+/// Total size in KB (or inode count) of everything below `path`, without a
+/// per-entry breakdown. Nothing is printed, not even the progress bar.
+pub fn directory_total(path: &str, opts: &Options) -> Result<u64, DustrError> {
+    let quiet: &ProgressHook<'_> = &|_, _, _| true;
+    scan_directory_with_progress(path, opts, Some(quiet)).map(|scan| scan.total(opts.inodes))
+}
+
+/// Size statistics of the regular files below `path`, as for `--stats`.
+/// Nothing is printed, not even the progress bar.
+pub fn file_size_stats(path: &str, opts: &Options) -> Result<FileSizeStats, DustrError> {
+    let opts = Options {
+        stats: true,
+        ..opts.clone()
+    };
+    let quiet: &ProgressHook<'_> = &|_, _, _| true;
+    let scan = scan_directory_with_progress(path, &opts, Some(quiet))?;
+    Ok(scan.file_size_stats.unwrap_or_default())
+}
+
+/// Regular files below `path` modified less than `since_seconds` ago and
+/// at least `min_bytes` large, largest first, as for `--recent`. Nothing
+/// is printed, not even the progress bar.
+pub fn recent_files(
+    path: &str,
+    since_seconds: u64,
+    min_bytes: u64,
+    opts: &Options,
+) -> Result<Vec<RecentFile>, DustrError> {
+    let opts = Options {
+        recent: Some(
+            AgeSpec::parse(&format!("{}s", since_seconds))
+                .map_err(|e| DustrError::OsError(e, Some(libc::EINVAL)))?,
+        ),
+        recent_min_size: min_bytes / 1024,
+        ..opts.clone()
+    };
+    let quiet: &ProgressHook<'_> = &|_, _, _| true;
+    let scan = scan_directory_with_progress(path, &opts, Some(quiet))?;
+    let mut recent = scan.recent.unwrap_or_default();
+    recent.retain(|file| file.bytes >= min_bytes);
+    Ok(recent)
+}
+
+/// Usage at each depth below `path`, as for `--by-depth`. Nothing is
+/// printed, not even the progress bar.
+pub fn usage_by_depth(path: &str, opts: &Options) -> Result<DepthUsage, DustrError> {
+    let opts = Options {
+        by_depth: true,
+        ..opts.clone()
+    };
+    let quiet: &ProgressHook<'_> = &|_, _, _| true;
+    let scan = scan_directory_with_progress(path, &opts, Some(quiet))?;
+    Ok(scan.by_depth.unwrap_or_default())
+}
+
+/// The `n` directories below `path` with the most direct children, as for
+/// `--inode-hogs`. Nothing is printed, not even the progress bar.
+pub fn inode_hogs(path: &str, n: u64, opts: &Options) -> Result<Vec<InodeHog>, DustrError> {
+    let opts = Options {
+        inode_hogs: Some(n),
+        ..opts.clone()
+    };
+    let quiet: &ProgressHook<'_> = &|_, _, _| true;
+    let scan = scan_directory_with_progress(path, &opts, Some(quiet))?;
+    Ok(scan.inode_hogs.unwrap_or_default())
+}
+
+/// Usage of each of `paths` as a whole (everything below a directory, as
+/// for `directory_total`), in input order. The paths are sized in parallel;
+/// one that cannot be read gets its error without failing the others.
+pub fn sizes_for(paths: &[String], opts: &Options) -> Vec<Result<EntryStats, DustrError>> {
+    let quiet: &ProgressHook<'_> = &|_, _, _| true;
+    paths
+        .par_iter()
+        .map(|path| {
+            let scan = scan_directory_with_progress(path, opts, Some(quiet))?;
+            let mut total = EntryStats::default();
+            for stats in scan.entries.values() {
+                total.absorb(stats);
+            }
+            Ok(total)
+        })
+        .collect()
+}
+
+/// Read the NUL-separated paths of `--files0-from` from `source`, or from
+/// stdin for "-". Empty names are skipped.
+pub fn read_files0(source: &str) -> Result<Vec<String>, DustrError> {
+    let mut data = Vec::new();
+    let read = if source == "-" {
+        io::stdin().lock().read_to_end(&mut data)
+    } else {
+        fs::File::open(source).and_then(|mut file| file.read_to_end(&mut data))
+    };
+    read.map_err(|e| DustrError::from_io(format!("Cannot read '{}': {}", source, e), &e))?;
+    Ok(data
+        .split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect())
+}
+
+/// Size the paths listed in `source` (see `read_files0`) and print one
+/// `SIZE<TAB>PATH` record each, like `du --files0-from`, or a JSON report
+/// with `--json`. Paths that cannot be read are warned about and make the
+/// whole run fail once the others are printed.
+pub fn print_sizes_for(source: &str, opts: &Options) -> Result<(), DustrError> {
+    let paths = read_files0(source)?;
+    let results = sizes_for(&paths, opts);
+    if results
+        .iter()
+        .any(|result| matches!(result, Err(DustrError::Cancelled)))
+    {
+        return Err(DustrError::Cancelled);
+    }
+    let mut out = Output::open(opts.output.as_deref())?;
+    let value = |stats: &EntryStats| stats.value(opts.inodes);
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    if opts.json {
+        let total: u64 = results.iter().flatten().map(value).sum();
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"dustr_version\": \"{}\",", VERSION)?;
+        writeln!(out, "  \"format_version\": {},", FORMAT_VERSION)?;
+        writeln!(
+            out,
+            "  \"mode\": \"{}\",",
+            if opts.inodes { "inodes" } else { "size" }
+        )?;
+        writeln!(out, "  \"paths\": [")?;
+        for (i, (path, result)) in paths.iter().zip(&results).enumerate() {
+            let comma = if i + 1 < paths.len() { "," } else { "" };
+            match result {
+                Ok(stats) => writeln!(
+                    out,
+                    "    {{\"path\": \"{}\", \"value\": {}, \"bytes\": {}, \"inodes\": {}, \"files\": {}}}{}",
+                    json_escape(path),
+                    value(stats),
+                    stats.bytes,
+                    stats.inodes,
+                    stats.files,
+                    comma
+                )?,
+                Err(e) => writeln!(
+                    out,
+                    "    {{\"path\": \"{}\", \"error\": \"{}\"}}{}",
+                    json_escape(path),
+                    json_escape(&e.to_string()),
+                    comma
+                )?,
+            }
+        }
+        writeln!(out, "  ],")?;
+        writeln!(out, "  \"errors\": {},", failed)?;
+        writeln!(out, "  \"total\": {}", total)?;
+        writeln!(out, "}}")?;
+    } else {
+        let end = if opts.null { '\0' } else { '\n' };
+        for (path, result) in paths.iter().zip(&results) {
+            match result {
+                Ok(stats) => write!(out, "{}\t{}{}", value(stats), path, end)?,
+                Err(e) => diagnostics::warn(&e.to_string()),
+            }
+        }
+    }
+    out.finish()?;
+    if failed > 0 {
+        return Err(DustrError::OsError(
+            format!("{} of {} paths could not be sized", failed, paths.len()),
+            None,
+        ));
+    }
+    Ok(())
+}
+
+/// Scan all items in a directory in parallel, gathering both sizes and inode counts
+pub fn scan_directory(path: &str, opts: &Options) -> Result<ScanResult, DustrError> {
+    scan_directory_with_progress(path, opts, None)
+}
+
+/// Progress hook for embedding callers, called with `(current, total,
+/// entry_name)` after each top-level entry is done (`total` is `None` when
+/// streaming). Returning `false` cancels the scan.
+pub type ProgressHook<'a> = dyn Fn(usize, Option<usize>, &str) -> bool + Sync + 'a;
+
+/// Hook called while a large top-level entry is walked, with `(entry_name,
+/// files, bytes)` counted in it so far, at most every
+/// `WALK_PROGRESS_INTERVAL`. Returning `false` cancels the scan.
+pub type WalkHook<'a> = dyn Fn(&str, u64, u64) -> bool + Sync + 'a;
+
+/// Hook called with `(path, errno, message)` for each path that cannot be
+/// read, in place of the warning. Returning `false` aborts the scan, which
+/// then fails with that error as with `--strict`.
+pub type ErrorHook<'a> = dyn Fn(&Path, Option<i32>, &str) -> bool + Sync + 'a;
+
+/// What an `EntryFilter` sees of a file or directory
+#[derive(Debug, Clone, Copy)]
+pub struct EntryInfo<'p> {
+    pub path: &'p Path,
+    pub is_dir: bool,
+    /// Apparent size in bytes
+    pub size: u64,
+    /// Modification time, epoch seconds
+    pub mtime: i64,
+    pub uid: u32,
+}
+
+impl<'p> EntryInfo<'p> {
+    fn new(path: &'p Path, meta: &fs::Metadata) -> Self {
+        EntryInfo {
+            path,
+            is_dir: meta.is_dir(),
+            size: meta.len(),
+            mtime: meta.mtime(),
+            uid: meta.uid(),
+        }
+    }
+}
+
+/// Predicate called for every entry that passes the exclude rules, saying
+/// whether it is counted; a directory it rejects is not descended into.
+/// Returning `None` cancels the scan. The walkers call it from within
+/// jwalk, hence `Arc` and `'static`.
+pub type EntryFilter = Arc<dyn Fn(&EntryInfo) -> Option<bool> + Send + Sync>;
+
+/// Whether `entry_filter` admits `path`, setting `cancelled` when it gives
+/// up on the scan. Entries without metadata are left to the walk, which
+/// reports them.
+fn admits(
+    entry_filter: &EntryFilter,
+    path: &Path,
+    meta: Option<&fs::Metadata>,
+    cancelled: &AtomicBool,
+) -> bool {
+    let Some(meta) = meta else {
+        return true;
+    };
+    match entry_filter(&EntryInfo::new(path, meta)) {
+        Some(keep) => keep,
+        None => {
+            cancelled.store(true, Ordering::Relaxed);
+            false
+        }
+    }
+}
+
+/// Minimum time between two reports of the walk inside one entry
+const WALK_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Like `scan_directory`, but reports progress to `hook` instead of drawing
+/// the terminal progress bar when one is given
+pub fn scan_directory_with_progress(
+    path: &str,
+    opts: &Options,
+    hook: Option<&ProgressHook<'_>>,
+) -> Result<ScanResult, DustrError> {
+    scan_directory_with_hooks(path, opts, hook, None, None, None)
+}
+
+/// Like `scan_directory_with_progress`, also reporting the files and bytes
+/// counted so far inside the entries being walked to `walk_hook`, and the
+/// paths that cannot be read to `error_hook`, and counting only what
+/// `entry_filter` admits. Without hooks, the terminal progress bar gets a
+/// second line showing the walk.
+pub fn scan_directory_with_hooks(
+    path: &str,
+    opts: &Options,
+    hook: Option<&ProgressHook<'_>>,
+    walk_hook: Option<&WalkHook<'_>>,
+    error_hook: Option<&ErrorHook<'_>>,
+    entry_filter: Option<&EntryFilter>,
+) -> Result<ScanResult, DustrError> {
+    scan_directory_cached(path, opts, None, hook, walk_hook, error_hook, entry_filter)
+}
+
+/// `(dev, ino)` of files already counted, shared by concurrent walkers
+type SeenFiles = Arc<Mutex<HashSet<(u64, u64)>>>;
+
+/// A file reached from two top-level entries, moved from the
+/// entry they were first counted in to the other, which comes first in
+/// name order
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ChargeMove {
+    from: Arc<str>,
+    to: Arc<str>,
+    kb: u64,
+    bytes: u64,
+    files: u64,
+}
+
+/// Apply `moves` in order, so a file reached from several entries ends up
+/// in the first of them in name order however the walkers raced
+fn apply_charge_moves(entries: &mut HashMap<String, EntryStats>, moves: Vec<ChargeMove>) {
+    for ChargeMove {
+        from,
+        to,
+        kb,
+        bytes,
+        files,
+    } in moves
+    {
+        if let Some(stats) = entries.get_mut(&*from) {
+            stats.size_kb = stats.size_kb.saturating_sub(kb);
+            stats.bytes = stats.bytes.saturating_sub(bytes);
+            stats.files = stats.files.saturating_sub(files);
+        }
+        if let Some(stats) = entries.get_mut(&*to) {
+            stats.size_kb += kb;
+            stats.bytes += bytes;
+            stats.files += files;
+        }
+    }
+}
+
+/// State kept between scans by callers that scan repeatedly with the same
+/// options, such as the Python `Scanner`: the compiled exclude rules and,
+/// optionally, the files counted so far
+#[derive(Debug)]
+pub struct ScanCache {
+    filter: Arc<PathFilter>,
+    /// `(dev, ino)` of the files counted so far, when every file is to be
+    /// counted only once
+    seen_files: Option<SeenFiles>,
+}
+
+impl ScanCache {
+    /// Compile the rules of `opts`. With `dedup`, a file hard-linked under
+    /// several names is counted once, by whichever scan meets it first.
+    pub fn new(opts: &Options, dedup: bool) -> Result<Self, DustrError> {
+        Ok(ScanCache {
+            filter: Arc::new(opts.path_filter()?),
+            seen_files: dedup.then(Arc::default),
+        })
+    }
+
+    /// Number of distinct files counted so far, with `dedup`
+    pub fn seen_files(&self) -> Option<usize> {
+        self.seen_files.as_ref().map(|seen| seen.lock().len())
+    }
+}
+
+/// Like `scan_directory_with_hooks`, reusing the state in `cache`, which
+/// must have been built from the same `opts`
+pub fn scan_directory_cached(
+    path: &str,
+    opts: &Options,
+    cache: Option<&ScanCache>,
+    hook: Option<&ProgressHook<'_>>,
+    walk_hook: Option<&WalkHook<'_>>,
+    error_hook: Option<&ErrorHook<'_>>,
+    entry_filter: Option<&EntryFilter>,
+) -> Result<ScanResult, DustrError> {
+    let start = Instant::now();
+    let verbose = opts.verbose > 0;
+    let live = opts.live;
+    let show_progress = !live && hook.is_none() && !opts.no_progress;
+    let progress_glyphs = opts.bar_glyphs('>');
+    let base_path = Path::new(path);
+
+    if !base_path.exists() {
+        return Err(DustrError::NotFound(
+            format!("Directory not found: {}", path),
+            Some(libc::ENOENT),
+        ));
+    }
+
+    let filter = match cache {
+        Some(cache) => cache.filter.clone(),
+        None => Arc::new(opts.path_filter()?),
+    };
+    if !base_path.is_dir() {
+        return scan_single_file(path, opts, &filter, start);
+    }
+
+    // Top-level entries left out by filters
+    let filtered = AtomicU64::new(0);
+    let included = |entry: &fs::DirEntry| {
+        let excluded = filter.excludes(Path::new(&entry.file_name()));
+        if excluded {
+            filtered.fetch_add(1, Ordering::Relaxed);
+        }
+        !excluded
+    };
+
+    let entries = match fs::read_dir(base_path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            let msg = match e.kind() {
+                io::ErrorKind::NotFound => format!("Directory not found: {}", path),
+                io::ErrorKind::PermissionDenied => format!("Permission denied: {}", path),
+                _ => format!("Cannot read directory '{}': {}", path, e),
+            };
+            return Err(DustrError::from_io(msg, &e));
+        }
+    };
+
+    // Collect entries first to get count, unless streaming them to bound memory
+    let (stream, entries_vec) = if opts.streaming {
+        (Some(entries), Vec::new())
+    } else {
+        (None, entries.flatten().filter(included).collect::<Vec<_>>())
+    };
+    let total_entries = stream.is_none().then_some(entries_vec.len());
+
+    // Compute the base directory device id once for mount boundary checks
+    let base_dev = if !opts.cross_mounts {
+        match fs::metadata(base_path) {
+            Ok(m) => Some(m.dev()),
+            Err(e) => {
+                return Err(DustrError::from_io(
+                    format!("Cannot read metadata for '{}': {}", path, e),
+                    &e,
+                ));
+            }
+        }
+    } else {
+        None
+    };
+
+    // Shared state for progress and cancellation
+    let progress = Arc::new(AtomicUsize::new(0));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let results: Arc<Mutex<HashMap<String, EntryStats>>> = Arc::new(Mutex::new(HashMap::new()));
+    let counters = match cache.and_then(|cache| cache.seen_files.clone()) {
+        Some(seen_files) => ScanCounters {
+            seen_files,
+            dedup: true,
+            ..ScanCounters::default()
+        },
+        None => ScanCounters::default(),
+    };
+    *counters.size_histogram.lock() = opts.new_size_histogram();
+    *counters.duplicates.lock() = opts.new_duplicate_candidates();
+    *counters.empty.lock() = opts.find_empty.then(EmptyEntries::default);
+    let junk_patterns = opts.junk_patterns();
+    *counters.junk.lock() = junk_patterns.as_ref().map(Junk::new);
+    *counters.by_depth.lock() = opts.by_depth.then(DepthUsage::default);
+    *counters.treemap.lock() = opts.new_treemap();
+    *counters.file_size_stats.lock() = opts.stats.then(FileSizeStats::default);
+    *counters.recent.lock() = opts.recent.as_ref().map(|_| Vec::new());
+    *counters.inode_hogs.lock() = opts.inode_hogs.map(|n| InodeHogs::new(n as usize));
+    // With --detect-cycles, a bind mount may lead back to the scanned
+    // directory itself
+    if opts.detect_cycles {
+        let meta = fs::metadata(base_path).ok();
+        ScanCounters::first_visit(&counters.visited_dirs, base_path, meta.as_ref());
+    }
+    let now = unix_now();
+    let current_entry: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
+
+    // Register OS signal handler to set cancelled flag directly on Ctrl+C.
+    let signal_id = match signal_hook::flag::register(SIGINT, cancelled.clone()) {
+        Ok(id) => Some(id),
+        Err(e) => {
+            diagnostics::warn(&format!(
+                "failed to register SIGINT handler: {}. \
+                 Ctrl+C responsiveness may be reduced.",
+                e
+            ));
+            None
+        }
+    };
+
+    // Spawn live display thread if requested
+    let live_last_lines = Arc::new(AtomicUsize::new(0));
+    let live_display = if live {
+        let results_for_display = results.clone();
+        let cancelled_for_display = cancelled.clone();
+        let progress_for_display = progress.clone();
+        let last_lines_for_display = live_last_lines.clone();
+        let dirname = path.to_string();
+        let display_opts = opts.clone();
+        let color = opts.use_color(io::stderr().is_terminal());
+        let columns = io::stderr()
+            .is_terminal()
+            .then(|| terminal_columns(libc::STDERR_FILENO))
+            .flatten();
+        Some(std::thread::spawn(move || {
+            let mut last_lines = 0usize;
+            loop {
+                std::thread::sleep(std::time::Duration::from_millis(500));
+                if cancelled_for_display.load(Ordering::Relaxed) {
+                    break;
+                }
+                let snapshot: Vec<(String, EntryStats)> = {
+                    let r = results_for_display.lock();
+                    r.iter().map(|(k, v)| (k.clone(), *v)).collect()
+                };
+                let current = progress_for_display.load(Ordering::Relaxed);
+                let table = render_stats_table(
+                    &dirname,
+                    &snapshot,
+                    &display_opts,
+                    Some((current, total_entries)),
+                    color,
+                    columns,
+                );
+                let bar = format_progress(current, total_entries, progress_glyphs);
+                // Move cursor up to overwrite previous output, then print
+                if last_lines > 0 {
+                    status(format_args!("\x1b[{}A\x1b[J", last_lines));
+                }
+                status(format_args!("{}{}\n", table, bar));
+                io::stderr().flush().ok();
+                last_lines = table.lines().count() + 1;
+                last_lines_for_display.store(last_lines, Ordering::Relaxed);
+            }
+        }))
+    } else {
+        None
+    };
+
+    let deadline = opts.timeout.map(|secs| start + Duration::from_secs(secs));
+    let truncated = Mutex::new(None);
+
+    // Second progress line with the walk inside the current entry
+    let walk_line_shown = AtomicBool::new(false);
+    let draw_walk = |name: &str, files: u64, bytes: u64| {
+        print_walk_progress(
+            format_progress(
+                progress.load(Ordering::Relaxed),
+                total_entries,
+                progress_glyphs,
+            ),
+            &format!(
+                "{}: {} files, {}",
+                name,
+                opts.group_digits(files),
+                opts.format_size(bytes.div_ceil(1024))
+            ),
+        );
+        walk_line_shown.store(true, Ordering::Relaxed);
+        true
+    };
+    let walk_hook: Option<&WalkHook<'_>> = match walk_hook {
+        Some(walk_hook) => Some(walk_hook),
+        None if show_progress => Some(&draw_walk),
+        None => None,
+    };
+    // The first report comes as soon as a walk is under way
+    let last_walk_report = Mutex::new(start.checked_sub(WALK_PROGRESS_INTERVAL).unwrap_or(start));
+
+    // Process entries in parallel
+    let process = |entry: &fs::DirEntry| {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let charged_to: Arc<str> = Arc::from(file_name.as_str());
+        let ctx = WalkContext {
+            opts,
+            start,
+            cancelled: &cancelled,
+            deadline,
+            truncated: &truncated,
+            base_dev,
+            current_entry: &current_entry,
+            walk_hook,
+            last_walk_report: &last_walk_report,
+            error_hook,
+            entry_filter,
+            counters: &counters,
+            now,
+            filter: &filter,
+            root: base_path,
+            junk_patterns: junk_patterns.as_ref(),
+            entry: Some(&charged_to),
+            prune: true,
+        };
+        // Check for cancellation
+        if ctx.should_stop() {
+            return;
+        }
+
+        let file_path = entry.path();
+
+        if verbose {
+            *current_entry.lock() = file_name.clone();
+        }
+
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        diagnostics::debug(&format!("Scanning {}", file_path.display()));
+        let meta = || fs::symlink_metadata(&file_path).ok();
+        if entry_filter.is_some_and(|f| !admits(f, &file_path, meta().as_ref(), &cancelled)) {
+            // Rejected by the entry filter
+            filtered.fetch_add(1, Ordering::Relaxed);
+        } else if is_dir && filter.is_known_junk(&entry.file_name()) {
+            counters.record_pruned(&file_path, &ctx);
+        } else if opts.skip_empty && fs::metadata(&file_path).is_ok_and(|m| opts.skips_empty(&m)) {
+            // Empty files are not listed
+            filtered.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let began = Instant::now();
+            let mut stats = walk_entry(&file_path, &ctx);
+            stats.scan_time = began.elapsed();
+
+            if let Some(limits) = &opts.limits {
+                stats.limit_kb = limits.limit_for(&file_name, &file_path.to_string_lossy());
+            }
+
+            // An entry deleted while it was walked gets no row
+            if fs::symlink_metadata(&file_path).is_err_and(|e| e.kind() == io::ErrorKind::NotFound)
+            {
+                counters.record_race(&file_path);
+            } else if !cancelled.load(Ordering::Relaxed) {
+                results.lock().insert(file_name.clone(), stats);
+            }
+        }
+
+        // Update progress periodically
+        let current = progress.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(hook) = hook {
+            if !hook(current, total_entries, &file_name) {
+                cancelled.store(true, Ordering::Relaxed);
+            }
+        } else if show_progress && current.is_multiple_of(10) {
+            let entry_name = if verbose {
+                Some(current_entry.lock().clone())
+            } else {
+                None
+            };
+            print_progress(
+                current,
+                total_entries,
+                entry_name.as_deref(),
+                progress_glyphs,
+            );
+        }
+    };
+    match stream {
+        Some(stream) => stream
+            .flatten()
+            .filter(included)
+            .par_bridge()
+            .for_each(|entry| process(&entry)),
+        None => entries_vec.par_iter().for_each(process),
+    }
+
+    // Ensure final progress state is shown after parallel iteration completes
+    if show_progress {
+        let done = total_entries.unwrap_or_else(|| progress.load(Ordering::Relaxed));
+        print_progress(done, total_entries, None, progress_glyphs);
+    }
+
+    // Hash the --duplicates candidates while Ctrl+C can still cancel.
+    // Unfinished walks would leave directories looking empty.
+    let complete = !cancelled.load(Ordering::Relaxed) && truncated.lock().is_none();
+    let duplicates = counters
+        .duplicates
+        .lock()
+        .take()
+        .filter(|_| complete)
+        .and_then(|candidates| candidates.into_sets(&cancelled).ok());
+    let empty = counters.empty.lock().take().filter(|_| complete);
+    let junk = counters.junk.lock().take().filter(|_| complete);
+    let inode_hogs = counters
+        .inode_hogs
+        .lock()
+        .take()
+        .filter(|_| complete)
+        .map(InodeHogs::into_sorted);
+
+    // Unregister our signal handler now that computation is done
+    if let Some(id) = signal_id {
+        signal_hook::low_level::unregister(id);
+    }
+    // Stop the live display thread
+    let was_cancelled = cancelled.load(Ordering::Relaxed);
+    cancelled.store(true, Ordering::Relaxed);
+    if let Some(handle) = live_display {
         let _ = handle.join();
     }
 
-    // Check if we were cancelled by SIGINT
-    if was_cancelled {
-        // Clear progress bar
-        eprint!("\r{}\r", " ".repeat(80));
-        io::stderr().flush().ok();
-        return Err(DustrError::Cancelled);
+    // With --strict an unreadable path fails the scan
+    if let Some(failure) = counters.failure.lock().take() {
+        if show_progress {
+            clear_progress(walk_line_shown.load(Ordering::Relaxed));
+        }
+        return Err(failure);
+    }
+
+    // Check if we were cancelled by SIGINT; a truncated scan still reports
+    // the entries it completed
+    let truncated = truncated.into_inner();
+    if was_cancelled && truncated.is_none() {
+        if show_progress {
+            clear_progress(walk_line_shown.load(Ordering::Relaxed));
+        }
+        return Err(DustrError::Cancelled);
+    }
+
+    // Clear progress bar / live display
+    if live {
+        let lines = live_last_lines.load(Ordering::Relaxed);
+        if lines > 0 {
+            status(format_args!("\x1b[{}A\x1b[J", lines));
+        }
+    } else if show_progress {
+        clear_progress(walk_line_shown.load(Ordering::Relaxed));
+    }
+    io::stderr().flush().ok();
+
+    let mut final_results = match Arc::try_unwrap(results) {
+        Ok(mutex) => mutex.into_inner(),
+        Err(arc) => arc.lock().clone(),
+    };
+    apply_charge_moves(&mut final_results, counters.charge_moves.into_inner());
+    if verbose {
+        log_entry_timings(&final_results);
+    }
+    let listed = final_results.len();
+    final_results.retain(|_, stats| opts.size_in_range(stats.size_kb));
+    let filtered = filtered.load(Ordering::Relaxed) + (listed - final_results.len()) as u64;
+    let treemap = counters
+        .treemap
+        .lock()
+        .take()
+        .filter(|_| complete)
+        .map(|tree| tree.into_tree(path, &final_results));
+
+    let scan = ScanResult {
+        entries: final_results,
+        errors: counters.errors.into_inner(),
+        broken_symlinks: counters.broken_symlinks.into_inner(),
+        races_skipped: counters.races_skipped.into_inner(),
+        broken_links: {
+            let mut links = counters.broken_links.into_inner();
+            links.sort();
+            links
+        },
+        types: counters.types.into_inner(),
+        size_histogram: counters.size_histogram.into_inner(),
+        file_size_stats: counters.file_size_stats.into_inner(),
+        by_depth: counters.by_depth.into_inner(),
+        inode_hogs,
+        recent: counters.recent.into_inner().map(|mut recent| {
+            sort_recent(&mut recent);
+            recent
+        }),
+        duplicates,
+        empty: empty.map(|mut empty| {
+            empty.sort();
+            empty
+        }),
+        junk: junk.map(|mut junk| {
+            junk.sort();
+            junk
+        }),
+        treemap,
+        filtered,
+        xattr_bytes: counters.xattr_bytes.into_inner(),
+        pruned_dirs: counters.pruned_dirs.into_inner(),
+        pruned_kb: counters.pruned_kb.into_inner(),
+        duration: start.elapsed(),
+        truncated,
+    };
+    match truncated {
+        Some(_) => Err(DustrError::Truncated(Box::new(scan))),
+        None => Ok(scan),
+    }
+}
+
+/// Scan a path that is not a directory as a single entry, like `du somefile`.
+/// Symlinks are resolved to their target, as for top-level entries in a directory.
+fn scan_single_file(
+    path: &str,
+    opts: &Options,
+    filter: &PathFilter,
+    start: Instant,
+) -> Result<ScanResult, DustrError> {
+    let meta = fs::metadata(path).map_err(|e| {
+        DustrError::from_io(format!("Cannot read metadata for '{}': {}", path, e), &e)
+    })?;
+    let name = single_entry_name(path);
+    let counted = opts.age_matches(meta.mtime(), unix_now())
+        && !opts.skips_empty(&meta)
+        && filter.includes_file(Path::new(&name));
+    let mut types = TypeBreakdown::default();
+    if let (true, true, Ok(lmeta)) = (opts.summary, counted, fs::symlink_metadata(path)) {
+        types.record(lmeta.file_type(), &lmeta);
+    }
+    let mut entries = HashMap::new();
+    let inodes = if opts.dirs_only || !counted { 0 } else { 1 };
+    let limit_kb = opts.limits.as_ref().and_then(|l| l.limit_for(&name, path));
+    let mut stats = EntryStats {
+        inodes,
+        mtime: Some(meta.mtime()),
+        files: u64::from(meta.is_file() && counted),
+        limit_kb,
+        scan_time: start.elapsed(),
+        ..EntryStats::default()
+    };
+    let mut size_histogram = opts.new_size_histogram();
+    let mut by_depth = opts.by_depth.then(DepthUsage::default);
+    let mut file_size_stats = opts.stats.then(FileSizeStats::default);
+    let recent = (meta.is_file() && counted && opts.is_recent(&meta, unix_now()))
+        .then(|| vec![RecentFile::new(Path::new(path), &meta)]);
+    let patterns = opts.junk_patterns();
+    let mut junk = patterns.as_ref().map(Junk::new);
+    let mut xattr_bytes = 0;
+    if meta.is_file() && counted {
+        stats.add_blocks(&meta);
+        if opts.xattrs {
+            xattr_bytes = crate::xattrs::xattr_bytes(Path::new(path));
+            stats.add_xattrs(xattr_bytes);
+        }
+        if let (Some(junk), Some(pattern)) =
+            (&mut junk, patterns.and_then(|p| p.find(Path::new(path))))
+        {
+            junk.record(pattern, Path::new(path), &meta);
+        }
+        if let Some(histogram) = &mut size_histogram {
+            histogram.record(meta.len());
+        }
+        if let Some(stats) = &mut file_size_stats {
+            stats.record(meta.len());
+        }
+        if let Some(by_depth) = &mut by_depth {
+            by_depth.record(1, &meta, true);
+        }
+    }
+    entries.insert(name, stats);
+    entries.retain(|_, stats| opts.size_in_range(stats.size_kb));
+    Ok(ScanResult {
+        entries,
+        types,
+        size_histogram,
+        file_size_stats,
+        by_depth,
+        recent: opts.recent.as_ref().map(|_| recent.unwrap_or_default()),
+        junk,
+        xattr_bytes,
+        duration: start.elapsed(),
+        ..Default::default()
+    })
+}
+
+/// Log how long each top-level entry took to walk, slowest first, so the
+/// subtrees dominating a slow scan stand out
+fn log_entry_timings(entries: &HashMap<String, EntryStats>) {
+    let mut timings: Vec<_> = entries.iter().collect();
+    timings.sort_by(|(a, x), (b, y)| y.scan_time.cmp(&x.scan_time).then_with(|| a.cmp(b)));
+    for (name, stats) in timings {
+        diagnostics::debug(&format!(
+            "Scanned {} in {:.2}s ({:.0} files/s)",
+            name,
+            stats.scan_seconds(),
+            stats.files_per_second()
+        ));
+    }
+}
+
+/// Name used for the single entry produced when scanning a non-directory path
+fn single_entry_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Shared state needed while walking a top-level entry
+#[derive(Clone, Copy)]
+struct WalkContext<'a> {
+    opts: &'a Options,
+    /// When the scan began, for the elapsed time in the `-vv` trace
+    start: Instant,
+    cancelled: &'a Arc<AtomicBool>,
+    /// When `--timeout` runs out
+    deadline: Option<Instant>,
+    /// Set, along with `cancelled`, once `--timeout` or `--max-files` is
+    /// exceeded
+    truncated: &'a Mutex<Option<Truncation>>,
+    base_dev: Option<u64>,
+    current_entry: &'a Mutex<String>,
+    /// Receives the files and bytes counted so far inside an entry
+    walk_hook: Option<&'a WalkHook<'a>>,
+    /// When `walk_hook` was last called, shared by all walkers
+    last_walk_report: &'a Mutex<Instant>,
+    /// Receives the paths that cannot be read
+    error_hook: Option<&'a ErrorHook<'a>>,
+    entry_filter: Option<&'a EntryFilter>,
+    counters: &'a ScanCounters,
+    /// Reference time for the age filters, fixed for the whole scan
+    now: i64,
+    filter: &'a Arc<PathFilter>,
+    /// The scanned directory, which exclude paths are relative to
+    root: &'a Path,
+    /// The `--find-junk` patterns, built once for the whole scan
+    junk_patterns: Option<&'a JunkPatterns>,
+    /// The top-level entry whose size the walk adds to, `None` while
+    /// sizing a pruned directory
+    entry: Option<&'a Arc<str>>,
+    /// Whether `--prune-known` directories are set aside (off while sizing
+    /// a pruned directory itself)
+    prune: bool,
+}
+
+impl WalkContext<'_> {
+    /// Add the blocks of the file or directory at `path` to a `--treemap`
+    /// tree
+    fn add_to_treemap(&self, tree: &mut NodeBuilder, path: &Path, meta: &fs::Metadata) {
+        let rel = path.strip_prefix(self.root).unwrap_or(path);
+        tree.record(rel, meta.blocks() * 512, self.opts.treemap_depth as usize);
+    }
+
+    /// Whether the walk should stop: after Ctrl+C, or once the deadline has
+    /// passed or too many files were found, which cancels the whole scan
+    fn should_stop(&self) -> bool {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return true;
+        }
+        let reason = if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.opts.timeout.map(Truncation::Timeout)
+        } else {
+            let seen = self.counters.files_seen.load(Ordering::Relaxed);
+            self.opts
+                .max_files
+                .filter(|&max| seen > max)
+                .map(Truncation::MaxFiles)
+        };
+        let Some(reason) = reason else {
+            return false;
+        };
+        self.truncated.lock().get_or_insert(reason);
+        self.cancelled.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Count a walked entry for `--max-files` and, with `-vv`, log each
+    /// directory as it is entered along with the elapsed time and the
+    /// number of files seen so far
+    fn note_entry(&self, path: &Path, is_dir: bool) {
+        if self.opts.verbose < 2 && (is_dir || self.opts.max_files.is_none()) {
+            return;
+        }
+        if is_dir {
+            diagnostics::trace(&format!(
+                "Entering {} ({:.1}s, {} files)",
+                path.display(),
+                self.start.elapsed().as_secs_f64(),
+                self.counters.files_seen.load(Ordering::Relaxed)
+            ));
+        } else {
+            ScanCounters::bump(&self.counters.files_seen);
+        }
+    }
+
+    /// Report how far the walk of the top-level entry `path` has got, at
+    /// most every `WALK_PROGRESS_INTERVAL` across all walkers
+    fn report_walk(&self, path: &Path, stats: &EntryStats) {
+        let Some(hook) = self.walk_hook else {
+            return;
+        };
+        {
+            let mut last = self.last_walk_report.lock();
+            if last.elapsed() < WALK_PROGRESS_INTERVAL {
+                return;
+            }
+            *last = Instant::now();
+        }
+        let name = path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy();
+        if !hook(&name, stats.files, stats.bytes) {
+            self.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a path that could not be read, passing it to the error hook
+    /// if there is one. With `--strict`, or when the hook says so, the first
+    /// one becomes the error of the scan, which stops.
+    fn record_error(&self, path: &Path, message: String, error: Option<&io::Error>) {
+        let abort = match self.error_hook {
+            Some(hook) => {
+                ScanCounters::bump(&self.counters.errors);
+                let go_on = hook(path, error.and_then(io::Error::raw_os_error), &message);
+                !go_on || self.opts.strict
+            }
+            None if self.opts.strict => {
+                ScanCounters::bump(&self.counters.errors);
+                true
+            }
+            None => {
+                self.counters.record_error(&message);
+                false
+            }
+        };
+        if !abort {
+            return;
+        }
+        self.counters
+            .failure
+            .lock()
+            .get_or_insert_with(|| match error {
+                Some(error) => DustrError::from_io(message, error),
+                None => DustrError::OsError(message, None),
+            });
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Tallies and findings updated concurrently by the walkers
+#[derive(Default)]
+struct ScanCounters {
+    errors: AtomicU64,
+    broken_symlinks: AtomicU64,
+    broken_links: Mutex<Vec<(String, String)>>,
+    seen_files: SeenFiles,
+    /// The top-level entry each file first seen in this scan is charged to
+    charges: Mutex<HashMap<(u64, u64), Arc<str>>>,
+    /// Charges taken over by entries earlier in name order
+    charge_moves: Mutex<Vec<ChargeMove>>,
+    /// Whether every file is counted once, not just with `count_symlink_size`
+    dedup: bool,
+    types: Mutex<TypeBreakdown>,
+    size_histogram: Mutex<Option<SizeHistogram>>,
+    duplicates: Mutex<Option<DuplicateCandidates>>,
+    file_size_stats: Mutex<Option<FileSizeStats>>,
+    by_depth: Mutex<Option<DepthUsage>>,
+    inode_hogs: Mutex<Option<InodeHogs>>,
+    recent: Mutex<Option<Vec<RecentFile>>>,
+    empty: Mutex<Option<EmptyEntries>>,
+    junk: Mutex<Option<Junk>>,
+    treemap: Mutex<Option<NodeBuilder>>,
+    xattr_bytes: AtomicU64,
+    pruned_dirs: AtomicU64,
+    pruned_kb: AtomicU64,
+    /// Entries other than directories walked so far, only kept for `-vv`
+    /// and `--max-files`
+    files_seen: AtomicU64,
+    races_skipped: AtomicU64,
+    /// The first unreadable path with `--strict`
+    failure: Mutex<Option<DustrError>>,
+    /// `(dev, ino)` of the directories entered, with `--detect-cycles`
+    visited_dirs: SeenFiles,
+}
+
+impl ScanCounters {
+    fn bump(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a path that could not be read, reporting it as a warning
+    fn record_error(&self, message: &str) {
+        Self::bump(&self.errors);
+        diagnostics::warn(message);
+    }
+
+    /// Whether a file's blocks should be counted. With `count_symlink_size`
+    /// a file may be reached both directly and through links, and with a
+    /// deduplicating `ScanCache` under several names or in several scans, so
+    /// each `(dev, ino)` is only counted the first time it is seen.
+    ///
+    /// Top-level entries are walked in parallel, so which of them sees a
+    /// file first is down to timing. A file reached from several entries is
+    /// charged to the first of them in name order instead: a later sighting
+    /// from an earlier entry records a `ChargeMove`, applied once the scan
+    /// is done.
+    fn first_sighting(&self, meta: &fs::Metadata, ctx: &WalkContext) -> bool {
+        if !(ctx.opts.count_symlink_size || self.dedup) {
+            return true;
+        }
+        let key = (meta.dev(), meta.ino());
+        // Held across the insert so that no sighting finds a file counted
+        // but not yet charged
+        let mut charges = self.charges.lock();
+        if self.seen_files.lock().insert(key) {
+            if let Some(entry) = ctx.entry {
+                charges.insert(key, entry.clone());
+            }
+            return true;
+        }
+        if let (Some(entry), Some(owner)) = (ctx.entry, charges.get_mut(&key)) {
+            if entry < owner {
+                self.charge_moves.lock().push(ChargeMove {
+                    from: std::mem::replace(owner, entry.clone()),
+                    to: entry.clone(),
+                    kb: blocks_to_kb(meta),
+                    bytes: meta.blocks() * 512,
+                    files: u64::from(meta.is_file()),
+                });
+            }
+        }
+        false
+    }
+
+    /// Whether the directory at `path` is entered for the first time, for
+    /// `--detect-cycles`; directories that cannot be read are left to the
+    /// walk, which reports them
+    fn first_visit(visited_dirs: &SeenFiles, path: &Path, meta: Option<&fs::Metadata>) -> bool {
+        let Some(meta) = meta else {
+            return true;
+        };
+        let first = visited_dirs.lock().insert((meta.dev(), meta.ino()));
+        if !first {
+            diagnostics::warn(&format!(
+                "Skipping '{}': directory already visited (a cycle or bind mount)",
+                path.display()
+            ));
+        }
+        first
+    }
+
+    /// Account for a directory pruned by `--prune-known`, summing its size
+    /// unless `--prune-skip` is set
+    fn record_pruned(&self, path: &Path, ctx: &WalkContext) {
+        Self::bump(&self.pruned_dirs);
+        diagnostics::debug(&format!("Pruned build/cache directory {}", path.display()));
+        if !ctx.opts.prune_skip {
+            let nested = WalkContext {
+                entry: None,
+                prune: false,
+                ..*ctx
+            };
+            let stats = walk_entry(path, &nested);
+            self.pruned_kb.fetch_add(stats.size_kb, Ordering::Relaxed);
+        }
+    }
+
+    /// Count an entry that disappeared between being listed and being read
+    fn record_race(&self, path: &Path) {
+        Self::bump(&self.races_skipped);
+        diagnostics::debug(&format!("{} vanished during the scan", path.display()));
+    }
+
+    /// Count a dangling symlink, remembering it when `--check-links` is on
+    fn record_broken_link(&self, path: &Path, opts: &Options) {
+        Self::bump(&self.broken_symlinks);
+        if opts.check_links {
+            let target = fs::read_link(path)
+                .map(|t| t.to_string_lossy().to_string())
+                .unwrap_or_default();
+            self.broken_links
+                .lock()
+                .push((path.to_string_lossy().to_string(), target));
+        }
+    }
+}
+
+/// Whether a walk error means the path disappeared after being listed,
+/// e.g. a log file rotated away during the scan
+fn vanished(e: &jwalk::Error) -> bool {
+    e.io_error()
+        .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+}
+
+/// Convert allocated 512-byte blocks to kilobytes, rounding up
+pub(crate) fn blocks_to_kb(meta: &fs::Metadata) -> u64 {
+    (meta.blocks() * 512).div_ceil(1024)
+}
+
+/// Approximate a directory's statistics from its immediate children only:
+/// their apparent sizes (`metadata.len()`) are summed and subdirectories
+/// count as their own entry, without being read
+fn shallow_entry(path: &Path, ctx: &WalkContext) -> EntryStats {
+    ctx.note_entry(path, true);
+    let mut stats = EntryStats {
+        inodes: 1,
+        ..EntryStats::default()
+    };
+    let read_dir = match fs::read_dir(path) {
+        Ok(read_dir) => read_dir,
+        Err(e) => {
+            ctx.record_error(
+                path,
+                format!("Cannot read '{}': {}", path.display(), e),
+                Some(&e),
+            );
+            return stats;
+        }
+    };
+    for entry in read_dir.flatten() {
+        let child = entry.path();
+        if ctx
+            .filter
+            .excludes(child.strip_prefix(ctx.root).unwrap_or(&child))
+        {
+            continue;
+        }
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if let Some(entry_filter) = ctx.entry_filter {
+            if !admits(entry_filter, &child, Some(&meta), ctx.cancelled) {
+                continue;
+            }
+        }
+        if !ctx.opts.dirs_only || meta.is_dir() {
+            stats.inodes += 1;
+        }
+        if meta.is_file() {
+            stats.files += 1;
+        }
+        stats.bytes += meta.len();
+        if let Some(tree) = &mut *ctx.counters.treemap.lock() {
+            let rel = child.strip_prefix(ctx.root).unwrap_or(&child);
+            tree.record(rel, meta.len(), ctx.opts.treemap_depth as usize);
+        }
+    }
+    stats.size_kb = stats.bytes.div_ceil(1024);
+    stats
+}
+
+/// Gather size and inode statistics by walking the tree serially.
+/// The caller's rayon `par_iter` already provides top-level parallelism;
+/// using Serial here avoids nested thread-pool oversubscription.
+fn walk_entry(path: &Path, ctx: &WalkContext) -> EntryStats {
+    if !path.is_dir() {
+        ctx.note_entry(path, false);
+        if is_broken_symlink(path) {
+            ctx.counters.record_broken_link(path, ctx.opts);
+        }
+        let mtime = if ctx.opts.needs_mtime() || ctx.opts.has_age_filter() {
+            fs::metadata(path).ok().map(|m| m.mtime())
+        } else {
+            None
+        };
+        let counted = mtime.is_none_or(|t| ctx.opts.age_matches(t, ctx.now))
+            && !(ctx.opts.skip_empty && fs::metadata(path).is_ok_and(|m| ctx.opts.skips_empty(&m)))
+            && ctx
+                .filter
+                .includes_file(path.strip_prefix(ctx.root).unwrap_or(path));
+        let mut stats = EntryStats {
+            inodes: if ctx.opts.dirs_only || !counted { 0 } else { 1 },
+            files: u64::from(path.is_file() && counted),
+            mtime: mtime.filter(|_| ctx.opts.needs_mtime()),
+            ..EntryStats::default()
+        };
+        if path.is_file() && counted {
+            match fs::metadata(path) {
+                Ok(m) if ctx.counters.first_sighting(&m, ctx) => {
+                    stats.add_blocks(&m);
+                    if ctx.opts.xattrs {
+                        let bytes = crate::xattrs::xattr_bytes(path);
+                        stats.add_xattrs(bytes);
+                        ctx.counters.xattr_bytes.fetch_add(bytes, Ordering::Relaxed);
+                    }
+                    if let Some(histogram) = &mut *ctx.counters.size_histogram.lock() {
+                        histogram.record(m.len());
+                    }
+                    if let Some(stats) = &mut *ctx.counters.file_size_stats.lock() {
+                        stats.record(m.len());
+                    }
+                    if let (Some(recent), true) = (
+                        &mut *ctx.counters.recent.lock(),
+                        ctx.opts.is_recent(&m, ctx.now),
+                    ) {
+                        recent.push(RecentFile::new(path, &m));
+                    }
+                    if let Some(candidates) = &mut *ctx.counters.duplicates.lock() {
+                        candidates.record(path.to_path_buf(), &m);
+                    }
+                    if let Some(by_depth) = &mut *ctx.counters.by_depth.lock() {
+                        by_depth.record(1, &m, true);
+                    }
+                    if let Some(tree) = &mut *ctx.counters.treemap.lock() {
+                        ctx.add_to_treemap(tree, path, &m);
+                    }
+                    if let Some(junk) = &mut *ctx.counters.junk.lock() {
+                        if let Some(pattern) = ctx.junk_patterns.and_then(|p| p.find(path)) {
+                            junk.record(pattern, path, &m);
+                        }
+                    }
+                }
+                Ok(_) => {}
+                // Deleted since it was listed; the caller drops the entry
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    ctx.record_error(
+                        path,
+                        format!("Cannot read '{}': {}", path.display(), e),
+                        Some(&e),
+                    );
+                }
+            }
+        }
+        if let (true, true, Ok(lmeta)) = (ctx.opts.summary, counted, fs::symlink_metadata(path)) {
+            ctx.counters.types.lock().record(lmeta.file_type(), &lmeta);
+        }
+        if ctx.opts.find_empty
+            && counted
+            && fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() == 0)
+        {
+            if let Some(empty) = &mut *ctx.counters.empty.lock() {
+                empty.files.push(path.to_string_lossy().to_string());
+            }
+        }
+        return stats;
+    }
+
+    if ctx.opts.detect_cycles
+        && !ScanCounters::first_visit(
+            &ctx.counters.visited_dirs,
+            path,
+            fs::metadata(path).ok().as_ref(),
+        )
+    {
+        return EntryStats::default();
+    }
+    if ctx.opts.shallow {
+        return shallow_entry(path, ctx);
+    }
+
+    let mut stats = EntryStats::default();
+    let mut types = TypeBreakdown::default();
+    let mut size_histogram = ctx.opts.new_size_histogram();
+    let mut file_size_stats = ctx.opts.stats.then(FileSizeStats::default);
+    let mut recent = ctx.opts.recent.as_ref().map(|_| Vec::new());
+    // Extended attributes of the files and directories walked, --xattrs
+    let mut xattr_bytes = 0;
+    let mut duplicates = ctx.opts.new_duplicate_candidates();
+    let mut empty = ctx.opts.find_empty.then(EmptyFinder::default);
+    let mut by_depth = ctx.opts.by_depth.then(DepthUsage::default);
+    // Pruned directories are not part of any entry, so not drawn either
+    let mut treemap = ctx.entry.and_then(|_| ctx.opts.new_treemap());
+    let junk_patterns = ctx.junk_patterns;
+    let mut junk = junk_patterns.map(Junk::new);
+    // Hidden junk such as .DS_Store, noted as hidden children are dropped
+    let hidden_junk = Arc::new(Mutex::new(junk_patterns.map(Junk::new)));
+    // Entries read in each directory, for --inode-hogs
+    let mut children: Option<HashMap<Arc<Path>, u64>> = ctx.opts.inode_hogs.map(|_| HashMap::new());
+    // Directories some of whose children were left out (hidden, excluded
+    // or rejected by a filter), for --find-empty
+    let hidden = Arc::new(Mutex::new(Vec::new()));
+    let mut count = 0;
+    // jwalk drops hidden children before the closure below sees them; with
+    // --find-empty they are dropped there instead, so their directories
+    // are not taken for empty ones, with --find-junk so that hidden junk
+    // files are still found, and with --prune-known so that hidden known
+    // directories such as .venv are kept and pruned
+    let find_empty = ctx.opts.find_empty;
+    let prune = ctx.prune && !ctx.filter.known.is_empty();
+    let keep_hidden = find_empty || junk_patterns.is_some() || prune;
+    let mut walker = JWalkDir::new(path)
+        .parallelism(jwalk::Parallelism::Serial)
+        .skip_hidden(!keep_hidden);
+    if ctx.filter.prunes()
+        || prune
+        || ctx.entry_filter.is_some()
+        || ctx.opts.detect_cycles
+        || keep_hidden
+    {
+        // Drop excluded children, and those the entry filter rejects,
+        // before they are yielded, so such directories are never descended
+        // into; known build/cache directories are yielded but not read
+        let filter = ctx.filter.clone();
+        let root = ctx.root.to_path_buf();
+        let entry_filter = ctx.entry_filter.cloned();
+        let cancelled = ctx.cancelled.clone();
+        let visited_dirs = ctx
+            .opts
+            .detect_cycles
+            .then(|| ctx.counters.visited_dirs.clone());
+        let hidden = find_empty.then(|| hidden.clone());
+        let junk_patterns = junk_patterns.cloned();
+        let hidden_junk = hidden_junk.clone();
+        let base_dev = ctx.base_dev;
+        walker = walker.process_read_dir(move |depth, dir, _, children| {
+            let listed = children.len();
+            // The top-level entry itself is kept even when hidden, like
+            // jwalk does
+            if let (true, Some(_)) = (keep_hidden, depth) {
+                children.retain(|child| {
+                    let Ok(e) = child else { return true };
+                    if !e.file_name.to_str().is_some_and(|s| s.starts_with('.')) {
+                        return true;
+                    }
+                    if prune && e.file_type.is_dir() && filter.is_known_junk(&e.file_name) {
+                        return true;
+                    }
+                    if let (Some(patterns), true) = (&junk_patterns, e.file_type.is_file()) {
+                        let path = e.path();
+                        let meta = e
+                            .metadata()
+                            .ok()
+                            .filter(|m| base_dev.is_none_or(|d| m.dev() == d));
+                        if let (Some(pattern), Some(m), false) = (
+                            patterns.find(&path),
+                            meta,
+                            filter.excludes(path.strip_prefix(&root).unwrap_or(&path)),
+                        ) {
+                            if let Some(junk) = &mut *hidden_junk.lock() {
+                                junk.record(pattern, &path, &m);
+                            }
+                        }
+                    }
+                    false
+                });
+            }
+            if filter.prunes() {
+                children.retain(|child| {
+                    child.as_ref().map_or(true, |e| {
+                        let path = e.path();
+                        !filter.excludes(path.strip_prefix(&root).unwrap_or(&path))
+                    })
+                });
+            }
+            // The top-level entry itself (without a depth) has already been
+            // entered and admitted
+            if let (Some(visited_dirs), Some(_)) = (&visited_dirs, depth) {
+                children.retain(|child| {
+                    child.as_ref().map_or(true, |e| {
+                        !e.file_type.is_dir()
+                            || ScanCounters::first_visit(
+                                visited_dirs,
+                                &e.path(),
+                                e.metadata().ok().as_ref(),
+                            )
+                    })
+                });
+            }
+            if let (Some(entry_filter), Some(_)) = (&entry_filter, depth) {
+                children.retain(|child| {
+                    child.as_ref().map_or(true, |e| {
+                        !cancelled.load(Ordering::Relaxed)
+                            && admits(
+                                entry_filter,
+                                &e.path(),
+                                e.metadata().ok().as_ref(),
+                                &cancelled,
+                            )
+                    })
+                });
+            }
+            if prune {
+                for child in children.iter_mut().flatten() {
+                    if child.file_type.is_dir() && filter.is_known_junk(&child.file_name) {
+                        child.read_children_path = None;
+                    }
+                }
+            }
+            if let (Some(hidden), true) = (&hidden, children.len() < listed) {
+                hidden.lock().push(dir.to_path_buf());
+            }
+        });
+    }
+    for entry in walker {
+        if count % ctx.opts.check_interval == 0 {
+            if ctx.should_stop() {
+                break;
+            }
+            if count > 0 {
+                ctx.report_walk(path, &stats);
+            }
+        }
+        count += 1;
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) if vanished(&e) => {
+                // A vanished top-level entry is accounted for by the caller
+                if e.depth() > 0 {
+                    ctx.counters.record_race(e.path().unwrap_or(path));
+                }
+                continue;
+            }
+            Err(e) => {
+                ctx.record_error(
+                    e.path().unwrap_or(path),
+                    format!("Cannot read: {}", e),
+                    e.io_error(),
+                );
+                if let (Some(empty), Some(failed)) = (&mut empty, e.path()) {
+                    empty.occupied_parent(failed);
+                }
+                continue;
+            }
+        };
+        if let (Some(children), 1..) = (&mut children, entry.depth()) {
+            *children.entry(entry.parent_path.clone()).or_default() += 1;
+        }
+        // jwalk attaches a failure to list a directory to its entry
+        match &entry.read_children_error {
+            Some(e) if vanished(e) => ctx.counters.record_race(&entry.path()),
+            Some(e) => ctx.record_error(&entry.path(), format!("Cannot read: {}", e), e.io_error()),
+            None => {}
+        }
+        let file_type = entry.file_type();
+        // Only stat when needed: for the device check, a regular file's block
+        // count, modification times, or the type summary.
+        let meta = if ctx.base_dev.is_some()
+            || file_type.is_file()
+            || (file_type.is_dir() && ctx.opts.include_dir_overhead)
+            || ctx.opts.needs_mtime()
+            || ctx.opts.has_age_filter()
+            || ctx.opts.summary
+        {
+            match entry.metadata() {
+                Ok(m) => Some(m),
+                Err(e) if vanished(&e) => {
+                    if entry.depth() > 0 {
+                        ctx.counters.record_race(&entry.path());
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    ctx.record_error(
+                        &entry.path(),
+                        format!(
+                            "Cannot read metadata for '{}': {}",
+                            entry.path().display(),
+                            e
+                        ),
+                        e.io_error(),
+                    );
+                    if let Some(empty) = &mut empty {
+                        empty.occupied_parent(&entry.path());
+                    }
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+        if let (Some(dev), Some(m)) = (ctx.base_dev, &meta) {
+            if m.dev() != dev {
+                if let Some(empty) = &mut empty {
+                    empty.occupied_parent(&entry.path());
+                }
+                continue;
+            }
+        }
+        if prune
+            && entry.depth() > 0
+            && file_type.is_dir()
+            && ctx.filter.is_known_junk(entry.file_name())
+        {
+            ctx.counters.record_pruned(&entry.path(), ctx);
+            if let Some(empty) = &mut empty {
+                empty.occupied_parent(&entry.path());
+            }
+            continue;
+        }
+        ctx.note_entry(&entry.path(), file_type.is_dir());
+        if let Some(empty) = &mut empty {
+            if !file_type.is_dir() {
+                empty.occupied_parent(&entry.path());
+            } else {
+                empty.enter_dir(entry.path());
+                // An unreadable directory may hold anything
+                if entry.read_children_error.is_some() {
+                    empty.occupied(&entry.path());
+                }
+            }
+        }
+        // Age, include and empty-file filters only apply to files;
+        // directories are always walked
+        let counted = file_type.is_dir()
+            || (meta.as_ref().is_none_or(|m| {
+                ctx.opts.age_matches(m.mtime(), ctx.now) && !ctx.opts.skips_empty(m)
+            }) && (!ctx.filter.has_includes() || {
+                let path = entry.path();
+                ctx.filter
+                    .includes_file(path.strip_prefix(ctx.root).unwrap_or(&path))
+            }));
+        if counted && (!ctx.opts.dirs_only || file_type.is_dir()) {
+            stats.inodes += 1;
+        }
+        if let (true, true, Some(m)) = (ctx.opts.summary, counted, &meta) {
+            types.record(file_type, m);
+        }
+        if let (true, Some(m)) = (ctx.opts.needs_mtime(), &meta) {
+            stats.mtime = stats.mtime.max(Some(m.mtime()));
+        }
+        if file_type.is_symlink() && is_broken_symlink(&entry.path()) {
+            ctx.counters.record_broken_link(&entry.path(), ctx.opts);
+        }
+        if file_type.is_dir() {
+            if let (true, Some(m)) = (ctx.opts.include_dir_overhead, &meta) {
+                if ctx.counters.first_sighting(m, ctx) {
+                    stats.add_blocks(m);
+                    if ctx.opts.xattrs {
+                        xattr_bytes += crate::xattrs::xattr_bytes(&entry.path());
+                    }
+                    if let Some(by_depth) = &mut by_depth {
+                        by_depth.record(entry.depth() + 1, m, false);
+                    }
+                    if let Some(tree) = &mut treemap {
+                        ctx.add_to_treemap(tree, &entry.path(), m);
+                    }
+                }
+            }
+            if count % ctx.opts.check_interval == 0 {
+                *ctx.current_entry.lock() = entry.path().to_string_lossy().to_string();
+            }
+        } else if !counted {
+            continue;
+        } else if let (true, Some(m)) = (file_type.is_file(), &meta) {
+            if let (Some(empty), 0) = (&mut empty, m.len()) {
+                empty.empty_file(&entry.path());
+            }
+            if ctx.counters.first_sighting(m, ctx) {
+                stats.add_blocks(m);
+                stats.files += 1;
+                if ctx.opts.xattrs {
+                    xattr_bytes += crate::xattrs::xattr_bytes(&entry.path());
+                }
+                if let Some(by_depth) = &mut by_depth {
+                    by_depth.record(entry.depth() + 1, m, true);
+                }
+                if let Some(tree) = &mut treemap {
+                    ctx.add_to_treemap(tree, &entry.path(), m);
+                }
+                if let Some(histogram) = &mut size_histogram {
+                    histogram.record(m.len());
+                }
+                if let Some(stats) = &mut file_size_stats {
+                    stats.record(m.len());
+                }
+                if let (Some(recent), true) = (&mut recent, ctx.opts.is_recent(m, ctx.now)) {
+                    recent.push(RecentFile::new(&entry.path(), m));
+                }
+                if let Some(candidates) = &mut duplicates {
+                    candidates.record(entry.path(), m);
+                }
+                if let (Some(junk), Some(patterns)) = (&mut junk, &junk_patterns) {
+                    let path = entry.path();
+                    if let Some(pattern) = patterns.find(&path) {
+                        junk.record(pattern, &path, m);
+                    }
+                }
+            }
+        } else if file_type.is_symlink() && ctx.opts.count_symlink_size {
+            // Count the target's blocks, but never descend into linked directories
+            if let Ok(target) = fs::metadata(entry.path()) {
+                if target.is_file() && ctx.counters.first_sighting(&target, ctx) {
+                    stats.add_blocks(&target);
+                    stats.files += 1;
+                    if let Some(by_depth) = &mut by_depth {
+                        by_depth.record(entry.depth() + 1, &target, true);
+                    }
+                    if let Some(tree) = &mut treemap {
+                        ctx.add_to_treemap(tree, &entry.path(), &target);
+                    }
+                }
+            }
+        }
+    }
+    if xattr_bytes > 0 {
+        stats.add_xattrs(xattr_bytes);
+        ctx.counters
+            .xattr_bytes
+            .fetch_add(xattr_bytes, Ordering::Relaxed);
+    }
+    if ctx.opts.summary {
+        ctx.counters.types.lock().merge(&types);
+    }
+    if let (Some(mine), Some(total)) = (&size_histogram, &mut *ctx.counters.size_histogram.lock()) {
+        total.merge(mine);
+    }
+    if let (Some(mine), Some(total)) = (recent, &mut *ctx.counters.recent.lock()) {
+        total.extend(mine);
+    }
+    if let (Some(mine), Some(total)) = (file_size_stats, &mut *ctx.counters.file_size_stats.lock())
+    {
+        total.merge(mine);
+    }
+    if let (Some(mine), Some(total)) = (duplicates, &mut *ctx.counters.duplicates.lock()) {
+        total.merge(mine);
+    }
+    if let (Some(mine), Some(total)) = (&by_depth, &mut *ctx.counters.by_depth.lock()) {
+        total.merge(mine);
+    }
+    if let (Some(mine), Some(total)) = (treemap, &mut *ctx.counters.treemap.lock()) {
+        total.merge(mine);
+    }
+    if let (Some(mine), Some(total)) = (children, &mut *ctx.counters.inode_hogs.lock()) {
+        total.offer_walk(mine);
+    }
+    if let (Some(mut mine), Some(total)) = (junk, &mut *ctx.counters.junk.lock()) {
+        if let Some(hidden) = hidden_junk.lock().take() {
+            mine.merge(hidden);
+        }
+        total.merge(mine);
+    }
+    if let Some(mut mine) = empty {
+        for dir in hidden.lock().iter() {
+            mine.occupied(dir);
+        }
+        if let Some(total) = &mut *ctx.counters.empty.lock() {
+            mine.finish(total);
+        }
+    }
+    stats
+}
+
+/// Render the statistics table as a string.
+/// `progress` is `Some((current, total))` while the live display is updating.
+pub fn render_stats_table(
+    dirname: &str,
+    entries: &[(String, EntryStats)],
+    opts: &Options,
+    progress: Option<(usize, Option<usize>)>,
+    color: bool,
+    columns: Option<usize>,
+) -> String {
+    let mut all: Vec<(String, EntryStats)> = entries.to_vec();
+    sort_entries(&mut all, opts);
+    let shown = shown_entries(dirname, &all, opts);
+    let counted = counted_entries(&all, &shown, opts);
+    let (mut sorted, remainder) = top_entries(shown.clone(), opts);
+
+    let value = |stats: &EntryStats| stats.value(opts.inodes);
+    let glyphs = opts.bar_glyphs('#');
+    let total_size: u64 = counted.iter().map(|(_, s)| value(s)).sum();
+    let max_size = if opts.show_root {
+        total_size
+    } else {
+        sorted.iter().map(|(_, s)| value(s)).max().unwrap_or(0)
+    };
+    let now = unix_now();
+
+    let col0_name = if opts.inodes { "inodes" } else { "Size" };
+    let progress_str = match progress {
+        Some((current, Some(total))) => format!("({}/{})", current, total),
+        Some((current, None)) => format!("({})", current),
+        None => String::new(),
+    };
+    let mut cumulative = running_percentages(sorted.iter().map(|(_, s)| value(s)));
+    // The whole directory comes first whatever the order, at 100%
+    if opts.show_root {
+        let mut root = EntryStats::default();
+        for (_, stats) in counted {
+            root.absorb(stats);
+        }
+        let whole = if total_size == 0 { 0.0 } else { 100.0 };
+        sorted.insert(0, (ROOT_ROW_NAME.to_string(), root));
+        cumulative.insert(0, whole);
+    }
+    let exact_col = if opts.exact {
+        format!("{:>EXACT_WIDTH$} ", "Bytes")
+    } else {
+        String::new()
+    };
+    let raw_col = if opts.show_raw {
+        format!("{:<14} ", "KB")
+    } else {
+        String::new()
+    };
+    let inodes_col = if opts.both {
+        format!("{:<14} ", "inodes")
+    } else {
+        String::new()
+    };
+    let cum_col = if opts.cumulative {
+        format!("{:<6} ", "Cum %")
+    } else {
+        String::new()
+    };
+    let avg_col = if opts.avg {
+        format!("{:<14} ", "Avg file")
+    } else {
+        String::new()
+    };
+    let time_col = if opts.time {
+        format!("{:<20} ", "Modified")
+    } else {
+        String::new()
+    };
+    let type_col = if opts.no_f {
+        String::new()
+    } else {
+        format!("{:<4} ", "Type")
+    };
+    let filter_note = match opts.age_filter_note() {
+        Some(note) => format!("{}\n", note),
+        None => String::new(),
+    };
+    let columns_before_name = format!(
+        "{:<14} {}{}{}{:<6} {}{:<20} {}{}{}",
+        col0_name,
+        exact_col,
+        raw_col,
+        inodes_col,
+        "In %",
+        cum_col,
+        match (opts.histogram_style, opts.scale) {
+            (HistogramStyle::Cumulative, _) => "Histogram (cum.)",
+            (_, HistogramScale::Linear) => "Histogram",
+            (_, HistogramScale::Log) => "Histogram (log)",
+        },
+        avg_col,
+        time_col,
+        type_col,
+    );
+    // Without color, --highlight marks rows in a column of its own
+    let gutter = if opts.highlight.is_empty() || color {
+        ""
+    } else {
+        "  "
+    };
+    let name_width = match opts.max_name_width {
+        Some(0) => None,
+        Some(width) => Some(width),
+        None => columns.map(|c| {
+            c.saturating_sub(gutter.len() + columns_before_name.chars().count() + 1)
+                .max(MIN_NAME_WIDTH)
+        }),
+    };
+    let mut out = format!(
+        "Statistics of directory \"{}\" {}:\n{}\n{}{}{:<10}\n",
+        dirname, progress_str, filter_note, gutter, columns_before_name, "Name"
+    );
+
+    for ((filename, stats), cumulative) in sorted.iter().zip(cumulative) {
+        let file_size = &value(stats);
+        let exact_str = if opts.exact {
+            format!("{:>EXACT_WIDTH$} ", opts.group_digits(stats.bytes))
+        } else {
+            String::new()
+        };
+        let raw_str = if opts.show_raw {
+            format!("{:<14} ", opts.group_digits(stats.size_kb))
+        } else {
+            String::new()
+        };
+        let inodes_str = if opts.both {
+            format!("{:<14} ", opts.group_digits(stats.inodes))
+        } else {
+            String::new()
+        };
+        let cum_str = if opts.cumulative {
+            format!("{:<6.2} ", cumulative)
+        } else {
+            String::new()
+        };
+        let percentage = if total_size != 0 {
+            100.0 * (*file_size as f64) / (total_size as f64)
+        } else {
+            0.0
+        };
+        let max_marks = HISTOGRAM_MARKS * glyphs.steps();
+        let units = match opts.histogram_style {
+            HistogramStyle::PerEntry if percentage < opts.min_mark_percent => 0,
+            HistogramStyle::PerEntry => {
+                histogram_marks(*file_size, max_size, max_marks, opts.scale)
+            }
+            // The "Cum %" value, in hundredths of a percent against the
+            // full 100%
+            HistogramStyle::Cumulative => histogram_marks(
+                (cumulative * 100.0).round() as u64,
+                10_000,
+                max_marks,
+                HistogramScale::Linear,
+            ),
+        };
+        let histogram = shade(&format!("{:<20}", glyphs.render(units)), percentage, color);
+        let avg_str = if opts.avg {
+            let avg = stats
+                .avg_file_kb()
+                .map(|kb| opts.format_size(kb.round() as u64))
+                .unwrap_or_else(|| "-".to_string());
+            format!("{:<14} ", avg)
+        } else {
+            String::new()
+        };
+        let time_str = if opts.time {
+            let t = stats
+                .mtime
+                .map(|t| format_timestamp(t, opts.time_style, now))
+                .unwrap_or_else(|| "-".to_string());
+            format!("{:<20} ", t)
+        } else {
+            String::new()
+        };
+        let type_str = if opts.no_f {
+            String::new()
+        } else {
+            format!("{:<4} ", entry_type(dirname, filename))
+        };
+        let limit_str = match stats.limit_note(opts) {
+            Some(note) if stats.over_limit() => {
+                format!("  [{}]", red(&format!("{}, OVER LIMIT", note), color))
+            }
+            Some(note) => format!("  [{}]", note),
+            None => String::new(),
+        };
+        let name = format!(
+            "{:<10}",
+            match name_width {
+                Some(width) => truncate_name(filename, width),
+                None => filename.clone(),
+            }
+        );
+        let (marker, name) = match opts.is_highlighted(filename) {
+            true if color => (gutter, bold(&name, true)),
+            true => ("> ", name),
+            false => (gutter, name),
+        };
+        out.push_str(&format!(
+            "{}{:<14} {}{}{}{:<6.2} {}{} {}{}{}{}{}\n",
+            marker,
+            format_value(*file_size, opts),
+            exact_str,
+            raw_str,
+            inodes_str,
+            percentage,
+            cum_str,
+            histogram,
+            avg_str,
+            time_str,
+            type_str,
+            name,
+            limit_str
+        ));
+    }
+
+    if let Some(remainder) = remainder {
+        out.push_str(&format!(
+            "... and {} more entries not shown ({})\n",
+            remainder.count,
+            format_value(value(&remainder.stats), opts)
+        ));
+    }
+    out.push_str(&format!(
+        "\nTotal directory size: {}\n",
+        format_value(total_size, opts)
+    ));
+    if opts.both {
+        let total_inodes = counted.iter().map(|(_, s)| s.inodes).sum();
+        out.push_str(&format!(
+            "Total inodes: {}\n",
+            opts.group_digits(total_inodes)
+        ));
+    }
+    out
+}
+
+/// Length of the longest histogram bar
+pub const HISTOGRAM_MARKS: usize = 20;
+
+/// Number of marks in the histogram bar of an entry of `value`, where the
+/// largest entry is `max_value` and gets `max_marks`. Nonzero entries get at
+/// least one mark; empty entries get none.
+pub fn histogram_marks(
+    value: u64,
+    max_value: u64,
+    max_marks: usize,
+    scale: HistogramScale,
+) -> usize {
+    if value == 0 || max_value == 0 || max_marks == 0 {
+        return 0;
+    }
+    let fraction = match scale {
+        HistogramScale::Linear => value as f64 / max_value as f64,
+        HistogramScale::Log => (value as f64).ln_1p() / (max_value as f64).ln_1p(),
+    };
+    1 + ((max_marks - 1) as f64 * fraction.min(1.0)) as usize
+}
+
+/// Percentage of the total covered by each value together with all values
+/// before it, so the last one is 100 (or 0 when everything is empty)
+pub fn running_percentages(values: impl IntoIterator<Item = u64> + Clone) -> Vec<f64> {
+    let total: u64 = values.clone().into_iter().sum();
+    let mut sum = 0;
+    values
+        .into_iter()
+        .map(|v| {
+            sum += v;
+            if total == 0 {
+                0.0
+            } else {
+                100.0 * sum as f64 / total as f64
+            }
+        })
+        .collect()
+}
+
+/// Sort `(name, stats)` entries according to the chosen sort key
+/// Ties are broken by name, so the order never depends on the hash map the
+/// entries were collected in.
+pub fn sort_entries(entries: &mut [(String, EntryStats)], opts: &Options) {
+    let by_name = |a: &(String, EntryStats), b: &(String, EntryStats)| {
+        if opts.case_sensitive {
+            a.0.cmp(&b.0)
+        } else {
+            caseless_cmp(&a.0, &b.0)
+        }
+    };
+    match opts.sort {
+        SortKey::Size => entries.sort_by(|a, b| {
+            a.1.value(opts.inodes)
+                .cmp(&b.1.value(opts.inodes))
+                .then_with(|| by_name(a, b))
+        }),
+        SortKey::Time => {
+            entries.sort_by(|a, b| a.1.mtime.cmp(&b.1.mtime).then_with(|| by_name(a, b)))
+        }
+        SortKey::Name if opts.natural => {
+            entries.sort_by(|a, b| natural_cmp(&a.0, &b.0).then_with(|| by_name(a, b)))
+        }
+        SortKey::Name => entries.sort_by(by_name),
+    }
+}
+
+/// Compare two names ignoring case. Names that differ only in case fall
+/// back to plain byte order so the result is a total order.
+pub fn caseless_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .cmp(b.chars().flat_map(char::to_lowercase))
+        .then_with(|| a.cmp(b))
+}
+
+/// Fold a character for collation: lowercase and strip common Latin accents
+fn collation_fold(c: char) -> char {
+    let lower = c.to_lowercase().next().unwrap_or(c);
+    match lower {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+        'ç' | 'ć' | 'č' => 'c',
+        'ď' | 'đ' => 'd',
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => 'e',
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' => 'i',
+        'ł' | 'ľ' => 'l',
+        'ñ' | 'ń' | 'ň' => 'n',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => 'o',
+        'ř' => 'r',
+        'ß' | 'ś' | 'š' => 's',
+        'ť' => 't',
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => 'u',
+        'ý' | 'ÿ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        other => other,
+    }
+}
+
+/// Compare two names "naturally": runs of digits compare by numeric value,
+/// everything else compares case- and accent-insensitively. Names that are
+/// equal under this folding fall back to plain byte order so the result is
+/// a total order.
+pub fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+
+    let mut ai = a.chars().peekable();
+    let mut bi = b.chars().peekable();
+    loop {
+        match (ai.peek().copied(), bi.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ca), Some(cb)) if ca.is_ascii_digit() && cb.is_ascii_digit() => {
+                let mut da = String::new();
+                while let Some(c) = ai.next_if(|c| c.is_ascii_digit()) {
+                    da.push(c);
+                }
+                let mut db = String::new();
+                while let Some(c) = bi.next_if(|c| c.is_ascii_digit()) {
+                    db.push(c);
+                }
+                let ta = da.trim_start_matches('0');
+                let tb = db.trim_start_matches('0');
+                let ord = ta.len().cmp(&tb.len()).then_with(|| ta.cmp(tb));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(ca), Some(cb)) => {
+                let ord = collation_fold(ca).cmp(&collation_fold(cb));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                ai.next();
+                bi.next();
+            }
+        }
+    }
+}
+
+/// Current time as seconds since the Unix epoch
+pub fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Convert a proleptic Gregorian (year, month, day) to days since 1970-01-01
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Convert days since 1970-01-01 to a proleptic Gregorian (year, month, day)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Format epoch seconds as an ISO-8601 UTC timestamp
+pub fn format_iso8601(epoch: i64) -> String {
+    let (year, month, day) = civil_from_days(epoch.div_euclid(86_400));
+    let secs = epoch.rem_euclid(86_400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Format the age of a timestamp relative to `now`, e.g. "3 days ago".
+/// Timestamps in the future (clock skew) are treated as age zero.
+pub fn format_relative_time(epoch: i64, now: i64) -> String {
+    let age = (now - epoch).max(0);
+    let (amount, unit) = match age {
+        0..60 => return "just now".to_string(),
+        60..3_600 => (age / 60, "minute"),
+        3_600..86_400 => (age / 3_600, "hour"),
+        86_400..2_592_000 => (age / 86_400, "day"),
+        2_592_000..31_536_000 => (age / 2_592_000, "month"),
+        _ => (age / 31_536_000, "year"),
+    };
+    let plural = if amount == 1 { "" } else { "s" };
+    format!("{} {}{} ago", amount, unit, plural)
+}
+
+/// Format a modification time in the requested style
+pub fn format_timestamp(epoch: i64, style: TimeStyle, now: i64) -> String {
+    match style {
+        TimeStyle::Iso => format_iso8601(epoch),
+        TimeStyle::Relative => format_relative_time(epoch, now),
     }
+}
 
-    // Clear progress bar / live display
-    if live {
-        let lines = live_last_lines.load(Ordering::Relaxed);
-        if lines > 0 {
-            eprint!("\x1b[{}A\x1b[J", lines);
-        }
+/// Format an entry's value for display: an inode count or a size in KB
+pub fn format_value(value: u64, opts: &Options) -> String {
+    if opts.inodes {
+        opts.group_digits(value)
     } else {
-        eprint!("\r{}\r", " ".repeat(80));
+        opts.format_size(value)
     }
-    io::stderr().flush().ok();
+}
 
-    let final_results = match Arc::try_unwrap(results) {
-        Ok(mutex) => mutex.into_inner(),
-        Err(arc) => arc.lock().clone(),
+/// Width of the rendered progress bar (number of characters between the brackets).
+pub const BAR_WIDTH: usize = 40;
+
+/// Format a progress bar as a string (no trailing newline)
+pub fn format_progress_bar(current: usize, total: usize) -> String {
+    format_progress_bar_with(current, total, BarGlyphs::Char('>'))
+}
+
+/// Format a progress bar drawn with `glyphs`
+pub fn format_progress_bar_with(current: usize, total: usize, glyphs: BarGlyphs) -> String {
+    let progress = if total > 0 {
+        (current as f64 / total as f64).min(1.0)
+    } else {
+        0.0
     };
+    let filled = (BAR_WIDTH as f64 * progress * glyphs.steps() as f64) as usize;
+    let empty = BAR_WIDTH - filled.div_ceil(glyphs.steps());
+    format!(
+        "[{}{}] {}/{}",
+        glyphs.render(filled),
+        glyphs.empty().to_string().repeat(empty),
+        current,
+        total
+    )
+}
+
+/// Eighth-width blocks for the partial cell at the end of a `Blocks` bar,
+/// indexed by the number of eighths
+const PARTIAL_BLOCKS: [char; 8] = [
+    ' ', '\u{258F}', '\u{258E}', '\u{258D}', '\u{258C}', '\u{258B}', '\u{258A}', '\u{2589}',
+];
 
-    Ok(final_results)
+/// Characters used to draw a bar. Every glyph is a single-width character,
+/// so padding by character count keeps columns aligned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarGlyphs {
+    /// One character per cell
+    Char(char),
+    /// Full blocks (U+2588) with an eighth-block partial cell at the end
+    Blocks,
 }
 
-/// Calculate total size in kilobytes by walking the tree serially.
-/// The caller's rayon `par_iter` already provides top-level parallelism;
-/// using Serial here avoids nested thread-pool oversubscription.
-fn calculate_size_kb(
-    path: &Path,
-    cancelled: &AtomicBool,
-    base_dev: Option<u64>,
-    current_entry: &Mutex<String>,
-) -> u64 {
-    if path.is_file() {
-        return fs::metadata(path)
-            .map(|m| (m.blocks() * 512).div_ceil(1024))
-            .unwrap_or(0);
+impl BarGlyphs {
+    /// Number of bar units that make up one character cell
+    pub fn steps(self) -> usize {
+        match self {
+            BarGlyphs::Char(_) => 1,
+            BarGlyphs::Blocks => 8,
+        }
     }
 
-    if !path.is_dir() {
-        return 0;
+    /// Draw a bar `units` long (see `steps`)
+    pub fn render(self, units: usize) -> String {
+        match self {
+            BarGlyphs::Char(c) => c.to_string().repeat(units),
+            BarGlyphs::Blocks => {
+                let mut bar = "\u{2588}".repeat(units / 8);
+                if !units.is_multiple_of(8) {
+                    bar.push(PARTIAL_BLOCKS[units % 8]);
+                }
+                bar
+            }
+        }
     }
 
-    let mut total: u64 = 0;
-    let mut count = 0;
-    for entry in JWalkDir::new(path)
-        .parallelism(jwalk::Parallelism::Serial)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        if cancelled.load(Ordering::Relaxed) {
-            break;
+    /// Character filling the unused part of a progress bar
+    pub fn empty(self) -> char {
+        match self {
+            BarGlyphs::Char(_) => '-',
+            BarGlyphs::Blocks => '\u{2591}',
         }
-        count += 1;
-        // Fetch metadata once and reuse for both the device check and block count.
-        let meta = match entry.metadata() {
-            Ok(m) => m,
-            Err(_) => continue,
-        };
-        if let Some(dev) = base_dev {
-            if meta.dev() != dev {
-                continue;
+    }
+}
+
+/// Decide whether to color output. NO_COLOR wins over `--color always`;
+/// only CLICOLOR_FORCE overrides it, and `--color never` always applies.
+pub fn color_enabled(choice: ColorChoice, is_terminal: bool, no_color: bool, force: bool) -> bool {
+    match choice {
+        ColorChoice::Never => false,
+        _ if force => true,
+        _ if no_color => false,
+        ColorChoice::Always => true,
+        ColorChoice::Auto => is_terminal,
+    }
+}
+
+/// Wrap `text` in the ANSI escape for bold red when `color` is set
+fn red(text: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[1;31m{}\x1b[0m", text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Wrap `text` in the ANSI escape for bold when `color` is set
+fn bold(text: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[1m{}\x1b[0m", text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Upper bounds (exclusive, in percent of the total) of the green and
+/// yellow histogram bars; larger entries are red
+const SHADE_LIMITS: (f64, f64) = (1.0, 10.0);
+
+/// ANSI color for a histogram bar of an entry holding `percentage` of the
+/// total
+fn shade_code(percentage: f64) -> &'static str {
+    if percentage < SHADE_LIMITS.0 {
+        "32"
+    } else if percentage <= SHADE_LIMITS.1 {
+        "33"
+    } else {
+        "31"
+    }
+}
+
+/// Color `text` by the share of the total it stands for, when `color` is set
+fn shade(text: &str, percentage: f64, color: bool) -> String {
+    if color {
+        format!("\x1b[{}m{}\x1b[0m", shade_code(percentage), text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Print the key to the histogram colors, e.g. for `--legend`
+fn print_legend(out: &mut dyn Write) -> io::Result<()> {
+    let (low, high) = SHADE_LIMITS;
+    writeln!(
+        out,
+        "\nColors: {} < {}%, {} {}-{}%, {} > {}% of the total",
+        shade("green", 0.0, true),
+        low,
+        shade("yellow", low, true),
+        low,
+        high,
+        shade("red", 100.0, true),
+        high
+    )
+}
+
+/// Width of the `--exact` column: room for byte counts up to 999 PB with
+/// digit grouping
+const EXACT_WIDTH: usize = 23;
+
+/// Inode totals of a filesystem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InodeCapacity {
+    pub total: u64,
+    pub free: u64,
+}
+
+impl InodeCapacity {
+    /// Percentage of the filesystem's inodes that `used` inodes make up,
+    /// `None` for filesystems without a fixed inode count (btrfs reports 0)
+    pub fn percent(&self, used: u64) -> Option<f64> {
+        (self.total > 0).then(|| 100.0 * used as f64 / self.total as f64)
+    }
+}
+
+/// Space of a filesystem in bytes. `used` and `free` need not add up to
+/// `total`: `free` is what unprivileged users may still write, without the
+/// blocks reserved for root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskSpace {
+    pub total: u64,
+    pub used: u64,
+    pub free: u64,
+}
+
+impl DiskSpace {
+    /// Percentage of the filesystem in use, as `df` computes it, `None` for
+    /// filesystems that report no blocks
+    pub fn percent_used(&self) -> Option<f64> {
+        let usable = self.used + self.free;
+        (usable > 0).then(|| 100.0 * self.used as f64 / usable as f64)
+    }
+}
+
+/// `statvfs` of the filesystem containing `path`
+fn statvfs(path: &str) -> Result<libc::statvfs, DustrError> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(OsStr::new(path).as_bytes()).map_err(|_| {
+        DustrError::OsError(
+            format!("Invalid path (contains NUL): {:?}", path),
+            Some(libc::EINVAL),
+        )
+    })?;
+    // SAFETY: statvfs only writes a `statvfs` into the struct passed
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        let e = io::Error::last_os_error();
+        return Err(DustrError::from_io(
+            format!("Cannot read filesystem of '{}': {}", path, e),
+            &e,
+        ));
+    }
+    Ok(stats)
+}
+
+/// Total and free inodes of the filesystem containing `path`, from
+/// `statvfs`
+pub fn inode_capacity(path: &str) -> Result<InodeCapacity, DustrError> {
+    let stats = statvfs(path)?;
+    #[allow(clippy::unnecessary_cast)] // fsfilcnt_t is 32-bit on some targets
+    Ok(InodeCapacity {
+        total: stats.f_files as u64,
+        free: stats.f_ffree as u64,
+    })
+}
+
+/// Total, used and free space of the filesystem containing `path`, from
+/// `statvfs`
+pub fn disk_space(path: &str) -> Result<DiskSpace, DustrError> {
+    let stats = statvfs(path)?;
+    #[allow(clippy::unnecessary_cast)] // fsblkcnt_t is 32-bit on some targets
+    let block = |count| count as u64 * stats.f_frsize as u64;
+    Ok(DiskSpace {
+        total: block(stats.f_blocks),
+        used: block(stats.f_blocks.saturating_sub(stats.f_bfree)),
+        free: block(stats.f_bavail),
+    })
+}
+
+/// Undo the octal escapes (`\040` for a space) of a `/proc/self/mountinfo`
+/// field
+pub(crate) fn unescape_mount_field(field: &str) -> String {
+    let mut out = Vec::with_capacity(field.len());
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = bytes.get(i + 1..i + 4).and_then(|digits| {
+            let digits = std::str::from_utf8(digits).ok()?;
+            u8::from_str_radix(digits, 8).ok()
+        });
+        match (bytes[i], code) {
+            (b'\\', Some(code)) => {
+                out.push(code);
+                i += 4;
             }
-        }
-        if entry.file_type().is_dir() {
-            if count % 100 == 0 {
-                *current_entry.lock() = entry.path().to_string_lossy().to_string();
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
             }
-        } else if entry.file_type().is_file() {
-            total += (meta.blocks() * 512).div_ceil(1024);
         }
     }
-    total
+    String::from_utf8_lossy(&out).into_owned()
 }
 
-/// Count inodes by walking the tree serially.
-/// The caller's rayon `par_iter` already provides top-level parallelism;
-/// using Serial here avoids nested thread-pool oversubscription.
-fn count_inodes(
-    path: &Path,
-    cancelled: &AtomicBool,
-    base_dev: Option<u64>,
-    current_entry: &Mutex<String>,
-) -> u64 {
-    if !path.is_dir() {
-        return 1;
+/// The device or source (`/dev/nvme0n1p2`, `tmpfs`, a server export) of the
+/// filesystem containing `path`, as `df` shows it, from
+/// `/proc/self/mountinfo`. Of several mounts of the filesystem, the one
+/// closest above `path` wins. `None` where there is no mount table to read.
+pub fn filesystem_source(path: &str) -> Option<String> {
+    let dev = fs::metadata(path).ok()?.dev();
+    let device = format!("{}:{}", libc::major(dev), libc::minor(dev));
+    let path = fs::canonicalize(path).ok()?;
+    let table = fs::read_to_string("/proc/self/mountinfo").ok()?;
+    table
+        .lines()
+        .filter_map(|line| {
+            // ID PARENT MAJOR:MINOR ROOT MOUNT-POINT ... - TYPE SOURCE ...
+            let (mount, fs) = line.split_once(" - ")?;
+            let mount: Vec<&str> = mount.split(' ').collect();
+            let mount_point = PathBuf::from(unescape_mount_field(mount.get(4)?));
+            let source = fs.split(' ').nth(1)?;
+            (mount.get(2) == Some(&device.as_str()) && path.starts_with(&mount_point))
+                .then(|| (mount_point, unescape_mount_field(source)))
+        })
+        .max_by_key(|(mount_point, _)| mount_point.components().count())
+        .map(|(_, source)| source)
+}
+
+/// Narrowest the Name column is shrunk to when fitting the terminal width
+const MIN_NAME_WIDTH: usize = 10;
+
+/// Width in columns of the terminal `fd` refers to, falling back to the
+/// COLUMNS variable
+pub fn terminal_columns(fd: libc::c_int) -> Option<usize> {
+    // SAFETY: TIOCGWINSZ only writes a `winsize` into the struct passed
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0 && size.ws_col > 0 {
+        return Some(size.ws_col as usize);
     }
+    std::env::var("COLUMNS")
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|&columns| columns > 0)
+}
 
-    let mut count: u64 = 0;
-    let mut iter_count = 0;
-    for entry in JWalkDir::new(path)
-        .parallelism(jwalk::Parallelism::Serial)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        if cancelled.load(Ordering::Relaxed) {
-            break;
-        }
-        iter_count += 1;
-        if let Some(dev) = base_dev {
-            match entry.metadata() {
-                Ok(m) => {
-                    if m.dev() != dev {
-                        continue;
-                    }
-                }
-                Err(_) => continue,
-            }
+/// Shorten `name` to at most `width` characters, ending with an ellipsis
+/// when anything was cut
+pub fn truncate_name(name: &str, width: usize) -> String {
+    if name.chars().count() <= width {
+        return name.to_string();
+    }
+    let ellipsis = if unicode_output() { "\u{2026}" } else { "..." };
+    let ellipsis_len = ellipsis.chars().count();
+    if width <= ellipsis_len {
+        return name.chars().take(width).collect();
+    }
+    let mut short: String = name.chars().take(width - ellipsis_len).collect();
+    short.push_str(ellipsis);
+    short
+}
+
+/// Whether the locale (LC_ALL, LC_CTYPE or LANG) uses UTF-8, so output can
+/// contain non-ASCII glyphs
+pub fn unicode_output() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .is_some_and(|locale| {
+            let locale = locale.to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+}
+
+/// Frames cycled through by the indeterminate progress spinner
+const SPINNER_FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Format an indeterminate spinner with the number of entries done so far.
+/// The frame advances every 10 entries, matching the progress update rate.
+pub fn format_spinner(current: usize) -> String {
+    format!(
+        "[{}] {}",
+        SPINNER_FRAMES[current / 10 % SPINNER_FRAMES.len()],
+        current
+    )
+}
+
+/// Format a progress bar when the total is known, a spinner otherwise
+pub fn format_progress(current: usize, total: Option<usize>, glyphs: BarGlyphs) -> String {
+    match total {
+        Some(total) => format_progress_bar_with(current, total, glyphs),
+        None => format_spinner(current),
+    }
+}
+
+/// Print a progress bar (or a spinner when the total is unknown) to stderr
+pub fn print_progress(
+    current: usize,
+    total: Option<usize>,
+    current_entry: Option<&str>,
+    glyphs: BarGlyphs,
+) {
+    let bar = format_progress(current, total, glyphs);
+
+    match current_entry {
+        Some(name) => {
+            let max_name_len = 30;
+            let display_name = if name.len() > max_name_len {
+                format!("{}...", &name[..max_name_len - 3])
+            } else {
+                name.to_string()
+            };
+            status(format_args!(
+                "\r{blank}\r{bar} {name}",
+                blank = " ".repeat(80),
+                bar = bar,
+                name = display_name,
+            ));
         }
-        if entry.file_type().is_dir() && iter_count % 100 == 0 {
-            *current_entry.lock() = entry.path().to_string_lossy().to_string();
+        None => {
+            status(format_args!("\r{}", bar));
         }
-        count += 1;
     }
-    count
+    io::stderr().flush().ok();
 }
 
-/// Render the statistics table as a string (used for live display)
-pub fn render_stats_table(
-    dirname: &str,
-    entries: &[(String, u64)],
-    inodes: bool,
-    no_grouping: bool,
-    current: usize,
-    total: usize,
-) -> String {
-    let max_marks = 20;
-    let mut sorted: Vec<(String, u64)> = entries.to_vec();
-    sorted.sort_by_key(|k| k.1);
+/// Print the progress bar with a second line showing the walk inside the
+/// current entry, leaving the cursor on the bar's line
+fn print_walk_progress(bar: String, walk: &str) {
+    let walk = truncate_name(walk, BAR_WIDTH + 30);
+    status(format_args!("\r\x1b[K{}\n\x1b[K{}\x1b[1A\r", bar, walk));
+    io::stderr().flush().ok();
+}
 
-    let total_size: u64 = sorted.iter().map(|(_, s)| s).sum();
-    let max_size = sorted.iter().map(|(_, s)| s).max().copied().unwrap_or(0);
+/// Wipe the progress bar, and the walk line below it when one was drawn
+fn clear_progress(walk_line_shown: bool) {
+    if walk_line_shown {
+        status(format_args!("\r\x1b[K\n\x1b[K\x1b[1A"));
+    } else {
+        status(format_args!("\r{}\r", " ".repeat(80)));
+    }
+    io::stderr().flush().ok();
+}
 
-    let col0_name = if inodes { "inodes" } else { "Size" };
-    let mut out = format!(
-        "Statistics of directory \"{}\" ({}/{}):\n\n{:<14} {:<6} {:<20} {:<10}\n",
-        dirname, current, total, col0_name, "In %", "Histogram", "Name"
-    );
+/// Write progress output to stderr. Unlike `eprint!`, this does not panic
+/// when stderr is a pipe whose reader has gone away.
+fn status(args: std::fmt::Arguments<'_>) {
+    let _ = io::stderr().write_fmt(args);
+}
 
-    for (filename, file_size) in &sorted {
-        let nmarks = if max_size != 0 {
-            ((max_marks - 1) as f64 * (*file_size as f64) / (max_size as f64)) as usize + 1
-        } else {
-            max_marks
-        };
-        let percentage = if total_size != 0 {
-            100.0 * (*file_size as f64) / (total_size as f64)
-        } else {
-            0.0
-        };
-        let size_str = if inodes {
-            if no_grouping {
-                file_size.to_string()
-            } else {
-                format_with_grouping(*file_size)
-            }
-        } else {
-            format_size(*file_size)
-        };
-        let histogram = "#".repeat(nmarks);
-        out.push_str(&format!(
-            "{:<14} {:<6.2} {:<20} {:<10}\n",
-            size_str, percentage, histogram, filename
-        ));
+/// Whether `path` is a symlink whose target does not exist
+pub fn is_broken_symlink(path: &Path) -> bool {
+    path.is_symlink() && matches!(fs::metadata(path), Err(e) if e.kind() == io::ErrorKind::NotFound)
+}
+
+/// What a path refers to, with symlinks classified by their target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathKind {
+    File,
+    Dir,
+    /// A symlink to anything but a directory
+    Symlink,
+    /// A symlink to a directory, which can be traversed
+    SymlinkDir,
+    BrokenSymlink,
+    Socket,
+    Fifo,
+    /// A block or character device
+    Device,
+    /// Nothing exists at the path
+    Missing,
+}
+
+impl PathKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PathKind::File => "file",
+            PathKind::Dir => "dir",
+            PathKind::Symlink => "symlink",
+            PathKind::SymlinkDir => "symlink-dir",
+            PathKind::BrokenSymlink => "broken-symlink",
+            PathKind::Socket => "socket",
+            PathKind::Fifo => "fifo",
+            PathKind::Device => "device",
+            PathKind::Missing => "missing",
+        }
     }
+}
 
-    let total_str = if inodes {
-        if no_grouping {
-            total_size.to_string()
-        } else {
-            format_with_grouping(total_size)
+/// Classify a path without following it, except to tell where a symlink
+/// leads
+pub fn classify_path(path: &str) -> PathKind {
+    let p = Path::new(path);
+    let Ok(meta) = fs::symlink_metadata(p) else {
+        return PathKind::Missing;
+    };
+    let file_type = meta.file_type();
+    if file_type.is_symlink() {
+        match fs::metadata(p) {
+            Ok(target) if target.is_dir() => PathKind::SymlinkDir,
+            Ok(_) => PathKind::Symlink,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => PathKind::BrokenSymlink,
+            // A target we may not look at still exists
+            Err(_) => PathKind::Symlink,
         }
+    } else if file_type.is_dir() {
+        PathKind::Dir
+    } else if file_type.is_socket() {
+        PathKind::Socket
+    } else if file_type.is_fifo() {
+        PathKind::Fifo
+    } else if file_type.is_block_device() || file_type.is_char_device() {
+        PathKind::Device
     } else {
-        format_size(total_size)
+        PathKind::File
+    }
+}
+
+/// Get file type indicator, following `ls -F`: `@!` for broken symlinks,
+/// `@/` for symlinks to directories, `@` for other symlinks, `/` for
+/// directories, `*` for executables, `=` for sockets, `|` for FIFOs, empty
+/// for other files and for missing paths
+pub fn get_file_type_indicator(path: &str) -> String {
+    let indicator = match classify_path(path) {
+        PathKind::BrokenSymlink => "@!",
+        PathKind::SymlinkDir => "@/",
+        PathKind::Symlink => "@",
+        PathKind::Dir => "/",
+        PathKind::Socket => "=",
+        PathKind::Fifo => "|",
+        PathKind::File if fs::metadata(path).is_ok_and(|meta| meta.mode() & 0o111 != 0) => "*",
+        PathKind::File | PathKind::Device | PathKind::Missing => "",
     };
-    out.push_str(&format!("\nTotal directory size: {}\n", total_str));
-    out
+    indicator.to_string()
 }
 
-/// Width of the rendered progress bar (number of characters between the brackets).
-pub const BAR_WIDTH: usize = 40;
+/// The rows of a report on `dirname`: all `entries`, or with `--only-dirs`
+/// just the directories and symlinks to them
+pub(crate) fn shown_entries(
+    dirname: &str,
+    entries: &[(String, EntryStats)],
+    opts: &Options,
+) -> Vec<(String, EntryStats)> {
+    entries
+        .iter()
+        .filter(|(name, _)| !opts.only_dirs || entry_type(dirname, name).ends_with('/'))
+        .cloned()
+        .collect()
+}
 
-/// Format a progress bar as a string (no trailing newline)
-pub fn format_progress_bar(current: usize, total: usize) -> String {
-    let progress = if total > 0 {
-        current as f64 / total as f64
+/// Number of top-level entries hidden by filters: those the scan left out
+/// and, with `--only-dirs`, the files among `entries` that are not `shown`
+pub(crate) fn filtered_count(
+    scan: &ScanResult,
+    entries: &[(String, EntryStats)],
+    shown: &[(String, EntryStats)],
+) -> u64 {
+    scan.filtered + (entries.len() - shown.len()) as u64
+}
+
+/// The note on entries hidden by filters, if any
+pub(crate) fn filtered_note(count: u64, opts: &Options) -> Option<String> {
+    let plural = if count == 1 { "entry" } else { "entries" };
+    (count > 0).then(|| {
+        format!(
+            "({} {} hidden by filters)",
+            opts.group_digits(count),
+            plural
+        )
+    })
+}
+
+/// The entries counted in the total: the shown rows, or all `entries` with
+/// `--total-all`
+pub(crate) fn counted_entries<'a>(
+    entries: &'a [(String, EntryStats)],
+    shown: &'a [(String, EntryStats)],
+    opts: &Options,
+) -> &'a [(String, EntryStats)] {
+    if opts.total_all {
+        entries
     } else {
-        0.0
+        shown
+    }
+}
+
+/// Name of the `--show-root` row for the whole directory
+pub const ROOT_ROW_NAME: &str = ".";
+
+/// Name of the row standing for the entries left out by `--top` with
+/// `--aggregate-remainder`
+pub const REMAINDER_NAME: &str = "<other>";
+
+/// Entries left out of a report by `--top`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Remainder {
+    pub count: usize,
+    /// Their combined usage
+    pub stats: EntryStats,
+}
+
+/// Apply `--top` to `shown`, which is in display order: keep the N largest
+/// rows in that order and return the others as a `Remainder`. With
+/// `--aggregate-remainder` they become a `<other>` row instead, sorted in
+/// among the rest.
+pub(crate) fn top_entries(
+    shown: Vec<(String, EntryStats)>,
+    opts: &Options,
+) -> (Vec<(String, EntryStats)>, Option<Remainder>) {
+    let Some(top) = opts.top.filter(|&top| top < shown.len()) else {
+        return (shown, None);
     };
-    let filled = (BAR_WIDTH as f64 * progress) as usize;
-    let empty = BAR_WIDTH - filled;
+    let mut by_size: Vec<usize> = (0..shown.len()).collect();
+    by_size.sort_by(|&a, &b| {
+        let value = |i: usize| shown[i].1.value(opts.inodes);
+        value(b)
+            .cmp(&value(a))
+            .then_with(|| shown[a].0.cmp(&shown[b].0))
+    });
+    let mut kept = vec![false; shown.len()];
+    for &i in &by_size[..top] {
+        kept[i] = true;
+    }
+    let mut rows = Vec::with_capacity(top + 1);
+    let mut remainder = Remainder::default();
+    for ((name, stats), kept) in shown.into_iter().zip(kept) {
+        if kept {
+            rows.push((name, stats));
+        } else {
+            remainder.count += 1;
+            remainder.stats.absorb(&stats);
+        }
+    }
+    if opts.aggregate_remainder {
+        rows.push((REMAINDER_NAME.to_string(), remainder.stats));
+        sort_entries(&mut rows, opts);
+        return (rows, None);
+    }
+    (rows, Some(remainder))
+}
+
+/// Type indicator of the entry `name` of `dirname`, for the Type column
+pub(crate) fn entry_type(dirname: &str, name: &str) -> String {
+    get_file_type_indicator(&Path::new(dirname).join(name).to_string_lossy())
+}
+
+/// Format size with units (KB, MB, GB, TB) using `precision` decimal places.
+/// Values are rounded to the nearest representable digit, not truncated.
+pub fn format_size(size_kb: u64, precision: usize) -> String {
+    if size_kb >= 1_000_000_000 {
+        let tb = size_kb as f64 / 1_000_000_000.0;
+        format!("{:.*} TB", precision, tb)
+    } else if size_kb >= 1_000_000 {
+        let gb = size_kb as f64 / 1_000_000.0;
+        format!("{:.*} GB", precision, gb)
+    } else if size_kb >= 1_000 {
+        let mb = size_kb as f64 / 1_000.0;
+        format!("{:.*} MB", precision, mb)
+    } else {
+        let kb = size_kb as f64;
+        format!("{:.*} KB", precision, kb)
+    }
+}
+
+/// Unit labels of `format_size`, starting at KB
+const KB_UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+
+/// Format a size in KB in `unit` whatever its magnitude, with the labels of
+/// `format_size`
+pub fn format_size_in(size_kb: u64, unit: SizeUnit, precision: usize) -> String {
+    format_in_unit(
+        size_kb as f64,
+        1000.0,
+        &KB_UNITS,
+        unit.power() - 1,
+        precision,
+    )
+}
+
+/// Format `value` in `units[power]`, i.e. divided by `base` `power` times
+fn format_in_unit(value: f64, base: f64, units: &[&str], power: usize, precision: usize) -> String {
     format!(
-        "[{}{}] {}/{}",
-        ">".repeat(filled),
-        "-".repeat(empty),
-        current,
-        total
+        "{:.*} {}",
+        precision,
+        value / base.powi(power as i32),
+        units[power]
     )
 }
 
-/// Print a progress bar to stderr
-pub fn print_progress(current: usize, total: usize, current_entry: Option<&str>) {
-    let bar = format_progress_bar(current, total);
+/// Decimal unit labels for `--si`, starting at bytes
+const SI_UNITS: [&str; 7] = ["B", "kB", "MB", "GB", "TB", "PB", "EB"];
+
+/// Binary unit labels for `--binary`, starting at bytes
+const BINARY_UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// Format a size in KB (1024 bytes) in powers of 1000: kB, MB, GB, ...
+pub fn format_size_si(size_kb: u64, precision: usize) -> String {
+    format_scaled(size_kb as f64 * 1024.0, 1000.0, &SI_UNITS, precision)
+}
+
+/// Format a size in KB (1024 bytes) in powers of 1024: KiB, MiB, GiB, ...
+pub fn format_size_binary(size_kb: u64, precision: usize) -> String {
+    format_scaled(size_kb as f64 * 1024.0, 1024.0, &BINARY_UNITS, precision)
+}
+
+/// Scale `bytes` to the largest unit it reaches. A value that would round
+/// up to `base` (e.g. "1000.0 kB") is shown in the next unit instead.
+fn format_scaled(bytes: f64, base: f64, units: &[&str], precision: usize) -> String {
+    let mut value = bytes;
+    let mut unit = 0;
+    let rounds_to_base = |v: f64| format!("{:.*}", precision, v).parse::<f64>() == Ok(base);
+    while unit + 1 < units.len() && (value >= base || rounds_to_base(value)) {
+        value /= base;
+        unit += 1;
+    }
+    format!("{:.*} {}", precision, value, units[unit])
+}
+
+/// A fixed output unit for `-B`/`--block-size`, following `du`: a unit
+/// letter (K, M, G, T, P, E) means powers of 1024 and is printed after each
+/// size, an added "B" (KB, MB) means powers of 1000, and a leading number
+/// ("4K", "512") sets the block size without printing a suffix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSize {
+    pub bytes: u64,
+    pub suffix: String,
+}
+
+impl BlockSize {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let text = s.trim();
+        let split = text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len());
+        let (number, unit) = text.split_at(split);
+        let count: Option<u64> = if number.is_empty() {
+            None
+        } else {
+            Some(
+                number
+                    .parse()
+                    .map_err(|_| format!("invalid block size {:?}", s))?,
+            )
+        };
+        let (scale, suffix) = match unit.to_ascii_uppercase().as_str() {
+            "" if count.is_some() => (1, String::new()),
+            "" => return Err(format!("invalid block size {:?}", s)),
+            upper => {
+                let letter = upper.chars().next().unwrap_or_default();
+                let power = "KMGTPE"
+                    .find(letter)
+                    .ok_or_else(|| format!("unknown block size unit {:?} in {:?}", unit, s))?
+                    as u32
+                    + 1;
+                match &upper[1..] {
+                    "" => (1024u64.pow(power), letter.to_string()),
+                    "IB" => (1024u64.pow(power), format!("{}iB", letter)),
+                    "B" if letter == 'K' => (1000u64.pow(power), "kB".to_string()),
+                    "B" => (1000u64.pow(power), format!("{}B", letter)),
+                    _ => return Err(format!("unknown block size unit {:?} in {:?}", unit, s)),
+                }
+            }
+        };
+        let bytes = count
+            .unwrap_or(1)
+            .checked_mul(scale)
+            .filter(|&b| b > 0)
+            .ok_or_else(|| format!("invalid block size {:?}", s))?;
+        let suffix = if count.is_some() {
+            String::new()
+        } else {
+            suffix
+        };
+        Ok(BlockSize { bytes, suffix })
+    }
+
+    /// A size in KB as a whole number of blocks, rounded up like `du`
+    pub fn format(&self, size_kb: u64) -> String {
+        let blocks = (size_kb as u128 * 1024).div_ceil(self.bytes as u128);
+        format!("{}{}", blocks, self.suffix)
+    }
+}
+
+/// Parse a human-readable size such as "500G", "1.5 TB" or "100m" into
+/// kilobytes. Units follow `format_size` (K, M, G, T, each 1000 times the
+/// previous); a bare number is taken as kilobytes.
+pub fn parse_size_arg(s: &str) -> Result<u64, String> {
+    let text = s.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size {:?} (expected e.g. 500M or 1.5G)", s))?;
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "k" | "kb" => 1.0,
+        "m" | "mb" => 1e3,
+        "g" | "gb" => 1e6,
+        "t" | "tb" => 1e9,
+        other => return Err(format!("unknown size unit {:?} in {:?}", other, s)),
+    };
+    Ok((number * multiplier).round() as u64)
+}
 
-    match current_entry {
-        Some(name) => {
-            let max_name_len = 30;
-            let display_name = if name.len() > max_name_len {
-                format!("{}...", &name[..max_name_len - 3])
-            } else {
-                name.to_string()
-            };
-            eprint!(
-                "\r{blank}\r{bar} {name}",
-                blank = " ".repeat(80),
-                bar = bar,
-                name = display_name,
-            );
-        }
-        None => {
-            eprint!("\r{}", bar);
-        }
-    }
-    io::stderr().flush().ok();
+/// How the digits of large counts are grouped, e.g. "1,234,567"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DigitGrouping {
+    pub separator: char,
+    /// Group the last three digits, then pairs ("12,34,567"), as in India
+    pub indian: bool,
 }
 
-/// Get file type indicator (@ for symlinks, / for directories, empty for files)
-pub fn get_file_type_indicator(path: &str) -> String {
-    let p = Path::new(path);
+impl DigitGrouping {
+    /// Used when the locale is unset, "C"/"POSIX", or not recognized
+    pub const DEFAULT: DigitGrouping = DigitGrouping {
+        separator: '\'',
+        indian: false,
+    };
 
-    if p.is_symlink() {
-        "@".to_string()
-    } else if p.is_dir() {
-        "/".to_string()
-    } else {
-        "".to_string()
+    /// The grouping of the numeric locale, taken from the first non-empty
+    /// of LC_ALL, LC_NUMERIC and LANG
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|locale| Self::for_locale(&locale))
+            .unwrap_or(Self::DEFAULT)
     }
-}
 
-/// Format size with units (KB, MB, GB, TB)
-pub fn format_size(size_kb: u64) -> String {
-    if size_kb >= 1_000_000_000 {
-        let tb = size_kb as f64 / 1_000_000_000.0;
-        format!("{:.1} TB", tb)
-    } else if size_kb >= 1_000_000 {
-        let gb = size_kb as f64 / 1_000_000.0;
-        format!("{:.1} GB", gb)
-    } else if size_kb >= 1_000 {
-        let mb = size_kb as f64 / 1_000.0;
-        format!("{:.1} MB", mb)
-    } else {
-        let kb = size_kb as f64;
-        format!("{:.1} KB", kb)
+    /// The grouping for a locale name such as "de_DE.UTF-8"
+    pub fn for_locale(locale: &str) -> Option<Self> {
+        let name = locale.split(['.', '@']).next().unwrap_or_default();
+        let (language, territory) = name.split_once('_').unwrap_or((name, ""));
+        let separator = match (language, territory) {
+            (_, "IN") => {
+                return Some(DigitGrouping {
+                    separator: ',',
+                    indian: true,
+                })
+            }
+            (_, "CH") => '\u{2019}',
+            ("en" | "zh" | "ja" | "ko" | "th" | "he" | "hi", _) => ',',
+            (
+                "de" | "es" | "it" | "nl" | "pt" | "id" | "da" | "tr" | "el" | "ro" | "hr" | "sl"
+                | "sr" | "vi",
+                _,
+            ) => '.',
+            // French uses a narrow no-break space
+            ("fr", _) => '\u{202F}',
+            (
+                "ru" | "pl" | "cs" | "sk" | "sv" | "nb" | "nn" | "no" | "fi" | "uk" | "hu" | "bg"
+                | "et" | "lv" | "lt",
+                _,
+            ) => '\u{A0}',
+            _ => return None,
+        };
+        Some(DigitGrouping {
+            separator,
+            indian: false,
+        })
     }
 }
 
-/// Format a number with thousand separators
-pub fn format_with_grouping(num: u64) -> String {
+/// Format a number with digit group separators
+pub fn format_with_grouping(num: u64, grouping: DigitGrouping) -> String {
     let s = num.to_string();
     let mut result = String::new();
     let len = s.len();
 
     for (i, c) in s.chars().enumerate() {
-        if i > 0 && (len - i).is_multiple_of(3) {
-            result.push('\'');
+        let remaining = len - i;
+        let boundary = if grouping.indian {
+            remaining == 3 || (remaining > 3 && (remaining - 3).is_multiple_of(2))
+        } else {
+            remaining.is_multiple_of(3)
+        };
+        if i > 0 && boundary {
+            result.push(grouping.separator);
         }
         result.push(c);
     }
@@ -544,126 +4950,1093 @@ pub fn json_escape(s: &str) -> String {
     result
 }
 
+/// Escape a string for use as a Prometheus label value.
+pub fn prometheus_escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '\n' => result.push_str("\\n"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Write scan results in the Prometheus text exposition format.
+/// Values are raw bytes and counts so they can be scraped by node_exporter's
+/// textfile collector.
+pub fn write_prometheus(out: &mut dyn Write, dirname: &str, scan: &ScanResult) -> io::Result<()> {
+    let root = prometheus_escape(dirname);
+    let mut names: Vec<&String> = scan.entries.keys().collect();
+    names.sort();
+
+    writeln!(
+        out,
+        "# HELP dustr_directory_bytes Disk usage of a top-level entry in bytes."
+    )?;
+    writeln!(out, "# TYPE dustr_directory_bytes gauge")?;
+    for name in &names {
+        writeln!(
+            out,
+            "dustr_directory_bytes{{root=\"{}\",entry=\"{}\"}} {}",
+            root,
+            prometheus_escape(name),
+            scan.entries[*name].bytes
+        )?;
+    }
+
+    writeln!(
+        out,
+        "# HELP dustr_directory_inodes Number of inodes in a top-level entry."
+    )?;
+    writeln!(out, "# TYPE dustr_directory_inodes gauge")?;
+    for name in &names {
+        writeln!(
+            out,
+            "dustr_directory_inodes{{root=\"{}\",entry=\"{}\"}} {}",
+            root,
+            prometheus_escape(name),
+            scan.entries[*name].inodes
+        )?;
+    }
+
+    writeln!(
+        out,
+        "# HELP dustr_scan_duration_seconds Time taken to scan the directory."
+    )?;
+    writeln!(out, "# TYPE dustr_scan_duration_seconds gauge")?;
+    writeln!(
+        out,
+        "dustr_scan_duration_seconds{{root=\"{}\"}} {:.6}",
+        root,
+        scan.duration.as_secs_f64()
+    )?;
+
+    writeln!(
+        out,
+        "# HELP dustr_scan_errors_total Paths that could not be read during the scan."
+    )?;
+    writeln!(out, "# TYPE dustr_scan_errors_total counter")?;
+    writeln!(
+        out,
+        "dustr_scan_errors_total{{root=\"{}\"}} {}",
+        root, scan.errors
+    )?;
+    Ok(())
+}
+
+/// Destination of a report: stdout, or a file that is written to a temporary
+/// path first and renamed into place so readers never see a partial report.
+pub struct Output {
+    writer: Box<dyn Write>,
+    target: Option<(PathBuf, PathBuf)>,
+}
+
+impl Output {
+    /// Open the report destination; `None` means stdout
+    pub fn open(path: Option<&str>) -> Result<Self, DustrError> {
+        match path {
+            None => Ok(Output {
+                writer: Box::new(io::stdout().lock()),
+                target: None,
+            }),
+            Some(path) => {
+                let dest = PathBuf::from(path);
+                let mut tmp_name = dest.file_name().unwrap_or_default().to_os_string();
+                tmp_name.push(format!(".{}.tmp", std::process::id()));
+                let tmp = dest.with_file_name(tmp_name);
+                let file = fs::File::create(&tmp).map_err(|e| {
+                    DustrError::from_io(format!("Cannot create output file '{}': {}", path, e), &e)
+                })?;
+                Ok(Output {
+                    writer: Box::new(BufWriter::new(file)),
+                    target: Some((tmp, dest)),
+                })
+            }
+        }
+    }
+
+    /// Whether the report goes to a terminal
+    pub fn is_terminal(&self) -> bool {
+        self.target.is_none() && io::stdout().is_terminal()
+    }
+
+    /// Flush the report and move it into place
+    pub fn finish(mut self) -> Result<(), DustrError> {
+        self.writer.flush()?;
+        drop(self.writer);
+        if let Some((tmp, dest)) = self.target {
+            fs::rename(&tmp, &dest)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
 /// Print the complete disk usage analysis
-#[allow(clippy::too_many_arguments)]
-pub fn print_disk_usage(
+pub fn print_disk_usage(dirname: &str, opts: &Options) -> Result<(), DustrError> {
+    report_disk_usage(dirname, opts, None)
+}
+
+/// One `--watch` refresh: `print_disk_usage`, marking the `--recent` files
+/// that earlier refreshes did not list
+pub fn print_watch_cycle(
     dirname: &str,
-    inodes: bool,
-    no_grouping: bool,
-    no_f: bool,
-    json: bool,
-    cross_mounts: bool,
-    verbose: bool,
-    live: bool,
+    opts: &Options,
+    state: &mut WatchState,
 ) -> Result<(), DustrError> {
-    let max_marks = 20;
+    report_disk_usage(dirname, opts, Some(state))
+}
 
-    // Calculate file sizes
-    let mut file_sizes: Vec<(String, u64)> = Vec::new();
+fn report_disk_usage(
+    dirname: &str,
+    opts: &Options,
+    watch: Option<&mut WatchState>,
+) -> Result<(), DustrError> {
+    let start = Instant::now();
+    let mut scan = match opts.with_threads(|| scan_directory(dirname, opts))? {
+        // Report what was gathered, then fail with the truncation
+        Err(DustrError::Truncated(scan)) => *scan,
+        result => result?,
+    };
+    if let (Some(state), Some(recent)) = (watch, &mut scan.recent) {
+        state.mark_new(recent);
+    }
+    match (&opts.treemap, &scan.treemap) {
+        (Some(dest), Some(tree)) => crate::treemap::write_treemap(dest, tree, opts)?,
+        (Some(_), None) if scan.truncated.is_some() => {
+            diagnostics::warn("treemap not written: the scan was truncated")
+        }
+        (Some(_), None) => {
+            return Err(DustrError::NotADirectory(
+                format!("Not a directory: {}", dirname),
+                Some(libc::ENOTDIR),
+            ))
+        }
+        (None, _) => {}
+    }
+    write_report(dirname, opts, &scan)?;
+    if opts.timing {
+        status(format_args!(
+            "Scanned in {:.2}s\n",
+            start.elapsed().as_secs_f64()
+        ));
+    }
+    match scan.truncated {
+        Some(_) => Err(DustrError::Truncated(Box::new(scan))),
+        None => Ok(()),
+    }
+}
 
-    let raw_sizes = calculate_directory_sizes(dirname, inodes, cross_mounts, verbose, live)?;
-    for (filename, size) in raw_sizes {
-        let mut display_name = filename.clone();
+/// Print the report for a finished (or truncated) scan
+fn write_report(dirname: &str, opts: &Options, scan: &ScanResult) -> Result<(), DustrError> {
+    let mut out = Output::open(opts.output.as_deref())?;
+    let color = opts.use_color(out.is_terminal());
+    let columns = out
+        .is_terminal()
+        .then(|| terminal_columns(libc::STDOUT_FILENO))
+        .flatten();
 
-        if !no_f {
-            let full_path = Path::new(dirname).join(&filename);
-            let indicator = get_file_type_indicator(&full_path.to_string_lossy());
-            display_name.push_str(&indicator);
-        }
+    if opts.prometheus {
+        write_prometheus(&mut out, dirname, scan)?;
+        return out.finish();
+    }
+
+    let inodes = opts.inodes;
 
-        file_sizes.push((display_name, size));
+    if opts.format == ReportFormat::Du {
+        let mut entries: Vec<(String, EntryStats)> = scan
+            .entries
+            .iter()
+            .map(|(name, stats)| (name.clone(), *stats))
+            .collect();
+        sort_entries(&mut entries, opts);
+        crate::export::write_du(&mut out, dirname, &entries, opts)?;
+        out.finish()?;
+        return check_strict_limits(&entries, opts);
     }
 
-    // Sort by size
-    file_sizes.sort_by_key(|k| k.1);
+    // A plain file (or a symlink to one) gets a one-line report
+    if !opts.json && !Path::new(dirname).is_dir() {
+        let stats = scan.entries.values().next().copied().unwrap_or_default();
+        let link_note = match fs::read_link(dirname) {
+            Ok(target) => format!(" (symlink to \"{}\")", target.to_string_lossy()),
+            Err(_) => String::new(),
+        };
+        let label = if inodes { "Inodes" } else { "Size" };
+        writeln!(
+            out,
+            "{} of file \"{}\"{}: {}",
+            label,
+            dirname,
+            link_note,
+            format_value(stats.value(inodes), opts)
+        )?;
+        return out.finish();
+    }
 
-    let total_size: u64 = file_sizes.iter().map(|(_, s)| s).sum();
+    let mut file_sizes: Vec<(String, EntryStats)> = scan
+        .entries
+        .iter()
+        .map(|(name, stats)| (name.clone(), *stats))
+        .collect();
+    sort_entries(&mut file_sizes, opts);
 
-    if json {
+    if opts.json {
+        let all_shown = shown_entries(dirname, &file_sizes, opts);
+        let counted = counted_entries(&file_sizes, &all_shown, opts);
+        let total_size: u64 = counted.iter().map(|(_, s)| s.value(inodes)).sum();
+        let (shown, remainder) = top_entries(all_shown.clone(), opts);
         let mode = if inodes { "inodes" } else { "size" };
-        println!("{{");
-        println!("  \"directory\": \"{}\",", json_escape(dirname));
-        println!("  \"mode\": \"{}\",", mode);
-        println!("  \"entries\": [");
-        for (i, (name, size)) in file_sizes.iter().enumerate() {
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"directory\": \"{}\",", json_escape(dirname))?;
+        writeln!(out, "  \"dustr_version\": \"{}\",", VERSION)?;
+        writeln!(out, "  \"format_version\": {},", FORMAT_VERSION)?;
+        writeln!(out, "  \"mode\": \"{}\",", mode)?;
+        writeln!(out, "  \"entries\": [")?;
+        let cumulative = running_percentages(shown.iter().map(|(_, s)| s.value(inodes)));
+        for (i, (name, stats)) in shown.iter().enumerate() {
+            let size = &stats.value(inodes);
             let percentage = if total_size != 0 {
                 100.0 * (*size as f64) / (total_size as f64)
             } else {
                 0.0
             };
-            let comma = if i + 1 < file_sizes.len() { "," } else { "" };
-            println!(
-                "    {{\"name\": \"{}\", \"value\": {}, \"percentage\": {:.2}}}{}",
+            let comma = if i + 1 < shown.len() { "," } else { "" };
+            let mtime = match (opts.time, stats.mtime) {
+                (true, Some(t)) => format!(", \"mtime\": {}", t),
+                (true, None) => ", \"mtime\": null".to_string(),
+                (false, _) => String::new(),
+            };
+            let inode_count = if opts.both {
+                format!(", \"inodes\": {}", stats.inodes)
+            } else {
+                String::new()
+            };
+            let cum = if opts.cumulative {
+                format!(", \"cumulative_percentage\": {:.2}", cumulative[i])
+            } else {
+                String::new()
+            };
+            let avg = match (opts.avg, stats.avg_file_kb()) {
+                (true, Some(kb)) => format!(", \"avg_file_kb\": {:.2}", kb),
+                (true, None) => ", \"avg_file_kb\": null".to_string(),
+                (false, _) => String::new(),
+            };
+            let limit = match (&opts.limits, stats.limit_kb) {
+                (Some(_), Some(kb)) => format!(
+                    ", \"limit_bytes\": {}, \"over_limit\": {}",
+                    kb * 1024,
+                    stats.over_limit()
+                ),
+                (Some(_), None) => ", \"limit_bytes\": null, \"over_limit\": false".to_string(),
+                (None, _) => String::new(),
+            };
+            let file_type = if opts.no_f {
+                String::new()
+            } else {
+                format!(", \"type\": \"{}\"", entry_type(dirname, name))
+            };
+            let scan_seconds = if opts.verbose > 0 {
+                format!(", \"scan_seconds\": {:.3}", stats.scan_seconds())
+            } else {
+                String::new()
+            };
+            writeln!(
+                out,
+                "    {{\"name\": \"{}\"{}, \"value\": {}, \"bytes\": {}, \"percentage\": {:.2}{}{}{}{}{}{}}}{}",
                 json_escape(name),
+                file_type,
                 size,
+                stats.bytes,
                 percentage,
+                inode_count,
+                cum,
+                avg,
+                mtime,
+                limit,
+                scan_seconds,
                 comma
-            );
+            )?;
+        }
+        writeln!(out, "  ],")?;
+        writeln!(out, "  \"broken_symlinks\": {},", scan.broken_symlinks)?;
+        writeln!(out, "  \"races_skipped\": {},", scan.races_skipped)?;
+        writeln!(
+            out,
+            "  \"filtered\": {},",
+            filtered_count(scan, &file_sizes, &all_shown)
+        )?;
+        if opts.shallow {
+            writeln!(out, "  \"approximate\": true,")?;
+        }
+        if let Some(remainder) = remainder {
+            writeln!(
+                out,
+                "  \"not_shown\": {{\"entries\": {}, \"value\": {}, \"bytes\": {}}},",
+                remainder.count,
+                remainder.stats.value(inodes),
+                remainder.stats.bytes
+            )?;
+        }
+        if opts.check_links {
+            writeln!(out, "  \"broken_links\": [")?;
+            for (i, (link, target)) in scan.broken_links.iter().enumerate() {
+                let comma = if i + 1 < scan.broken_links.len() {
+                    ","
+                } else {
+                    ""
+                };
+                writeln!(
+                    out,
+                    "    {{\"link\": \"{}\", \"target\": \"{}\"}}{}",
+                    json_escape(link),
+                    json_escape(target),
+                    comma
+                )?;
+            }
+            writeln!(out, "  ],")?;
+        }
+        if opts.xattrs {
+            writeln!(out, "  \"xattr_bytes\": {},", scan.xattr_bytes)?;
+        }
+        if opts.prune_known {
+            let bytes = if opts.prune_skip {
+                "null".to_string()
+            } else {
+                (scan.pruned_kb * 1024).to_string()
+            };
+            writeln!(
+                out,
+                "  \"pruned\": {{\"directories\": {}, \"bytes\": {}}},",
+                scan.pruned_dirs, bytes
+            )?;
+        }
+        if opts.summary {
+            writeln!(out, "  \"summary\": {{")?;
+            let kinds = scan.types.kinds();
+            for (i, (key, _, totals)) in kinds.iter().enumerate() {
+                let comma = if i + 1 < kinds.len() { "," } else { "" };
+                writeln!(
+                    out,
+                    "    \"{}\": {{\"count\": {}, \"bytes\": {}}}{}",
+                    key,
+                    totals.count,
+                    totals.size_kb * 1024,
+                    comma
+                )?;
+            }
+            writeln!(out, "  }},")?;
+        }
+        if let Some(histogram) = &scan.size_histogram {
+            writeln!(out, "  \"size_histogram\": [")?;
+            for (i, bucket) in histogram.buckets.iter().enumerate() {
+                let comma = if i + 1 < histogram.buckets.len() {
+                    ","
+                } else {
+                    ""
+                };
+                let le = bucket
+                    .le
+                    .map_or("null".to_string(), |le| (le * 1024).to_string());
+                writeln!(
+                    out,
+                    "    {{\"le\": {}, \"count\": {}, \"bytes\": {}}}{}",
+                    le, bucket.count, bucket.bytes, comma
+                )?;
+            }
+            writeln!(out, "  ],")?;
+        }
+        if let Some(stats) = &scan.file_size_stats {
+            writeln!(
+                out,
+                "  \"file_size_stats\": {{\"count\": {}, \"total\": {}, \"mean\": {:.1}, \"median\": {}, \"p90\": {}, \"p99\": {}, \"max\": {}}},",
+                stats.count,
+                stats.total,
+                stats.mean(),
+                stats.quantile(0.5),
+                stats.quantile(0.9),
+                stats.quantile(0.99),
+                stats.max
+            )?;
         }
-        println!("  ],");
-        println!("  \"total\": {}", total_size);
-        println!("}}");
+        if let Some(by_depth) = &scan.by_depth {
+            writeln!(out, "  \"by_depth\": [")?;
+            for (i, level) in by_depth.levels.iter().enumerate() {
+                let comma = if i + 1 < by_depth.levels.len() {
+                    ","
+                } else {
+                    ""
+                };
+                writeln!(
+                    out,
+                    "    {{\"depth\": {}, \"bytes\": {}, \"files\": {}}}{}",
+                    level.depth, level.bytes, level.files, comma
+                )?;
+            }
+            writeln!(out, "  ],")?;
+        }
+        if let Some(hogs) = &scan.inode_hogs {
+            writeln!(out, "  \"inode_hogs\": [")?;
+            for (i, hog) in hogs.iter().enumerate() {
+                let comma = if i + 1 < hogs.len() { "," } else { "" };
+                writeln!(
+                    out,
+                    "    {{\"path\": \"{}\", \"direct\": {}, \"recursive\": {}}}{}",
+                    json_escape(&hog.path),
+                    hog.direct,
+                    hog.recursive,
+                    comma
+                )?;
+            }
+            writeln!(out, "  ],")?;
+        }
+        if let Some(sets) = &scan.duplicates {
+            writeln!(out, "  \"duplicates\": [")?;
+            for (i, set) in sets.iter().enumerate() {
+                let comma = if i + 1 < sets.len() { "," } else { "" };
+                let paths: Vec<String> = set
+                    .paths
+                    .iter()
+                    .map(|p| format!("\"{}\"", json_escape(p)))
+                    .collect();
+                writeln!(
+                    out,
+                    "    {{\"size\": {}, \"reclaimable\": {}, \"paths\": [{}]}}{}",
+                    set.size,
+                    set.reclaimable(),
+                    paths.join(", "),
+                    comma
+                )?;
+            }
+            writeln!(out, "  ],")?;
+        }
+        if let Some(empty) = &scan.empty {
+            let list = |paths: &[String]| {
+                paths
+                    .iter()
+                    .map(|p| format!("\"{}\"", json_escape(p)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            writeln!(out, "  \"empty\": {{")?;
+            writeln!(out, "    \"files\": [{}],", list(&empty.files))?;
+            writeln!(out, "    \"dirs\": [{}]", list(&empty.dirs))?;
+            writeln!(out, "  }},")?;
+        }
+        if let Some(recent) = &scan.recent {
+            writeln!(out, "  \"recent\": [")?;
+            for (i, file) in recent.iter().enumerate() {
+                let comma = if i + 1 < recent.len() { "," } else { "" };
+                writeln!(
+                    out,
+                    "    {{\"path\": \"{}\", \"bytes\": {}, \"mtime\": {}, \"new\": {}}}{}",
+                    json_escape(&file.path),
+                    file.bytes,
+                    file.mtime,
+                    file.new,
+                    comma
+                )?;
+            }
+            writeln!(out, "  ],")?;
+        }
+        if let Some(junk) = &scan.junk {
+            let totals: Vec<String> = junk
+                .totals()
+                .iter()
+                .map(|t| {
+                    format!(
+                        "{{\"pattern\": \"{}\", \"files\": {}, \"bytes\": {}}}",
+                        json_escape(&t.pattern),
+                        t.files,
+                        t.bytes
+                    )
+                })
+                .collect();
+            writeln!(out, "  \"junk\": {{")?;
+            writeln!(out, "    \"bytes\": {},", junk.bytes())?;
+            writeln!(out, "    \"patterns\": [{}],", totals.join(", "))?;
+            writeln!(out, "    \"files\": [")?;
+            for (i, file) in junk.files.iter().enumerate() {
+                let comma = if i + 1 < junk.files.len() { "," } else { "" };
+                writeln!(
+                    out,
+                    "      {{\"path\": \"{}\", \"bytes\": {}, \"mtime\": {}, \"pattern\": \"{}\"}}{}",
+                    json_escape(&file.path),
+                    file.bytes,
+                    file.mtime,
+                    json_escape(&junk.patterns[file.pattern]),
+                    comma
+                )?;
+            }
+            writeln!(out, "    ]")?;
+            writeln!(out, "  }},")?;
+        }
+        if opts.both {
+            let total_inodes: u64 = counted.iter().map(|(_, s)| s.inodes).sum();
+            writeln!(out, "  \"total_inodes\": {},", total_inodes)?;
+        }
+        if let Some(reason) = scan.truncated {
+            writeln!(out, "  \"truncated\": \"{}\",", reason)?;
+        }
+        if let (true, Ok(capacity)) = (inodes || opts.both, inode_capacity(dirname)) {
+            writeln!(
+                out,
+                "  \"filesystem_inodes\": {{\"total\": {}, \"free\": {}}},",
+                capacity.total, capacity.free
+            )?;
+        }
+        if opts.free {
+            match disk_space(dirname) {
+                Ok(space) => writeln!(
+                    out,
+                    "  \"filesystem_space\": {{\"device\": {}, \"total\": {}, \"used\": {}, \"free\": {}}},",
+                    filesystem_source(dirname)
+                        .map_or("null".to_string(), |source| format!("\"{}\"", json_escape(&source))),
+                    space.total,
+                    space.used,
+                    space.free
+                )?,
+                Err(e) => {
+                    crate::diagnostics::warn(&e.to_string());
+                    writeln!(out, "  \"filesystem_space\": null,")?;
+                }
+            }
+        }
+        writeln!(out, "  \"total\": {}", total_size)?;
+        writeln!(out, "}}")?;
 
-        return Ok(());
+        out.finish()?;
+        return check_strict_limits(&file_sizes, opts);
     }
 
-    let max_size = file_sizes
-        .iter()
-        .map(|(_, s)| s)
-        .max()
-        .copied()
-        .unwrap_or(0);
+    match opts.format {
+        ReportFormat::Table | ReportFormat::Du => {}
+        ReportFormat::Markdown => {
+            crate::export::write_markdown(&mut out, dirname, &file_sizes, scan, opts)?;
+            out.finish()?;
+            return check_strict_limits(&file_sizes, opts);
+        }
+        ReportFormat::Html => {
+            crate::export::write_html(&mut out, dirname, &file_sizes, scan, opts)?;
+            out.finish()?;
+            return check_strict_limits(&file_sizes, opts);
+        }
+    }
 
-    // Print header
-    println!("Statistics of directory \"{}\" :\n", dirname);
-    let col0_name = if inodes { "inodes" } else { "Size" };
-    println!(
-        "{:<14} {:<6} {:<20} {:<10}",
-        col0_name, "In %", "Histogram", "Name"
-    );
+    if file_sizes.is_empty()
+        && scan.pruned_dirs == 0
+        && scan.filtered == 0
+        && scan.truncated.is_none()
+    {
+        writeln!(
+            out,
+            "0 files, 0 bytes \u{2014} directory \"{}\" is empty",
+            dirname
+        )?;
+        return out.finish();
+    }
 
-    // Print files
-    for (filename, file_size) in &file_sizes {
-        let nmarks = if max_size != 0 {
-            ((max_marks - 1) as f64 * (*file_size as f64) / (max_size as f64)) as usize + 1
+    if opts.free {
+        writeln!(out, "{}", free_space_line(dirname, opts))?;
+    }
+    write!(
+        out,
+        "{}",
+        render_stats_table(dirname, &file_sizes, opts, None, color, columns)
+    )?;
+    let shown = shown_entries(dirname, &file_sizes, opts);
+    if let Some(note) = filtered_note(filtered_count(scan, &file_sizes, &shown), opts) {
+        writeln!(out, "{}", note)?;
+    }
+    if opts.inodes || opts.both {
+        let used: u64 = file_sizes.iter().map(|(_, s)| s.inodes).sum();
+        if let Some((capacity, percent)) = inode_capacity(dirname)
+            .ok()
+            .and_then(|c| Some((c, c.percent(used)?)))
+        {
+            writeln!(
+                out,
+                "Filesystem inodes: {:.2}% of {} ({} free)",
+                percent,
+                opts.group_digits(capacity.total),
+                opts.group_digits(capacity.free)
+            )?;
+        }
+    }
+    if opts.xattrs {
+        writeln!(
+            out,
+            "xattrs: {} (included in the sizes)",
+            opts.format_size(scan.xattr_bytes.div_ceil(1024))
+        )?;
+    }
+    if opts.prune_known && scan.pruned_dirs > 0 {
+        let plural = if scan.pruned_dirs == 1 { "y" } else { "ies" };
+        if opts.prune_skip {
+            writeln!(
+                out,
+                "build/cache artifacts: {} director{} skipped",
+                opts.group_digits(scan.pruned_dirs),
+                plural
+            )?;
         } else {
-            max_marks
-        };
-
-        let percentage = if total_size != 0 {
-            100.0 * (*file_size as f64) / (total_size as f64)
+            writeln!(
+                out,
+                "build/cache artifacts: {} ({} director{})",
+                opts.format_size(scan.pruned_kb),
+                opts.group_digits(scan.pruned_dirs),
+                plural
+            )?;
+        }
+    }
+    if opts.summary {
+        writeln!(out, "\nSummary by type:")?;
+        for (_, label, totals) in scan.types.kinds() {
+            writeln!(
+                out,
+                "  {:<14} {:>10}  {}",
+                label,
+                opts.group_digits(totals.count),
+                opts.format_size(totals.size_kb)
+            )?;
+        }
+    }
+    if let Some(histogram) = &scan.size_histogram {
+        write_size_histogram(&mut out, histogram, opts)?;
+    }
+    if let Some(stats) = &scan.file_size_stats {
+        write_file_size_stats(&mut out, stats, opts)?;
+    }
+    if let Some(by_depth) = &scan.by_depth {
+        write_depth_usage(&mut out, by_depth, opts)?;
+    }
+    if let Some(hogs) = &scan.inode_hogs {
+        writeln!(out, "\nDirectories with the most entries:")?;
+        writeln!(out, "  {:>10}  {:>10}  Directory", "Entries", "All below")?;
+        for hog in hogs {
+            writeln!(
+                out,
+                "  {:>10}  {:>10}  {}",
+                opts.group_digits(hog.direct),
+                opts.group_digits(hog.recursive),
+                hog.path
+            )?;
+        }
+    }
+    if let Some(sets) = &scan.duplicates {
+        write_duplicates(&mut out, sets, opts)?;
+    }
+    if let Some(empty) = &scan.empty {
+        write_empty_list(&mut out, "Empty files", &empty.files, opts)?;
+        write_empty_list(&mut out, "Empty directories", &empty.dirs, opts)?;
+    }
+    if let Some(junk) = &scan.junk {
+        write_junk(&mut out, junk, opts)?;
+    }
+    if let (Some(recent), Some(spec)) = (&scan.recent, &opts.recent) {
+        write_recent(&mut out, recent, spec, opts, color)?;
+    }
+    if opts.check_links && !scan.broken_links.is_empty() {
+        writeln!(out, "\nDangling symlinks (link -> target):")?;
+        for (link, target) in &scan.broken_links {
+            writeln!(out, "  {} -> {}", link, target)?;
+        }
+    }
+    if scan.broken_symlinks > 0 {
+        writeln!(
+            out,
+            "Broken symlinks: {}",
+            opts.group_digits(scan.broken_symlinks)
+        )?;
+    }
+    if opts.shallow {
+        writeln!(
+            out,
+            "Shallow scan: directory sizes count only their immediate children"
+        )?;
+    }
+    if scan.races_skipped > 0 {
+        let plural = if scan.races_skipped == 1 {
+            "entry"
         } else {
-            0.0
+            "entries"
         };
-        let size_str = if inodes {
-            if no_grouping {
-                file_size.to_string()
-            } else {
-                format_with_grouping(*file_size)
-            }
-        } else {
-            format_size(*file_size)
+        writeln!(
+            out,
+            "{} {} changed during scan",
+            opts.group_digits(scan.races_skipped),
+            plural
+        )?;
+    }
+    let over: Vec<_> = file_sizes.iter().filter(|(_, s)| s.over_limit()).collect();
+    if !over.is_empty() {
+        writeln!(out, "\nOver limit:")?;
+        for (name, stats) in over {
+            writeln!(
+                out,
+                "  {}: {} ({})",
+                red(name, color),
+                opts.format_size(stats.size_kb),
+                stats.limit_note(opts).unwrap_or_default()
+            )?;
+        }
+    }
+    if opts.legend && color {
+        print_legend(&mut out)?;
+    }
+    if let Some(reason) = scan.truncated {
+        writeln!(
+            out,
+            "\nScan truncated ({}): only completed entries are shown",
+            reason
+        )?;
+    }
+
+    out.finish()?;
+    check_strict_limits(&file_sizes, opts)
+}
+
+/// The `--free` line above the table, like `df` for the filesystem holding
+/// `dirname`: `Filesystem: /dev/sda1  1.8 TB total, 120.0 GB free (93% used)`.
+/// A tree spanning several filesystems is reported by its root's.
+fn free_space_line(dirname: &str, opts: &Options) -> String {
+    let space = match disk_space(dirname) {
+        Ok(space) => space,
+        Err(e) => return format!("Filesystem: free space unavailable ({})", e),
+    };
+    let source = filesystem_source(dirname).map_or(String::new(), |source| source + "  ");
+    let percent = space
+        .percent_used()
+        .map_or(String::new(), |p| format!(" ({:.0}% used)", p));
+    format!(
+        "Filesystem: {}{} total, {} free{}",
+        source,
+        opts.format_size(space.total / 1024),
+        opts.format_size(space.free / 1024),
+        percent
+    )
+}
+
+/// The `--size-histogram` section of the table: files and bytes per size
+/// bucket, with bars for the file counts
+fn write_size_histogram(
+    out: &mut dyn Write,
+    histogram: &SizeHistogram,
+    opts: &Options,
+) -> io::Result<()> {
+    let glyphs = opts.bar_glyphs('#');
+    let max_count = histogram.buckets.iter().map(|b| b.count).max().unwrap_or(0);
+    writeln!(out, "\nFile sizes:")?;
+    writeln!(
+        out,
+        "  {:<14} {:>10}  {:<14} Histogram",
+        "File size", "Files", "Total"
+    )?;
+    let mut lower = None;
+    for bucket in &histogram.buckets {
+        let label = match (bucket.le, lower) {
+            (Some(0), _) => "empty".to_string(),
+            (Some(le), _) => format!("<= {}", opts.format_size(le)),
+            (None, Some(lower)) => format!("> {}", opts.format_size(lower)),
+            (None, None) => "any".to_string(),
         };
+        lower = bucket.le;
+        let marks = histogram_marks(
+            bucket.count,
+            max_count,
+            HISTOGRAM_MARKS * glyphs.steps(),
+            opts.scale,
+        );
+        writeln!(
+            out,
+            "  {:<14} {:>10}  {:<14} {}",
+            label,
+            opts.group_digits(bucket.count),
+            opts.format_size(bucket.bytes.div_ceil(1024)),
+            glyphs.render(marks)
+        )?;
+    }
+    Ok(())
+}
 
-        let histogram = "#".repeat(nmarks);
+/// The `--stats` section of the table
+fn write_file_size_stats(
+    out: &mut dyn Write,
+    stats: &FileSizeStats,
+    opts: &Options,
+) -> io::Result<()> {
+    writeln!(out, "\nFile size statistics:")?;
+    writeln!(
+        out,
+        "  {:<8} {:>14}",
+        "Files",
+        opts.group_digits(stats.count)
+    )?;
+    let rows = [
+        ("Total", stats.total),
+        ("Mean", stats.mean().round() as u64),
+        ("Median", stats.quantile(0.5)),
+        ("p90", stats.quantile(0.9)),
+        ("p99", stats.quantile(0.99)),
+        ("Max", stats.max),
+    ];
+    for (label, bytes) in rows {
+        writeln!(
+            out,
+            "  {:<8} {:>14}  ({} bytes)",
+            label,
+            opts.format_size(bytes.div_ceil(1024)),
+            opts.group_digits(bytes)
+        )?;
+    }
+    Ok(())
+}
 
-        println!(
-            "{:<14} {:<6.2} {:<20} {:<10}",
-            size_str, percentage, histogram, filename
+/// The `--by-depth` section of the table: files and space per depth, with
+/// bars for the space, the deepest levels folded into a `N+` row
+fn write_depth_usage(out: &mut dyn Write, by_depth: &DepthUsage, opts: &Options) -> io::Result<()> {
+    let glyphs = opts.bar_glyphs('#');
+    let max_rows = usize::try_from(opts.max_depth_rows).unwrap_or(usize::MAX);
+    let rows = by_depth.capped(max_rows);
+    let max_kb = rows.iter().map(|level| level.size_kb).max().unwrap_or(0);
+    writeln!(out, "\nUsage by depth:")?;
+    writeln!(
+        out,
+        "  {:<8} {:>10}  {:<14} Histogram",
+        "Depth", "Files", "Size"
+    )?;
+    for (i, level) in rows.iter().enumerate() {
+        let label = if i + 1 == rows.len() && by_depth.levels.len() > rows.len() {
+            format!("{}+", level.depth)
+        } else {
+            level.depth.to_string()
+        };
+        let marks = histogram_marks(
+            level.size_kb,
+            max_kb,
+            HISTOGRAM_MARKS * glyphs.steps(),
+            opts.scale,
         );
+        writeln!(
+            out,
+            "  {:<8} {:>10}  {:<14} {}",
+            label,
+            opts.group_digits(level.files),
+            opts.format_size(level.size_kb),
+            glyphs.render(marks)
+        )?;
+    }
+    Ok(())
+}
+
+/// The `--duplicates` section of the table: each set of identical files
+/// with the space it wastes, most wasteful first
+fn write_duplicates(out: &mut dyn Write, sets: &[DuplicateSet], opts: &Options) -> io::Result<()> {
+    let reclaimable: u64 = sets.iter().map(DuplicateSet::reclaimable).sum();
+    let plural = if sets.len() == 1 { "" } else { "s" };
+    writeln!(
+        out,
+        "\nDuplicate files: {} set{}, {} reclaimable",
+        opts.group_digits(sets.len() as u64),
+        plural,
+        opts.format_size(reclaimable.div_ceil(1024))
+    )?;
+    for set in sets {
+        writeln!(
+            out,
+            "  {} reclaimable: {} copies of {}",
+            opts.format_size(set.reclaimable().div_ceil(1024)),
+            set.paths.len(),
+            opts.format_size(set.size.div_ceil(1024))
+        )?;
+        for path in &set.paths {
+            writeln!(out, "    {}", path)?;
+        }
     }
+    Ok(())
+}
 
-    // Print footer
-    let total_str = if inodes {
-        if no_grouping {
-            total_size.to_string()
+/// The `--recent` section of the table: at most `--recent-limit` files,
+/// those new since the previous `--watch` refresh marked with `+`
+fn write_recent(
+    out: &mut dyn Write,
+    recent: &[RecentFile],
+    spec: &AgeSpec,
+    opts: &Options,
+    color: bool,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "\nFiles of at least {} newer than {}: {}",
+        opts.format_size(opts.recent_min_size),
+        spec.text,
+        opts.group_digits(recent.len() as u64)
+    )?;
+    let now = unix_now();
+    for file in recent.iter().take(opts.recent_limit) {
+        let line = format!(
+            "{} {:>10}  {}  {}",
+            if file.new { '+' } else { ' ' },
+            opts.format_size(file.bytes.div_ceil(1024)),
+            format_timestamp(file.mtime, opts.time_style, now),
+            file.path
+        );
+        if file.new {
+            writeln!(out, "{}", red(&line, color))?;
         } else {
-            format_with_grouping(total_size)
+            writeln!(out, "{}", line)?;
         }
+    }
+    if recent.len() > opts.recent_limit {
+        writeln!(
+            out,
+            "  ... and {} more (--json lists them all)",
+            opts.group_digits((recent.len() - opts.recent_limit) as u64)
+        )?;
+    }
+    Ok(())
+}
+
+/// The `--find-junk` section of the table: the totals per pattern, then at
+/// most `--find-junk-limit` of the largest matches
+fn write_junk(out: &mut dyn Write, junk: &Junk, opts: &Options) -> io::Result<()> {
+    writeln!(
+        out,
+        "\nJunk files: {}, {} reclaimable",
+        opts.group_digits(junk.files.len() as u64),
+        opts.format_size(junk.bytes().div_ceil(1024))
+    )?;
+    let totals = junk.totals();
+    let width = totals
+        .iter()
+        .map(|t| t.pattern.chars().count())
+        .max()
+        .unwrap_or(0);
+    for total in &totals {
+        writeln!(
+            out,
+            "  {:<width$}  {:>8}  {}",
+            total.pattern,
+            opts.group_digits(total.files),
+            opts.format_size(total.bytes.div_ceil(1024)),
+        )?;
+    }
+    if junk.files.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "Largest:")?;
+    let now = unix_now();
+    for file in junk.files.iter().take(opts.find_junk_limit) {
+        writeln!(
+            out,
+            "  {:>10}  {}  {}",
+            opts.format_size(file.bytes.div_ceil(1024)),
+            format_timestamp(file.mtime, opts.time_style, now),
+            file.path
+        )?;
+    }
+    if junk.files.len() > opts.find_junk_limit {
+        writeln!(
+            out,
+            "  ... and {} more (--json lists them all)",
+            opts.group_digits((junk.files.len() - opts.find_junk_limit) as u64)
+        )?;
+    }
+    Ok(())
+}
+
+/// A `--find-empty` section of the table: the count, then at most
+/// `--find-empty-limit` of the paths
+fn write_empty_list(
+    out: &mut dyn Write,
+    title: &str,
+    paths: &[String],
+    opts: &Options,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "\n{}: {}",
+        title,
+        opts.group_digits(paths.len() as u64)
+    )?;
+    for path in paths.iter().take(opts.find_empty_limit) {
+        writeln!(out, "  {}", path)?;
+    }
+    if paths.len() > opts.find_empty_limit {
+        writeln!(
+            out,
+            "  ... and {} more (--json lists them all)",
+            opts.group_digits((paths.len() - opts.find_empty_limit) as u64)
+        )?;
+    }
+    Ok(())
+}
+
+/// With `--strict-limits`, fail when any entry is over its limit
+fn check_strict_limits(entries: &[(String, EntryStats)], opts: &Options) -> Result<(), DustrError> {
+    let over = entries.iter().filter(|(_, s)| s.over_limit()).count();
+    if opts.strict_limits && over > 0 {
+        Err(DustrError::LimitsExceeded(over))
     } else {
-        format_size(total_size)
+        Ok(())
+    }
+}
+
+/// Clear the terminal and print the header shown before each watch cycle.
+/// Write errors are left to the report that follows, which stops the watch
+/// with `DustrError::BrokenPipe` once stdout is closed.
+pub fn print_watch_header(dirname: &str, interval_secs: u64) {
+    let _ = write!(
+        io::stdout(),
+        "\x1b[2J\x1b[HEvery {}s: dustr {}    {}\n\n",
+        interval_secs,
+        dirname,
+        format_iso8601(unix_now())
+    );
+}
+
+/// Re-run `print_disk_usage` every `interval_secs` seconds until Ctrl+C.
+/// Returns `DustrError::Cancelled` when interrupted.
+pub fn watch_disk_usage(
+    dirname: &str,
+    opts: &Options,
+    interval_secs: u64,
+) -> Result<(), DustrError> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let signal_id = signal_hook::flag::register(SIGINT, interrupted.clone()).ok();
+
+    let mut state = WatchState::default();
+    let result = loop {
+        if opts.output.is_none() {
+            print_watch_header(dirname, interval_secs);
+        }
+        if let Err(e) = print_watch_cycle(dirname, opts, &mut state) {
+            break Err(e);
+        }
+        // Sleep in small steps so Ctrl+C is noticed promptly
+        let deadline = Instant::now() + Duration::from_secs(interval_secs);
+        while Instant::now() < deadline && !interrupted.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        if interrupted.load(Ordering::Relaxed) {
+            break Err(DustrError::Cancelled);
+        }
     };
-    println!("\nTotal directory size: {}", total_str);
 
-    Ok(())
+    if let Some(id) = signal_id {
+        signal_hook::low_level::unregister(id);
+    }
+    result
 }
@@ -0,0 +1,74 @@
+//! Non-fatal diagnostics: unreadable paths, pruning decisions and
+//! traversal notes.
+//!
+//! Messages go to one process-wide sink. The CLI installs a stderr sink
+//! whose threshold follows `--verbose` and `--quiet`; the Python extension
+//! forwards everything to the `dustr` logger of Python's `logging` module.
+//! Without a sink, messages are dropped.
+
+use parking_lot::{const_rwlock, RwLock};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Severity of a diagnostic message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Every directory entered, with timings, shown with `-vv`
+    Trace,
+    /// Traversal details, shown with `--verbose`
+    Debug,
+    /// Problems that make the results incomplete, such as unreadable paths
+    Warning,
+}
+
+/// Receives every diagnostic message
+pub type Sink = Arc<dyn Fn(Level, &str) + Send + Sync>;
+
+static SINK: RwLock<Option<Sink>> = const_rwlock(None);
+
+/// Install `sink` as the destination of all diagnostics, returning the
+/// previous one
+pub fn set_sink(sink: Option<Sink>) -> Option<Sink> {
+    std::mem::replace(&mut *SINK.write(), sink)
+}
+
+/// A sink printing messages at or above `min_level` to stderr; `None`
+/// discards everything
+pub fn stderr_sink(min_level: Option<Level>) -> Sink {
+    Arc::new(move |level, message| {
+        if min_level.is_some_and(|min| level >= min) {
+            let label = match level {
+                Level::Trace => "trace",
+                Level::Debug => "debug",
+                Level::Warning => "warning",
+            };
+            // Unlike eprintln!, don't panic when stderr is a closed pipe
+            let _ = writeln!(io::stderr(), "dustr: {}: {}", label, message);
+        }
+    })
+}
+
+/// Send a message to the installed sink. The sink runs without the lock
+/// held, so it may block (e.g. on the Python GIL) while another thread
+/// replaces it.
+pub fn log(level: Level, message: &str) {
+    let sink = SINK.read().clone();
+    if let Some(sink) = sink {
+        sink(level, message);
+    }
+}
+
+/// Report a problem that makes the results incomplete
+pub fn warn(message: &str) {
+    log(Level::Warning, message);
+}
+
+/// Report a traversal detail
+pub fn debug(message: &str) {
+    log(Level::Debug, message);
+}
+
+/// Report a step of the walk, such as entering a directory
+pub fn trace(message: &str) {
+    log(Level::Trace, message);
+}
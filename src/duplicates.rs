@@ -0,0 +1,286 @@
+//! Duplicate-file detection.
+//!
+//! Files are grouped by size first, so only same-sized candidates are read.
+//! Candidates are then grouped by a hash of their first 4K, and only files
+//! whose heads match are hashed in full. Every member of a hash group is
+//! compared byte-for-byte with the group's first file before being
+//! reported, so hash collisions can never produce a false duplicate.
+//!
+//! `find_duplicates` walks a tree on its own; with `--duplicates` the scan
+//! records the candidates as it goes (`DuplicateCandidates`).
+
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, Metadata};
+use std::hash::{DefaultHasher, Hasher};
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::core::{DustrError, Options, TreeWalk};
+
+/// Read buffer size used while hashing and comparing files
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Bytes hashed first to tell same-sized files apart cheaply
+const HEAD_SIZE: u64 = 4096;
+
+/// Find groups of identical regular files under `path` that are at least
+/// `min_size_kb` kilobytes long (apparent size). Empty files and hard
+/// links to the same inode are not reported as duplicates. Groups are
+/// ordered by file size, largest first, and the paths within a group are
+/// sorted.
+///
+/// Honors `cross_mounts` and the exclude and include options; hidden files
+/// are compared like any other. Ctrl+C is checked during
+/// both the walk and the hashing phase and yields `DustrError::Cancelled`.
+pub fn find_duplicates(
+    path: &str,
+    min_size_kb: u64,
+    opts: &Options,
+) -> Result<Vec<Vec<String>>, DustrError> {
+    let walk = TreeWalk::new(path, opts)?;
+    let mut candidates = DuplicateCandidates::new(min_size_kb.saturating_mul(1024));
+    for entry in walk.walker().into_iter().flatten() {
+        if walk.is_cancelled() {
+            return Err(DustrError::Cancelled);
+        }
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        let path = entry.path();
+        if walk.other_device(&meta) || !walk.filter.includes_file(walk.rel(&path)) {
+            continue;
+        }
+        candidates.record(path, &meta);
+    }
+
+    let mut sets = candidates.into_sets(&walk.cancelled)?;
+    sets.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.paths.cmp(&b.paths)));
+    Ok(sets.into_iter().map(|set| set.paths).collect())
+}
+
+/// `(dev, ino)` of a file
+type FileId = (u64, u64);
+
+/// Identical regular files found with `--duplicates`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateSet {
+    /// Apparent size of each copy, in bytes
+    pub size: u64,
+    /// The copies, sorted
+    pub paths: Vec<String>,
+}
+
+impl DuplicateSet {
+    /// Bytes freed by keeping a single copy
+    pub fn reclaimable(&self) -> u64 {
+        self.size
+            .saturating_mul(self.paths.len().saturating_sub(1) as u64)
+    }
+}
+
+/// Order sets by reclaimable space, largest first, then by file size and
+/// paths so the order is stable
+pub fn sort_by_waste(sets: &mut [DuplicateSet]) {
+    sets.sort_by(|a, b| {
+        b.reclaimable()
+            .cmp(&a.reclaimable())
+            .then_with(|| b.size.cmp(&a.size))
+            .then_with(|| a.paths.cmp(&b.paths))
+    });
+}
+
+/// Regular files gathered during a walk, grouped by size, from which
+/// `into_sets` finds the duplicates. Hard links to an already recorded
+/// inode are left out, so they are never reported as copies.
+#[derive(Debug, Default)]
+pub struct DuplicateCandidates {
+    min_bytes: u64,
+    seen_inodes: HashSet<FileId>,
+    /// The files of each size
+    by_size: HashMap<u64, Vec<(FileId, PathBuf)>>,
+}
+
+impl DuplicateCandidates {
+    /// Candidates of at least `min_bytes` bytes; empty files never count
+    pub fn new(min_bytes: u64) -> Self {
+        DuplicateCandidates {
+            min_bytes: min_bytes.max(1),
+            ..DuplicateCandidates::default()
+        }
+    }
+
+    /// Whether a file of `len` bytes is large enough to be recorded
+    pub fn wants(&self, len: u64) -> bool {
+        len >= self.min_bytes
+    }
+
+    /// Add a regular file
+    pub fn record(&mut self, path: PathBuf, meta: &Metadata) {
+        if self.wants(meta.len()) {
+            self.insert(meta.len(), (meta.dev(), meta.ino()), path);
+        }
+    }
+
+    fn insert(&mut self, size: u64, inode: FileId, path: PathBuf) {
+        if self.seen_inodes.insert(inode) {
+            self.by_size.entry(size).or_default().push((inode, path));
+        }
+    }
+
+    /// Add the files recorded by another walker
+    pub fn merge(&mut self, other: DuplicateCandidates) {
+        for (size, files) in other.by_size {
+            for (inode, path) in files {
+                self.insert(size, inode, path);
+            }
+        }
+    }
+
+    /// The sets of identical files, ordered by `sort_by_waste`. Same-sized
+    /// files are told apart by a hash of their first 4K, only those whose
+    /// heads match are hashed in full, and every set is confirmed
+    /// byte-for-byte. Unreadable files are skipped. Stops with
+    /// `DustrError::Cancelled` once `cancelled` is set.
+    pub fn into_sets(self, cancelled: &AtomicBool) -> Result<Vec<DuplicateSet>, DustrError> {
+        let candidates: Vec<(u64, PathBuf)> = self
+            .by_size
+            .into_iter()
+            .filter(|(_, files)| files.len() > 1)
+            .flat_map(|(size, files)| files.into_iter().map(move |(_, p)| (size, p)))
+            .collect();
+        let heads = group_by_hash(candidates, Some(HEAD_SIZE), cancelled);
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(DustrError::Cancelled);
+        }
+
+        // A head covering the whole file already is its full hash
+        let (small, large): (Vec<_>, Vec<_>) =
+            heads.into_iter().partition(|(size, _)| *size <= HEAD_SIZE);
+        let large = large
+            .into_iter()
+            .flat_map(|(size, paths)| paths.into_iter().map(move |p| (size, p)))
+            .collect();
+        let mut groups = small;
+        groups.extend(group_by_hash(large, None, cancelled));
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(DustrError::Cancelled);
+        }
+
+        let mut sets: Vec<DuplicateSet> = groups
+            .into_par_iter()
+            .flat_map_iter(|(size, mut paths)| {
+                paths.sort();
+                confirm_identical(paths, cancelled)
+                    .into_iter()
+                    .map(move |paths| DuplicateSet { size, paths })
+            })
+            .collect();
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(DustrError::Cancelled);
+        }
+        sort_by_waste(&mut sets);
+        Ok(sets)
+    }
+}
+
+/// Hash `(size, path)` files in parallel, reading at most `limit` bytes
+/// of each, and keep the groups of two or more with the same size and hash
+fn group_by_hash(
+    files: Vec<(u64, PathBuf)>,
+    limit: Option<u64>,
+    cancelled: &AtomicBool,
+) -> Vec<(u64, Vec<PathBuf>)> {
+    // Unreadable files are skipped rather than failing the whole search
+    let hashed: Vec<((u64, u64), PathBuf)> = files
+        .into_par_iter()
+        .filter_map(|(size, path)| {
+            hash_file(&path, limit, cancelled)
+                .ok()
+                .map(|hash| ((size, hash), path))
+        })
+        .collect();
+    let mut by_hash: HashMap<(u64, u64), Vec<PathBuf>> = HashMap::new();
+    for (key, path) in hashed {
+        by_hash.entry(key).or_default().push(path);
+    }
+    by_hash
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|((size, _), paths)| (size, paths))
+        .collect()
+}
+
+/// Hash a file's contents, or its first `limit` bytes, giving up early
+/// when cancelled
+fn hash_file(path: &Path, limit: Option<u64>, cancelled: &AtomicBool) -> io::Result<u64> {
+    let mut file = File::open(path)?.take(limit.unwrap_or(u64::MAX));
+    let mut hasher = DefaultHasher::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(io::Error::from(io::ErrorKind::Interrupted));
+        }
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hasher.finish());
+        }
+        hasher.write(&buf[..n]);
+    }
+}
+
+/// Split files with equal hashes into groups of byte-identical files,
+/// keeping only groups with at least two members
+fn confirm_identical(paths: Vec<PathBuf>, cancelled: &AtomicBool) -> Vec<Vec<String>> {
+    let mut remaining = paths;
+    let mut groups = Vec::new();
+    while remaining.len() > 1 {
+        let first = remaining.remove(0);
+        let (same, rest): (Vec<PathBuf>, Vec<PathBuf>) = remaining
+            .into_iter()
+            .partition(|other| same_contents(&first, other, cancelled).unwrap_or(false));
+        if !same.is_empty() {
+            let mut group = vec![first.to_string_lossy().to_string()];
+            group.extend(same.iter().map(|p| p.to_string_lossy().to_string()));
+            groups.push(group);
+        }
+        remaining = rest;
+    }
+    groups
+}
+
+/// Compare two files byte-for-byte
+fn same_contents(a: &Path, b: &Path, cancelled: &AtomicBool) -> io::Result<bool> {
+    let (mut fa, mut fb) = (File::open(a)?, File::open(b)?);
+    let (mut ba, mut bb) = (vec![0u8; CHUNK_SIZE], vec![0u8; CHUNK_SIZE]);
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        let na = read_full(&mut fa, &mut ba)?;
+        let nb = read_full(&mut fb, &mut bb)?;
+        if na != nb || ba[..na] != bb[..nb] {
+            return Ok(false);
+        }
+        if na == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Fill `buf` as far as possible, returning the number of bytes read
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
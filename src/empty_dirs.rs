@@ -0,0 +1,175 @@
+//! Empty-directory detection.
+//!
+//! Directories are evaluated after their children, so a directory holding
+//! only empty subdirectories can itself be reported as empty.
+//! `find_empty_dirs` walks a tree on its own; with `--find-empty` the scan
+//! notes empty files and directories as it goes (`EmptyFinder`).
+
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use parking_lot::Mutex;
+
+use crate::core::{DustrError, Options, TreeWalk};
+
+/// Settings shared by every directory visited during one search
+struct EmptyDirSearch<'a> {
+    walk: &'a TreeWalk<'a>,
+    recursive_empty: bool,
+    found: Mutex<Vec<String>>,
+}
+
+/// Find empty directories below `path` (the directory itself is not
+/// reported). With `recursive_empty`, a directory containing nothing but
+/// empty directories also counts as empty; otherwise only directories with
+/// no entries at all are returned. The result is sorted, so a parent comes
+/// before its empty children.
+///
+/// Anything that is not a directory makes its parent non-empty, as do
+/// excluded entries, mount points (unless `cross_mounts` is set) and
+/// unreadable directories, so every reported directory is safe to remove.
+/// Ctrl+C yields `DustrError::Cancelled`.
+pub fn find_empty_dirs(
+    path: &str,
+    recursive_empty: bool,
+    opts: &Options,
+) -> Result<Vec<String>, DustrError> {
+    let walk = TreeWalk::new(path, opts)?;
+    let search = EmptyDirSearch {
+        walk: &walk,
+        recursive_empty,
+        found: Mutex::new(Vec::new()),
+    };
+    search.visit(walk.root);
+    let mut found = search.found.into_inner();
+    walk.finish()?;
+
+    found.sort();
+    Ok(found)
+}
+
+impl EmptyDirSearch<'_> {
+    /// Visit a directory's subdirectories, then decide whether it is empty
+    fn visit(&self, dir: &Path) -> bool {
+        if self.walk.is_cancelled() {
+            return false;
+        }
+        let Ok(read_dir) = fs::read_dir(dir) else {
+            return false;
+        };
+        let mut has_content = false;
+        let mut subdirs = Vec::new();
+        for entry in read_dir {
+            let Ok(entry) = entry else {
+                has_content = true;
+                continue;
+            };
+            let path = entry.path();
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            let same_dev = || {
+                self.walk.base_dev.is_none()
+                    || entry
+                        .metadata()
+                        .is_ok_and(|meta| !self.walk.other_device(&meta))
+            };
+            if is_dir && !self.walk.filter.excludes(self.walk.rel(&path)) && same_dev() {
+                subdirs.push(path);
+            } else {
+                has_content = true;
+            }
+        }
+
+        let all_subdirs_empty = subdirs
+            .par_iter()
+            .map(|sub| self.visit(sub))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .all(|empty| empty);
+        let empty =
+            !has_content && (subdirs.is_empty() || (self.recursive_empty && all_subdirs_empty));
+        if empty && dir != self.walk.root {
+            self.found.lock().push(dir.to_string_lossy().to_string());
+        }
+        empty
+    }
+}
+
+/// Empty files and directories found by a scan with `--find-empty`, sorted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EmptyEntries {
+    /// Zero-byte regular files
+    pub files: Vec<String>,
+    /// Directories holding nothing but empty directories
+    pub dirs: Vec<String>,
+}
+
+impl EmptyEntries {
+    fn merge(&mut self, other: EmptyEntries) {
+        self.files.extend(other.files);
+        self.dirs.extend(other.dirs);
+    }
+
+    /// Sort both lists, so a parent comes before its empty children
+    pub fn sort(&mut self) {
+        self.files.sort();
+        self.dirs.sort();
+    }
+}
+
+/// Empty entries noted during one walk of the scan. Directories are
+/// yielded before their children, so each is registered as empty when
+/// entered, and anything found below it later marks it and its ancestors
+/// as occupied; whatever is still unmarked once the walk is done held
+/// nothing but empty directories.
+#[derive(Debug, Default)]
+pub struct EmptyFinder {
+    files: Vec<String>,
+    /// Every directory entered, with whether anything was found below it
+    dirs: HashMap<PathBuf, bool>,
+}
+
+impl EmptyFinder {
+    /// A directory was entered
+    pub fn enter_dir(&mut self, path: PathBuf) {
+        self.dirs.entry(path).or_insert(false);
+    }
+
+    /// A zero-byte file was found
+    pub fn empty_file(&mut self, path: &Path) {
+        self.files.push(path.to_string_lossy().to_string());
+    }
+
+    /// Something that is not an empty directory was found in `dir`: a
+    /// file, or an entry that could not be read, was left out or is on
+    /// another filesystem
+    pub fn occupied(&mut self, dir: &Path) {
+        let mut dir = Some(dir);
+        // A marked directory already has all its ancestors marked
+        while let Some(has_content @ false) = dir.and_then(|d| self.dirs.get_mut(d)) {
+            *has_content = true;
+            dir = dir.and_then(Path::parent);
+        }
+    }
+
+    /// Something was found next to `path`, in its parent directory
+    pub fn occupied_parent(&mut self, path: &Path) {
+        if let Some(parent) = path.parent() {
+            self.occupied(parent);
+        }
+    }
+
+    /// Add what this walk found to `found`
+    pub fn finish(self, found: &mut EmptyEntries) {
+        found.merge(EmptyEntries {
+            files: self.files,
+            dirs: self
+                .dirs
+                .into_iter()
+                .filter(|(_, has_content)| !has_content)
+                .map(|(dir, _)| dir.to_string_lossy().to_string())
+                .collect(),
+        });
+    }
+}
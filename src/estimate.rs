@@ -0,0 +1,183 @@
+//! Quick forecast of a scan (`estimate_scan`).
+//!
+//! Lists the top level of a directory, then walks a few of its
+//! subdirectories up to a fixed number of entries each and extrapolates
+//! from them, so the cost stays bounded however large the tree is. The
+//! figures are rough: they assume the unsampled subdirectories look like
+//! the sampled ones, and that the rest reads as fast as the samples did.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::core::{DustrError, Options, PathFilter};
+
+/// Most top-level subdirectories walked
+pub const SAMPLE_DIRS: usize = 8;
+/// Most entries read in each sampled subdirectory
+pub const SAMPLE_ENTRIES: u64 = 2_000;
+
+/// The forecast for a scan
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScanEstimate {
+    /// Entries (files, directories and others) the scan would count
+    pub estimated_entries: u64,
+    /// Space they take, in bytes
+    pub estimated_bytes: u64,
+    /// Wall-clock time the scan would take
+    pub estimated_seconds: f64,
+    /// Number of top-level subdirectories walked to get there
+    pub sampled_dirs: usize,
+}
+
+/// What one partial walk of a subdirectory found
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SubtreeSample {
+    pub entries: u64,
+    pub bytes: u64,
+    /// Directories whose children were read
+    pub dirs_read: u64,
+    /// Directories found but left unread when the walk stopped
+    pub dirs_pending: u64,
+}
+
+impl SubtreeSample {
+    /// `(entries, bytes)` of the whole subtree, assuming each unread
+    /// directory holds as much as the average directory read
+    pub fn extrapolated(&self) -> (u64, u64) {
+        if self.dirs_pending == 0 || self.dirs_read == 0 {
+            return (self.entries, self.bytes);
+        }
+        let scale = (self.dirs_read + self.dirs_pending) as f64 / self.dirs_read as f64;
+        (
+            (self.entries as f64 * scale) as u64,
+            (self.bytes as f64 * scale) as u64,
+        )
+    }
+}
+
+/// Estimate entries, size and duration of scanning `path` by sampling at
+/// most `SAMPLE_DIRS` of its subdirectories, spread over them in name
+/// order, for at most `SAMPLE_ENTRIES` entries each.
+///
+/// The unsampled subdirectories are taken to hold the sampled average.
+/// The time comes from the rate at which the samples were read, spread
+/// over the threads a scan would use, so a warm cache makes it optimistic.
+/// Honors `cross_mounts` and the exclude options, and skips hidden entries
+/// below the top level like the scan.
+pub fn estimate_scan(path: &str, opts: &Options) -> Result<ScanEstimate, DustrError> {
+    let start = Instant::now();
+    let root = Path::new(path);
+    let root_meta = fs::metadata(root).map_err(|e| {
+        DustrError::from_io(format!("Cannot read metadata for '{}': {}", path, e), &e)
+    })?;
+    if !root_meta.is_dir() {
+        return Ok(ScanEstimate {
+            estimated_entries: 1,
+            estimated_bytes: root_meta.blocks() * 512,
+            estimated_seconds: start.elapsed().as_secs_f64(),
+            sampled_dirs: 0,
+        });
+    }
+    let filter = opts.path_filter()?;
+    let base_dev = (!opts.cross_mounts).then(|| root_meta.dev());
+    let listing = fs::read_dir(root)
+        .map_err(|e| DustrError::from_io(format!("Cannot read directory '{}': {}", path, e), &e))?;
+
+    let mut entries = 0;
+    let mut bytes = 0;
+    let mut dirs = Vec::new();
+    for entry in listing.flatten() {
+        let child = entry.path();
+        if filter.excludes(child.strip_prefix(root).unwrap_or(&child)) {
+            continue;
+        }
+        // Top-level entries are resolved like the scan does
+        let Ok(meta) = fs::metadata(&child) else {
+            continue;
+        };
+        if base_dev.is_some_and(|dev| meta.dev() != dev) {
+            continue;
+        }
+        entries += 1;
+        bytes += meta.blocks() * 512;
+        if meta.is_dir() {
+            dirs.push(child);
+        }
+    }
+    dirs.sort();
+
+    let step = dirs.len().div_ceil(SAMPLE_DIRS).max(1);
+    let samples: Vec<SubtreeSample> = dirs
+        .iter()
+        .step_by(step)
+        .map(|dir| sample_subtree(dir, root, &filter, base_dev))
+        .collect();
+    let read: u64 = samples.iter().map(|s| s.entries).sum();
+    if !samples.is_empty() {
+        let n = samples.len() as f64;
+        let (below, below_bytes) = samples
+            .iter()
+            .map(SubtreeSample::extrapolated)
+            .fold((0, 0), |(e, b), (se, sb)| (e + se, b + sb));
+        entries += (below as f64 / n * dirs.len() as f64) as u64;
+        bytes += (below_bytes as f64 / n * dirs.len() as f64) as u64;
+    }
+
+    // Entries per second while sampling, times the walkers a scan runs
+    let elapsed = start.elapsed().as_secs_f64();
+    let rate = (read + dirs.len() as u64).max(1) as f64 / elapsed.max(1e-6);
+    let walkers = rayon::current_num_threads().min(dirs.len()).max(1);
+    Ok(ScanEstimate {
+        estimated_entries: entries,
+        estimated_bytes: bytes,
+        estimated_seconds: entries as f64 / (rate * walkers as f64),
+        sampled_dirs: samples.len(),
+    })
+}
+
+/// Walk `dir` breadth-first until `SAMPLE_ENTRIES` entries have been read
+fn sample_subtree(
+    dir: &Path,
+    root: &Path,
+    filter: &PathFilter,
+    base_dev: Option<u64>,
+) -> SubtreeSample {
+    let mut sample = SubtreeSample::default();
+    let mut pending: VecDeque<PathBuf> = VecDeque::from([dir.to_path_buf()]);
+    while sample.entries < SAMPLE_ENTRIES {
+        let Some(dir) = pending.pop_front() else {
+            break;
+        };
+        sample.dirs_read += 1;
+        let Ok(listing) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in listing.flatten() {
+            let path = entry.path();
+            if entry
+                .file_name()
+                .to_str()
+                .is_some_and(|s| s.starts_with('.'))
+                || filter.excludes(path.strip_prefix(root).unwrap_or(&path))
+            {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if base_dev.is_some_and(|dev| meta.dev() != dev) {
+                continue;
+            }
+            sample.entries += 1;
+            sample.bytes += meta.blocks() * 512;
+            if meta.is_dir() {
+                pending.push_back(path);
+            }
+        }
+    }
+    sample.dirs_pending = pending.len() as u64;
+    sample
+}
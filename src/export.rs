@@ -0,0 +1,341 @@
+//! Markdown, HTML and du-compatible reports (`--format markdown|html|du`).
+//!
+//! All are rendered from the same sorted entries as the terminal table. The
+//! Markdown and HTML reports carry the same header metadata: the scanned
+//! path, the time of the report, the total, the number of unreadable paths
+//! and, for a truncated scan, why it stopped.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::core::{
+    counted_entries, entry_type, filtered_count, format_iso8601, format_value, shown_entries,
+    top_entries, unix_now, EntryStats, Options, ScanResult, FORMAT_VERSION, VERSION,
+};
+
+/// Totals shown in the report header
+struct Summary {
+    total_kb: u64,
+    total_inodes: u64,
+    generated: String,
+}
+
+impl Summary {
+    fn new(entries: &[(String, EntryStats)]) -> Self {
+        Summary {
+            total_kb: entries.iter().map(|(_, s)| s.size_kb).sum(),
+            total_inodes: entries.iter().map(|(_, s)| s.inodes).sum(),
+            generated: format_iso8601(unix_now()),
+        }
+    }
+}
+
+/// Percentage of `part` in `total`, 0 when the total is 0
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * part as f64 / total as f64
+    }
+}
+
+/// "entry" or "entries", to follow `count`
+fn entries_word(count: u64) -> &'static str {
+    if count == 1 {
+        "entry"
+    } else {
+        "entries"
+    }
+}
+
+/// Escape text for a GitHub-flavored Markdown table cell or paragraph
+pub fn markdown_escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '|' | '#' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '\n' | '\r' => result.push(' '),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Escape text for HTML element content and attribute values
+pub fn html_escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Write the report as a GitHub-flavored Markdown table. `entries` are in
+/// display order; `--only-dirs` hides the files among them and `--top` all
+/// but the largest.
+pub fn write_markdown(
+    out: &mut dyn Write,
+    dirname: &str,
+    entries: &[(String, EntryStats)],
+    scan: &ScanResult,
+    opts: &Options,
+) -> io::Result<()> {
+    let all_shown = shown_entries(dirname, entries, opts);
+    let summary = Summary::new(counted_entries(entries, &all_shown, opts));
+    let (shown, remainder) = top_entries(all_shown.clone(), opts);
+    let total = if opts.inodes {
+        summary.total_inodes
+    } else {
+        summary.total_kb
+    };
+
+    writeln!(out, "## Disk usage of {}", markdown_escape(dirname))?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "- Generated: {} by dustr {} (format {})",
+        summary.generated, VERSION, FORMAT_VERSION
+    )?;
+    writeln!(
+        out,
+        "- Total: {} ({} inodes)",
+        opts.format_size(summary.total_kb),
+        opts.group_digits(summary.total_inodes)
+    )?;
+    writeln!(out, "- Errors: {}", scan.errors)?;
+    let filtered = filtered_count(scan, entries, &all_shown);
+    if filtered > 0 {
+        writeln!(
+            out,
+            "- Hidden by filters: {} {}",
+            filtered,
+            entries_word(filtered)
+        )?;
+    }
+    if let Some(reason) = scan.truncated {
+        writeln!(out, "- Truncated: {}", reason)?;
+    }
+    if let Some(remainder) = remainder {
+        writeln!(
+            out,
+            "- Not shown: {} more entries ({})",
+            remainder.count,
+            format_value(remainder.stats.value(opts.inodes), opts)
+        )?;
+    }
+    writeln!(out)?;
+    if opts.no_f {
+        writeln!(out, "| Size | Inodes | In % | Name |")?;
+        writeln!(out, "|---:|---:|---:|:---|")?;
+    } else {
+        writeln!(out, "| Size | Inodes | In % | Type | Name |")?;
+        writeln!(out, "|---:|---:|---:|:---:|:---|")?;
+    }
+    for (name, stats) in &shown {
+        let file_type = if opts.no_f {
+            String::new()
+        } else {
+            format!(" {} |", markdown_escape(&entry_type(dirname, name)))
+        };
+        writeln!(
+            out,
+            "| {} | {} | {:.2} |{} {} |",
+            opts.format_size(stats.size_kb),
+            opts.group_digits(stats.inodes),
+            percent(stats.value(opts.inodes), total),
+            file_type,
+            markdown_escape(name)
+        )?;
+    }
+    Ok(())
+}
+
+/// Column sorting for the HTML report: clicking a header sorts by that
+/// column, clicking again reverses the order
+const SORT_SCRIPT: &str = r#"document.querySelectorAll("th[data-type]").forEach(function (th) {
+  th.addEventListener("click", function () {
+    var body = th.closest("table").tBodies[0];
+    var idx = th.cellIndex;
+    var numeric = th.dataset.type === "number";
+    var asc = th.dataset.order !== "asc";
+    th.dataset.order = asc ? "asc" : "desc";
+    var rows = Array.prototype.slice.call(body.rows);
+    rows.sort(function (a, b) {
+      var x = a.cells[idx].dataset.value, y = b.cells[idx].dataset.value;
+      var c = numeric ? x - y : x.localeCompare(y);
+      return asc ? c : -c;
+    });
+    rows.forEach(function (row) { body.appendChild(row); });
+  });
+});"#;
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { padding: 0.2em 0.8em; text-align: left; }
+th[data-type] { cursor: pointer; user-select: none; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+.bar { background: #4a90d9; height: 0.8em; }
+.track { width: 12em; background: #eee; }";
+
+/// Write the report as a standalone HTML page with a sortable table and
+/// CSS histogram bars. `entries` are in display order; `--only-dirs` hides
+/// the files among them and `--top` all but the largest.
+pub fn write_html(
+    out: &mut dyn Write,
+    dirname: &str,
+    entries: &[(String, EntryStats)],
+    scan: &ScanResult,
+    opts: &Options,
+) -> io::Result<()> {
+    let all_shown = shown_entries(dirname, entries, opts);
+    let counted = counted_entries(entries, &all_shown, opts);
+    let summary = Summary::new(counted);
+    let (shown, remainder) = top_entries(all_shown.clone(), opts);
+    let value = |stats: &EntryStats| stats.value(opts.inodes);
+    let total = counted.iter().map(|(_, s)| value(s)).sum();
+    let max = shown.iter().map(|(_, s)| value(s)).max().unwrap_or(0);
+    let title = format!("Disk usage of {}", html_escape(dirname));
+
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(out, "<html lang=\"en\">")?;
+    writeln!(out, "<head>")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(
+        out,
+        "<meta name=\"generator\" content=\"dustr {}\">",
+        VERSION
+    )?;
+    writeln!(out, "<title>{}</title>", title)?;
+    writeln!(out, "<style>\n{}\n</style>", STYLE)?;
+    writeln!(out, "</head>")?;
+    writeln!(out, "<body>")?;
+    writeln!(out, "<h1>{}</h1>", title)?;
+    writeln!(out, "<ul>")?;
+    writeln!(
+        out,
+        "<li>Generated: {} by dustr {} (format {})</li>",
+        summary.generated, VERSION, FORMAT_VERSION
+    )?;
+    writeln!(
+        out,
+        "<li>Total: {} ({} inodes)</li>",
+        html_escape(&opts.format_size(summary.total_kb)),
+        html_escape(&opts.group_digits(summary.total_inodes))
+    )?;
+    writeln!(out, "<li>Errors: {}</li>", scan.errors)?;
+    let filtered = filtered_count(scan, entries, &all_shown);
+    if filtered > 0 {
+        writeln!(
+            out,
+            "<li>Hidden by filters: {} {}</li>",
+            filtered,
+            entries_word(filtered)
+        )?;
+    }
+    if let Some(reason) = scan.truncated {
+        writeln!(out, "<li>Truncated: {}</li>", reason)?;
+    }
+    if let Some(remainder) = remainder {
+        writeln!(
+            out,
+            "<li>Not shown: {} more entries ({})</li>",
+            remainder.count,
+            html_escape(&format_value(remainder.stats.value(opts.inodes), opts))
+        )?;
+    }
+    writeln!(out, "</ul>")?;
+    writeln!(out, "<table>")?;
+    let type_header = if opts.no_f {
+        ""
+    } else {
+        "<th data-type=\"text\">Type</th>"
+    };
+    writeln!(
+        out,
+        "<thead><tr><th data-type=\"number\">Size</th><th data-type=\"number\">Inodes</th>\
+         <th data-type=\"number\">In %</th><th>Histogram</th>{}\
+         <th data-type=\"text\">Name</th></tr></thead>",
+        type_header
+    )?;
+    writeln!(out, "<tbody>")?;
+    for (name, stats) in &shown {
+        let width = percent(value(stats), max);
+        let file_type = if opts.no_f {
+            String::new()
+        } else {
+            let indicator = html_escape(&entry_type(dirname, name));
+            format!("<td data-value=\"{}\">{}</td>", indicator, indicator)
+        };
+        writeln!(
+            out,
+            "<tr><td class=\"num\" data-value=\"{}\">{}</td>\
+             <td class=\"num\" data-value=\"{}\">{}</td>\
+             <td class=\"num\" data-value=\"{:.2}\">{:.2}</td>\
+             <td><div class=\"track\"><div class=\"bar\" style=\"width: {:.1}%\"></div></div></td>\
+             {}<td data-value=\"{}\">{}</td></tr>",
+            stats.bytes,
+            html_escape(&opts.format_size(stats.size_kb)),
+            stats.inodes,
+            html_escape(&opts.group_digits(stats.inodes)),
+            percent(value(stats), total),
+            percent(value(stats), total),
+            width,
+            file_type,
+            html_escape(name),
+            html_escape(name)
+        )?;
+    }
+    writeln!(out, "</tbody>")?;
+    writeln!(out, "</table>")?;
+    writeln!(out, "<script>\n{}\n</script>", SORT_SCRIPT)?;
+    writeln!(out, "</body>")?;
+    writeln!(out, "</html>")?;
+    Ok(())
+}
+
+/// Write one `SIZE<TAB>PATH` record per entry, with sizes in kilobytes (or
+/// inode counts) like `du -k`, followed by the directory's total like
+/// `du -d1`. `entries` are in display order; `--only-dirs` hides the files
+/// among them and `--top` all but the largest, which still count in the
+/// total. With `opts.null` records end with NUL rather than a newline,
+/// so any file name survives `xargs -0`.
+pub fn write_du(
+    out: &mut dyn Write,
+    dirname: &str,
+    entries: &[(String, EntryStats)],
+    opts: &Options,
+) -> io::Result<()> {
+    let end = if opts.null { '\0' } else { '\n' };
+    let dir = Path::new(dirname);
+    if !dir.is_dir() {
+        let value = entries.first().map_or(0, |(_, s)| s.value(opts.inodes));
+        return write!(out, "{}\t{}{}", value, dirname, end);
+    }
+    let shown = shown_entries(dirname, entries, opts);
+    let (rows, _) = top_entries(shown.clone(), opts);
+    for (name, stats) in &rows {
+        let path = dir.join(name);
+        write!(
+            out,
+            "{}\t{}{}",
+            stats.value(opts.inodes),
+            path.display(),
+            end
+        )?;
+    }
+    let total: u64 = counted_entries(entries, &shown, opts)
+        .iter()
+        .map(|(_, s)| s.value(opts.inodes))
+        .sum();
+    write!(out, "{}\t{}{}", total, dirname, end)
+}
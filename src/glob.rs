@@ -0,0 +1,144 @@
+//! Shell-style glob matching for path patterns.
+//!
+//! Supports `*` (any run of characters except `/`), `**` (anything,
+//! including `/`), `?` (one character except `/`), character classes such
+//! as `[abc]`, `[a-z]` and `[!0-9]`, and `\` to escape a special character.
+
+use std::path::Path;
+
+/// Whether `text` matches the glob `pattern` in full
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+fn match_from(p: &[char], t: &[char]) -> bool {
+    match p.first() {
+        None => t.is_empty(),
+        Some('*') => {
+            let double = p.get(1) == Some(&'*');
+            let rest = if double { &p[2..] } else { &p[1..] };
+            // "**/" also matches zero directories, so "a/**/b" matches "a/b"
+            if double && rest.first() == Some(&'/') && match_from(&rest[1..], t) {
+                return true;
+            }
+            for i in 0..=t.len() {
+                if match_from(rest, &t[i..]) {
+                    return true;
+                }
+                if i < t.len() && t[i] == '/' && !double {
+                    return false;
+                }
+            }
+            false
+        }
+        Some('?') => t.first().is_some_and(|&c| c != '/') && match_from(&p[1..], &t[1..]),
+        Some('[') => match parse_class(&p[1..]) {
+            Some((class, len)) => {
+                t.first().is_some_and(|&c| c != '/' && class.matches(c))
+                    && match_from(&p[1 + len..], &t[1..])
+            }
+            // An unterminated class is a literal '['
+            None => t.first() == Some(&'[') && match_from(&p[1..], &t[1..]),
+        },
+        Some('\\') if p.len() > 1 => t.first() == Some(&p[1]) && match_from(&p[2..], &t[1..]),
+        Some(&c) => t.first() == Some(&c) && match_from(&p[1..], &t[1..]),
+    }
+}
+
+/// A bracketed character class such as `[a-z_]` or `[!0-9]`
+struct CharClass {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    fn matches(&self, c: char) -> bool {
+        self.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != self.negated
+    }
+}
+
+/// Parse the class following a `[`, returning it and the number of pattern
+/// characters consumed (including the closing `]`)
+fn parse_class(p: &[char]) -> Option<(CharClass, usize)> {
+    let mut i = 0;
+    let negated = matches!(p.first(), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    // A ']' right after the opening bracket is a literal member
+    let mut first = true;
+    loop {
+        let c = *p.get(i)?;
+        if c == ']' && !first {
+            return Some((CharClass { negated, ranges }, i + 1));
+        }
+        first = false;
+        if p.get(i + 1) == Some(&'-') && p.get(i + 2).is_some_and(|&hi| hi != ']') {
+            ranges.push((c, p[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+}
+
+/// A set of exclude patterns, matched against paths relative to the scan root.
+/// Patterns containing a `/` are matched against the whole relative path (a
+/// leading `/` is optional), others against the final path component.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GlobSet {
+    patterns: Vec<CompiledPattern>,
+}
+
+/// A pattern split into chars once, since exclude lists can be long and are
+/// checked against every entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CompiledPattern {
+    chars: Vec<char>,
+    has_slash: bool,
+}
+
+impl GlobSet {
+    pub fn new<I, S>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let patterns = patterns
+            .into_iter()
+            .filter_map(|p| {
+                let p = p.as_ref();
+                let p = p.strip_prefix("./").unwrap_or(p);
+                let p = p.strip_prefix('/').unwrap_or(p);
+                let p = p.trim_end_matches('/');
+                (!p.is_empty()).then(|| CompiledPattern {
+                    chars: p.chars().collect(),
+                    has_slash: p.contains('/'),
+                })
+            })
+            .collect();
+        GlobSet { patterns }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether a path relative to the scan root matches any pattern
+    pub fn matches(&self, rel_path: &Path) -> bool {
+        let rel: Vec<char> = rel_path.to_string_lossy().chars().collect();
+        let name_start = rel.iter().rposition(|&c| c == '/').map_or(0, |i| i + 1);
+        self.patterns.iter().any(|p| {
+            let subject = if p.has_slash {
+                &rel[..]
+            } else {
+                &rel[name_start..]
+            };
+            match_from(&p.chars, subject)
+        })
+    }
+}
@@ -0,0 +1,64 @@
+//! Full-tree inventory (`dump_tree_json`).
+//!
+//! Unlike the reports, which break usage down by top-level entry, the
+//! inventory lists every file below a directory with its size. Keys are
+//! relative paths in byte order, one per line, so the dump of an unchanged
+//! tree is byte-identical and a changed one diffs line by line.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::core::{blocks_to_kb, json_escape, slash_path, DustrError, Options, Output, TreeWalk};
+
+/// Sizes in KB (allocated blocks, like the reports) of every regular file
+/// below `path`, keyed by the path relative to it. Symlinks are neither
+/// followed nor listed; hard links appear under every name.
+///
+/// Honors `cross_mounts` and the exclude and include options; hidden files
+/// are listed like any other. Ctrl+C yields
+/// `DustrError::Cancelled`.
+pub fn tree_sizes(path: &str, opts: &Options) -> Result<BTreeMap<String, u64>, DustrError> {
+    let walk = TreeWalk::new(path, opts)?;
+    let mut sizes = BTreeMap::new();
+    for entry in walk.walker().into_iter().flatten() {
+        if walk.is_cancelled() {
+            break;
+        }
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if walk.other_device(&meta) || !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let rel = walk.rel(&path);
+        if walk.filter.includes_file(rel) {
+            sizes.insert(slash_path(rel), blocks_to_kb(&meta));
+        }
+    }
+    walk.finish()?;
+    Ok(sizes)
+}
+
+/// Write `sizes` as a JSON object with one `"path": size_kb` pair per line
+pub fn write_tree_json(out: &mut dyn Write, sizes: &BTreeMap<String, u64>) -> io::Result<()> {
+    if sizes.is_empty() {
+        return writeln!(out, "{{}}");
+    }
+    writeln!(out, "{{")?;
+    for (i, (path, size_kb)) in sizes.iter().enumerate() {
+        let comma = if i + 1 < sizes.len() { "," } else { "" };
+        writeln!(out, "  \"{}\": {}{}", json_escape(path), size_kb, comma)?;
+    }
+    writeln!(out, "}}")
+}
+
+/// Write the inventory of `path` to `output_file` (replaced atomically),
+/// returning the number of files listed
+pub fn dump_tree_json(path: &str, output_file: &str, opts: &Options) -> Result<usize, DustrError> {
+    let sizes = tree_sizes(path, opts)?;
+    let mut out = Output::open(Some(output_file))?;
+    write_tree_json(&mut out, &sizes)?;
+    out.finish()?;
+    Ok(sizes.len())
+}
@@ -0,0 +1,145 @@
+//! Reclaimable leftovers for `--find-junk`: core dumps, temporary files,
+//! editor backup, lock and swap files, Finder metadata and crash dumps.
+//!
+//! Files are matched by name as the scan walks past them, so nothing is
+//! read or stat'ed beyond what the scan does anyway.
+
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::glob::glob_match;
+
+/// The built-in patterns, matched against file names. Globs, except for
+/// the numbered core dumps (`core.1234`), which are checked by hand.
+pub const BUILTIN_PATTERNS: &[&str] = &[
+    "core",
+    NUMBERED_CORE,
+    "*.tmp",
+    "*~",
+    ".#*",
+    "*.swp",
+    ".DS_Store",
+    "*.dmp",
+    "*.crash",
+    "hs_err_pid*.log",
+];
+
+/// Label of the built-in pattern for numbered core dumps, which a glob
+/// cannot express
+const NUMBERED_CORE: &str = "core.[0-9]+";
+
+/// The patterns a file name is checked against: the built-in ones, then
+/// those given with `--junk-pattern`
+#[derive(Debug, Clone)]
+pub struct JunkPatterns {
+    /// Each pattern as shown in the report
+    patterns: Vec<String>,
+}
+
+impl JunkPatterns {
+    pub fn new(extra: &[String]) -> Self {
+        let patterns = BUILTIN_PATTERNS
+            .iter()
+            .map(|&p| p.to_string())
+            .chain(extra.iter().cloned())
+            .collect();
+        JunkPatterns { patterns }
+    }
+
+    /// The patterns in the order they are tried
+    pub fn labels(&self) -> Vec<String> {
+        self.patterns.clone()
+    }
+
+    /// Index of the first pattern matching the file name of `path`
+    pub fn find(&self, path: &Path) -> Option<usize> {
+        let name = path.file_name()?.to_str()?;
+        self.patterns.iter().enumerate().position(|(i, pattern)| {
+            if i < BUILTIN_PATTERNS.len() && pattern == NUMBERED_CORE {
+                name.strip_prefix("core.")
+                    .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            } else {
+                glob_match(pattern, name)
+            }
+        })
+    }
+}
+
+/// A file matching one of the junk patterns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JunkFile {
+    pub path: String,
+    /// Space it takes on disk, freed by deleting it
+    pub bytes: u64,
+    pub mtime: i64,
+    /// Index of the pattern it matched in `Junk::patterns`
+    pub pattern: usize,
+}
+
+/// Matches for one pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JunkTotal {
+    pub pattern: String,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// The files matching the junk patterns, collected with `--find-junk`
+#[derive(Debug, Clone, Default)]
+pub struct Junk {
+    pub patterns: Vec<String>,
+    pub files: Vec<JunkFile>,
+}
+
+impl Junk {
+    pub fn new(patterns: &JunkPatterns) -> Self {
+        Junk {
+            patterns: patterns.labels(),
+            files: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, pattern: usize, path: &Path, meta: &Metadata) {
+        self.files.push(JunkFile {
+            path: path.to_string_lossy().to_string(),
+            bytes: meta.blocks() * 512,
+            mtime: meta.mtime(),
+            pattern,
+        });
+    }
+
+    pub fn merge(&mut self, other: Junk) {
+        self.files.extend(other.files);
+    }
+
+    /// Largest first, then by path
+    pub fn sort(&mut self) {
+        self.files
+            .sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+    }
+
+    /// Combined space of every match
+    pub fn bytes(&self) -> u64 {
+        self.files.iter().map(|f| f.bytes).sum()
+    }
+
+    /// Totals for each pattern with matches, in pattern order
+    pub fn totals(&self) -> Vec<JunkTotal> {
+        let mut totals: Vec<JunkTotal> = self
+            .patterns
+            .iter()
+            .map(|pattern| JunkTotal {
+                pattern: pattern.clone(),
+                files: 0,
+                bytes: 0,
+            })
+            .collect();
+        for file in &self.files {
+            totals[file.pattern].files += 1;
+            totals[file.pattern].bytes += file.bytes;
+        }
+        totals.retain(|t| t.files > 0);
+        totals
+    }
+}
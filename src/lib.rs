@@ -1,160 +1,3036 @@
+pub mod completions;
+pub mod config;
 pub mod core;
+pub mod diagnostics;
+pub mod duplicates;
+pub mod empty_dirs;
+pub mod estimate;
+pub mod export;
+pub mod glob;
+pub mod inventory;
+pub mod junk;
+pub mod limits;
+pub mod treemap;
+pub mod xattrs;
 
 #[cfg(feature = "extension-module")]
 mod python {
+    use parking_lot::Mutex;
+    use pyo3::marker::Ungil;
     use pyo3::prelude::*;
+    use pyo3::sync::PyOnceLock;
+    use pyo3::types::{PyCFunction, PyDict, PyTuple};
     use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+    use std::sync::Arc;
 
-    use crate::core::DustrError;
+    use crate::core::{
+        AgeSpec, BarStyle, BlockSize, ColorChoice, DustrError, HistogramScale, HistogramStyle,
+        Options, PathKind, ReportFormat, SizeUnit, SortKey, TimeStyle, WatchState,
+    };
+    use crate::diagnostics;
+
+    pyo3::create_exception!(
+        _dustr,
+        ScanTruncated,
+        pyo3::exceptions::PyTimeoutError,
+        "A scan stopped by its time or file budget. The values of the \
+         completed top-level entries are in `partial_results`, the cause in \
+         `reason`. A subclass of `TimeoutError`, which scans past \
+         `timeout_secs` raised before it existed."
+    );
+
+    pyo3::create_exception!(
+        _dustr,
+        LimitsExceeded,
+        pyo3::exceptions::PyException,
+        "Entries were over their `--limits` size with `--strict-limits`. The \
+         number of such entries is in `count`."
+    );
+
+    /// Parse a clap value-enum option passed from Python as a string
+    fn parse_enum<T: clap::ValueEnum>(name: &str, value: &str) -> PyResult<T> {
+        T::from_str(value, true).map_err(|_| {
+            pyo3::exceptions::PyValueError::new_err(format!(
+                "invalid value for {}: {:?}",
+                name, value
+            ))
+        })
+    }
+
+    /// Parse an optional age limit ("30d", "1y", "2023-01-31") passed from Python
+    fn parse_age(name: &str, value: Option<&str>) -> PyResult<Option<AgeSpec>> {
+        value
+            .map(|v| {
+                AgeSpec::parse(v).map_err(|e| {
+                    pyo3::exceptions::PyValueError::new_err(format!(
+                        "invalid value for {}: {}",
+                        name, e
+                    ))
+                })
+            })
+            .transpose()
+    }
+
+    /// Build an OSError subclass carrying the errno (when known) so that
+    /// `except OSError as e: e.errno` works from Python
+    fn os_err<T: pyo3::PyTypeInfo>(msg: String, errno: Option<i32>) -> PyErr {
+        match errno {
+            Some(errno) => PyErr::new::<T, _>((errno, msg)),
+            None => PyErr::new::<T, _>(msg),
+        }
+    }
 
     /// Convert a DustrError to a PyErr
-    fn to_pyerr(_py: Python, e: DustrError) -> PyErr {
+    fn to_pyerr(py: Python, e: DustrError) -> PyErr {
+        use pyo3::exceptions::{
+            PyBrokenPipeError, PyFileNotFoundError, PyKeyboardInterrupt, PyNotADirectoryError,
+            PyOSError, PyPermissionError,
+        };
+
         match e {
-            DustrError::NotFound(msg) => {
-                PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(msg)
-            }
-            DustrError::PermissionDenied(msg) => {
-                PyErr::new::<pyo3::exceptions::PyPermissionError, _>(msg)
+            DustrError::NotFound(msg, errno) => os_err::<PyFileNotFoundError>(msg, errno),
+            DustrError::PermissionDenied(msg, errno) => os_err::<PyPermissionError>(msg, errno),
+            DustrError::NotADirectory(msg, errno) => os_err::<PyNotADirectoryError>(msg, errno),
+            DustrError::OsError(msg, errno) => os_err::<PyOSError>(msg, errno),
+            DustrError::Cancelled => PyKeyboardInterrupt::new_err(""),
+            DustrError::BrokenPipe => os_err::<PyBrokenPipeError>("Broken pipe".into(), e.errno()),
+            // Callers that know whether inodes were counted use
+            // `scan_truncated` directly; sizes are the default
+            DustrError::Truncated(scan) => scan_truncated(py, &scan, false),
+            DustrError::LimitsExceeded(count) => {
+                let err = LimitsExceeded::new_err(e.to_string());
+                if let Err(set) = err.value(py).setattr("count", count) {
+                    return set;
+                }
+                err
             }
-            DustrError::OsError(msg) => PyErr::new::<pyo3::exceptions::PyOSError, _>(msg),
-            DustrError::Cancelled => PyErr::new::<pyo3::exceptions::PyKeyboardInterrupt, _>(""),
         }
     }
 
     /// Calculate directory sizes for all items in a directory (parallel version)
     #[pyfunction]
-    #[pyo3(signature = (path, use_inodes, cross_mounts=false, verbose=false, live=false))]
+    #[pyo3(signature = (path, use_inodes, cross_mounts=false, verbose=0, live=false, dirs_only=false, count_symlink_size=false, min_age=None, max_age=None, streaming=false, check_interval=100, exclude=None, exclude_from=None, min_size_kb=None, max_size_kb=None, include=None, include_dir_overhead=true, skip_empty=false, progress_callback=None, timeout_secs=None, max_files=None, partial=false, shallow=false, walk_callback=None, strict=false, on_error=None, filter=None, detect_cycles=false))]
+    #[allow(clippy::too_many_arguments)]
     fn calculate_directory_sizes(
         py: Python,
         path: &str,
         use_inodes: bool,
         cross_mounts: bool,
-        verbose: bool,
+        verbose: u8,
         live: bool,
+        dirs_only: bool,
+        count_symlink_size: bool,
+        min_age: Option<&str>,
+        max_age: Option<&str>,
+        streaming: bool,
+        check_interval: u64,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+        min_size_kb: Option<u64>,
+        max_size_kb: Option<u64>,
+        include: Option<Vec<String>>,
+        include_dir_overhead: bool,
+        skip_empty: bool,
+        progress_callback: Option<Py<PyAny>>,
+        timeout_secs: Option<u64>,
+        max_files: Option<u64>,
+        partial: bool,
+        shallow: bool,
+        walk_callback: Option<Py<PyAny>>,
+        strict: bool,
+        on_error: Option<Py<PyAny>>,
+        filter: Option<Py<PyAny>>,
+        detect_cycles: bool,
     ) -> PyResult<HashMap<String, u64>> {
-        let result = py.detach(|| {
-            crate::core::calculate_directory_sizes(path, use_inodes, cross_mounts, verbose, live)
+        if check_interval == 0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "check_interval must be at least 1",
+            ));
+        }
+        if timeout_secs == Some(0) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "timeout_secs must be at least 1",
+            ));
+        }
+        if max_files == Some(0) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "max_files must be at least 1",
+            ));
+        }
+        let opts = Options {
+            inodes: use_inodes,
+            dirs_only,
+            count_symlink_size,
+            older_than: parse_age("min_age", min_age)?,
+            newer_than: parse_age("max_age", max_age)?,
+            streaming,
+            check_interval,
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            min_size: min_size_kb,
+            max_size: max_size_kb,
+            include: include.unwrap_or_default(),
+            include_dir_overhead,
+            skip_empty,
+            timeout: timeout_secs,
+            max_files,
+            shallow,
+            strict,
+            detect_cycles,
+            cross_mounts,
+            verbose,
+            live,
+            ..Options::default()
+        };
+        opts.validate()
+            .map_err(pyo3::exceptions::PyValueError::new_err)?;
+        // An exception raised by a callback cancels the scan and is re-raised
+        let callback_error: Mutex<Option<PyErr>> = Mutex::new(None);
+        let hook = |current: usize, total: Option<usize>, name: &str| {
+            let Some(callback) = &progress_callback else {
+                return true;
+            };
+            Python::attach(|py| match callback.call1(py, (current, total, name)) {
+                Ok(_) => true,
+                Err(e) => {
+                    callback_error.lock().get_or_insert(e);
+                    false
+                }
+            })
+        };
+        let walk_hook = |name: &str, files: u64, bytes: u64| {
+            let Some(callback) = &walk_callback else {
+                return true;
+            };
+            Python::attach(|py| match callback.call1(py, (name, files, bytes)) {
+                Ok(_) => true,
+                Err(e) => {
+                    callback_error.lock().get_or_insert(e);
+                    false
+                }
+            })
+        };
+        // Returning False aborts the scan; None or anything else goes on
+        let error_hook = |path: &std::path::Path, errno: Option<i32>, message: &str| {
+            let Some(callback) = &on_error else {
+                return true;
+            };
+            Python::attach(|py| {
+                match callback.call1(py, (path.to_string_lossy(), errno, message)) {
+                    Ok(verdict) => !verdict.bind(py).is(pyo3::types::PyBool::new(py, false)),
+                    Err(e) => {
+                        callback_error.lock().get_or_insert(e);
+                        false
+                    }
+                }
+            })
+        };
+        let result = with_entry_filter(py, filter, &callback_error, |entry_filter| {
+            let hook: Option<&crate::core::ProgressHook<'_>> =
+                progress_callback.as_ref().map(|_| &hook as _);
+            let walk_hook: Option<&crate::core::WalkHook<'_>> =
+                walk_callback.as_ref().map(|_| &walk_hook as _);
+            let error_hook: Option<&crate::core::ErrorHook<'_>> =
+                on_error.as_ref().map(|_| &error_hook as _);
+            crate::core::scan_directory_with_hooks(
+                path,
+                &opts,
+                hook,
+                walk_hook,
+                error_hook,
+                entry_filter,
+            )
+            .map(|scan| scan.values(opts.inodes))
         });
+        if let Some(e) = callback_error.into_inner() {
+            return Err(e);
+        }
 
         py.check_signals()?;
 
-        result.map_err(|e| to_pyerr(py, e))
+        match result {
+            // With `partial`, return whatever a truncated scan gathered
+            Err(DustrError::Truncated(scan)) if partial => Ok(scan.values(opts.inodes)),
+            Err(DustrError::Truncated(scan)) => Err(scan_truncated(py, &scan, opts.inodes)),
+            result => result.map_err(|e| to_pyerr(py, e)),
+        }
+    }
+
+    /// What a `filter` callable sees of a file or directory: its path, type,
+    /// apparent size in bytes, modification time and owner
+    #[pyclass(frozen, get_all, module = "dustr._dustr")]
+    struct EntryInfo {
+        path: String,
+        is_dir: bool,
+        size: u64,
+        mtime: i64,
+        uid: u32,
+    }
+
+    #[pymethods]
+    impl EntryInfo {
+        fn __repr__(&self) -> String {
+            format!(
+                "EntryInfo(path={:?}, is_dir={}, size={}, mtime={}, uid={})",
+                self.path,
+                if self.is_dir { "True" } else { "False" },
+                self.size,
+                self.mtime,
+                self.uid
+            )
+        }
+    }
+
+    /// An entry for the `filter` callable and where to send its verdict
+    type FilterRequest = (EntryInfo, std::sync::mpsc::SyncSender<bool>);
+
+    /// Run `scan` on a thread of its own with `predicate`, if given, as
+    /// its entry filter. The walkers hand their entries to this thread,
+    /// which calls the predicate on every pending one with the GIL taken
+    /// once, instead of each walker contending for the GIL per entry. An
+    /// exception raised by the predicate goes to `callback_error` and
+    /// cancels the scan.
+    fn with_entry_filter<R: Send>(
+        py: Python,
+        predicate: Option<Py<PyAny>>,
+        callback_error: &Mutex<Option<PyErr>>,
+        scan: impl FnOnce(Option<&crate::core::EntryFilter>) -> R + Send,
+    ) -> R {
+        sync_log_level(py);
+        let Some(predicate) = predicate else {
+            return py.detach(|| scan(None));
+        };
+        let (requests, pending) = std::sync::mpsc::channel::<FilterRequest>();
+        let entry_filter: crate::core::EntryFilter = Arc::new(move |info| {
+            let (verdict, answer) = std::sync::mpsc::sync_channel(1);
+            let entry = EntryInfo {
+                path: info.path.to_string_lossy().into_owned(),
+                is_dir: info.is_dir,
+                size: info.size,
+                mtime: info.mtime,
+                uid: info.uid,
+            };
+            requests.send((entry, verdict)).ok()?;
+            // No answer once the predicate has failed: the scan stops
+            answer.recv().ok()
+        });
+        let pending = Mutex::new(pending);
+        std::thread::scope(|s| {
+            // The requests end when the scan is done and drops the filter
+            let worker = s.spawn(move || scan(Some(&entry_filter)));
+            while let Ok(first) = py.detach(|| pending.lock().recv()) {
+                let batch: Vec<FilterRequest> = std::iter::once(first)
+                    .chain(pending.lock().try_iter())
+                    .collect();
+                for (entry, verdict) in batch {
+                    if callback_error.lock().is_some() {
+                        continue;
+                    }
+                    let keep = predicate
+                        .call1(py, (entry,))
+                        .and_then(|keep| keep.bind(py).is_truthy());
+                    match keep {
+                        Ok(keep) => {
+                            verdict.send(keep).ok();
+                        }
+                        Err(e) => {
+                            callback_error.lock().get_or_insert(e);
+                        }
+                    }
+                }
+            }
+            worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+
+    /// The scan arguments `scan_async` and `start_scan` were called with:
+    /// path, `use_inodes` and the other keyword arguments
+    type ScanArgs<'a, 'py> = (String, bool, Option<&'a Bound<'py, PyDict>>);
+
+    /// Run `calculate_directory_sizes` on a thread of its own, for
+    /// `scan_async` and `start_scan`. `wrap` builds the callback the scan
+    /// calls from the caller's one, if any, and whether it is the progress
+    /// callback; the wrapped progress callback is always installed, as it
+    /// is how a cancellation reaches the scan, the walk callback only when
+    /// one is given. `deliver` gets the dict or the exception on the scan
+    /// thread, with the GIL held.
+    fn spawn_scan<'py>(
+        py: Python<'py>,
+        (path, use_inodes, kwargs): ScanArgs<'_, 'py>,
+        (progress_callback, walk_callback): (Option<Py<PyAny>>, Option<Py<PyAny>>),
+        wrap: impl Fn(Option<Py<PyAny>>, bool) -> PyResult<Bound<'py, PyCFunction>>,
+        deliver: impl FnOnce(Python<'_>, ScanOutcome) + Send + 'static,
+    ) -> PyResult<()> {
+        let kwargs = match kwargs {
+            Some(kwargs) => kwargs.copy()?,
+            None => PyDict::new(py),
+        };
+        kwargs.set_item("progress_callback", wrap(progress_callback, true)?)?;
+        if walk_callback.is_some() {
+            kwargs.set_item("walk_callback", wrap(walk_callback, false)?)?;
+        }
+        let scan = wrap_pyfunction!(calculate_directory_sizes, py)?.unbind();
+        let kwargs = kwargs.unbind();
+        std::thread::spawn(move || {
+            Python::attach(|py| {
+                let outcome = scan
+                    .bind(py)
+                    .call((path, use_inodes), Some(kwargs.bind(py)))
+                    .map(Bound::unbind);
+                deliver(py, outcome);
+            })
+        });
+        Ok(())
+    }
+
+    /// A callback for the scan thread of `scan_async`: it raises
+    /// `asyncio.CancelledError` once `future` is done, which stops the
+    /// scan, and otherwise hands the call to `callback` on the event loop
+    fn forward_to_loop<'py>(
+        py: Python<'py>,
+        future: &Bound<'py, PyAny>,
+        event_loop: &Bound<'py, PyAny>,
+        callback: Option<Py<PyAny>>,
+    ) -> PyResult<Bound<'py, PyCFunction>> {
+        let is_coroutine = match &callback {
+            Some(callback) => py
+                .import("inspect")?
+                .call_method1("iscoroutinefunction", (callback,))?
+                .is_truthy()?,
+            None => false,
+        };
+        let future = future.clone().unbind();
+        let event_loop = event_loop.clone().unbind();
+        PyCFunction::new_closure(py, None, None, move |args, _| -> PyResult<()> {
+            let py = args.py();
+            if future.bind(py).call_method0("done")?.is_truthy()? {
+                return Err(pyo3::exceptions::asyncio::CancelledError::new_err(
+                    "scan cancelled",
+                ));
+            }
+            let Some(callback) = &callback else {
+                return Ok(());
+            };
+            let event_loop = event_loop.bind(py);
+            if is_coroutine {
+                let coroutine = callback.bind(py).call1(args)?;
+                py.import("asyncio")?
+                    .call_method1("run_coroutine_threadsafe", (coroutine, event_loop))?;
+            } else {
+                let mut call = vec![callback.bind(py).clone()];
+                call.extend(args);
+                let call = PyTuple::new(py, call)?;
+                event_loop.call_method1("call_soon_threadsafe", call)?;
+            }
+            Ok(())
+        })
     }
 
-    /// Get file type indicator (@ for symlinks, / for directories, empty for files)
+    /// Scan like `calculate_directory_sizes`, with the same arguments,
+    /// without blocking the asyncio event loop: the scan runs on a thread
+    /// of its own and the returned future resolves to the same dict (or
+    /// raises the same exceptions). Cancelling it, or the task awaiting it,
+    /// stops the walk. `progress_callback` and `walk_callback` are called
+    /// on the event loop; they may be coroutine functions, or e.g.
+    /// `lambda *args: queue.put_nowait(args)` to feed an `asyncio.Queue`.
     #[pyfunction]
-    fn get_file_type_indicator(path: &str) -> PyResult<String> {
-        Ok(crate::core::get_file_type_indicator(path))
+    #[pyo3(signature = (path, use_inodes=false, progress_callback=None, walk_callback=None, **kwargs))]
+    fn scan_async<'py>(
+        py: Python<'py>,
+        path: String,
+        use_inodes: bool,
+        progress_callback: Option<Py<PyAny>>,
+        walk_callback: Option<Py<PyAny>>,
+        kwargs: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let event_loop = py.import("asyncio")?.call_method0("get_running_loop")?;
+        let future = event_loop.call_method0("create_future")?;
+        // Settles the future on the event loop, unless it was cancelled
+        let resolve = {
+            let future = future.clone().unbind();
+            PyCFunction::new_closure(py, None, None, move |args, _| -> PyResult<()> {
+                let future = future.bind(args.py());
+                if future.call_method0("done")?.is_truthy()? {
+                    return Ok(());
+                }
+                let (value, error): (Bound<'_, PyAny>, Bound<'_, PyAny>) = args.extract()?;
+                if error.is_none() {
+                    future.call_method1("set_result", (value,))?;
+                } else {
+                    future.call_method1("set_exception", (error,))?;
+                }
+                Ok(())
+            })?
+        };
+
+        let resolve = resolve.unbind();
+        let event_loop_ref = event_loop.clone().unbind();
+        spawn_scan(
+            py,
+            (path, use_inodes, kwargs),
+            (progress_callback, walk_callback),
+            |callback, _| forward_to_loop(py, &future, &event_loop, callback),
+            move |py, outcome| {
+                let none = py.None();
+                let (value, error) = match outcome {
+                    Ok(value) => (value, none),
+                    Err(e) => (none, e.into_value(py).into_any()),
+                };
+                // A closed loop has nobody left waiting for the result
+                let _ = event_loop_ref
+                    .bind(py)
+                    .call_method1("call_soon_threadsafe", (resolve, value, error));
+            },
+        )?;
+        Ok(future)
     }
 
-    /// Print the complete disk usage analysis
+    /// How far a `start_scan` scan has got, shared with its thread
+    #[derive(Default)]
+    struct ScanProgress {
+        current: AtomicUsize,
+        /// Number of top-level entries, 0 while unknown (with `streaming`)
+        total: AtomicUsize,
+        cancelled: AtomicBool,
+    }
+
+    /// What a scan ended with: the dict, or the exception it raised
+    type ScanOutcome = Result<Py<PyAny>, PyErr>;
+
+    /// A scan started by `start_scan`, running on a thread of its own
+    #[pyclass(frozen, module = "dustr._dustr")]
+    struct ScanHandle {
+        progress: Arc<ScanProgress>,
+        receiver: Mutex<std::sync::mpsc::Receiver<ScanOutcome>>,
+        outcome: Mutex<Option<ScanOutcome>>,
+    }
+
+    impl ScanHandle {
+        /// Whether the scan has finished, taking its outcome off the channel
+        /// after waiting for it up to `wait`
+        fn finished(&self, wait: std::time::Duration) -> bool {
+            use std::sync::mpsc::RecvTimeoutError;
+
+            if self.outcome.lock().is_some() {
+                return true;
+            }
+            let outcome = match self.receiver.lock().recv_timeout(wait) {
+                Ok(outcome) => outcome,
+                Err(RecvTimeoutError::Timeout) => return false,
+                Err(RecvTimeoutError::Disconnected) => Err(
+                    pyo3::exceptions::PyRuntimeError::new_err("the scan thread has gone away"),
+                ),
+            };
+            self.outcome.lock().get_or_insert(outcome);
+            true
+        }
+    }
+
+    #[pymethods]
+    impl ScanHandle {
+        /// Fraction of the top-level entries scanned so far, 1.0 once the
+        /// scan has finished and 0.0 while their number is unknown
+        fn poll(&self) -> f64 {
+            if self.finished(std::time::Duration::ZERO) {
+                return 1.0;
+            }
+            let current = self.progress.current.load(Ordering::Relaxed);
+            match self.progress.total.load(Ordering::Relaxed) {
+                0 => 0.0,
+                total => (current as f64 / total as f64).min(1.0),
+            }
+        }
+
+        /// Whether the scan has finished, so that `result()` returns at once
+        fn done(&self) -> bool {
+            self.finished(std::time::Duration::ZERO)
+        }
+
+        /// Stop the scan; `result()` then raises
+        /// `concurrent.futures.CancelledError`
+        fn cancel(&self) {
+            self.progress.cancelled.store(true, Ordering::Relaxed);
+        }
+
+        /// Wait for the scan, at most `timeout` seconds (then raising
+        /// `TimeoutError`), and return the dict `calculate_directory_sizes`
+        /// would have, or raise what it would have
+        #[pyo3(signature = (timeout=None))]
+        fn result(&self, py: Python, timeout: Option<f64>) -> PyResult<Py<PyAny>> {
+            use std::time::{Duration, Instant};
+
+            let deadline = match timeout {
+                Some(secs) if !(secs >= 0.0 && secs.is_finite()) => {
+                    return Err(pyo3::exceptions::PyValueError::new_err(
+                        "timeout must be a non-negative number of seconds",
+                    ))
+                }
+                timeout => timeout.map(|secs| Instant::now() + Duration::from_secs_f64(secs)),
+            };
+            // Wake up now and then for Ctrl+C
+            let tick = Duration::from_millis(100);
+            loop {
+                let wait = deadline.map_or(tick, |deadline| {
+                    deadline.saturating_duration_since(Instant::now()).min(tick)
+                });
+                if py.detach(|| self.finished(wait)) {
+                    break;
+                }
+                py.check_signals()?;
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Err(pyo3::exceptions::PyTimeoutError::new_err(
+                        "the scan is still running",
+                    ));
+                }
+            }
+            match self.outcome.lock().as_ref() {
+                Some(Ok(sizes)) => Ok(sizes.clone_ref(py)),
+                Some(Err(e)) => Err(e.clone_ref(py)),
+                None => unreachable!("a finished scan has an outcome"),
+            }
+        }
+    }
+
+    /// A callback for the scan thread of `start_scan`: it records the
+    /// progress (with `record`), calls `callback` and then raises
+    /// `concurrent.futures.CancelledError` once the scan is cancelled
+    fn track_scan<'py>(
+        py: Python<'py>,
+        progress: &Arc<ScanProgress>,
+        callback: Option<Py<PyAny>>,
+        record: bool,
+    ) -> PyResult<Bound<'py, PyCFunction>> {
+        let cancelled_error = py
+            .import("concurrent.futures")?
+            .getattr("CancelledError")?
+            .unbind();
+        let progress = progress.clone();
+        PyCFunction::new_closure(py, None, None, move |args, _| -> PyResult<()> {
+            let py = args.py();
+            if record {
+                let (current, total, _): (usize, Option<usize>, Bound<'_, PyAny>) =
+                    args.extract()?;
+                progress.current.store(current, Ordering::Relaxed);
+                progress.total.store(total.unwrap_or(0), Ordering::Relaxed);
+            }
+            if let Some(callback) = &callback {
+                callback.bind(py).call1(args)?;
+            }
+            if progress.cancelled.load(Ordering::Relaxed) {
+                let error = cancelled_error.bind(py).call1(("scan cancelled",))?;
+                return Err(PyErr::from_value(error));
+            }
+            Ok(())
+        })
+    }
+
+    /// Start scanning like `calculate_directory_sizes`, with the same
+    /// arguments, on a thread of its own and return a `ScanHandle` at once:
+    /// `poll()` gives the fraction done, `result()` waits for the dict and
+    /// `cancel()` stops the walk. For event loops other than asyncio, which
+    /// has `scan_async`.
     #[pyfunction]
-    #[pyo3(signature = (dirname, inodes=false, no_grouping=false, no_f=false, json=false, cross_mounts=false, verbose=false, live=false))]
-    #[allow(clippy::too_many_arguments)]
-    fn print_disk_usage(
+    #[pyo3(signature = (path, use_inodes=false, progress_callback=None, walk_callback=None, **kwargs))]
+    fn start_scan<'py>(
+        py: Python<'py>,
+        path: String,
+        use_inodes: bool,
+        progress_callback: Option<Py<PyAny>>,
+        walk_callback: Option<Py<PyAny>>,
+        kwargs: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<ScanHandle> {
+        let progress = Arc::new(ScanProgress::default());
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        spawn_scan(
+            py,
+            (path, use_inodes, kwargs),
+            (progress_callback, walk_callback),
+            |callback, record| track_scan(py, &progress, callback, record),
+            move |_, outcome| {
+                // Nobody is waiting once the handle is gone
+                let _ = sender.send(outcome);
+            },
+        )?;
+        Ok(ScanHandle {
+            progress,
+            receiver: Mutex::new(receiver),
+            outcome: Mutex::new(None),
+        })
+    }
+
+    /// Calculate `(size_kb, inode_count)` for all items in a directory in a
+    /// single traversal
+    #[pyfunction]
+    #[pyo3(signature = (path, cross_mounts=false, dirs_only=false, count_symlink_size=false, exclude=None, exclude_from=None))]
+    fn calculate_both(
         py: Python,
-        dirname: &str,
-        inodes: bool,
-        no_grouping: bool,
-        no_f: bool,
-        json: bool,
+        path: &str,
         cross_mounts: bool,
-        verbose: bool,
-        live: bool,
-    ) -> PyResult<()> {
-        let result = py.detach(|| {
-            crate::core::print_disk_usage(
-                dirname,
-                inodes,
-                no_grouping,
-                no_f,
-                json,
-                cross_mounts,
-                verbose,
-                live,
-            )
-        });
+        dirs_only: bool,
+        count_symlink_size: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+    ) -> PyResult<HashMap<String, (u64, u64)>> {
+        let opts = Options {
+            dirs_only,
+            count_symlink_size,
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            cross_mounts,
+            ..Options::default()
+        };
+        let result = detach_scan(py, || crate::core::calculate_both(path, &opts));
 
         py.check_signals()?;
 
         result.map_err(|e| to_pyerr(py, e))
     }
 
-    /// Main entry point for the dustr command (called from Python)
+    /// Total size in KB (or inode count with `use_inodes`) of everything
+    /// below a path, as a single number and without printing anything
     #[pyfunction]
-    #[pyo3(signature = (args=vec![]))]
-    fn main(py: Python, args: Vec<String>) -> PyResult<()> {
-        use clap::Parser;
-        let cli = match crate::core::Cli::try_parse_from(
-            std::iter::once("dustr".to_string()).chain(args),
-        ) {
-            Ok(cli) => cli,
-            Err(e) => {
-                eprintln!("{}", e);
-                return Ok(());
-            }
+    #[pyo3(signature = (path, use_inodes=false, cross_mounts=false))]
+    fn directory_total_kb(
+        py: Python,
+        path: &str,
+        use_inodes: bool,
+        cross_mounts: bool,
+    ) -> PyResult<u64> {
+        let opts = Options {
+            inodes: use_inodes,
+            cross_mounts,
+            ..Options::default()
         };
+        let result = detach_scan(py, || crate::core::directory_total(path, &opts));
 
-        print_disk_usage(
-            py,
-            &cli.dirname,
-            cli.inodes,
-            cli.nogrouping,
-            cli.no_f,
-            cli.json,
-            cli.cross_mounts,
-            cli.verbose,
-            cli.live,
-        )
+        py.check_signals()?;
+
+        result.map_err(|e| to_pyerr(py, e))
     }
 
-    /// Python module definition
-    #[pymodule]
-    pub fn _dustr(m: &Bound<'_, PyModule>) -> PyResult<()> {
-        m.add_function(wrap_pyfunction!(calculate_directory_sizes, m)?)?;
-        m.add_function(wrap_pyfunction!(get_file_type_indicator, m)?)?;
-        m.add_function(wrap_pyfunction!(print_disk_usage, m)?)?;
-        m.add_function(wrap_pyfunction!(main, m)?)?;
-        Ok(())
+    /// Usage counters of a `Scanner`
+    #[derive(Default)]
+    struct ScannerStats {
+        scans: u64,
+        errors: u64,
+        seconds: f64,
     }
-}
 
-#[cfg(feature = "extension-module")]
-pub use python::_dustr;
+    /// A scanner for repeated scans with the same options. The exclude
+    /// rules are compiled once, the thread pool (with `threads`) is kept,
+    /// and with `dedup` the files counted so far are remembered, so a file
+    /// hard-linked under several names, or met again by a later scan, is
+    /// counted once.
+    ///
+    /// A Scanner may be shared between threads: scans run concurrently,
+    /// each without the GIL, and share the compiled rules and the set of
+    /// counted files. Scans draw no progress bar.
+    #[pyclass(frozen, module = "dustr._dustr")]
+    struct Scanner {
+        opts: Options,
+        cache: crate::core::ScanCache,
+        pool: Option<rayon::ThreadPool>,
+        stats: Mutex<ScannerStats>,
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::core::{format_progress_bar, BAR_WIDTH};
+    impl Scanner {
+        fn run(&self, py: Python, path: &str) -> PyResult<crate::core::ScanResult> {
+            let began = std::time::Instant::now();
+            let result = detach_scan(py, || {
+                let quiet: &crate::core::ProgressHook<'_> = &|_, _, _| true;
+                let scan = || {
+                    crate::core::scan_directory_cached(
+                        path,
+                        &self.opts,
+                        Some(&self.cache),
+                        Some(quiet),
+                        None,
+                        None,
+                        None,
+                    )
+                };
+                match &self.pool {
+                    Some(pool) => pool.install(scan),
+                    None => scan(),
+                }
+            });
+            {
+                let mut stats = self.stats.lock();
+                stats.scans += 1;
+                stats.seconds += began.elapsed().as_secs_f64();
+                if let Ok(scan) = &result {
+                    stats.errors += scan.errors;
+                }
+            }
 
-    #[test]
-    fn progress_bar_zero_total() {
-        let bar = format_progress_bar(0, 0);
-        assert_eq!(bar, format!("[{}] 0/0", "-".repeat(BAR_WIDTH)));
+            py.check_signals()?;
+
+            result.map_err(|e| to_pyerr(py, e))
+        }
     }
 
-    #[test]
-    fn progress_bar_half() {
-        let bar = format_progress_bar(5, 10);
-        let filled = BAR_WIDTH / 2;
-        assert_eq!(
-            bar,
+    #[pymethods]
+    impl Scanner {
+        #[new]
+        #[pyo3(signature = (use_inodes=false, cross_mounts=false, dirs_only=false, count_symlink_size=false, exclude=None, exclude_from=None, include=None, include_dir_overhead=true, skip_empty=false, strict=false, dedup=false, threads=None))]
+        #[allow(clippy::too_many_arguments)]
+        fn new(
+            py: Python,
+            use_inodes: bool,
+            cross_mounts: bool,
+            dirs_only: bool,
+            count_symlink_size: bool,
+            exclude: Option<Vec<String>>,
+            exclude_from: Option<String>,
+            include: Option<Vec<String>>,
+            include_dir_overhead: bool,
+            skip_empty: bool,
+            strict: bool,
+            dedup: bool,
+            threads: Option<usize>,
+        ) -> PyResult<Self> {
+            let opts = Options {
+                inodes: use_inodes,
+                cross_mounts,
+                dirs_only,
+                count_symlink_size,
+                exclude: exclude.unwrap_or_default(),
+                exclude_from,
+                include: include.unwrap_or_default(),
+                include_dir_overhead,
+                skip_empty,
+                strict,
+                ..Options::default()
+            };
+            let cache = crate::core::ScanCache::new(&opts, dedup).map_err(|e| to_pyerr(py, e))?;
+            let pool = match threads {
+                Some(0) => {
+                    return Err(pyo3::exceptions::PyValueError::new_err(
+                        "threads must be at least 1",
+                    ))
+                }
+                Some(threads) => Some(
+                    rayon::ThreadPoolBuilder::new()
+                        .num_threads(threads)
+                        .build()
+                        .map_err(|e| pyo3::exceptions::PyOSError::new_err(e.to_string()))?,
+                ),
+                None => None,
+            };
+            Ok(Scanner {
+                opts,
+                cache,
+                pool,
+                stats: Mutex::new(ScannerStats::default()),
+            })
+        }
+
+        /// Sizes in KB (or inode counts) of the entries of `path`, like
+        /// `calculate_directory_sizes`
+        fn scan(&self, py: Python, path: &str) -> PyResult<HashMap<String, u64>> {
+            let scan = self.run(py, path)?;
+            Ok(scan.values(self.opts.inodes))
+        }
+
+        /// Total size in KB (or inode count) of everything below `path`,
+        /// like `directory_total_kb`
+        fn tree_size(&self, py: Python, path: &str) -> PyResult<u64> {
+            let scan = self.run(py, path)?;
+            Ok(scan.total(self.opts.inodes))
+        }
+
+        /// Counters over the scans so far: `scans`, `errors` (unreadable
+        /// paths), `seconds` spent scanning, `threads` and, with `dedup`,
+        /// `seen_files`
+        fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+            let stats = PyDict::new(py);
+            {
+                let counters = self.stats.lock();
+                stats.set_item("scans", counters.scans)?;
+                stats.set_item("errors", counters.errors)?;
+                stats.set_item("seconds", counters.seconds)?;
+            }
+            let threads = match &self.pool {
+                Some(pool) => pool.current_num_threads(),
+                None => rayon::current_num_threads(),
+            };
+            stats.set_item("threads", threads)?;
+            stats.set_item("seen_files", self.cache.seen_files())?;
+            Ok(stats)
+        }
+    }
+
+    /// Size each of `paths` as a whole, in parallel. Returns a dict in input
+    /// order mapping each path to `{"size_kb", "bytes", "inodes", "files"}`,
+    /// or to `{"error": exc}` with the `OSError` of a path that could not
+    /// be read; one failing path does not fail the others.
+    #[pyfunction]
+    #[pyo3(signature = (paths, cross_mounts=false, dirs_only=false, count_symlink_size=false, exclude=None, exclude_from=None, include=None, include_dir_overhead=true, skip_empty=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn sizes_for<'py>(
+        py: Python<'py>,
+        paths: Vec<String>,
+        cross_mounts: bool,
+        dirs_only: bool,
+        count_symlink_size: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+        include: Option<Vec<String>>,
+        include_dir_overhead: bool,
+        skip_empty: bool,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let opts = Options {
+            cross_mounts,
+            dirs_only,
+            count_symlink_size,
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            include: include.unwrap_or_default(),
+            include_dir_overhead,
+            skip_empty,
+            ..Options::default()
+        };
+        let results = detach_scan(py, || crate::core::sizes_for(&paths, &opts));
+
+        py.check_signals()?;
+
+        let sizes = PyDict::new(py);
+        for (path, result) in paths.iter().zip(results) {
+            let entry = PyDict::new(py);
+            match result {
+                Ok(stats) => {
+                    entry.set_item("size_kb", stats.size_kb)?;
+                    entry.set_item("bytes", stats.bytes)?;
+                    entry.set_item("inodes", stats.inodes)?;
+                    entry.set_item("files", stats.files)?;
+                }
+                Err(DustrError::Cancelled) => return Err(to_pyerr(py, DustrError::Cancelled)),
+                Err(e) => entry.set_item("error", to_pyerr(py, e).into_value(py))?,
+            }
+            sizes.set_item(path, entry)?;
+        }
+        Ok(sizes)
+    }
+
+    /// A rough forecast of a scan, from `estimate_scan`
+    #[pyclass(frozen, get_all, module = "dustr._dustr")]
+    struct EstimateResult {
+        estimated_seconds: f64,
+        estimated_entries: u64,
+        estimated_bytes: u64,
+        sampled_dirs: usize,
+    }
+
+    #[pymethods]
+    impl EstimateResult {
+        fn __repr__(&self) -> String {
             format!(
-                "[{}{}] 5/10",
-                ">".repeat(filled),
-                "-".repeat(BAR_WIDTH - filled)
+                "EstimateResult(estimated_seconds={:.1}, estimated_entries={}, estimated_bytes={}, sampled_dirs={})",
+                self.estimated_seconds,
+                self.estimated_entries,
+                self.estimated_bytes,
+                self.sampled_dirs
             )
-        );
+        }
     }
 
-    #[test]
-    fn progress_bar_full() {
-        let bar = format_progress_bar(10, 10);
-        assert_eq!(bar, format!("[{}] 10/10", ">".repeat(BAR_WIDTH)));
+    /// Estimate how long scanning a directory would take, and how many
+    /// entries and bytes it would find, from a bounded sample of its
+    /// subdirectories. Approximate: meant for "this may take ~2 minutes",
+    /// not for the figures themselves.
+    #[pyfunction]
+    #[pyo3(signature = (path, cross_mounts=false, exclude=None, exclude_from=None))]
+    fn estimate_scan(
+        py: Python,
+        path: &str,
+        cross_mounts: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+    ) -> PyResult<EstimateResult> {
+        let opts = Options {
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            cross_mounts,
+            ..Options::default()
+        };
+        let estimate = detach_scan(py, || crate::estimate::estimate_scan(path, &opts))
+            .map_err(|e| to_pyerr(py, e))?;
+        Ok(EstimateResult {
+            estimated_seconds: estimate.estimated_seconds,
+            estimated_entries: estimate.estimated_entries,
+            estimated_bytes: estimate.estimated_bytes,
+            sampled_dirs: estimate.sampled_dirs,
+        })
     }
 
-    #[test]
-    fn progress_bar_no_newline() {
-        let bar = format_progress_bar(3, 7);
-        assert!(!bar.contains('\n'));
+    /// `(total, free)` inodes of the filesystem containing `path`
+    #[pyfunction]
+    fn inode_capacity(py: Python, path: &str) -> PyResult<(u64, u64)> {
+        crate::core::inode_capacity(path)
+            .map(|capacity| (capacity.total, capacity.free))
+            .map_err(|e| to_pyerr(py, e))
+    }
+
+    /// `(total, used, free)` bytes of the filesystem containing `path`, like
+    /// `shutil.disk_usage`
+    #[pyfunction]
+    fn disk_usage(py: Python, path: &str) -> PyResult<(u64, u64, u64)> {
+        crate::core::disk_space(path)
+            .map(|space| (space.total, space.used, space.free))
+            .map_err(|e| to_pyerr(py, e))
+    }
+
+    /// List dangling symlinks under a directory as `(link, target)` tuples
+    #[pyfunction]
+    #[pyo3(signature = (path, cross_mounts=false, exclude=None, exclude_from=None))]
+    fn broken_symlinks(
+        py: Python,
+        path: &str,
+        cross_mounts: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+    ) -> PyResult<Vec<(String, String)>> {
+        let opts = Options {
+            check_links: true,
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            cross_mounts,
+            ..Options::default()
+        };
+        let result = detach_scan(py, || crate::core::scan_directory(path, &opts));
+
+        py.check_signals()?;
+
+        result
+            .map(|scan| scan.broken_links)
+            .map_err(|e| to_pyerr(py, e))
+    }
+
+    /// The entries that changed between two results of
+    /// `calculate_directory_sizes` (or `Scanner.scan`), largest change
+    /// first: a dict mapping each name to `{"old", "new", "delta"}`, with
+    /// `None` for an entry that was added or removed
+    #[pyfunction]
+    fn compare<'py>(
+        py: Python<'py>,
+        old: HashMap<String, u64>,
+        new: HashMap<String, u64>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let changes = PyDict::new(py);
+        for delta in crate::core::compare_values(&old, &new) {
+            let entry = PyDict::new(py);
+            entry.set_item("old", delta.old)?;
+            entry.set_item("new", delta.new)?;
+            entry.set_item("delta", delta.delta())?;
+            changes.set_item(&delta.name, entry)?;
+        }
+        Ok(changes)
+    }
+
+    /// Count and size (in bytes) of files, directories, symlinks and special
+    /// files under a directory, keyed by kind
+    #[pyfunction]
+    #[pyo3(signature = (path, cross_mounts=false, exclude=None, exclude_from=None))]
+    fn type_breakdown(
+        py: Python,
+        path: &str,
+        cross_mounts: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+    ) -> PyResult<HashMap<&'static str, HashMap<&'static str, u64>>> {
+        let opts = Options {
+            summary: true,
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            cross_mounts,
+            ..Options::default()
+        };
+        let result = detach_scan(py, || crate::core::scan_directory(path, &opts));
+
+        py.check_signals()?;
+
+        let scan = result.map_err(|e| to_pyerr(py, e))?;
+        Ok(scan
+            .types
+            .kinds()
+            .into_iter()
+            .map(|(key, _, totals)| {
+                let fields =
+                    HashMap::from([("count", totals.count), ("bytes", totals.size_kb * 1024)]);
+                (key, fields)
+            })
+            .collect())
+    }
+
+    /// Find groups of identical files (at least `min_size_kb` KB) under a
+    /// directory. Files are grouped by size, then by content hash, and
+    /// confirmed byte-for-byte; returns lists of paths, largest files first.
+    #[pyfunction]
+    #[pyo3(signature = (path, min_size_kb=0, cross_mounts=false, exclude=None, exclude_from=None))]
+    fn find_duplicates(
+        py: Python,
+        path: &str,
+        min_size_kb: u64,
+        cross_mounts: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+    ) -> PyResult<Vec<Vec<String>>> {
+        let opts = Options {
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            cross_mounts,
+            ..Options::default()
+        };
+        let result = detach_scan(py, || {
+            crate::duplicates::find_duplicates(path, min_size_kb, &opts)
+        });
+
+        py.check_signals()?;
+
+        result.map_err(|e| to_pyerr(py, e))
+    }
+
+    /// Usage at each depth below a directory: `(depth, bytes, files)` for
+    /// the top-level entries (depth 1), their children (depth 2) and so
+    /// on, in order. Directories count at their own depth.
+    #[pyfunction]
+    #[pyo3(signature = (path, cross_mounts=false, exclude=None, exclude_from=None))]
+    fn usage_by_depth(
+        py: Python,
+        path: &str,
+        cross_mounts: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+    ) -> PyResult<Vec<(usize, u64, u64)>> {
+        let opts = Options {
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            cross_mounts,
+            ..Options::default()
+        };
+        let result = detach_scan(py, || crate::core::usage_by_depth(path, &opts));
+
+        py.check_signals()?;
+
+        let usage = result.map_err(|e| to_pyerr(py, e))?;
+        Ok(usage
+            .levels
+            .iter()
+            .map(|level| (level.depth, level.bytes, level.files))
+            .collect())
+    }
+
+    /// Statistics of the regular-file sizes below a directory, as a dict
+    /// with `count`, `total`, `mean`, `median`, `p90`, `p99` and `max` (in
+    /// bytes; all 0 without files). The percentiles are exact up to
+    /// 100,000 files and within 1% beyond.
+    #[pyfunction]
+    #[pyo3(signature = (path, cross_mounts=false, exclude=None, exclude_from=None))]
+    fn file_size_stats<'py>(
+        py: Python<'py>,
+        path: &str,
+        cross_mounts: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let opts = Options {
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            cross_mounts,
+            ..Options::default()
+        };
+        let result = detach_scan(py, || crate::core::file_size_stats(path, &opts));
+
+        py.check_signals()?;
+
+        let stats = result.map_err(|e| to_pyerr(py, e))?;
+        let dict = PyDict::new(py);
+        dict.set_item("count", stats.count)?;
+        dict.set_item("total", stats.total)?;
+        dict.set_item("mean", stats.mean())?;
+        dict.set_item("median", stats.quantile(0.5))?;
+        dict.set_item("p90", stats.quantile(0.9))?;
+        dict.set_item("p99", stats.quantile(0.99))?;
+        dict.set_item("max", stats.max)?;
+        Ok(dict)
+    }
+
+    /// Regular files below a directory modified less than `since_seconds`
+    /// ago and at least `min_size` bytes large, largest first, as dicts
+    /// with `path`, `bytes` and `mtime` (epoch seconds), e.g. to alert on
+    #[pyfunction]
+    #[pyo3(signature = (path, since_seconds, min_size=0, cross_mounts=false, exclude=None, exclude_from=None))]
+    fn recent_files<'py>(
+        py: Python<'py>,
+        path: &str,
+        since_seconds: u64,
+        min_size: u64,
+        cross_mounts: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+    ) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let opts = Options {
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            cross_mounts,
+            ..Options::default()
+        };
+        let result = detach_scan(py, || {
+            crate::core::recent_files(path, since_seconds, min_size, &opts)
+        });
+
+        py.check_signals()?;
+
+        result
+            .map_err(|e| to_pyerr(py, e))?
+            .into_iter()
+            .map(|file| {
+                let dict = PyDict::new(py);
+                dict.set_item("path", file.path)?;
+                dict.set_item("bytes", file.bytes)?;
+                dict.set_item("mtime", file.mtime)?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
+    /// Bytes at each depth below a directory, keyed by depth: the same
+    /// sizes as `usage_by_depth`, for lookups rather than plotting
+    #[pyfunction]
+    #[pyo3(signature = (path, cross_mounts=false, exclude=None, exclude_from=None))]
+    fn sizes_by_depth(
+        py: Python,
+        path: &str,
+        cross_mounts: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+    ) -> PyResult<HashMap<usize, u64>> {
+        Ok(
+            usage_by_depth(py, path, cross_mounts, exclude, exclude_from)?
+                .into_iter()
+                .map(|(depth, bytes, _)| (depth, bytes))
+                .collect(),
+        )
+    }
+
+    /// The `n` directories below a directory with the most direct
+    /// children, as `(path, direct_children, recursive_children)` with the
+    /// largest first; ties are broken by path.
+    #[pyfunction]
+    #[pyo3(signature = (path, n, cross_mounts=false, exclude=None, exclude_from=None))]
+    fn inode_hogs(
+        py: Python,
+        path: &str,
+        n: u64,
+        cross_mounts: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+    ) -> PyResult<Vec<(String, u64, u64)>> {
+        if n == 0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "n must be at least 1",
+            ));
+        }
+        let opts = Options {
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            cross_mounts,
+            ..Options::default()
+        };
+        let result = detach_scan(py, || crate::core::inode_hogs(path, n, &opts));
+
+        py.check_signals()?;
+
+        let hogs = result.map_err(|e| to_pyerr(py, e))?;
+        Ok(hogs
+            .into_iter()
+            .map(|hog| (hog.path, hog.direct, hog.recursive))
+            .collect())
+    }
+
+    /// Write every file under a directory with its size in KB to
+    /// `output_file`, as a JSON object keyed by relative path and sorted so
+    /// that dumps can be committed and diffed; returns the number of files
+    #[pyfunction]
+    #[pyo3(signature = (path, output_file, cross_mounts=false, exclude=None, exclude_from=None))]
+    fn dump_tree_json(
+        py: Python,
+        path: &str,
+        output_file: &str,
+        cross_mounts: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+    ) -> PyResult<usize> {
+        let opts = Options {
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            cross_mounts,
+            ..Options::default()
+        };
+        let result = detach_scan(py, || {
+            crate::inventory::dump_tree_json(path, output_file, &opts)
+        });
+
+        py.check_signals()?;
+
+        result.map_err(|e| to_pyerr(py, e))
+    }
+
+    /// Find empty directories under a directory. With `recursive_empty`,
+    /// directories holding only empty directories are reported too.
+    #[pyfunction]
+    #[pyo3(signature = (path, recursive_empty=true, cross_mounts=false, exclude=None, exclude_from=None))]
+    fn find_empty_dirs(
+        py: Python,
+        path: &str,
+        recursive_empty: bool,
+        cross_mounts: bool,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+    ) -> PyResult<Vec<String>> {
+        let opts = Options {
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            cross_mounts,
+            ..Options::default()
+        };
+        let result = detach_scan(py, || {
+            crate::empty_dirs::find_empty_dirs(path, recursive_empty, &opts)
+        });
+
+        py.check_signals()?;
+
+        result.map_err(|e| to_pyerr(py, e))
+    }
+
+    /// Get file type indicator (@ for symlinks, @/ for symlinks to
+    /// directories, / for directories, empty for files). With `strict`, a
+    /// path that does not exist raises FileNotFoundError instead of
+    /// yielding an empty indicator.
+    #[pyfunction]
+    #[pyo3(signature = (path, strict=false))]
+    fn get_file_type_indicator(py: Python, path: &str, strict: bool) -> PyResult<String> {
+        if strict && crate::core::classify_path(path) == PathKind::Missing {
+            return Err(to_pyerr(
+                py,
+                DustrError::NotFound(format!("Path not found: {}", path), Some(libc::ENOENT)),
+            ));
+        }
+        Ok(crate::core::get_file_type_indicator(path))
+    }
+
+    /// Classify a path as "file", "dir", "symlink", "symlink-dir",
+    /// "broken-symlink", "socket", "fifo", "device" or "missing"
+    #[pyfunction]
+    fn classify_path(path: &str) -> &'static str {
+        crate::core::classify_path(path).as_str()
+    }
+
+    /// Print the complete disk usage analysis
+    #[pyfunction]
+    #[pyo3(signature = (dirname, inodes=false, no_grouping=false, no_f=false, json=false, cross_mounts=false, verbose=0, live=false, prometheus=false, output=None, precision=1, dirs_only=false, sort="size", natural=false, check_links=false, count_symlink_size=false, time=false, time_style="iso", min_age=None, max_age=None, streaming=false, summary=false, limits=None, exclude=None, exclude_from=None, include=None, avg=false, prune_known=false, prune_add=None, prune_remove=None, prune_skip=false, si=false, binary=false, block_size=None, group_separator=None, scale="linear", bar_style="ascii", bar_char=None, color="auto", cumulative=false, both=false, format="table", treemap=None, treemap_depth=3, include_dir_overhead=true, null=false, max_name_width=None, histogram_style="per-entry", min_mark_percent=0.0, skip_empty=false, legend=false, show_raw=false, exact=false, dirs_only_display=false, total_all=false, shallow=false, show_mtime=false, top=None, aggregate_remainder=false, strict=false, show_free_space=false, unit=None, case_sensitive=false, detect_cycles=false, size_histogram=false, buckets=None, show_root_row=false, duplicates=false, dup_min_size=None, threads=None, no_progress=false, find_empty=false, find_empty_limit=None, by_depth=false, max_depth_rows=None, inode_hogs=None, find_junk=false, junk_pattern=None, find_junk_limit=None, stats=false, recent=None, recent_min_size=None, recent_limit=None, timed=false, xattrs=false, highlight=None, strict_limits=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn print_disk_usage(
+        py: Python,
+        dirname: &str,
+        inodes: bool,
+        no_grouping: bool,
+        no_f: bool,
+        json: bool,
+        cross_mounts: bool,
+        verbose: u8,
+        live: bool,
+        prometheus: bool,
+        output: Option<String>,
+        precision: usize,
+        dirs_only: bool,
+        sort: &str,
+        natural: bool,
+        check_links: bool,
+        count_symlink_size: bool,
+        time: bool,
+        time_style: &str,
+        min_age: Option<&str>,
+        max_age: Option<&str>,
+        streaming: bool,
+        summary: bool,
+        limits: Option<&str>,
+        exclude: Option<Vec<String>>,
+        exclude_from: Option<String>,
+        include: Option<Vec<String>>,
+        avg: bool,
+        prune_known: bool,
+        prune_add: Option<Vec<String>>,
+        prune_remove: Option<Vec<String>>,
+        prune_skip: bool,
+        si: bool,
+        binary: bool,
+        block_size: Option<&str>,
+        group_separator: Option<char>,
+        scale: &str,
+        bar_style: &str,
+        bar_char: Option<char>,
+        color: &str,
+        cumulative: bool,
+        both: bool,
+        format: &str,
+        treemap: Option<String>,
+        treemap_depth: u64,
+        include_dir_overhead: bool,
+        null: bool,
+        max_name_width: Option<usize>,
+        histogram_style: &str,
+        min_mark_percent: f64,
+        skip_empty: bool,
+        legend: bool,
+        show_raw: bool,
+        exact: bool,
+        dirs_only_display: bool,
+        total_all: bool,
+        shallow: bool,
+        show_mtime: bool,
+        top: Option<usize>,
+        aggregate_remainder: bool,
+        strict: bool,
+        show_free_space: bool,
+        unit: Option<&str>,
+        case_sensitive: bool,
+        detect_cycles: bool,
+        size_histogram: bool,
+        buckets: Option<Vec<String>>,
+        show_root_row: bool,
+        duplicates: bool,
+        dup_min_size: Option<&str>,
+        threads: Option<u64>,
+        no_progress: bool,
+        find_empty: bool,
+        find_empty_limit: Option<usize>,
+        by_depth: bool,
+        max_depth_rows: Option<u64>,
+        inode_hogs: Option<u64>,
+        find_junk: bool,
+        junk_pattern: Option<Vec<String>>,
+        find_junk_limit: Option<usize>,
+        stats: bool,
+        recent: Option<&str>,
+        recent_min_size: Option<&str>,
+        recent_limit: Option<usize>,
+        timed: bool,
+        xattrs: bool,
+        highlight: Option<Vec<String>>,
+        strict_limits: bool,
+    ) -> PyResult<()> {
+        if both && inodes {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "both and inodes cannot be combined",
+            ));
+        }
+        if si && binary {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "si and binary cannot be combined",
+            ));
+        }
+        if natural && case_sensitive {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "natural and case_sensitive cannot be combined",
+            ));
+        }
+        let block_size = block_size
+            .map(BlockSize::parse)
+            .transpose()
+            .map_err(pyo3::exceptions::PyValueError::new_err)?;
+        if block_size.is_some() && (si || binary) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "block_size cannot be combined with si or binary",
+            ));
+        }
+        if block_size.is_some() && unit.is_some() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "block_size cannot be combined with unit",
+            ));
+        }
+        if total_all && !dirs_only_display {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "total_all requires dirs_only_display",
+            ));
+        }
+        if treemap_depth == 0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "treemap_depth must be at least 1",
+            ));
+        }
+        if buckets.is_some() && !size_histogram {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "buckets requires size_histogram",
+            ));
+        }
+        if dup_min_size.is_some() && !duplicates {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "dup_min_size requires duplicates",
+            ));
+        }
+        if max_depth_rows.is_some() && !by_depth {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "max_depth_rows requires by_depth",
+            ));
+        }
+        if max_depth_rows == Some(0) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "max_depth_rows must be at least 1",
+            ));
+        }
+        if inode_hogs == Some(0) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "inode_hogs must be at least 1",
+            ));
+        }
+        if (junk_pattern.is_some() || find_junk_limit.is_some()) && !find_junk {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "junk_pattern and find_junk_limit require find_junk",
+            ));
+        }
+        if find_empty_limit.is_some() && !find_empty {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "find_empty_limit requires find_empty",
+            ));
+        }
+        if threads == Some(0) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "threads must be at least 1",
+            ));
+        }
+        let dup_min_size = crate::core::parse_size_arg(dup_min_size.unwrap_or("1M"))
+            .map_err(pyo3::exceptions::PyValueError::new_err)?;
+        if (recent_min_size.is_some() || recent_limit.is_some()) && recent.is_none() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "recent_min_size and recent_limit require recent",
+            ));
+        }
+        let recent_min_size = crate::core::parse_size_arg(recent_min_size.unwrap_or("1M"))
+            .map_err(pyo3::exceptions::PyValueError::new_err)?;
+        let buckets = buckets
+            .unwrap_or_default()
+            .iter()
+            .map(|size| crate::core::parse_size_arg(size))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(pyo3::exceptions::PyValueError::new_err)?;
+        if strict_limits && limits.is_none() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "strict_limits requires limits",
+            ));
+        }
+        let limits = limits
+            .map(crate::limits::Limits::load)
+            .transpose()
+            .map_err(|e| to_pyerr(py, e))?;
+        let opts = Options {
+            inodes,
+            both,
+            dirs_only,
+            only_dirs: dirs_only_display,
+            total_all,
+            shallow,
+            top,
+            aggregate_remainder,
+            show_root: show_root_row,
+            strict,
+            nogrouping: no_grouping,
+            group_separator,
+            no_f,
+            json,
+            prometheus,
+            format: parse_enum::<ReportFormat>("format", format)?,
+            null,
+            output,
+            treemap,
+            treemap_depth,
+            precision,
+            sort: parse_enum::<SortKey>("sort", sort)?,
+            natural,
+            case_sensitive,
+            time: time || show_mtime,
+            time_style: parse_enum::<TimeStyle>("time_style", time_style)?,
+            scale: parse_enum::<HistogramScale>("scale", scale)?,
+            histogram_style: parse_enum::<HistogramStyle>("histogram_style", histogram_style)?,
+            min_mark_percent,
+            bar_style: parse_enum::<BarStyle>("bar_style", bar_style)?,
+            bar_char,
+            color: parse_enum::<ColorChoice>("color", color)?,
+            legend,
+            show_raw,
+            exact,
+            check_links,
+            count_symlink_size,
+            detect_cycles,
+            include_dir_overhead,
+            skip_empty,
+            older_than: parse_age("min_age", min_age)?,
+            newer_than: parse_age("max_age", max_age)?,
+            streaming,
+            summary,
+            size_histogram,
+            buckets,
+            stats,
+            duplicates,
+            dup_min_size,
+            recent: parse_age("recent", recent)?,
+            recent_min_size,
+            recent_limit: recent_limit.unwrap_or(20),
+            timing: timed,
+            xattrs,
+            highlight: highlight.unwrap_or_default(),
+            strict_limits,
+            find_empty,
+            find_empty_limit: find_empty_limit.unwrap_or(20),
+            find_junk,
+            junk_pattern: junk_pattern.unwrap_or_default(),
+            find_junk_limit: find_junk_limit.unwrap_or(20),
+            by_depth,
+            max_depth_rows: max_depth_rows.unwrap_or(10),
+            inode_hogs,
+            threads,
+            no_progress,
+            free: show_free_space,
+            limits,
+            exclude: exclude.unwrap_or_default(),
+            exclude_from,
+            include: include.unwrap_or_default(),
+            avg,
+            max_name_width,
+            cumulative,
+            prune_known,
+            prune_add: prune_add.unwrap_or_default(),
+            prune_remove: prune_remove.unwrap_or_default(),
+            prune_skip,
+            si,
+            binary,
+            block_size,
+            unit: unit
+                .map(|unit| parse_enum::<SizeUnit>("unit", unit))
+                .transpose()?,
+            cross_mounts,
+            verbose,
+            live,
+            ..Options::default()
+        };
+        opts.validate()
+            .map_err(pyo3::exceptions::PyValueError::new_err)?;
+        run_print_disk_usage(py, dirname, &opts, None)
+    }
+
+    /// The `dustr` logger of Python's `logging` module, looked up once
+    static LOGGER: PyOnceLock<Py<PyAny>> = PyOnceLock::new();
+
+    fn logger(py: Python<'_>) -> PyResult<&Bound<'_, PyAny>> {
+        LOGGER
+            .get_or_try_init(py, || {
+                let logging = py.import("logging")?;
+                Ok(logging.call_method1("getLogger", ("dustr",))?.unbind())
+            })
+            .map(|logger| logger.bind(py))
+    }
+
+    /// The `logging` level each diagnostic is logged at
+    fn python_level(level: diagnostics::Level) -> u32 {
+        match level {
+            diagnostics::Level::Trace => 5,
+            diagnostics::Level::Debug => 10,
+            diagnostics::Level::Warning => 30,
+        }
+    }
+
+    /// Lowest `logging` level the `dustr` logger handles, read before each
+    /// scan by `sync_log_level`
+    static LOG_LEVEL: AtomicU32 = AtomicU32::new(0);
+
+    /// Forward diagnostics to the `dustr` logger: unreadable paths at
+    /// WARNING, traversal notes at DEBUG and the directory trace of
+    /// `verbose=2` at level 5. Messages the logger would discard are
+    /// dropped without taking the GIL.
+    fn python_logging_sink() -> diagnostics::Sink {
+        Arc::new(|level, message| {
+            if python_level(level) < LOG_LEVEL.load(Ordering::Relaxed) {
+                return;
+            }
+            Python::attach(|py| {
+                // A failing log call must not abort the scan
+                let _ = logger(py)
+                    .and_then(|logger| logger.call_method1("log", (python_level(level), message)));
+            });
+        })
+    }
+
+    /// Read which levels the `dustr` logger handles, so that the sink can
+    /// drop the other diagnostics without taking the GIL
+    fn sync_log_level(py: Python<'_>) {
+        use diagnostics::Level;
+
+        let enabled = |level| {
+            logger(py)
+                .and_then(|logger| logger.call_method1("isEnabledFor", (python_level(level),)))
+                .and_then(|enabled| enabled.is_truthy())
+                .unwrap_or(true)
+        };
+        let min = [Level::Trace, Level::Debug, Level::Warning]
+            .into_iter()
+            .find(|&level| enabled(level))
+            .map_or(u32::MAX, python_level);
+        LOG_LEVEL.store(min, Ordering::Relaxed);
+    }
+
+    /// Run a scan with the GIL released, once the logging level is known
+    fn detach_scan<T: Ungil>(py: Python<'_>, scan: impl Ungil + FnOnce() -> T) -> T {
+        sync_log_level(py);
+        py.detach(scan)
+    }
+
+    /// Build a `ScanTruncated` exception for a truncated scan. The partial
+    /// values (inode counts with `use_inodes`) are in the message and, as a
+    /// dict, in the `partial_results` attribute.
+    fn scan_truncated(py: Python, scan: &crate::core::ScanResult, use_inodes: bool) -> PyErr {
+        let dict = pyo3::types::PyDict::new(py);
+        let mut entries: Vec<_> = scan.values(use_inodes).into_iter().collect();
+        entries.sort();
+        for (name, value) in &entries {
+            let _ = dict.set_item(name, value);
+        }
+        let reason = scan.truncated.map(|r| r.to_string()).unwrap_or_default();
+        let repr = dict.repr().map(|r| r.to_string()).unwrap_or_default();
+        let err = ScanTruncated::new_err(format!(
+            "Scan truncated ({}); partial results: {}",
+            reason, repr
+        ));
+        let _ = err.value(py).setattr("partial_results", dict);
+        let _ = err.value(py).setattr("reason", reason);
+        err
+    }
+
+    /// Run the analysis with the GIL released, then surface any pending
+    /// signal. `watch` carries state between `--watch` refreshes.
+    fn run_print_disk_usage(
+        py: Python,
+        dirname: &str,
+        opts: &Options,
+        watch: Option<&mut WatchState>,
+    ) -> PyResult<()> {
+        let result = detach_scan(py, || match watch {
+            Some(state) => crate::core::print_watch_cycle(dirname, opts, state),
+            None => crate::core::print_disk_usage(dirname, opts),
+        });
+
+        py.check_signals()?;
+
+        result.map_err(|e| match e {
+            DustrError::Truncated(scan) => scan_truncated(py, &scan, opts.inodes),
+            e => to_pyerr(py, e),
+        })
+    }
+
+    /// Format a count with digit grouping. The separator defaults to the one
+    /// of the current locale (LC_ALL, LC_NUMERIC or LANG).
+    #[pyfunction]
+    #[pyo3(signature = (num, separator=None))]
+    fn group_digits(num: u64, separator: Option<char>) -> String {
+        let opts = Options {
+            group_separator: separator,
+            ..Options::default()
+        };
+        opts.group_digits(num)
+    }
+
+    /// Return the dustr version string
+    #[pyfunction]
+    fn version() -> &'static str {
+        crate::core::VERSION
+    }
+
+    /// Describe the build: version, target triple, compiler, Cargo profile
+    /// and the version of the structured report formats
+    #[pyfunction]
+    fn build_info(py: Python<'_>) -> PyResult<Bound<'_, pyo3::types::PyDict>> {
+        let info = pyo3::types::PyDict::new(py);
+        info.set_item("version", crate::core::VERSION)?;
+        info.set_item("target", crate::core::TARGET)?;
+        info.set_item("rustc", crate::core::RUSTC_VERSION)?;
+        info.set_item("profile", crate::core::PROFILE)?;
+        info.set_item("format_version", crate::core::FORMAT_VERSION)?;
+        Ok(info)
+    }
+
+    /// Main entry point for the dustr command (called from Python)
+    #[pyfunction]
+    #[pyo3(signature = (args=vec![], quiet_errors=false))]
+    fn main(py: Python, args: Vec<String>, quiet_errors: bool) -> PyResult<()> {
+        use clap::{CommandFactory, FromArgMatches};
+        use std::io::Write;
+        let cmd = crate::core::Cli::command().name("dustr");
+        let (args, defaults) = match crate::config::Defaults::with_args(
+            &cmd,
+            "dustr",
+            args.into_iter().map(Into::into).collect(),
+        ) {
+            Ok(merged) => merged,
+            Err(msg) => {
+                let _ = cmd
+                    .clone()
+                    .error(clap::error::ErrorKind::InvalidValue, msg)
+                    .print();
+                return Ok(());
+            }
+        };
+        let parsed = cmd
+            .clone()
+            .try_get_matches_from(args)
+            .and_then(|matches| Ok((crate::core::Cli::from_arg_matches(&matches)?, matches)));
+        let (mut cli, matches) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                // Help and version go to stdout, usage errors to stderr
+                let _ = e.print();
+                return Ok(());
+            }
+        };
+        if cli.dump_config {
+            let _ = defaults.dump(&mut std::io::stdout(), &cmd, &matches);
+            return Ok(());
+        }
+        if let Some(crate::core::CliCommand::Completions { shell }) = cli.command {
+            let script = crate::completions::generate(&cmd, shell);
+            let _ = std::io::Write::write_all(&mut std::io::stdout(), script.as_bytes());
+            return Ok(());
+        }
+        cli.opts.apply_block_size_env();
+        if let Err(msg) = cli.opts.validate() {
+            let _ = cmd
+                .clone()
+                .error(clap::error::ErrorKind::ArgumentConflict, msg)
+                .print();
+            return Ok(());
+        }
+
+        // Behave like dustr-cli: diagnostics go to stderr per --verbose/--quiet
+        let previous =
+            diagnostics::set_sink(Some(diagnostics::stderr_sink(cli.opts.diagnostics_level())));
+        let result = match (&cli.files0_from, cli.opts.watch) {
+            (Some(source), _) => {
+                let result = detach_scan(py, || crate::core::print_sizes_for(source, &cli.opts));
+                py.check_signals()?;
+                match result {
+                    // Like dustr-cli: the failed paths have been warned about
+                    Err(e @ DustrError::OsError(..)) => {
+                        eprintln!("dustr: {}", e);
+                        Err(pyo3::exceptions::PySystemExit::new_err(1))
+                    }
+                    result => result.map_err(|e| to_pyerr(py, e)),
+                }
+            }
+            (None, Some(interval)) => run_watch(py, &cli.dirname, &cli.opts, interval),
+            (None, None) => run_print_disk_usage(py, &cli.dirname, &cli.opts, None),
+        };
+        diagnostics::set_sink(previous);
+        match result {
+            // Like dustr-cli: the partial report has been printed, so
+            // report the truncation and exit with status 3
+            Err(e) if e.is_instance_of::<ScanTruncated>(py) => {
+                let reason = e.value(py).getattr("reason")?;
+                eprintln!("dustr: Scan truncated ({})", reason);
+                Err(pyo3::exceptions::PySystemExit::new_err(3))
+            }
+            // Like dustr-cli: the report with the violations has been printed
+            Err(e) if e.is_instance_of::<LimitsExceeded>(py) => {
+                eprintln!("dustr: {}", e.value(py));
+                Err(pyo3::exceptions::PySystemExit::new_err(1))
+            }
+            // The reader went away (e.g. `| head`): stop quietly with the
+            // status of a process killed by SIGPIPE
+            Err(e) if e.is_instance_of::<pyo3::exceptions::PyBrokenPipeError>(py) => {
+                Err(pyo3::exceptions::PySystemExit::new_err(141))
+            }
+            // With --quiet-errors, report the error plainly here instead of
+            // leaving it to the decorative messages of the Python entry point
+            Err(e)
+                if (quiet_errors || cli.quiet_errors)
+                    && !e.is_instance_of::<pyo3::exceptions::PySystemExit>(py) =>
+            {
+                if !e.is_instance_of::<pyo3::exceptions::PyKeyboardInterrupt>(py) {
+                    let value = e.value(py);
+                    let message = match value.getattr("strerror") {
+                        Ok(strerror) if !strerror.is_none() => strerror.to_string(),
+                        _ => value.to_string(),
+                    };
+                    let _ = writeln!(std::io::stderr(), "dustr: {}", message);
+                }
+                Err(pyo3::exceptions::PySystemExit::new_err(1))
+            }
+            result => result,
+        }
+    }
+
+    /// Re-run the analysis every `interval_secs` seconds until interrupted
+    #[pyfunction]
+    #[pyo3(signature = (path, interval_secs=2))]
+    fn watch(py: Python, path: &str, interval_secs: u64) -> PyResult<()> {
+        if interval_secs == 0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "interval_secs must be at least 1",
+            ));
+        }
+        run_watch(py, path, &Options::default(), interval_secs)
+    }
+
+    /// Watch loop driven from Python so Ctrl+C surfaces as KeyboardInterrupt
+    fn run_watch(py: Python, path: &str, opts: &Options, interval_secs: u64) -> PyResult<()> {
+        let mut state = WatchState::default();
+        loop {
+            if opts.output.is_none() {
+                crate::core::print_watch_header(path, interval_secs);
+            }
+            run_print_disk_usage(py, path, opts, Some(&mut state))?;
+            for _ in 0..interval_secs * 10 {
+                py.detach(|| std::thread::sleep(std::time::Duration::from_millis(100)));
+                py.check_signals()?;
+            }
+        }
+    }
+
+    /// Python module definition
+    #[pymodule]
+    pub fn _dustr(m: &Bound<'_, PyModule>) -> PyResult<()> {
+        diagnostics::set_sink(Some(python_logging_sink()));
+        m.add("ScanTruncated", m.py().get_type::<ScanTruncated>())?;
+        m.add("LimitsExceeded", m.py().get_type::<LimitsExceeded>())?;
+        m.add_class::<Scanner>()?;
+        m.add_class::<EntryInfo>()?;
+        m.add_class::<EstimateResult>()?;
+        m.add_function(wrap_pyfunction!(calculate_directory_sizes, m)?)?;
+        m.add_function(wrap_pyfunction!(scan_async, m)?)?;
+        m.add_function(wrap_pyfunction!(start_scan, m)?)?;
+        m.add_class::<ScanHandle>()?;
+        m.add_function(wrap_pyfunction!(calculate_both, m)?)?;
+        m.add_function(wrap_pyfunction!(directory_total_kb, m)?)?;
+        m.add_function(wrap_pyfunction!(inode_capacity, m)?)?;
+        m.add_function(wrap_pyfunction!(disk_usage, m)?)?;
+        m.add_function(wrap_pyfunction!(sizes_for, m)?)?;
+        m.add_function(wrap_pyfunction!(compare, m)?)?;
+        m.add_function(wrap_pyfunction!(get_file_type_indicator, m)?)?;
+        m.add_function(wrap_pyfunction!(classify_path, m)?)?;
+        m.add_function(wrap_pyfunction!(broken_symlinks, m)?)?;
+        m.add_function(wrap_pyfunction!(type_breakdown, m)?)?;
+        m.add_function(wrap_pyfunction!(find_duplicates, m)?)?;
+        m.add_function(wrap_pyfunction!(dump_tree_json, m)?)?;
+        m.add_function(wrap_pyfunction!(find_empty_dirs, m)?)?;
+        m.add_function(wrap_pyfunction!(usage_by_depth, m)?)?;
+        m.add_function(wrap_pyfunction!(inode_hogs, m)?)?;
+        m.add_function(wrap_pyfunction!(sizes_by_depth, m)?)?;
+        m.add_function(wrap_pyfunction!(estimate_scan, m)?)?;
+        m.add_function(wrap_pyfunction!(file_size_stats, m)?)?;
+        m.add_function(wrap_pyfunction!(recent_files, m)?)?;
+        m.add_function(wrap_pyfunction!(print_disk_usage, m)?)?;
+        m.add_function(wrap_pyfunction!(watch, m)?)?;
+        m.add_function(wrap_pyfunction!(version, m)?)?;
+        m.add_function(wrap_pyfunction!(build_info, m)?)?;
+        m.add("__version__", crate::core::VERSION)?;
+        m.add_function(wrap_pyfunction!(group_digits, m)?)?;
+        m.add_function(wrap_pyfunction!(main, m)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "extension-module")]
+pub use python::_dustr;
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{
+        color_enabled, format_iso8601, format_progress, format_progress_bar,
+        format_progress_bar_with, format_relative_time, format_size, format_size_binary,
+        format_size_si, format_spinner, format_with_grouping, histogram_marks, natural_cmp,
+        parse_size_arg, running_percentages, sort_entries, top_entries, truncate_name,
+        write_prometheus, AgeSpec, BarGlyphs, BlockSize, Cli, ColorChoice, DigitGrouping,
+        EntryStats, HistogramScale, Options, ScanResult, SortKey, BAR_WIDTH, KNOWN_JUNK_DIRS,
+    };
+    use crate::export::{html_escape, markdown_escape};
+    use crate::glob::{glob_match, GlobSet};
+    use crate::limits::Limits;
+    use crate::treemap::{squarify, Rect};
+
+    #[test]
+    fn progress_bar_zero_total() {
+        let bar = format_progress_bar(0, 0);
+        assert_eq!(bar, format!("[{}] 0/0", "-".repeat(BAR_WIDTH)));
+    }
+
+    #[test]
+    fn progress_bar_half() {
+        let bar = format_progress_bar(5, 10);
+        let filled = BAR_WIDTH / 2;
+        assert_eq!(
+            bar,
+            format!(
+                "[{}{}] 5/10",
+                ">".repeat(filled),
+                "-".repeat(BAR_WIDTH - filled)
+            )
+        );
+    }
+
+    #[test]
+    fn progress_bar_full() {
+        let bar = format_progress_bar(10, 10);
+        assert_eq!(bar, format!("[{}] 10/10", ">".repeat(BAR_WIDTH)));
+    }
+
+    #[test]
+    fn progress_bar_no_newline() {
+        let bar = format_progress_bar(3, 7);
+        assert!(!bar.contains('\n'));
+    }
+
+    #[test]
+    fn progress_unknown_total_shows_spinner() {
+        assert_eq!(format_spinner(0), "[|] 0");
+        assert_eq!(format_spinner(15), "[/] 15");
+        assert_eq!(format_spinner(35), "[\\] 35");
+        let ascii = BarGlyphs::Char('>');
+        assert_eq!(format_progress(3, None, ascii), format_spinner(3));
+        assert_eq!(
+            format_progress(3, Some(7), ascii),
+            format_progress_bar(3, 7)
+        );
+    }
+
+    #[test]
+    fn block_bars_use_eighths_and_keep_width() {
+        let blocks = BarGlyphs::Blocks;
+        assert_eq!(blocks.render(0), "");
+        assert_eq!(blocks.render(1), "\u{258F}");
+        assert_eq!(blocks.render(8), "\u{2588}");
+        assert_eq!(blocks.render(12), "\u{2588}\u{258C}");
+        assert_eq!(BarGlyphs::Char('=').render(3), "===");
+
+        // 1/40 of the largest entry gets half a cell, not a full mark
+        let units = histogram_marks(1, 40, 20 * blocks.steps(), HistogramScale::Linear);
+        assert_eq!(blocks.render(units), "\u{258C}");
+
+        for (current, total) in [(0, 7), (1, 7), (3, 7), (7, 7), (0, 0)] {
+            let bar = format_progress_bar_with(current, total, blocks);
+            let cells = bar.chars().skip(1).take_while(|&c| c != ']').count();
+            assert_eq!(cells, BAR_WIDTH, "{}", bar);
+        }
+        let padded = format!("{:<20}|", blocks.render(12));
+        assert_eq!(padded.chars().count(), 21);
+        assert!(format_progress_bar_with(7, 7, blocks)
+            .starts_with(&format!("[{}]", "\u{2588}".repeat(BAR_WIDTH))));
+    }
+
+    #[test]
+    fn prometheus_output_escapes_labels() {
+        let mut scan = ScanResult::default();
+        scan.entries.insert(
+            "we\"ird\\name".to_string(),
+            EntryStats {
+                size_kb: 2,
+                bytes: 2048,
+                inodes: 3,
+                ..EntryStats::default()
+            },
+        );
+        let mut buf = Vec::new();
+        write_prometheus(&mut buf, "/var/lib", &scan).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(text.contains(
+            "dustr_directory_bytes{root=\"/var/lib\",entry=\"we\\\"ird\\\\name\"} 2048\n"
+        ));
+        assert!(text
+            .contains("dustr_directory_inodes{root=\"/var/lib\",entry=\"we\\\"ird\\\\name\"} 3\n"));
+        for metric in [
+            "dustr_directory_bytes",
+            "dustr_directory_inodes",
+            "dustr_scan_duration_seconds",
+            "dustr_scan_errors_total",
+        ] {
+            assert!(text.contains(&format!("# HELP {} ", metric)));
+            assert!(text.contains(&format!("# TYPE {} ", metric)));
+        }
+    }
+
+    #[test]
+    fn format_size_precision_rounds() {
+        assert_eq!(format_size(1_600, 0), "2 MB");
+        assert_eq!(format_size(1_400, 0), "1 MB");
+        assert_eq!(format_size(12_345, 2), "12.35 MB");
+        assert_eq!(format_size(512, 1), "512.0 KB");
+    }
+
+    #[test]
+    fn fixed_unit_does_not_scale() {
+        use crate::core::{format_size_in, SizeUnit};
+
+        assert_eq!(format_size_in(512, SizeUnit::M, 1), "0.5 MB");
+        assert_eq!(format_size_in(12_345_678, SizeUnit::M, 0), "12346 MB");
+        assert_eq!(format_size_in(1_500, SizeUnit::K, 0), "1500 KB");
+        assert_eq!(format_size_in(2_500_000_000, SizeUnit::T, 2), "2.50 TB");
+
+        let mut opts = Options {
+            unit: Some(SizeUnit::M),
+            binary: true,
+            ..Options::default()
+        };
+        assert_eq!(opts.format_size(512), "0.5 MiB");
+        assert_eq!(opts.format_size(3 * 1024 * 1024), "3072.0 MiB");
+        opts.binary = false;
+        opts.si = true;
+        assert_eq!(opts.format_size(1_000_000), "1024.0 MB");
+    }
+
+    #[test]
+    fn si_units_cross_prefix_boundaries() {
+        let cases = [
+            (0, 1, "0.0 B"),
+            (1, 1, "1.0 kB"),
+            (1, 3, "1.024 kB"),
+            (976, 1, "999.4 kB"),
+            (977, 1, "1.0 MB"),
+            // 999.999488 MB rounds to 1000.0, so it moves up a unit
+            (976_562, 1, "1.0 GB"),
+            (976_562, 3, "999.999 MB"),
+            (976_563, 3, "1.000 GB"),
+            (976_562_499, 2, "1.00 TB"),
+            (976_562_500, 1, "1.0 TB"),
+            (976_562_500_000, 1, "1.0 PB"),
+            (976_562_500_000_000, 1, "1.0 EB"),
+            (976_562_500_000_000, 0, "1 EB"),
+        ];
+        for (kb, precision, expected) in cases {
+            assert_eq!(format_size_si(kb, precision), expected, "{} KB", kb);
+        }
+    }
+
+    #[test]
+    fn binary_units_cross_prefix_boundaries() {
+        let cases = [
+            (0, 1, "0.0 B"),
+            (1, 1, "1.0 KiB"),
+            (1023, 1, "1023.0 KiB"),
+            (1024, 1, "1.0 MiB"),
+            (1536, 2, "1.50 MiB"),
+            (1024 * 1024 - 1, 3, "1023.999 MiB"),
+            (1024 * 1024 - 1, 1, "1.0 GiB"),
+            (1024 * 1024, 1, "1.0 GiB"),
+            (1024u64.pow(3), 1, "1.0 TiB"),
+            (1024u64.pow(4), 1, "1.0 PiB"),
+            (1024u64.pow(5), 1, "1.0 EiB"),
+            (u64::MAX, 0, "16384 EiB"),
+        ];
+        for (kb, precision, expected) in cases {
+            assert_eq!(format_size_binary(kb, precision), expected, "{} KB", kb);
+        }
+    }
+
+    #[test]
+    fn block_size_parsing_and_formatting() {
+        let m = BlockSize::parse("M").unwrap();
+        assert_eq!((m.bytes, m.suffix.as_str()), (1 << 20, "M"));
+        assert_eq!(m.format(0), "0M");
+        assert_eq!(m.format(1), "1M");
+        assert_eq!(m.format(1024), "1M");
+        assert_eq!(m.format(1025), "2M");
+
+        assert_eq!(BlockSize::parse("k").unwrap().format(5), "5K");
+        assert_eq!(BlockSize::parse("GiB").unwrap().format(1 << 20), "1GiB");
+        let kb = BlockSize::parse("KB").unwrap();
+        assert_eq!((kb.bytes, kb.suffix.as_str()), (1000, "kB"));
+        assert_eq!(kb.format(1), "2kB");
+        assert_eq!(BlockSize::parse("MB").unwrap().bytes, 1_000_000);
+        assert_eq!(BlockSize::parse("E").unwrap().bytes, 1 << 60);
+
+        let four_k = BlockSize::parse("4K").unwrap();
+        assert_eq!((four_k.bytes, four_k.suffix.as_str()), (4096, ""));
+        assert_eq!(four_k.format(9), "3");
+        assert_eq!(BlockSize::parse("512").unwrap().format(1), "2");
+
+        for bad in ["", "0", "0K", "X", "MQ", "1.5M", "100000E"] {
+            assert!(BlockSize::parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn digit_grouping_by_locale() {
+        let group = |locale: &str, num| {
+            format_with_grouping(num, DigitGrouping::for_locale(locale).unwrap())
+        };
+        assert_eq!(group("en_US.UTF-8", 1_234_567), "1,234,567");
+        assert_eq!(group("de_DE.UTF-8@euro", 1_234_567), "1.234.567");
+        assert_eq!(group("fr_FR", 1_234_567), "1\u{202F}234\u{202F}567");
+        assert_eq!(group("de_CH.UTF-8", 1_234), "1\u{2019}234");
+        assert_eq!(group("en_IN", 123_456_789), "12,34,56,789");
+        assert_eq!(group("hi_IN.UTF-8", 1_000), "1,000");
+        assert_eq!(group("en_IN", 999), "999");
+        assert_eq!(group("en_US", 0), "0");
+        assert_eq!(group("en_US", 100_000), "100,000");
+        for locale in ["C", "POSIX", "C.UTF-8", ""] {
+            assert_eq!(DigitGrouping::for_locale(locale), None, "{:?}", locale);
+        }
+        assert_eq!(
+            format_with_grouping(1_234_567, DigitGrouping::DEFAULT),
+            "1'234'567"
+        );
+    }
+
+    #[test]
+    fn histogram_bar_lengths() {
+        use HistogramScale::*;
+
+        for scale in [Linear, Log] {
+            // Zero sizes get no bar, even when everything is empty
+            assert_eq!(histogram_marks(0, 1000, 20, scale), 0);
+            assert_eq!(histogram_marks(0, 0, 20, scale), 0);
+            // A single entry, or equal entries, get the full bar
+            assert_eq!(histogram_marks(7, 7, 20, scale), 20);
+            assert_eq!(histogram_marks(1, 1, 20, scale), 20);
+            // Nonzero entries always get at least one mark
+            assert_eq!(histogram_marks(1, u64::MAX, 20, scale), 1);
+            assert_eq!(histogram_marks(5, 10, 1, scale), 1);
+        }
+        assert_eq!(histogram_marks(50, 100, 20, Linear), 10);
+        // 1 GB next to 500 GB: one mark linear, most of the bar in log scale
+        let (small, large) = (1_000_000, 500_000_000);
+        assert_eq!(histogram_marks(small, large, 20, Linear), 1);
+        assert_eq!(histogram_marks(small, large, 20, Log), 14);
+    }
+
+    #[test]
+    fn no_color_overrides_color_always() {
+        use ColorChoice::*;
+
+        // (choice, is_terminal, NO_COLOR, CLICOLOR_FORCE, expected)
+        let cases = [
+            (Auto, true, false, false, true),
+            (Auto, false, false, false, false),
+            (Always, false, false, false, true),
+            (Never, true, false, false, false),
+            (Auto, true, true, false, false),
+            (Always, true, true, false, false),
+            (Always, true, true, true, true),
+            (Auto, false, true, true, true),
+            (Never, true, false, true, false),
+        ];
+        for (choice, tty, no_color, force, expected) in cases {
+            assert_eq!(
+                color_enabled(choice, tty, no_color, force),
+                expected,
+                "{:?} tty={} NO_COLOR={} CLICOLOR_FORCE={}",
+                choice,
+                tty,
+                no_color,
+                force
+            );
+        }
+    }
+
+    #[test]
+    fn running_percentages_accumulate_to_100() {
+        assert_eq!(running_percentages([10, 30, 60]), vec![10.0, 40.0, 100.0]);
+        assert_eq!(running_percentages([0, 0]), vec![0.0, 0.0]);
+        assert_eq!(running_percentages([0, 5]), vec![0.0, 100.0]);
+        assert_eq!(running_percentages([7]), vec![100.0]);
+        assert!(running_percentages(Vec::<u64>::new()).is_empty());
+    }
+
+    #[test]
+    fn top_keeps_the_largest_entries_in_order() {
+        let entry = |name: &str, size_kb| {
+            (
+                name.to_string(),
+                EntryStats {
+                    size_kb,
+                    inodes: 1,
+                    ..EntryStats::default()
+                },
+            )
+        };
+        // In name order: the two largest keep their place
+        let entries = vec![entry("a", 5), entry("b", 40), entry("c", 1), entry("d", 30)];
+        let opts = Options {
+            top: Some(2),
+            ..Options::default()
+        };
+        let (rows, remainder) = top_entries(entries.clone(), &opts);
+        assert_eq!(rows, vec![entry("b", 40), entry("d", 30)]);
+        let remainder = remainder.unwrap();
+        assert_eq!((remainder.count, remainder.stats.size_kb), (2, 6));
+        assert_eq!(remainder.stats.inodes, 2);
+
+        let opts = Options {
+            aggregate_remainder: true,
+            sort: SortKey::Size,
+            ..opts
+        };
+        let (rows, remainder) = top_entries(entries.clone(), &opts);
+        assert!(remainder.is_none());
+        let names: Vec<&str> = rows.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["<other>", "d", "b"]);
+
+        let opts = Options {
+            top: Some(4),
+            ..opts
+        };
+        assert_eq!(top_entries(entries.clone(), &opts), (entries, None));
+    }
+
+    #[test]
+    fn inode_capacity_of_the_root_filesystem() {
+        use crate::core::{inode_capacity, InodeCapacity};
+
+        let capacity = inode_capacity("/").unwrap();
+        assert!(capacity.free <= capacity.total);
+        assert!(inode_capacity("/no/such/path").is_err());
+
+        let capacity = InodeCapacity {
+            total: 1_000,
+            free: 600,
+        };
+        assert_eq!(capacity.percent(125), Some(12.5));
+        assert_eq!(InodeCapacity { total: 0, free: 0 }.percent(5), None);
+    }
+
+    #[test]
+    fn disk_space_of_the_root_filesystem() {
+        use crate::core::{disk_space, filesystem_source, unescape_mount_field, DiskSpace};
+
+        let space = disk_space("/").unwrap();
+        assert!(space.used <= space.total && space.free <= space.total);
+        assert!(disk_space("/no/such/path").is_err());
+        assert!(filesystem_source("/").is_some_and(|source| !source.is_empty()));
+        assert_eq!(filesystem_source("/no/such/path"), None);
+        assert_eq!(unescape_mount_field("/mnt/my\\040disk"), "/mnt/my disk");
+        assert_eq!(unescape_mount_field("a\\b"), "a\\b");
+
+        let space = DiskSpace {
+            total: 1_000,
+            used: 300,
+            free: 600,
+        };
+        assert_eq!(space.percent_used(), Some(100.0 / 3.0));
+        let empty = DiskSpace {
+            total: 0,
+            used: 0,
+            free: 0,
+        };
+        assert_eq!(empty.percent_used(), None);
+    }
+
+    #[test]
+    fn report_escaping() {
+        assert_eq!(markdown_escape("a|b"), "a\\|b");
+        assert_eq!(markdown_escape("node_modules"), "node\\_modules");
+        assert_eq!(markdown_escape("x\ny"), "x y");
+        assert_eq!(markdown_escape("plain name.txt"), "plain name.txt");
+        assert_eq!(
+            html_escape("<a href=\"x\">&'"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&#39;"
+        );
+    }
+
+    #[test]
+    fn sort_breaks_ties_by_name() {
+        let entry = |name: &str, size_kb| {
+            (
+                name.to_string(),
+                EntryStats {
+                    size_kb,
+                    inodes: 1,
+                    ..EntryStats::default()
+                },
+            )
+        };
+        let forward = vec![entry("b", 5), entry("a", 5), entry("c", 1), entry("d", 5)];
+        let mut reversed = forward.clone();
+        reversed.reverse();
+        for sort in [SortKey::Size, SortKey::Time] {
+            let opts = Options {
+                sort,
+                ..Options::default()
+            };
+            let (mut x, mut y) = (forward.clone(), reversed.clone());
+            sort_entries(&mut x, &opts);
+            sort_entries(&mut y, &opts);
+            assert_eq!(x, y, "{:?}", sort);
+        }
+        let mut sorted = forward;
+        sort_entries(&mut sorted, &Options::default());
+        let names: Vec<_> = sorted.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["c", "a", "b", "d"]);
+    }
+
+    #[test]
+    fn compare_lists_changed_entries_largest_first() {
+        use crate::core::compare_values;
+
+        let values = |pairs: &[(&str, u64)]| -> std::collections::HashMap<String, u64> {
+            pairs
+                .iter()
+                .map(|&(name, value)| (name.to_string(), value))
+                .collect()
+        };
+        let old = values(&[("same", 5), ("grew", 10), ("shrank", 40), ("gone", 3)]);
+        let new = values(&[("same", 5), ("grew", 30), ("shrank", 8), ("added", 1)]);
+        let deltas = compare_values(&old, &new);
+        let summary: Vec<_> = deltas
+            .iter()
+            .map(|d| (d.name.as_str(), d.old, d.new, d.delta()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("shrank", Some(40), Some(8), -32),
+                ("grew", Some(10), Some(30), 20),
+                ("gone", Some(3), None, -3),
+                ("added", None, Some(1), 1),
+            ]
+        );
+        assert!(compare_values(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn name_sort_ignores_case_unless_asked() {
+        let mut entries: Vec<_> = ["notes", "README", "b", "Makefile", "a", "A"]
+            .iter()
+            .map(|name| (name.to_string(), EntryStats::default()))
+            .collect();
+        let mut opts = Options {
+            sort: SortKey::Name,
+            ..Options::default()
+        };
+        let names = |entries: &[(String, EntryStats)]| {
+            entries.iter().map(|(n, _)| n.clone()).collect::<Vec<_>>()
+        };
+        sort_entries(&mut entries, &opts);
+        assert_eq!(
+            names(&entries),
+            ["A", "a", "b", "Makefile", "notes", "README"]
+        );
+        opts.case_sensitive = true;
+        sort_entries(&mut entries, &opts);
+        assert_eq!(
+            names(&entries),
+            ["A", "Makefile", "README", "a", "b", "notes"]
+        );
+    }
+
+    #[test]
+    fn long_names_are_truncated_with_an_ellipsis() {
+        assert_eq!(truncate_name("short.txt", 10), "short.txt");
+        assert_eq!(truncate_name("exactly10!", 10), "exactly10!");
+        let long = truncate_name("a_rather_long_file_name.tar.gz", 12);
+        assert_eq!(long.chars().count(), 12);
+        assert!(long.starts_with("a_rather"));
+        assert!(long.ends_with('\u{2026}') || long.ends_with("..."));
+        // Multi-byte characters are counted, not bytes
+        assert_eq!(truncate_name("\u{e9}\u{e9}\u{e9}", 3), "\u{e9}\u{e9}\u{e9}");
+        assert_eq!(truncate_name("abcdef", 2), "ab");
+    }
+
+    #[test]
+    fn squarify_tiles_the_rectangle_proportionally() {
+        let area = Rect {
+            x: 0.0,
+            y: 0.0,
+            w: 6.0,
+            h: 4.0,
+        };
+        // The example from the squarified treemaps paper
+        let sizes = [6.0, 6.0, 4.0, 3.0, 2.0, 2.0, 1.0];
+        let rects = squarify(&sizes, area);
+        assert_eq!(rects.len(), sizes.len());
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        // The two largest fill a 3x4 column as two 3x2 rectangles
+        assert!(close(rects[0].w, 3.0) && close(rects[0].h, 2.0));
+        assert!(close(rects[1].y, 2.0) && close(rects[1].w, 3.0));
+        for (size, r) in sizes.iter().zip(&rects) {
+            assert!(close(r.w * r.h, *size), "{:?} for {}", r, size);
+            assert!(r.x >= -1e-9 && r.y >= -1e-9);
+            assert!(r.x + r.w <= 6.0 + 1e-9 && r.y + r.h <= 4.0 + 1e-9);
+            assert!(r.w.max(r.h) / r.w.min(r.h) < 3.0, "{:?} is too thin", r);
+        }
+        for (i, a) in rects.iter().enumerate() {
+            for b in &rects[i + 1..] {
+                let overlap_w = (a.x + a.w).min(b.x + b.w) - a.x.max(b.x);
+                let overlap_h = (a.y + a.h).min(b.y + b.h) - a.y.max(b.y);
+                assert!(
+                    overlap_w <= 1e-9 || overlap_h <= 1e-9,
+                    "{:?} overlaps {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+
+        let zeros = squarify(&[5.0, 0.0], area);
+        assert!(close(zeros[0].w * zeros[0].h, 24.0));
+        assert_eq!(zeros[1].w * zeros[1].h, 0.0);
+        assert!(squarify(&[0.0, 0.0], area).iter().all(|r| r.w * r.h == 0.0));
+        assert!(squarify(&[], area).is_empty());
+    }
+
+    #[test]
+    fn natural_cmp_tricky_cases() {
+        use std::cmp::Ordering::*;
+
+        let cases = [
+            ("a2", "a10", Less),
+            ("log.2", "log.10", Less),
+            ("a10", "a2", Greater),
+            ("file007", "file7", Less),
+            ("Ä", "a", Greater),
+            ("Äpfel", "apfel", Greater),
+            ("Äpfel", "b", Less),
+            ("README", "readme", Less),
+            ("Zebra", "apple", Greater),
+            ("same", "same", Equal),
+            ("x", "x1", Less),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(natural_cmp(a, b), expected, "{:?} vs {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn timestamp_formatting() {
+        assert_eq!(format_iso8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_iso8601(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_iso8601(1_700_000_000), "2023-11-14T22:13:20Z");
+        assert_eq!(format_relative_time(100, 100), "just now");
+        assert_eq!(format_relative_time(200, 100), "just now");
+        assert_eq!(format_relative_time(0, 3 * 86_400), "3 days ago");
+        assert_eq!(format_relative_time(0, 3_600), "1 hour ago");
+    }
+
+    #[test]
+    fn age_spec_parsing_and_matching() {
+        assert_eq!(
+            AgeSpec::parse("30d").unwrap().cutoff(100 * 86_400),
+            70 * 86_400
+        );
+        assert_eq!(AgeSpec::parse("1y").unwrap().cutoff(0), -365 * 86_400);
+        assert_eq!(AgeSpec::parse("6m").unwrap().cutoff(0), -180 * 86_400);
+        assert_eq!(AgeSpec::parse("2000-02-29").unwrap().cutoff(0), 951_782_400);
+        for bad in ["30", "30x", "d", "2023-02-30", "2023-13-01", ""] {
+            assert!(AgeSpec::parse(bad).is_err(), "{:?}", bad);
+        }
+
+        let now = 1_000 * 86_400;
+        let opts = Options {
+            older_than: Some(AgeSpec::parse("30d").unwrap()),
+            ..Options::default()
+        };
+        assert!(opts.age_matches(now - 31 * 86_400, now));
+        assert!(!opts.age_matches(now - 29 * 86_400, now));
+        // Future mtimes count as age zero rather than underflowing
+        assert!(!opts.age_matches(i64::MAX, now));
+
+        let opts = Options {
+            newer_than: Some(AgeSpec::parse("1d").unwrap()),
+            ..Options::default()
+        };
+        assert!(opts.age_matches(now + 3_600, now));
+        assert!(!opts.age_matches(now - 2 * 86_400, now));
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("project-*", "project-alpha"));
+        assert!(!glob_match("project-*", "projects"));
+        assert!(glob_match("*.log", ".log"));
+        assert!(!glob_match("*.log", "a/b.log"));
+        assert!(glob_match("**/*.log", "a/b/c.log"));
+        assert!(glob_match("a/**/b", "a/b"));
+        assert!(glob_match("file?.txt", "file1.txt"));
+        assert!(glob_match("[a-c]x[!0-9]", "bxy"));
+        assert!(!glob_match("[a-c]x[!0-9]", "bx5"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("lit\\*", "lit*"));
+        assert!(!glob_match("lit\\*", "literal"));
+        assert!(glob_match("[oops", "[oops"));
+    }
+
+    #[test]
+    fn size_arguments() {
+        assert_eq!(parse_size_arg("512"), Ok(512));
+        assert_eq!(parse_size_arg("100M"), Ok(100_000));
+        assert_eq!(parse_size_arg("1.5 GB"), Ok(1_500_000));
+        assert_eq!(parse_size_arg("2t"), Ok(2_000_000_000));
+        assert!(parse_size_arg("10 parsecs").is_err());
+        assert!(parse_size_arg("G").is_err());
+    }
+
+    #[test]
+    fn duplicate_sets_by_waste() {
+        use crate::duplicates::{sort_by_waste, DuplicateSet};
+
+        let set = |size, copies: usize| DuplicateSet {
+            size,
+            paths: (0..copies).map(|i| format!("f{}", i)).collect(),
+        };
+        assert_eq!(set(1000, 3).reclaimable(), 2000);
+        assert_eq!(set(1000, 1).reclaimable(), 0);
+        let mut sets = vec![set(1500, 2), set(1000, 3), set(500, 5)];
+        sort_by_waste(&mut sets);
+        let order: Vec<_> = sets.iter().map(|s| (s.size, s.reclaimable())).collect();
+        assert_eq!(order, [(1000, 2000), (500, 2000), (1500, 1500)]);
+    }
+
+    #[test]
+    fn empty_directories_propagate_upward() {
+        use crate::empty_dirs::{EmptyEntries, EmptyFinder};
+        use std::path::{Path, PathBuf};
+
+        let mut finder = EmptyFinder::default();
+        for dir in ["t", "t/a", "t/a/b", "t/c", "t/c/d", "t/e"] {
+            finder.enter_dir(PathBuf::from(dir));
+        }
+        finder.empty_file(Path::new("t/c/d/zero"));
+        finder.occupied_parent(Path::new("t/c/d/zero"));
+        finder.occupied(Path::new("t/e"));
+        let mut found = EmptyEntries::default();
+        finder.finish(&mut found);
+        found.sort();
+        assert_eq!(found.files, ["t/c/d/zero"]);
+        assert_eq!(found.dirs, ["t/a", "t/a/b"]);
+    }
+
+    #[test]
+    fn depth_rows_fold_the_deepest_levels() {
+        use crate::core::{DepthLevel, DepthUsage};
+
+        let level = |depth, size_kb, files| DepthLevel {
+            depth,
+            size_kb,
+            bytes: size_kb * 1024,
+            files,
+        };
+        let usage = DepthUsage {
+            levels: vec![
+                level(1, 10, 1),
+                level(2, 20, 2),
+                level(3, 30, 3),
+                level(4, 40, 4),
+            ],
+        };
+        assert_eq!(usage.capped(10), usage.levels);
+        assert_eq!(usage.capped(2), [level(1, 10, 1), level(2, 90, 9)],);
+        assert_eq!(usage.capped(1), [level(1, 100, 10)]);
+    }
+
+    #[test]
+    fn inode_hogs_keep_the_largest_directories() {
+        use crate::core::InodeHogs;
+        use std::path::Path;
+
+        let mut hogs = InodeHogs::new(2);
+        hogs.offer(Path::new("/a"), 5, 5);
+        hogs.offer(Path::new("/b"), 9, 12);
+        hogs.offer(Path::new("/d"), 5, 7);
+        hogs.offer(Path::new("/c"), 5, 6);
+        hogs.offer(Path::new("/e"), 1, 100);
+        let kept: Vec<_> = hogs
+            .into_sorted()
+            .into_iter()
+            .map(|hog| (hog.path, hog.direct, hog.recursive))
+            .collect();
+        assert_eq!(kept, [("/b".to_string(), 9, 12), ("/a".to_string(), 5, 5)]);
+    }
+
+    #[test]
+    fn junk_patterns_match_file_names() {
+        use crate::junk::JunkPatterns;
+        use std::path::Path;
+
+        let patterns = JunkPatterns::new(&["*.bak".to_string()]);
+        let label = |name: &str| {
+            patterns
+                .find(Path::new("/data/dir").join(name).as_path())
+                .map(|i| patterns.labels()[i].clone())
+        };
+        assert_eq!(label("core").as_deref(), Some("core"));
+        assert_eq!(label("core.4242").as_deref(), Some("core.[0-9]+"));
+        assert_eq!(label("core.rs"), None);
+        assert_eq!(label("core.12x"), None);
+        assert_eq!(label(".main.rs.swp").as_deref(), Some("*.swp"));
+        assert_eq!(label(".#notes.org").as_deref(), Some(".#*"));
+        assert_eq!(label("notes.org~").as_deref(), Some("*~"));
+        assert_eq!(label(".DS_Store").as_deref(), Some(".DS_Store"));
+        assert_eq!(
+            label("hs_err_pid123.log").as_deref(),
+            Some("hs_err_pid*.log")
+        );
+        assert_eq!(label("old.bak").as_deref(), Some("*.bak"));
+        assert_eq!(label("main.rs"), None);
+    }
+
+    #[test]
+    fn junk_totals_per_pattern() {
+        use crate::junk::{Junk, JunkFile, JunkPatterns};
+
+        let file = |path: &str, bytes, pattern| JunkFile {
+            path: path.to_string(),
+            bytes,
+            mtime: 0,
+            pattern,
+        };
+        let mut junk = Junk::new(&JunkPatterns::new(&[]));
+        junk.files = vec![
+            file("/a.tmp", 10, 2),
+            file("/core.1", 300, 1),
+            file("/b.tmp", 20, 2),
+        ];
+        junk.sort();
+        let paths: Vec<_> = junk.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["/core.1", "/b.tmp", "/a.tmp"]);
+        assert_eq!(junk.bytes(), 330);
+        let totals: Vec<_> = junk
+            .totals()
+            .into_iter()
+            .map(|t| (t.pattern, t.files, t.bytes))
+            .collect();
+        assert_eq!(
+            totals,
+            [
+                ("core.[0-9]+".to_string(), 1, 300),
+                ("*.tmp".to_string(), 2, 30)
+            ]
+        );
+    }
+
+    #[test]
+    fn estimate_extrapolates_unread_directories() {
+        use crate::estimate::SubtreeSample;
+
+        let complete = SubtreeSample {
+            entries: 40,
+            bytes: 4000,
+            dirs_read: 3,
+            dirs_pending: 0,
+        };
+        assert_eq!(complete.extrapolated(), (40, 4000));
+        let partial = SubtreeSample {
+            entries: 100,
+            bytes: 1000,
+            dirs_read: 4,
+            dirs_pending: 12,
+        };
+        assert_eq!(partial.extrapolated(), (400, 4000));
+    }
+
+    #[test]
+    fn file_size_stats_exact_quantiles() {
+        use crate::core::FileSizeStats;
+
+        let empty = FileSizeStats::default();
+        assert_eq!((empty.mean(), empty.quantile(0.5), empty.max), (0.0, 0, 0));
+
+        let mut stats = FileSizeStats::default();
+        for bytes in (1..=100).rev() {
+            stats.record(bytes * 10);
+        }
+        assert_eq!((stats.count, stats.total, stats.max), (100, 50_500, 1000));
+        assert_eq!(stats.mean(), 505.0);
+        assert_eq!(stats.quantile(0.5), 500);
+        assert_eq!(stats.quantile(0.9), 900);
+        assert_eq!(stats.quantile(0.99), 990);
+        assert_eq!(stats.quantile(1.0), 1000);
+    }
+
+    #[test]
+    fn file_size_stats_sketch_stays_close() {
+        use crate::core::{FileSizeStats, EXACT_SIZE_LIMIT};
+
+        let n = 2 * EXACT_SIZE_LIMIT as u64;
+        let (mut low, mut high) = (FileSizeStats::default(), FileSizeStats::default());
+        for bytes in 1..=n {
+            let half = if bytes % 2 == 0 { &mut low } else { &mut high };
+            half.record(bytes * 3);
+        }
+        low.merge(high);
+        assert_eq!(low.count, n);
+        assert_eq!(low.max, n * 3);
+        for q in [0.5, 0.9, 0.99] {
+            let exact = (q * n as f64) * 3.0;
+            let sketched = low.quantile(q) as f64;
+            assert!(
+                (sketched - exact).abs() / exact <= 0.011,
+                "{} {} {}",
+                q,
+                sketched,
+                exact
+            );
+        }
+    }
+
+    #[test]
+    fn watch_marks_files_new_since_the_last_refresh() {
+        use crate::core::{RecentFile, WatchState};
+
+        let file = |path: &str| RecentFile {
+            path: path.to_string(),
+            bytes: 1,
+            mtime: 0,
+            new: false,
+        };
+        let new_paths = |files: &[RecentFile]| -> Vec<String> {
+            files
+                .iter()
+                .filter(|f| f.new)
+                .map(|f| f.path.clone())
+                .collect()
+        };
+        let mut state = WatchState::default();
+        let mut first = [file("/a"), file("/b")];
+        state.mark_new(&mut first);
+        assert!(new_paths(&first).is_empty());
+        let mut second = [file("/b"), file("/c")];
+        state.mark_new(&mut second);
+        assert_eq!(new_paths(&second), ["/c"]);
+        // "/a" dropped out, so it is new again when it comes back
+        let mut third = [file("/a"), file("/b"), file("/c")];
+        state.mark_new(&mut third);
+        assert_eq!(new_paths(&third), ["/a"]);
+    }
+
+    #[test]
+    fn size_histogram_buckets() {
+        use crate::core::SizeHistogram;
+
+        let mut histogram = SizeHistogram::new(&SizeHistogram::DEFAULT_BOUNDS);
+        for bytes in [0, 1, 4096, 4097, 5_000_000_000] {
+            histogram.record(bytes);
+        }
+        let counts: Vec<_> = histogram.buckets.iter().map(|b| b.count).collect();
+        assert_eq!(counts, [1, 2, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(histogram.buckets[1].bytes, 4097);
+        assert_eq!(histogram.buckets.last().unwrap().le, None);
+
+        // Bounds are sorted and deduplicated
+        let histogram = SizeHistogram::new(&[1_000, 1, 1_000]);
+        let bounds: Vec<_> = histogram.buckets.iter().map(|b| b.le).collect();
+        assert_eq!(bounds, [Some(1), Some(1_000), None]);
+    }
+
+    #[test]
+    fn limits_file_parsing() {
+        let limits = Limits::parse(
+            "# per-project quotas\n\
+             [limits]\n\
+             \"project-*\" = \"500G\"  # shared\n\
+             '/scratch/#tmp' = '1T'\n\
+             tmp = 2_048\n",
+        )
+        .unwrap();
+        assert_eq!(limits.rules.len(), 3);
+        assert_eq!(
+            limits.limit_for("project-a", "/x/project-a"),
+            Some(500_000_000)
+        );
+        assert_eq!(
+            limits.limit_for("#tmp", "/scratch/#tmp"),
+            Some(1_000_000_000)
+        );
+        // Integers are kilobytes, like a number without a unit
+        assert_eq!(limits.limit_for("tmp", "/scratch/tmp"), Some(2_048));
+        assert_eq!(limits.limit_for("other", "/scratch/other"), None);
+
+        let err = Limits::parse("ok = \"1G\"\n[quotas]\n").unwrap_err();
+        assert!(err.starts_with("2: "), "{}", err);
+        assert!(Limits::parse("x = \"lots\"").is_err());
+        assert!(Limits::parse("\"unterminated = 1").is_err());
+    }
+
+    #[test]
+    fn config_file_parsing() {
+        use crate::config::{parse_config, split_args};
+        use crate::core::SizeUnit;
+        use clap::{CommandFactory, Parser};
+
+        let cmd = Cli::command();
+        let args = parse_config(
+            "# defaults\n\
+             sort = \"name\"\n\
+             nogrouping = true\n\
+             cross_mounts = false\n\
+             exclude = [\".git\", 'node #modules']  # both\n\
+             verbose = 2\n\
+             precision = 3\n",
+            &cmd,
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "--sort=name",
+                "--nogrouping",
+                "--exclude=.git",
+                "--exclude=node #modules",
+                "--verbose",
+                "--verbose",
+                "--precision=3"
+            ]
+        );
+
+        // The usual preferences: unit, color, sort and exclude patterns
+        let args = parse_config(
+            "unit = \"m\"\ncolor = \"never\"\nsort = \"name\"\nexclude = [\"*.tmp\"]\n",
+            &cmd,
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "--unit=m",
+                "--color=never",
+                "--sort=name",
+                "--exclude=*.tmp"
+            ]
+        );
+        let cli = Cli::try_parse_from(std::iter::once("dustr".to_string()).chain(args)).unwrap();
+        assert_eq!(cli.opts.unit, Some(SizeUnit::M));
+        assert_eq!(cli.opts.color, ColorChoice::Never);
+
+        // The former spelling of directory counting still works, and the
+        // last of it and --no-dir-entries wins
+        let args = parse_config("include-dir-overhead = true\n", &cmd).unwrap();
+        assert_eq!(args, ["--include-dir-overhead"]);
+        let overhead = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once(&"dustr").chain(args))
+                .unwrap()
+                .opts
+                .include_dir_overhead
+        };
+        assert!(overhead(&["--include-dir-overhead"]));
+        assert!(!overhead(&["--include-dir-overhead", "--no-dir-entries"]));
+        assert!(overhead(&["--no-dir-entries", "--include-dir-overhead"]));
+
+        let err = parse_config("sort = \"size\"\nsort = \"bogus\"\n", &cmd).unwrap_err();
+        assert!(err.starts_with("2: key 'sort': invalid value"), "{}", err);
+        let err = parse_config("\nfrobnicate = 1\n", &cmd).unwrap_err();
+        assert_eq!(err, "2: key 'frobnicate': unknown option");
+        assert!(parse_config("json = \"yes\"", &cmd).is_err());
+        assert!(parse_config("sort = [\"name\", \"size\"]", &cmd).is_err());
+        assert!(parse_config("[dustr]", &cmd).is_err());
+
+        assert_eq!(
+            split_args(r#"--sort name  --exclude "a b" -e 'c d' x\ y"#).unwrap(),
+            ["--sort", "name", "--exclude", "a b", "-e", "c d", "x y"]
+        );
+        assert!(split_args("--exclude 'open").is_err());
+    }
+
+    #[test]
+    fn defaults_yield_to_conflicting_arguments() {
+        use crate::config::drop_conflicting;
+        use clap::CommandFactory;
+        use std::ffi::OsString;
+
+        let cmd = Cli::command();
+        let defaults: Vec<String> = ["--si", "--sort", "name", "--json", "-g"]
+            .map(String::from)
+            .to_vec();
+        let later = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            drop_conflicting(&cmd, &defaults, &later(&["/data"])),
+            defaults
+        );
+        assert_eq!(
+            drop_conflicting(&cmd, &defaults, &later(&["--binary", "--format", "html"])),
+            ["--sort", "name", "-g"]
+        );
+        // Giving the same option again is not a conflict: the later one wins
+        assert_eq!(
+            drop_conflicting(&cmd, &defaults, &later(&["--sort=size"])),
+            defaults
+        );
+    }
+
+    #[test]
+    fn completion_scripts_cover_every_option() {
+        use crate::completions::{generate, Shell};
+        use clap::CommandFactory;
+
+        let cmd = Cli::command().name("dustr");
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = generate(&cmd, shell);
+            for arg in cmd
+                .get_arguments()
+                .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+            {
+                let aliases = arg.get_visible_aliases().unwrap_or_default();
+                for long in std::iter::once(arg.get_long().unwrap()).chain(aliases) {
+                    let spelling = match shell {
+                        Shell::Fish => format!("-l {}", long),
+                        _ => format!("--{}", long),
+                    };
+                    assert!(script.contains(&spelling), "{:?} lacks {}", shell, long);
+                }
+            }
+            assert!(script.contains("size name time"), "{:?}", shell);
+            assert!(script.contains("bash zsh fish"), "{:?}", shell);
+        }
+        assert!(
+            generate(&cmd, Shell::Bash).contains("--exclude)\n            COMPREPLY=($(compgen -d")
+        );
+        assert!(generate(&cmd, Shell::Zsh).contains("'*--exclude=[Skip files"));
+        assert!(generate(&cmd, Shell::Zsh).contains(":PATTERN:_directories'"));
+        assert!(generate(&cmd, Shell::Fish)
+            .contains("-l exclude -x -a '(__fish_complete_directories)'"));
+    }
+
+    #[test]
+    fn exclude_set_matching() {
+        use std::path::Path;
+
+        let set = GlobSet::new(["*.tmp", "./build/", "/src/generated", "cache"]);
+        assert!(set.matches(Path::new("a.tmp")));
+        assert!(set.matches(Path::new("deep/down/b.tmp")));
+        assert!(set.matches(Path::new("build")));
+        assert!(set.matches(Path::new("x/build")));
+        assert!(set.matches(Path::new("src/generated")));
+        assert!(!set.matches(Path::new("lib/src/generated")));
+        assert!(set.matches(Path::new("lib/cache")));
+        assert!(!set.matches(Path::new("cached")));
+        assert!(GlobSet::new(["", "/"]).is_empty());
+    }
+
+    #[test]
+    fn include_patterns_select_files() {
+        use std::path::Path;
+
+        let opts = Options {
+            include: vec!["*.txt".to_string(), "src/**/*.rs".to_string()],
+            ..Options::default()
+        };
+        let filter = opts.path_filter().unwrap();
+        assert!(filter.has_includes());
+        assert!(filter.includes_file(Path::new("notes.txt")));
+        assert!(filter.includes_file(Path::new("docs/old/notes.txt")));
+        assert!(filter.includes_file(Path::new("src/core/main.rs")));
+        assert!(!filter.includes_file(Path::new("tests/main.rs")));
+        assert!(!filter.includes_file(Path::new("notes.txt.bak")));
+        assert!(Options::default()
+            .path_filter()
+            .unwrap()
+            .includes_file(Path::new("any")));
+    }
+
+    #[test]
+    fn prune_known_help_lists_every_directory() {
+        use clap::CommandFactory;
+
+        let mut cmd = Cli::command();
+        let help = cmd.render_long_help().to_string();
+        for name in KNOWN_JUNK_DIRS {
+            assert!(help.contains(name), "--help does not mention {}", name);
+        }
     }
 }
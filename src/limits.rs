@@ -0,0 +1,185 @@
+//! Per-directory size limits read from a TOML file (`--limits`).
+//!
+//! The file maps glob patterns to limits, either at the top level or in a
+//! `[limits]` table:
+//!
+//! ```toml
+//! [limits]
+//! "project-*" = "500G"
+//! "/scratch/shared" = "2T"
+//! tmp = 1048576   # integers are kilobytes
+//! ```
+//!
+//! Patterns without a `/` are matched against entry names, others against
+//! the entry's full path. The first matching pattern in the file wins.
+//! Patterns are globs as described in [`crate::glob`].
+//!
+//! Limits use the units of `--min-size` and of the sizes in the table: a
+//! number is kilobytes (1024 bytes) and the suffixes K, M, G and T (or KB,
+//! MB, GB and TB, in any case) each multiply the previous one by 1000, so
+//! `"500G"` is 500,000,000 KB and shows as 500.0 GB.
+//!
+//! This is the subset of TOML that is accepted, one item per line:
+//!
+//! - blank lines, and comments from `#` (outside quotes) to the end of the
+//!   line
+//! - the table header `[limits]`, which may appear any number of times;
+//!   other tables are errors
+//! - `key = value`, where the key is bare (`A-Za-z0-9_-`), a "basic"
+//!   string with the escapes `\"`, `\\` and `\t`, or a 'literal' string
+//! - a value that is an integer (`_` separators allowed) or a basic or
+//!   literal string holding a number with an optional unit, e.g. `"1.5T"`
+//!
+//! Arrays, inline tables, dotted keys, multi-line strings and the other
+//! value types of TOML are rejected.
+
+use std::fs;
+
+use crate::core::{parse_size_arg, DustrError};
+use crate::glob::glob_match;
+
+/// A single `pattern = limit` line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitRule {
+    pub pattern: String,
+    pub limit_kb: u64,
+}
+
+/// Size limits loaded from a limits file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    pub rules: Vec<LimitRule>,
+}
+
+impl Limits {
+    /// Read and parse a limits file
+    pub fn load(path: &str) -> Result<Self, DustrError> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            DustrError::from_io(format!("Cannot read limits file '{}': {}", path, e), &e)
+        })?;
+        Self::parse(&text)
+            .map_err(|msg| DustrError::OsError(format!("{}:{}", path, msg), Some(libc::EINVAL)))
+    }
+
+    /// Parse the contents of a limits file. Errors are prefixed with the
+    /// 1-based line number.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for (lineno, raw) in text.lines().enumerate() {
+            let err = |msg: String| format!("{}: {}", lineno + 1, msg);
+            let line = strip_comment(raw).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(table) = line.strip_prefix('[') {
+                match table.strip_suffix(']').map(str::trim) {
+                    Some("limits") => continue,
+                    Some(other) => return Err(err(format!("unsupported table [{}]", other))),
+                    None => return Err(err("unterminated table header".to_string())),
+                }
+            }
+            let (key, rest) = parse_key(line).map_err(err)?;
+            let value = rest
+                .trim_start()
+                .strip_prefix('=')
+                .ok_or_else(|| err(format!("expected '=' after {:?}", key)))?
+                .trim();
+            let limit_kb = parse_limit_value(value).map_err(err)?;
+            rules.push(LimitRule {
+                pattern: key,
+                limit_kb,
+            });
+        }
+        Ok(Limits { rules })
+    }
+
+    /// The limit (in KB) for an entry, given its name and full path
+    pub fn limit_for(&self, name: &str, full_path: &str) -> Option<u64> {
+        self.rules
+            .iter()
+            .find(|rule| {
+                let subject = if rule.pattern.contains('/') {
+                    full_path
+                } else {
+                    name
+                };
+                glob_match(&rule.pattern, subject)
+            })
+            .map(|rule| rule.limit_kb)
+    }
+}
+
+/// Drop a trailing `# comment`, ignoring `#` inside quoted strings
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// Parse a bare, "basic" or 'literal' key, returning it and the rest of the line
+pub(crate) fn parse_key(line: &str) -> Result<(String, &str), String> {
+    match line.chars().next() {
+        Some('"') | Some('\'') => parse_string(line),
+        _ => {
+            let end = line
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                .unwrap_or(line.len());
+            if end == 0 {
+                return Err(format!("expected a key, found {:?}", line));
+            }
+            Ok((line[..end].to_string(), &line[end..]))
+        }
+    }
+}
+
+/// Parse a quoted string at the start of `s`, returning it and the rest
+pub(crate) fn parse_string(s: &str) -> Result<(String, &str), String> {
+    let mut chars = s.char_indices();
+    let quote = chars.next().map(|(_, c)| c).unwrap_or('"');
+    let mut out = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((out, &s[i + 1..])),
+            '\\' if quote == '"' => match chars.next().map(|(_, c)| c) {
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some('t') => out.push('\t'),
+                Some(other) => return Err(format!("unsupported escape '\\{}'", other)),
+                None => break,
+            },
+            c => out.push(c),
+        }
+    }
+    Err(format!("unterminated string {}", s))
+}
+
+/// Parse a limit into kilobytes: a size string such as "500G", or an
+/// integer number of kilobytes
+fn parse_limit_value(value: &str) -> Result<u64, String> {
+    if value.starts_with('"') || value.starts_with('\'') {
+        let (size, rest) = parse_string(value)?;
+        if !rest.trim().is_empty() {
+            return Err(format!("unexpected {:?} after value", rest.trim()));
+        }
+        return parse_size_arg(&size);
+    }
+    value.replace('_', "").parse().map_err(|_| {
+        format!(
+            "invalid limit {:?} (use e.g. \"500G\" or a number of kilobytes)",
+            value
+        )
+    })
+}
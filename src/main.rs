@@ -1,28 +1,61 @@
 use clap::{CommandFactory, FromArgMatches};
+use std::io::Write;
 use std::process;
 
-mod core;
+use dustr::{completions, config, core, diagnostics};
 
 fn main() {
-    // Parse using the shared Cli struct but display as "dustr-cli"
-    let matches = core::Cli::command().name("dustr-cli").get_matches();
-    let cli = core::Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // Parse using the shared Cli struct but display as "dustr-cli", with
+    // the defaults from the config file and DUSTR_OPTS before the arguments
+    let cmd = core::Cli::command().name("dustr-cli");
+    let (args, defaults) =
+        config::Defaults::with_args(&cmd, "dustr-cli", std::env::args_os().skip(1).collect())
+            .unwrap_or_else(|msg| {
+                cmd.clone()
+                    .error(clap::error::ErrorKind::InvalidValue, msg)
+                    .exit()
+            });
+    let matches = cmd.clone().get_matches_from(args);
+    let mut cli = core::Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if cli.dump_config {
+        let _ = defaults.dump(&mut std::io::stdout(), &cmd, &matches);
+        return;
+    }
+    if let Some(core::CliCommand::Completions { shell }) = cli.command {
+        let _ = write!(std::io::stdout(), "{}", completions::generate(&cmd, shell));
+        return;
+    }
+    cli.opts.apply_block_size_env();
+    diagnostics::set_sink(Some(diagnostics::stderr_sink(cli.opts.diagnostics_level())));
+    if let Err(msg) = cli.opts.validate() {
+        core::Cli::command()
+            .name("dustr-cli")
+            .error(clap::error::ErrorKind::ArgumentConflict, msg)
+            .exit();
+    }
+
+    let result = match (&cli.files0_from, cli.opts.watch) {
+        (Some(source), _) => core::print_sizes_for(source, &cli.opts),
+        (None, Some(interval)) => core::watch_disk_usage(&cli.dirname, &cli.opts, interval),
+        (None, None) => core::print_disk_usage(&cli.dirname, &cli.opts),
+    };
 
-    match core::print_disk_usage(
-        &cli.dirname,
-        cli.inodes,
-        cli.nogrouping,
-        cli.no_f,
-        cli.json,
-        cli.cross_mounts,
-        cli.verbose,
-        cli.live,
-    ) {
+    match result {
         Ok(()) => {}
         Err(core::DustrError::Cancelled) => {
             // Clean exit on Ctrl-C
             process::exit(130);
         }
+        Err(core::DustrError::BrokenPipe) => {
+            // The reader went away (e.g. `| head`): stop quietly with the
+            // status of a process killed by SIGPIPE
+            process::exit(141);
+        }
+        Err(e @ core::DustrError::Truncated(_)) => {
+            // The partial report has been printed
+            eprintln!("dustr-cli: {}", e);
+            process::exit(3);
+        }
         Err(e) => {
             eprintln!("dustr-cli: {}", e);
             process::exit(1);
@@ -0,0 +1,341 @@
+//! SVG treemap export (`--treemap FILE.svg`).
+//!
+//! The tree is gathered by the main scan down to `--treemap-depth` levels
+//! (anything deeper is folded into its ancestor at the last level), so it
+//! counts exactly what the table does, and is laid out with the squarified
+//! algorithm of Bruls, Huizing and van Wijk, and written as a standalone SVG.
+//! The layout works on plain numbers and rectangles so it can be tested
+//! without any SVG involved.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::core::{DustrError, EntryStats, Options, Output};
+use crate::export::html_escape;
+
+/// Size of the generated image in pixels
+const WIDTH: f64 = 1200.0;
+const HEIGHT: f64 = 800.0;
+/// Gap between a directory's border and its children
+const PADDING: f64 = 2.0;
+/// Height of the label strip at the top of a directory
+const LABEL_HEIGHT: f64 = 16.0;
+/// Approximate width of one label character at the font size used
+const CHAR_WIDTH: f64 = 7.0;
+
+/// An axis-aligned rectangle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+impl Rect {
+    fn area(&self) -> f64 {
+        self.w * self.h
+    }
+
+    /// Shrink by `left_right` on both sides, `top` at the top and
+    /// `bottom` at the bottom, never below zero size
+    fn inset(&self, left_right: f64, top: f64, bottom: f64) -> Rect {
+        Rect {
+            x: self.x + left_right,
+            y: self.y + top,
+            w: (self.w - 2.0 * left_right).max(0.0),
+            h: (self.h - top - bottom).max(0.0),
+        }
+    }
+}
+
+/// Lay out `sizes` in `rect` as a squarified treemap: each returned
+/// rectangle (in the same order as `sizes`) has an area proportional to its
+/// size, and together they tile `rect`. Sizes should be sorted largest
+/// first, which is what keeps the aspect ratios close to 1. Zero sizes get
+/// empty rectangles.
+pub fn squarify(sizes: &[f64], rect: Rect) -> Vec<Rect> {
+    let empty = Rect {
+        w: 0.0,
+        h: 0.0,
+        ..rect
+    };
+    let total: f64 = sizes.iter().sum();
+    if total <= 0.0 || rect.area() <= 0.0 {
+        return vec![empty; sizes.len()];
+    }
+    let scale = rect.area() / total;
+    let areas: Vec<f64> = sizes.iter().map(|s| s * scale).collect();
+
+    let mut result = Vec::with_capacity(sizes.len());
+    let mut free = rect;
+    let mut start = 0;
+    while start < areas.len() {
+        // Grow the row along the shorter side while that keeps improving
+        // the worst aspect ratio in it
+        let side = free.w.min(free.h);
+        let mut end = start + 1;
+        while end < areas.len()
+            && worst_ratio(&areas[start..=end], side) <= worst_ratio(&areas[start..end], side)
+        {
+            end += 1;
+        }
+        let row = &areas[start..end];
+        let row_area: f64 = row.iter().sum();
+
+        if free.w >= free.h {
+            // A column on the left of the free space
+            let width = if free.h > 0.0 { row_area / free.h } else { 0.0 };
+            let mut y = free.y;
+            for &area in row {
+                let h = if width > 0.0 { area / width } else { 0.0 };
+                result.push(Rect {
+                    x: free.x,
+                    y,
+                    w: width,
+                    h,
+                });
+                y += h;
+            }
+            free = Rect {
+                x: free.x + width,
+                w: (free.w - width).max(0.0),
+                ..free
+            };
+        } else {
+            // A row along the top of the free space
+            let height = if free.w > 0.0 { row_area / free.w } else { 0.0 };
+            let mut x = free.x;
+            for &area in row {
+                let w = if height > 0.0 { area / height } else { 0.0 };
+                result.push(Rect {
+                    x,
+                    y: free.y,
+                    w,
+                    h: height,
+                });
+                x += w;
+            }
+            free = Rect {
+                y: free.y + height,
+                h: (free.h - height).max(0.0),
+                ..free
+            };
+        }
+        start = end;
+    }
+    result
+}
+
+/// The largest aspect ratio among `row` laid out along a side of length
+/// `side`; infinite when the row contains an empty area
+fn worst_ratio(row: &[f64], side: f64) -> f64 {
+    let sum: f64 = row.iter().sum();
+    let max = row.iter().copied().fold(0.0, f64::max);
+    let min = row.iter().copied().fold(f64::INFINITY, f64::min);
+    if min <= 0.0 || sum <= 0.0 {
+        return f64::INFINITY;
+    }
+    let side2 = side * side;
+    let sum2 = sum * sum;
+    (side2 * max / sum2).max(sum2 / (side2 * min))
+}
+
+/// A file or directory in the treemap, with its disk usage in bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeNode {
+    pub name: String,
+    pub path: String,
+    pub bytes: u64,
+    /// Children sorted largest first; empty for files and for directories
+    /// at the depth limit
+    pub children: Vec<TreeNode>,
+}
+
+/// Accumulates sizes per path component while walking
+#[derive(Debug, Default)]
+pub struct NodeBuilder {
+    bytes: u64,
+    children: BTreeMap<String, NodeBuilder>,
+}
+
+impl NodeBuilder {
+    /// Add `bytes` used at `rel_path`, relative to the scan root, keeping
+    /// `depth` levels of detail
+    pub fn record(&mut self, rel_path: &Path, bytes: u64, depth: usize) {
+        let components: Vec<String> = rel_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        self.add(&components, bytes, depth);
+    }
+
+    /// Add `bytes` to this node and to the nodes along `components`, down
+    /// to `depth` levels
+    fn add(&mut self, components: &[String], bytes: u64, depth: usize) {
+        self.bytes += bytes;
+        if let (Some((first, rest)), true) = (components.split_first(), depth > 0) {
+            self.children
+                .entry(first.clone())
+                .or_default()
+                .add(rest, bytes, depth - 1);
+        }
+    }
+
+    /// Add the sizes gathered by another walker
+    pub fn merge(&mut self, other: NodeBuilder) {
+        self.bytes += other.bytes;
+        for (name, child) in other.children {
+            self.children.entry(name).or_default().merge(child);
+        }
+    }
+
+    /// The finished tree of the directory `path`, keeping only the
+    /// top-level entries listed in the report
+    pub fn into_tree(mut self, path: &str, entries: &HashMap<String, EntryStats>) -> TreeNode {
+        self.children.retain(|name, _| entries.contains_key(name));
+        self.bytes = self.children.values().map(|child| child.bytes).sum();
+        self.build(path.to_string(), Path::new(path))
+    }
+
+    fn build(self, name: String, path: &Path) -> TreeNode {
+        let mut children: Vec<TreeNode> = self
+            .children
+            .into_iter()
+            .map(|(child, builder)| {
+                let child_path = path.join(&child);
+                builder.build(child, &child_path)
+            })
+            .collect();
+        children.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+        TreeNode {
+            name,
+            path: path.to_string_lossy().to_string(),
+            bytes: self.bytes,
+            children,
+        }
+    }
+}
+
+/// Fill color of a rectangle: the hue identifies the top-level ancestor,
+/// and deeper levels get lighter
+fn fill_color(top_level_index: usize, depth: usize) -> String {
+    let hue = (top_level_index as f64 * 137.508) % 360.0;
+    let lightness = (45 + 10 * depth.saturating_sub(1)).min(85);
+    format!("hsl({:.0}, 55%, {}%)", hue, lightness)
+}
+
+/// The label to show in a rectangle `width` pixels wide, shortened with an
+/// ellipsis when needed; `None` when not even a few characters fit
+fn fit_label(name: &str, width: f64) -> Option<String> {
+    let max_chars = ((width - 6.0) / CHAR_WIDTH).floor();
+    if max_chars < 3.0 {
+        return None;
+    }
+    let max_chars = max_chars as usize;
+    if name.chars().count() <= max_chars {
+        Some(name.to_string())
+    } else {
+        let short: String = name.chars().take(max_chars - 1).collect();
+        Some(format!("{}…", short))
+    }
+}
+
+/// Write `root` as a standalone SVG treemap
+pub fn write_svg(out: &mut dyn Write, root: &TreeNode, opts: &Options) -> io::Result<()> {
+    writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+         viewBox=\"0 0 {} {}\" font-family=\"sans-serif\" font-size=\"12\">",
+        WIDTH, HEIGHT, WIDTH, HEIGHT
+    )?;
+    writeln!(out, "<title>{}</title>", html_escape(&tooltip(root, opts)))?;
+    writeln!(
+        out,
+        "<rect width=\"{}\" height=\"{}\" fill=\"#f4f4f4\"/>",
+        WIDTH, HEIGHT
+    )?;
+    let area = Rect {
+        x: 0.0,
+        y: 0.0,
+        w: WIDTH,
+        h: HEIGHT,
+    };
+    write_children(out, root, area, 1, None, opts)?;
+    writeln!(out, "</svg>")
+}
+
+/// Tooltip text: full path and exact size
+fn tooltip(node: &TreeNode, opts: &Options) -> String {
+    format!(
+        "{}\n{} ({} bytes)",
+        node.path,
+        opts.format_size(node.bytes.div_ceil(1024)),
+        opts.group_digits(node.bytes)
+    )
+}
+
+/// Lay out and draw the children of `parent` inside `area`
+fn write_children(
+    out: &mut dyn Write,
+    parent: &TreeNode,
+    area: Rect,
+    depth: usize,
+    top_level_index: Option<usize>,
+    opts: &Options,
+) -> io::Result<()> {
+    let sizes: Vec<f64> = parent.children.iter().map(|c| c.bytes as f64).collect();
+    let rects = squarify(&sizes, area);
+    for (index, (child, rect)) in parent.children.iter().zip(rects).enumerate() {
+        // Too small to see
+        if rect.w < 1.0 || rect.h < 1.0 {
+            continue;
+        }
+        let color_index = top_level_index.unwrap_or(index);
+        writeln!(out, "<g>")?;
+        writeln!(out, "<title>{}</title>", html_escape(&tooltip(child, opts)))?;
+        writeln!(
+            out,
+            "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" \
+             fill=\"{}\" stroke=\"#ffffff\" stroke-width=\"0.5\"/>",
+            rect.x,
+            rect.y,
+            rect.w,
+            rect.h,
+            fill_color(color_index, depth)
+        )?;
+        let label = (rect.h >= LABEL_HEIGHT)
+            .then(|| fit_label(&child.name, rect.w))
+            .flatten();
+        if let Some(label) = &label {
+            writeln!(
+                out,
+                "<text x=\"{:.2}\" y=\"{:.2}\">{}</text>",
+                rect.x + 3.0,
+                rect.y + LABEL_HEIGHT - 4.0,
+                html_escape(label)
+            )?;
+        }
+        writeln!(out, "</g>")?;
+        if !child.children.is_empty() {
+            let top = if label.is_some() {
+                LABEL_HEIGHT
+            } else {
+                PADDING
+            };
+            let inner = rect.inset(PADDING, top, PADDING);
+            if inner.w >= 1.0 && inner.h >= 1.0 {
+                write_children(out, child, inner, depth + 1, Some(color_index), opts)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the treemap of a scan to `dest`, replacing the file only once it is complete
+pub fn write_treemap(dest: &str, tree: &TreeNode, opts: &Options) -> Result<(), DustrError> {
+    let mut out = Output::open(Some(dest))?;
+    write_svg(&mut out, tree, opts)?;
+    out.finish()
+}
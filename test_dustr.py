@@ -1,15 +1,77 @@
 #!/usr/bin/env python3
 """Tests for dustr"""
 
+import contextlib
+import errno
+import json
+import logging
 import os
+import re
 import tempfile
 import signal
+import socket
+import stat
 import subprocess
 import sys
+import threading
 import time
 from pathlib import Path
 
-from dustr._dustr import calculate_directory_sizes, get_file_type_indicator
+from dustr._dustr import (
+    Scanner,
+    EntryInfo,
+    LimitsExceeded,
+    ScanTruncated,
+    broken_symlinks,
+    build_info,
+    calculate_both,
+    calculate_directory_sizes,
+    classify_path,
+    compare,
+    directory_total_kb,
+    disk_usage,
+    dump_tree_json,
+    estimate_scan,
+    file_size_stats,
+    find_duplicates,
+    find_empty_dirs,
+    get_file_type_indicator,
+    group_digits,
+    inode_capacity,
+    inode_hogs,
+    print_disk_usage,
+    recent_files,
+    scan_async,
+    sizes_by_depth,
+    sizes_for,
+    start_scan,
+    type_breakdown,
+    usage_by_depth,
+    version,
+    watch,
+)
+
+
+@contextlib.contextmanager
+def _clean_env(*names):
+    """Unset the given environment variables (the color ones by default), restoring them on exit"""
+    saved = {var: os.environ.pop(var, None) for var in names or ("NO_COLOR", "CLICOLOR_FORCE")}
+    try:
+        yield
+    finally:
+        for var, value in saved.items():
+            os.environ.pop(var, None)
+            if value is not None:
+                os.environ[var] = value
+
+
+@contextlib.contextmanager
+def _data_tree(report_name="report"):
+    """Yield a temporary directory, an empty data/ directory in it and a report path beside it"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        data = Path(tmpdir) / "data"
+        data.mkdir()
+        yield tmpdir, data, Path(tmpdir) / report_name
 
 
 def test_calculate_directory_sizes():
@@ -57,6 +119,21 @@ def test_calculate_directory_sizes_inodes():
         assert inodes["subdir"] == 3  # Directory + 2 files
 
 
+def test_dirs_only():
+    """Test counting only directories in inode mode"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        (Path(tmpdir) / "file1.txt").write_text("x")
+        subdir = Path(tmpdir) / "subdir"
+        (subdir / "a" / "b").mkdir(parents=True)
+        (subdir / "c").mkdir()
+        (subdir / "file2.txt").write_text("y")
+
+        counts = calculate_directory_sizes(tmpdir, use_inodes=True, dirs_only=True)
+
+        assert counts["file1.txt"] == 0
+        assert counts["subdir"] == 4  # subdir, a, a/b, c
+
+
 def test_get_file_type_indicator():
     """Test file type indicators"""
     with tempfile.TemporaryDirectory() as tmpdir:
@@ -77,6 +154,1965 @@ def test_get_file_type_indicator():
         link_path.symlink_to(file_path)
         assert get_file_type_indicator(str(link_path)) == "@"
 
+        # Create a dangling symlink
+        broken_path = tmppath / "brokenlink"
+        broken_path.symlink_to(tmppath / "missing")
+        assert get_file_type_indicator(str(broken_path)) == "@!"
+
+        # An executable file
+        exe_path = tmppath / "run.sh"
+        exe_path.write_text("#!/bin/sh\n")
+        exe_path.chmod(0o755)
+        assert get_file_type_indicator(str(exe_path)) == "*"
+
+        # A FIFO
+        fifo_path = tmppath / "pipe"
+        os.mkfifo(fifo_path)
+        assert get_file_type_indicator(str(fifo_path)) == "|"
+
+        # A Unix socket
+        sock_path = tmppath / "sock"
+        sock = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+        try:
+            sock.bind(str(sock_path))
+            assert get_file_type_indicator(str(sock_path)) == "="
+        finally:
+            sock.close()
+
+        # A symlink to a directory is marked as traversable
+        dir_link = tmppath / "dirlink"
+        dir_link.symlink_to(dir_path)
+        assert get_file_type_indicator(str(dir_link)) == "@/"
+
+        # Missing paths are empty unless strict
+        missing = tmppath / "missing"
+        assert get_file_type_indicator(str(missing)) == ""
+        try:
+            get_file_type_indicator(str(missing), strict=True)
+            assert False, "Should have raised FileNotFoundError"
+        except FileNotFoundError as e:
+            assert e.errno == errno.ENOENT
+        assert get_file_type_indicator(str(file_path), strict=True) == ""
+
+        assert {
+            name: classify_path(str(tmppath / name))
+            for name in (
+                "testfile.txt", "testdir", "testlink", "dirlink", "brokenlink",
+                "run.sh", "pipe", "sock", "missing",
+            )
+        } == {
+            "testfile.txt": "file",
+            "testdir": "dir",
+            "testlink": "symlink",
+            "dirlink": "symlink-dir",
+            "brokenlink": "broken-symlink",
+            "run.sh": "file",
+            "pipe": "fifo",
+            "sock": "socket",
+            "missing": "missing",
+        }
+        if stat.S_ISCHR(os.stat("/dev/null").st_mode):
+            assert classify_path("/dev/null") == "device"
+
+        # Names are listed unchanged, with the indicator in its own column
+        report = tmppath / "report.txt"
+        print_disk_usage(str(tmppath), output=str(report))
+        lines = report.read_text().splitlines()
+        header = next(line for line in lines if line.startswith("Size"))
+        assert "Type" in header
+        row = next(line for line in lines if line.rstrip().endswith("testdir"))
+        assert row.split()[-2:] == ["/", "testdir"], row
+        print_disk_usage(str(tmppath), no_f=True, output=str(report))
+        assert "Type" not in report.read_text()
+
+
+def test_version():
+    """Test that the version string is exposed and matches --version"""
+    v = version()
+    assert v and v[0].isdigit()
+
+    result = subprocess.run(
+        [sys.executable, "-m", "dustr", "--version"],
+        capture_output=True,
+        text=True,
+        check=True,
+    )
+    assert v in result.stdout
+
+    import dustr._dustr
+
+    assert dustr._dustr.__version__ == v
+    info = build_info()
+    assert info["version"] == v
+    assert info["format_version"] >= 1
+    assert info["profile"] in ("debug", "release")
+    assert info["rustc"].startswith("rustc ")
+    assert info["target"] in result.stdout and info["rustc"] in result.stdout
+
+    # Reports record the version that wrote them
+    with tempfile.TemporaryDirectory() as tmpdir:
+        report = Path(tmpdir) / "report"
+        print_disk_usage(tmpdir, json=True, output=str(report))
+        data = json.loads(report.read_text())
+        assert data["dustr_version"] == v
+        assert data["format_version"] == info["format_version"]
+        print_disk_usage(tmpdir, format="markdown", output=str(report))
+        assert f"by dustr {v} (format {info['format_version']})" in report.read_text()
+        print_disk_usage(tmpdir, format="html", output=str(report))
+        assert f'<meta name="generator" content="dustr {v}">' in report.read_text()
+
+
+def test_broken_symlinks():
+    """Test that dangling symlinks are found anywhere in the tree"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        root = Path(tmpdir)
+        (root / "target.txt").write_text("x")
+        (root / "good").symlink_to(root / "target.txt")
+        (root / "dead").symlink_to(root / "missing.txt")
+        subdir = root / "subdir"
+        subdir.mkdir()
+        (subdir / "dead2").symlink_to("nowhere")
+
+        links = broken_symlinks(tmpdir)
+
+        assert links == [
+            (str(root / "dead"), str(root / "missing.txt")),
+            (str(subdir / "dead2"), "nowhere"),
+        ]
+
+
+def test_count_symlink_size():
+    """Test counting symlink targets without double-counting in-tree targets"""
+    with tempfile.TemporaryDirectory() as tmpdir, tempfile.TemporaryDirectory() as other:
+        big = Path(other) / "big.bin"
+        big.write_bytes(b"x" * 65536)
+        big_kb = (os.stat(big).st_blocks * 512 + 1023) // 1024
+
+        links = Path(tmpdir) / "links"
+        links.mkdir()
+        (links / "a").symlink_to(big)
+        (links / "b").symlink_to(big)
+
+        # Only the targets' blocks, not those of the directories
+        def sizes_of(**kwargs):
+            return calculate_directory_sizes(tmpdir, False, include_dir_overhead=False, **kwargs)
+
+        sizes = sizes_of()
+        assert sizes["links"] == 0
+
+        sizes = sizes_of(count_symlink_size=True)
+        assert sizes["links"] == big_kb
+
+        # A target that is itself inside the scanned tree is only counted once
+        data = Path(tmpdir) / "data"
+        data.mkdir()
+        (data / "real.bin").write_bytes(b"y" * 65536)
+        (links / "c").symlink_to(data / "real.bin")
+        real_kb = (os.stat(data / "real.bin").st_blocks * 512 + 1023) // 1024
+
+        sizes = sizes_of(count_symlink_size=True)
+        assert sum(sizes.values()) == big_kb + real_kb
+
+        # ... and charged to the entry first in name order, whichever entry
+        # the parallel walkers reach it from first
+        (Path(tmpdir) / "aaa").mkdir()
+        (Path(tmpdir) / "aaa" / "d").symlink_to(data / "real.bin")
+        for _ in range(5):
+            sizes = sizes_of(count_symlink_size=True)
+            assert sizes == {"aaa": real_kb, "data": 0, "links": big_kb}, sizes
+
+
+def test_age_filters():
+    """Test that min_age/max_age restrict which files are counted"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        d = Path(tmpdir) / "d"
+        d.mkdir()
+        old = d / "old.bin"
+        old.write_bytes(b"x" * 65536)
+        new = d / "new.bin"
+        new.write_bytes(b"y" * 65536)
+        two_years = 2 * 365 * 86400
+        os.utime(old, (time.time() - two_years, time.time() - two_years))
+        old_kb = (os.stat(old).st_blocks * 512 + 1023) // 1024
+        new_kb = (os.stat(new).st_blocks * 512 + 1023) // 1024
+
+        def size_of_d(**ages):
+            sizes = calculate_directory_sizes(tmpdir, False, include_dir_overhead=False, **ages)
+            return sizes["d"]
+
+        assert size_of_d(min_age="1y") == old_kb
+        assert size_of_d(max_age="30d") == new_kb
+        assert size_of_d(min_age="1y", max_age="30d") == 0
+
+        # Directories are still counted as inodes, only files are filtered
+        assert calculate_directory_sizes(tmpdir, True, min_age="1y")["d"] == 2
+
+        # A file with a future mtime has age zero
+        future = time.time() + 86400
+        os.utime(new, (future, future))
+        assert size_of_d(max_age="1d") == new_kb
+
+        try:
+            calculate_directory_sizes(tmpdir, False, min_age="soon")
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_streaming():
+    """Test that streaming mode gives the same results as the default"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        for i in range(50):
+            d = Path(tmpdir) / f"d{i}"
+            d.mkdir()
+            (d / "f.bin").write_bytes(b"x" * (1024 * (i + 1)))
+        (Path(tmpdir) / "top.txt").write_text("hello")
+
+        for use_inodes in (False, True):
+            expected = calculate_directory_sizes(tmpdir, use_inodes)
+            assert calculate_directory_sizes(tmpdir, use_inodes, streaming=True) == expected
+
+
+def test_type_breakdown():
+    """Test the per-kind counts collected with the type summary"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        d = Path(tmpdir) / "d"
+        d.mkdir()
+        (d / "a.bin").write_bytes(b"x" * 8192)
+        (d / "b.txt").write_text("hello")
+        (d / "link").symlink_to(d / "a.bin")
+        os.mkfifo(d / "pipe")
+        (Path(tmpdir) / "top.txt").write_text("top")
+
+        types = type_breakdown(tmpdir)
+        assert set(types) == {"files", "directories", "symlinks", "special"}
+        assert types["files"]["count"] == 3
+        assert types["directories"]["count"] == 1
+        assert types["symlinks"]["count"] == 1
+        assert types["special"]["count"] == 1
+        assert types["files"]["bytes"] >= 8192
+
+
+def test_size_histogram():
+    """Test the distribution of file sizes in the table and JSON report"""
+    with _data_tree() as (_, data, report):
+        (data / "sub").mkdir(parents=True)
+        (data / "empty").touch()
+        (data / "small.txt").write_text("x" * 100)
+        (data / "sub" / "medium.bin").write_bytes(b"x" * 100_000)
+        (data / "sub" / "large.bin").write_bytes(b"x" * 2_000_000)
+
+        print_disk_usage(str(data), size_histogram=True, json=True, output=str(report))
+        buckets = json.loads(report.read_text())["size_histogram"]
+        assert [b["le"] for b in buckets] == [
+            0, 4096, 65536, 1024000, 16384000, 262144000, 4096000000, None
+        ]
+        assert [b["count"] for b in buckets] == [1, 1, 0, 1, 1, 0, 0, 0]
+        assert sum(b["bytes"] for b in buckets) == 2_100_100
+
+        print_disk_usage(
+            str(data), size_histogram=True, buckets=["1K", "1M"], json=True, output=str(report)
+        )
+        buckets = json.loads(report.read_text())["size_histogram"]
+        assert [(b["le"], b["count"]) for b in buckets] == [(1024, 2), (1024000, 1), (None, 1)]
+
+        print_disk_usage(str(data), size_histogram=True, output=str(report))
+        text = report.read_text()
+        assert "File sizes:" in text and "empty" in text and "> 4.0 GB" in text, text
+        print_disk_usage(str(data), json=True, output=str(report))
+        assert "size_histogram" not in json.loads(report.read_text())
+
+        for kwargs in ({"buckets": ["1K"]}, {"size_histogram": True, "buckets": ["lots"]}):
+            try:
+                print_disk_usage(str(data), output=str(report), **kwargs)
+                assert False, "Should have raised ValueError"
+            except ValueError:
+                pass
+
+
+def test_duplicates_report():
+    """Test the sets of identical files found during the scan"""
+    with _data_tree() as (_, data, report):
+        (data / "a").mkdir(parents=True)
+        (data / "b").mkdir()
+        content = os.urandom(20_000)
+        (data / "a" / "one.bin").write_bytes(content)
+        (data / "b" / "two.bin").write_bytes(content)
+        os.link(data / "a" / "one.bin", data / "b" / "link.bin")
+        # Same size and head, different tail
+        (data / "a" / "other.bin").write_bytes(content[:-1] + b"!")
+        (data / "small1").write_bytes(b"s" * 3000)
+        (data / "small2").write_bytes(b"s" * 3000)
+
+        print_disk_usage(
+            str(data), duplicates=True, dup_min_size="1K", threads=2, json=True,
+            output=str(report)
+        )
+        sets = json.loads(report.read_text())["duplicates"]
+        assert [(s["size"], s["reclaimable"], len(s["paths"])) for s in sets] == [
+            (20_000, 20_000, 2), (3000, 3000, 2)
+        ], sets
+        assert str(data / "b" / "two.bin") in sets[0]["paths"]
+
+        print_disk_usage(str(data), duplicates=True, json=True, output=str(report))
+        assert json.loads(report.read_text())["duplicates"] == []
+        print_disk_usage(str(data), duplicates=True, dup_min_size="1K", output=str(report))
+        text = report.read_text()
+        assert "Duplicate files: 2 sets" in text and "2 copies" in text, text
+        print_disk_usage(str(data), json=True, output=str(report))
+        assert "duplicates" not in json.loads(report.read_text())
+
+        for kwargs in ({"dup_min_size": "1K"}, {"threads": 0},
+                       {"duplicates": True, "dup_min_size": "lots"}):
+            try:
+                print_disk_usage(str(data), output=str(report), **kwargs)
+                assert False, "Should have raised ValueError"
+            except ValueError:
+                pass
+
+
+def test_find_empty():
+    """Test the empty files and directories found during the scan"""
+    with _data_tree() as (_, data, report):
+        (data / "nested" / "deeper").mkdir(parents=True)
+        (data / "bare").mkdir()
+        (data / "used" / "sub").mkdir(parents=True)
+        (data / "used" / "sub" / "file.txt").write_text("x")
+        (data / "dotted" / ".git").mkdir(parents=True)
+        (data / "dotted" / ".git" / "HEAD").write_text("x")
+        (data / "used" / "zero").touch()
+        (data / "top-zero").touch()
+        for i in range(5):
+            (data / "many" / f"d{i}").mkdir(parents=True)
+
+        print_disk_usage(str(data), find_empty=True, json=True, output=str(report))
+        empty = json.loads(report.read_text())["empty"]
+        rel = lambda paths: [os.path.relpath(p, data) for p in paths]
+        assert rel(empty["files"]) == ["top-zero", "used/zero"], empty
+        assert rel(empty["dirs"]) == [
+            "bare", "many", "many/d0", "many/d1", "many/d2", "many/d3", "many/d4",
+            "nested", "nested/deeper",
+        ], empty
+        # Recursively empty, as find_empty_dirs sees it
+        assert sorted(rel(find_empty_dirs(str(data)))) == rel(empty["dirs"])
+
+        print_disk_usage(str(data), find_empty=True, find_empty_limit=3, output=str(report))
+        text = report.read_text()
+        assert "Empty files: 2" in text and "Empty directories: 9" in text, text
+        assert "... and 6 more" in text, text
+        print_disk_usage(str(data), json=True, output=str(report))
+        assert "empty" not in json.loads(report.read_text())
+
+        try:
+            print_disk_usage(str(data), find_empty_limit=3, output=str(report))
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_quiet_errors():
+    """Test that --quiet-errors reports errors without the decorative text"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        # Writing the report over a directory fails
+        cmd = [sys.executable, "-m", "dustr", "--no-config", "--no-progress", tmpdir, "-o", tmpdir]
+        result = subprocess.run(cmd, capture_output=True, text=True)
+        assert result.returncode == 1, result
+        assert "eaten by the Python" in result.stderr, result.stderr
+
+        result = subprocess.run(cmd + ["--quiet-errors"], capture_output=True, text=True)
+        assert result.returncode == 1, result
+        assert result.stderr.startswith("dustr: "), result.stderr
+        assert "Python" not in result.stderr and result.stdout == "", result
+
+
+def test_timing():
+    """Test that --timing and timed=True report the scan duration on stderr"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        report = Path(tmpdir) / "report"
+        code = (
+            "from dustr._dustr import print_disk_usage; "
+            f"print_disk_usage({tmpdir!r}, output={str(report)!r}, no_progress=True, timed=True)"
+        )
+        result = subprocess.run([sys.executable, "-c", code], capture_output=True, text=True)
+        assert result.returncode == 0, result
+        assert re.fullmatch(r"Scanned in \d+\.\d\ds\n", result.stderr), result.stderr
+
+        cmd = [sys.executable, "-m", "dustr", "--no-config", "--no-progress", tmpdir]
+        result = subprocess.run(cmd + ["--timing"], capture_output=True, text=True)
+        assert result.returncode == 0, result
+        assert result.stderr.startswith("Scanned in "), result.stderr
+        assert "Scanned in" not in subprocess.run(cmd, capture_output=True, text=True).stderr
+
+
+def test_xattrs():
+    """Test that --xattrs adds the extended attribute sizes"""
+    with _data_tree() as (_, data, report):
+        (data / "d").mkdir(parents=True)
+        target = data / "d" / "f"
+        target.write_bytes(b"x" * 100)
+        try:
+            os.setxattr(target, "user.dustr", b"x" * 2000)
+        except (AttributeError, OSError):
+            return  # no user xattrs on this platform or filesystem
+
+
+        def scan(**kwargs):
+            print_disk_usage(str(data), json=True, output=str(report), **kwargs)
+            return json.loads(report.read_text())
+
+        plain, with_xattrs = scan(), scan(xattrs=True)
+        assert "xattr_bytes" not in plain
+        assert with_xattrs["xattr_bytes"] == 2000, with_xattrs
+        delta = with_xattrs["entries"][0]["bytes"] - plain["entries"][0]["bytes"]
+        assert delta == 2000, (plain, with_xattrs)
+
+
+def test_highlight():
+    """Test that --highlight marks the rows of matching entries"""
+    with _clean_env():
+        with _data_tree("report.txt") as (_, data, report):
+            names = ("node_modules", "src", "build")
+            for name in names:
+                (data / name).mkdir(parents=True)
+                (data / name / "f").write_text("x")
+
+            def rows(**kwargs):
+                print_disk_usage(str(data), output=str(report), **kwargs)
+                lines = report.read_text().splitlines()
+                return {line.split()[-1]: line for line in lines if line.rstrip().endswith(names)}
+
+            marked = rows(color="never", highlight=["node_modules", "b*"])
+            assert marked["node_modules"].startswith("> "), marked
+            assert marked["build"].startswith("> "), marked
+            assert marked["src"].startswith("  "), marked
+            header = next(line for line in report.read_text().splitlines() if "Name" in line)
+            assert header.startswith("  Size"), header
+
+            # With color the name is bold instead, and no column is added
+            rows(color="always", highlight=["node_modules"])
+            text = report.read_text()
+            assert "\x1b[1mnode_modules" in text, text
+            assert "> " not in text
+
+            plain = rows(color="never")
+            assert not any(line.startswith(("> ", "  ")) for line in plain.values()), plain
+
+
+def test_usage_by_depth():
+    """Test the usage at each depth below the scanned directory"""
+    with _data_tree() as (_, data, report):
+        (data / "a" / "b" / "c").mkdir(parents=True)
+        (data / "top.bin").write_bytes(b"x" * 5000)
+        (data / "a" / "one.bin").write_bytes(b"x" * 9000)
+        (data / "a" / "b" / "c" / "deep1.bin").write_bytes(b"x" * 20000)
+        (data / "a" / "b" / "c" / "deep2.bin").write_bytes(b"x" * 100)
+
+        expected = {}
+        for root, dirs, files in os.walk(data):
+            for name in dirs + files:
+                path = Path(root) / name
+                depth = len(path.relative_to(data).parts)
+                size, count = expected.get(depth, (0, 0))
+                expected[depth] = (size + path.stat().st_blocks * 512, count + path.is_file())
+        levels = usage_by_depth(str(data))
+        assert levels == [(d, *expected[d]) for d in sorted(expected)], (levels, expected)
+        assert [(d, n) for d, _, n in levels] == [(1, 1), (2, 1), (3, 0), (4, 2)]
+        assert usage_by_depth(str(data), exclude=["c"])[-1][0] == 2
+        assert sizes_by_depth(str(data)) == {d: size for d, size, _ in levels}
+
+        print_disk_usage(str(data), by_depth=True, json=True, output=str(report))
+        rows = json.loads(report.read_text())["by_depth"]
+        assert [(r["depth"], r["bytes"], r["files"]) for r in rows] == levels
+
+        print_disk_usage(str(data), by_depth=True, max_depth_rows=2, output=str(report))
+        text = report.read_text()
+        assert "Usage by depth:" in text, text
+        assert any(line.split()[:2] == ["2+", "3"] for line in text.splitlines()), text
+
+        for kwargs in ({"max_depth_rows": 2}, {"by_depth": True, "max_depth_rows": 0}):
+            try:
+                print_disk_usage(str(data), output=str(report), **kwargs)
+                assert False, "Should have raised ValueError"
+            except ValueError:
+                pass
+
+
+def test_inode_hogs():
+    """Test the directories with the most direct children"""
+    with _data_tree() as (_, data, report):
+        (data / "many").mkdir(parents=True)
+        (data / "some" / "few").mkdir(parents=True)
+        for i in range(30):
+            (data / "many" / f"f{i}").touch()
+        for i in range(4):
+            (data / "some" / f"s{i}").touch()
+            (data / "some" / "few" / f"g{i}").touch()
+
+        hogs = inode_hogs(str(data), 3)
+        assert hogs == [
+            (str(data / "many"), 30, 30),
+            (str(data / "some"), 5, 9),
+            (str(data / "some" / "few"), 4, 4),
+        ], hogs
+        assert inode_hogs(str(data), 1, exclude=["many"])[0][0] == str(data / "some")
+        try:
+            inode_hogs(str(data), 0)
+            assert False, "n=0 should be rejected"
+        except ValueError:
+            pass
+
+        print_disk_usage(str(data), inode_hogs=2, inodes=True, json=True, output=str(report))
+        rows = json.loads(report.read_text())["inode_hogs"]
+        assert [(r["path"], r["direct"], r["recursive"]) for r in rows] == hogs[:2]
+
+
+def test_find_junk():
+    """Test the report of reclaimable leftovers"""
+    with _data_tree() as (_, data, report):
+        (data / "app").mkdir(parents=True)
+        (data / "app" / "core.4242").write_bytes(b"x" * 50000)
+        (data / "app" / "core.rs").write_bytes(b"fn main() {}")
+        (data / "app" / ".DS_Store").write_bytes(b"x")
+        (data / "app" / "notes.txt~").write_bytes(b"x")
+        (data / "app" / "old.bak").write_bytes(b"x")
+        (data / "build.tmp").write_bytes(b"x")
+
+        print_disk_usage(str(data), find_junk=True, json=True, output=str(report))
+        junk = json.loads(report.read_text())["junk"]
+        paths = [f["path"] for f in junk["files"]]
+        assert paths[0] == str(data / "app" / "core.4242"), paths
+        assert sorted(paths[1:]) == sorted(
+            str(data / p) for p in ("app/.DS_Store", "app/notes.txt~", "build.tmp")
+        ), paths
+        assert junk["bytes"] == sum(f["bytes"] for f in junk["files"])
+        assert {t["pattern"]: t["files"] for t in junk["patterns"]} == {
+            "core.[0-9]+": 1,
+            "*.tmp": 1,
+            "*~": 1,
+            ".DS_Store": 1,
+        }, junk["patterns"]
+
+        print_disk_usage(
+            str(data), find_junk=True, junk_pattern=["*.bak"], find_junk_limit=1, output=str(report)
+        )
+        text = report.read_text()
+        assert "Junk files: 5," in text, text
+        assert "core.4242" in text and "... and 4 more" in text, text
+
+        try:
+            print_disk_usage(str(data), junk_pattern=["*.bak"], output=str(report))
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_estimate_scan():
+    """Test that a small tree, sampled in full, is estimated exactly"""
+    with _data_tree() as (_, data, _):
+        for d in range(3):
+            (data / f"d{d}" / "sub").mkdir(parents=True)
+            for i in range(5):
+                (data / f"d{d}" / "sub" / f"f{i}").write_bytes(b"x" * 100)
+        (data / "top.bin").write_bytes(b"x" * 100)
+
+        estimate = estimate_scan(str(data))
+        assert estimate.sampled_dirs == 3, estimate
+        entries = sum(calculate_directory_sizes(str(data), True).values())
+        assert estimate.estimated_entries == entries, (estimate, entries)
+        assert estimate.estimated_bytes > 0 and estimate.estimated_seconds >= 0
+        assert "EstimateResult(" in repr(estimate)
+
+        try:
+            estimate_scan(str(data / "missing"))
+            assert False, "Should have raised for a missing path"
+        except FileNotFoundError:
+            pass
+
+
+def test_file_size_stats():
+    """Test the file size statistics"""
+    with _data_tree() as (_, data, report):
+        (data / "sub").mkdir(parents=True)
+        sizes = [0, 10, 200, 3000, 40000]
+        for i, size in enumerate(sizes):
+            (data / ("sub" if i % 2 else ".") / f"f{i}").write_bytes(b"x" * size)
+
+        stats = file_size_stats(str(data))
+        assert stats == {
+            "count": 5,
+            "total": sum(sizes),
+            "mean": sum(sizes) / 5,
+            "median": 200,
+            "p90": 40000,
+            "p99": 40000,
+            "max": 40000,
+        }, stats
+        assert file_size_stats(str(data), exclude=["sub"])["count"] == 3
+
+        (data / "empty").mkdir()
+        stats = file_size_stats(str(data / "empty"))
+        assert stats["count"] == 0 and stats["mean"] == 0 and stats["median"] == 0, stats
+
+        print_disk_usage(str(data), stats=True, json=True, output=str(report))
+        block = json.loads(report.read_text())["file_size_stats"]
+        assert (block["count"], block["median"], block["max"]) == (5, 200, 40000), block
+        print_disk_usage(str(data), stats=True, output=str(report))
+        assert "File size statistics:" in report.read_text()
+
+
+def test_recent_files():
+    """Test the listing of large recently modified files"""
+    with _data_tree() as (_, data, report):
+        (data / "logs").mkdir(parents=True)
+        (data / "logs" / "big.log").write_bytes(b"x" * 300_000)
+        (data / "fresh.bin").write_bytes(b"x" * 200_000)
+        (data / "tiny.txt").write_bytes(b"x" * 10)
+        old = data / "old.bin"
+        old.write_bytes(b"x" * 400_000)
+        week_ago = time.time() - 7 * 86400
+        os.utime(old, (week_ago, week_ago))
+
+        files = recent_files(str(data), 3600, 100_000)
+        assert [(f["path"], f["bytes"]) for f in files] == [
+            (str(data / "logs" / "big.log"), 300_000),
+            (str(data / "fresh.bin"), 200_000),
+        ], files
+        assert abs(files[0]["mtime"] - time.time()) < 600
+        assert len(recent_files(str(data), 30 * 86400, 100_000)) == 3
+        assert [f["bytes"] for f in recent_files(str(data), 3600)] == [300_000, 200_000, 10]
+
+        print_disk_usage(str(data), recent="1h", recent_min_size="100K", json=True, output=str(report))
+        rows = json.loads(report.read_text())["recent"]
+        assert [r["path"] for r in rows] == [f["path"] for f in files], rows
+        print_disk_usage(str(data), recent="1h", recent_limit=1, output=str(report))
+        text = report.read_text()
+        assert "newer than 1h: 0" in text, text
+        try:
+            print_disk_usage(str(data), recent_min_size="1K", output=str(report))
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_check_interval():
+    """Test that the interrupt-check interval does not change results"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        d = Path(tmpdir) / "d"
+        d.mkdir()
+        for i in range(30):
+            (d / f"f{i}").write_bytes(b"x" * 100)
+
+        expected = calculate_directory_sizes(tmpdir, True)
+        for interval in (1, 7, 1000):
+            assert calculate_directory_sizes(tmpdir, True, check_interval=interval) == expected
+
+        try:
+            calculate_directory_sizes(tmpdir, True, check_interval=0)
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_watch_interval():
+    """Test that watch rejects an interval that would refresh in a busy loop"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        try:
+            watch(tmpdir, interval_secs=0)
+            assert False, "Should have raised ValueError"
+        except ValueError as e:
+            assert "interval_secs" in str(e)
+
+
+def test_exclude_from():
+    """Test exclude patterns read from a file, merged with inline ones"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        root = Path(tmpdir) / "root"
+        keep = root / "keep"
+        keep.mkdir(parents=True)
+        (keep / "data.bin").write_bytes(b"x" * 8192)
+        (keep / "scratch.tmp").write_bytes(b"x" * 8192)
+        (keep / "node_modules").mkdir()
+        (keep / "node_modules" / "dep.js").write_bytes(b"x" * 8192)
+        (root / "build").mkdir()
+        (root / "notes.log").write_text("log")
+
+        patterns = Path(tmpdir) / "excludes.txt"
+        patterns.write_text("# build output\n\nbuild\n*.tmp\n   \nnode_modules\n")
+
+        inodes = calculate_directory_sizes(str(root), True, exclude_from=str(patterns))
+        assert set(inodes) == {"keep", "notes.log"}
+        assert inodes["keep"] == 2
+
+        inodes = calculate_directory_sizes(
+            str(root), True, exclude=["*.log"], exclude_from=str(patterns)
+        )
+        assert set(inodes) == {"keep"}
+
+        types = type_breakdown(str(root), exclude_from=str(patterns))
+        assert types["files"]["count"] == 2
+
+        result = subprocess.run(
+            [sys.executable, "-m", "dustr", "--exclude-from", str(patterns), str(root)],
+            capture_output=True,
+            text=True,
+            check=True,
+        )
+        assert "build" not in result.stdout
+
+        try:
+            calculate_directory_sizes(str(root), True, exclude_from=str(Path(tmpdir) / "nope"))
+            assert False, "Should have raised FileNotFoundError"
+        except FileNotFoundError as e:
+            assert "exclude file" in str(e)
+
+
+def test_size_range():
+    """Test min_size_kb/max_size_kb filtering of entries"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        for name, size in (("small", 4096), ("medium", 65536), ("large", 1048576)):
+            d = Path(tmpdir) / name
+            d.mkdir()
+            (d / "data.bin").write_bytes(b"x" * size)
+        sizes = calculate_directory_sizes(tmpdir, False)
+
+        mid = calculate_directory_sizes(
+            tmpdir, False, min_size_kb=sizes["medium"], max_size_kb=sizes["medium"]
+        )
+        assert mid == {"medium": sizes["medium"]}
+        assert set(calculate_directory_sizes(tmpdir, False, min_size_kb=sizes["medium"])) == {
+            "medium",
+            "large",
+        }
+        assert set(calculate_directory_sizes(tmpdir, False, max_size_kb=sizes["medium"])) == {
+            "small",
+            "medium",
+        }
+
+        try:
+            calculate_directory_sizes(tmpdir, False, min_size_kb=100, max_size_kb=10)
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_include_filters():
+    """Test path exclude globs and include file selection"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        root = Path(tmpdir)
+        for name in ("build-20240101", "build-20240201", "src"):
+            d = root / name
+            d.mkdir()
+            (d / "out.o").write_bytes(b"x" * 4096)
+            (d / "notes.txt").write_text("n")
+
+        inodes = calculate_directory_sizes(tmpdir, True, exclude=["build-[0-9]*"])
+        assert set(inodes) == {"src"}
+
+        # Nested paths are matched relative to the root with "/" separators
+        inodes = calculate_directory_sizes(tmpdir, True, exclude=["src/*.o"])
+        assert inodes["src"] == 2
+
+        inodes = calculate_directory_sizes(tmpdir, True, include=["*.txt"])
+        assert inodes == {"build-20240101": 2, "build-20240201": 2, "src": 2}
+        inodes = calculate_directory_sizes(tmpdir, True, include=["src/*.txt"])
+        assert inodes == {"build-20240101": 1, "build-20240201": 1, "src": 2}
+
+
+def test_find_duplicates():
+    """Test duplicate detection by size and content"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        root = Path(tmpdir)
+        (root / "a").mkdir()
+        (root / "b").mkdir()
+        payload = os.urandom(20000)
+        (root / "a" / "one.bin").write_bytes(payload)
+        (root / "b" / "two.bin").write_bytes(payload)
+        (root / "b" / "three.bin").write_bytes(payload)
+        # Same size, different content
+        (root / "a" / "other.bin").write_bytes(os.urandom(20000))
+        # Hard links are not duplicates
+        os.link(root / "a" / "other.bin", root / "b" / "other_link.bin")
+        # Small duplicates are skipped by min_size_kb
+        (root / "a" / "small1").write_bytes(b"tiny")
+        (root / "b" / "small2").write_bytes(b"tiny")
+        (root / "a" / "empty1").write_bytes(b"")
+        (root / "b" / "empty2").write_bytes(b"")
+
+        groups = find_duplicates(tmpdir, 1)
+        assert groups == [
+            sorted(str(root / p) for p in ("a/one.bin", "b/three.bin", "b/two.bin"))
+        ]
+
+        groups = find_duplicates(tmpdir)
+        assert len(groups) == 2
+        assert groups[1] == [str(root / "a" / "small1"), str(root / "b" / "small2")]
+
+        assert find_duplicates(tmpdir, 1, exclude=["b"]) == []
+
+        # Hidden files are compared like dump_tree_json lists them
+        (root / ".cache").mkdir()
+        (root / ".cache" / "copy.bin").write_bytes(payload)
+        assert str(root / ".cache" / "copy.bin") in find_duplicates(tmpdir, 1)[0]
+        assert len(find_duplicates(tmpdir, 1, exclude=[".*"])[0]) == 3
+
+
+def test_dump_tree_json():
+    """Test the sorted path-to-size inventory of a whole tree"""
+    with _data_tree() as (tmpdir, data, _):
+        (data / "b" / "deep").mkdir(parents=True)
+        (data / "a").mkdir()
+        (data / "b" / "deep" / "big.bin").write_bytes(b"x" * 10000)
+        (data / "a" / "z.txt").write_text("x")
+        (data / "top.txt").write_text("x")
+        (data / ".git").mkdir()
+        (data / ".git" / "HEAD").write_text("ref")
+        os.symlink("top.txt", data / "link")
+
+        out = Path(tmpdir) / "tree.json"
+        assert dump_tree_json(str(data), str(out), exclude=[".git"]) == 3
+        text = out.read_text()
+        tree = json.loads(text)
+        assert list(tree) == ["a/z.txt", "b/deep/big.bin", "top.txt"]
+        assert tree["b/deep/big.bin"] >= 10
+        first = out.read_bytes()
+        dump_tree_json(str(data), str(out), exclude=[".git"])
+        assert out.read_bytes() == first
+
+        # A change shows up as a single changed line
+        (data / "a" / "z.txt").write_text("x" * 20000)
+        dump_tree_json(str(data), str(out), exclude=[".git"])
+        changed = [
+            (old, new)
+            for old, new in zip(first.decode().splitlines(), out.read_text().splitlines())
+            if old != new
+        ]
+        assert len(changed) == 1 and '"a/z.txt"' in changed[0][0], changed
+
+        assert dump_tree_json(str(data), str(out)) == 4
+        assert ".git/HEAD" in json.loads(out.read_text())
+
+        empty = Path(tmpdir) / "empty"
+        empty.mkdir()
+        assert dump_tree_json(str(empty), str(out)) == 0
+        assert out.read_text() == "{}\n"
+        try:
+            dump_tree_json(str(data / "top.txt"), str(out))
+            assert False, "Should have raised NotADirectoryError"
+        except NotADirectoryError:
+            pass
+
+
+def test_find_empty_dirs():
+    """Test empty-directory detection, with and without recursion"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        root = Path(tmpdir)
+        (root / "leaf").mkdir()
+        (root / "nested" / "a" / "b").mkdir(parents=True)
+        (root / "nested" / "c").mkdir()
+        (root / "mixed" / "empty").mkdir(parents=True)
+        (root / "mixed" / "file.txt").write_text("x")
+        (root / "linked").mkdir()
+        os.symlink("/nonexistent", root / "linked" / "dangling")
+        (root / "skipped" / "cache").mkdir(parents=True)
+
+        def rel(paths):
+            return [os.path.relpath(p, tmpdir) for p in paths]
+
+        assert rel(find_empty_dirs(tmpdir)) == [
+            "leaf",
+            "mixed/empty",
+            "nested",
+            "nested/a",
+            "nested/a/b",
+            "nested/c",
+            "skipped",
+            "skipped/cache",
+        ]
+        assert rel(find_empty_dirs(tmpdir, recursive_empty=False)) == [
+            "leaf",
+            "mixed/empty",
+            "nested/a/b",
+            "nested/c",
+            "skipped/cache",
+        ]
+        # Excluded entries make their parent non-empty
+        assert "skipped" not in rel(find_empty_dirs(tmpdir, exclude=["cache"]))
+
+
+def test_group_digits():
+    """Test locale-dependent and explicit digit grouping"""
+    with _clean_env("LC_ALL", "LC_NUMERIC", "LANG"):
+        assert group_digits(1234567, separator=" ") == "1 234 567"
+        assert group_digits(999) == "999"
+
+        os.environ["LANG"] = "de_DE.UTF-8"
+        assert group_digits(1234567) == "1.234.567"
+        os.environ["LC_NUMERIC"] = "en_US.UTF-8"
+        assert group_digits(1234567) == "1,234,567"
+        os.environ["LC_ALL"] = "en_IN"
+        assert group_digits(123456789) == "12,34,56,789"
+        assert group_digits(123456789, separator="_") == "12_34_56_789"
+        os.environ["LC_ALL"] = "C"
+        assert group_digits(1234567) == "1'234'567"
+
+        with _data_tree("report.txt") as (_, data, report):
+            many = data / "many"
+            many.mkdir()
+            for i in range(1233):
+                (many / f"f{i}").touch()
+            print_disk_usage(
+                str(data), inodes=True, group_separator=".", output=str(report)
+            )
+            assert "1.234" in report.read_text()
+
+
+def test_log_scale_histogram():
+    """Test that the log scale keeps small entries visible"""
+    with _data_tree("report.txt") as (_, data, report):
+        (data / "huge.bin").write_bytes(os.urandom(4 * 1024 * 1024))
+        (data / "tiny.bin").write_bytes(b"x" * 4096)
+
+        def bars(scale):
+            print_disk_usage(str(data), scale=scale, output=str(report))
+            text = report.read_text()
+            return text, {
+                line.split()[-1]: line.split()[3].count("#")
+                for line in text.splitlines()
+                if ".bin" in line
+            }
+
+        text, linear = bars("linear")
+        assert "Histogram (log)" not in text
+        assert linear == {"huge.bin": 20, "tiny.bin": 1}
+
+        text, log = bars("log")
+        assert "Histogram (log)" in text
+        assert log["huge.bin"] == 20
+        assert 1 < log["tiny.bin"] < 20
+
+        try:
+            print_disk_usage(str(data), scale="sqrt", output=str(report))
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_progress_callback():
+    """Test that progress is reported to a Python callback"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        for i in range(25):
+            (Path(tmpdir) / f"file{i}.txt").write_text("x" * i)
+
+        calls = []
+        sizes = calculate_directory_sizes(
+            tmpdir, False, progress_callback=lambda *args: calls.append(args)
+        )
+        assert len(sizes) == 25
+        assert sorted(current for current, _, _ in calls) == list(range(1, 26))
+        assert {total for _, total, _ in calls} == {25}
+        assert {name for _, _, name in calls} == set(sizes)
+
+        calls.clear()
+        calculate_directory_sizes(
+            tmpdir,
+            False,
+            streaming=True,
+            progress_callback=lambda *args: calls.append(args),
+        )
+        assert len(calls) == 25
+        assert {total for _, total, _ in calls} == {None}
+
+        class Stop(Exception):
+            pass
+
+        def stop(current, total, name):
+            raise Stop(name)
+
+        try:
+            calculate_directory_sizes(tmpdir, False, progress_callback=stop)
+            assert False, "Should have raised the callback's exception"
+        except Stop:
+            pass
+
+
+def test_walk_callback():
+    """Test that the walk inside a large entry is reported while it runs"""
+    with _data_tree() as (_, data, _):
+        (data / "big").mkdir(parents=True)
+        for i in range(50):
+            (data / "big" / f"file{i}.bin").write_bytes(b"x" * 1024)
+        (data / "loose.txt").write_text("x")
+
+        calls = []
+        sizes = calculate_directory_sizes(
+            str(data), False, check_interval=10, walk_callback=lambda *args: calls.append(args)
+        )
+        assert calls, "walk_callback was never called"
+        assert {name for name, _, _ in calls} == {"big"}
+        for _, files, size in calls:
+            assert 0 < files <= 50
+            assert size >= files * 1024
+        assert max(size for _, _, size in calls) <= sizes["big"] * 1024
+
+        class Stop(Exception):
+            pass
+
+        def stop(name, files, size):
+            raise Stop(name)
+
+        try:
+            calculate_directory_sizes(str(data), False, check_interval=10, walk_callback=stop)
+            assert False, "Should have raised the callback's exception"
+        except Stop:
+            pass
+
+        # The terminal progress shows the same counters on a second line,
+        # wiped along with the bar
+        proc = subprocess.run(
+            [sys.executable, "-m", "dustr", "--check-interval", "10", str(data)],
+            capture_output=True,
+        )
+        stderr = proc.stderr.decode()
+        assert proc.returncode == 0, stderr
+        assert "big: " in stderr and " files, " in stderr, stderr
+        assert stderr.endswith("\r\x1b[K\n\x1b[K\x1b[1A"), repr(stderr[-40:])
+
+
+def test_bar_styles():
+    """Test block-character bars, custom bar characters and ASCII fallback"""
+    with _clean_env("LC_ALL", "LC_CTYPE", "LANG"):
+        with _data_tree("report.txt") as (_, data, report):
+            (data / "big.bin").write_bytes(os.urandom(400 * 1024))
+            (data / "small.bin").write_bytes(os.urandom(16 * 1024))
+
+            def table(**kwargs):
+                print_disk_usage(str(data), output=str(report), **kwargs)
+                return report.read_text(encoding="utf-8")
+
+            os.environ["LANG"] = "en_US.UTF-8"
+            text = table(bar_style="blocks")
+            assert "\u2588" * 20 in text
+            assert "#" not in text
+            assert table(bar_style="unicode") == text
+            assert "=" * 20 in table(bar_char="=")
+            assert "\u2593" * 20 in table(bar_char="\u2593")
+
+            # Without a UTF-8 locale, non-ASCII glyphs fall back to ASCII
+            os.environ["LANG"] = "C"
+            assert "#" * 20 in table(bar_style="blocks")
+            assert "#" * 20 in table(bar_char="\u2593")
+            assert "=" * 20 in table(bar_char="=")
+
+
+def test_no_color():
+    """Test that NO_COLOR disables color even with color=always"""
+    with _clean_env():
+        with _data_tree("report.txt") as (tmpdir, data, report):
+            (data / "big.bin").write_bytes(b"x" * 65536)
+            limits = Path(tmpdir) / "limits.toml"
+            limits.write_text('"*.bin" = "1K"\n')
+
+            def colored(color):
+                print_disk_usage(
+                    str(data), limits=str(limits), color=color, output=str(report)
+                )
+                text = report.read_text()
+                assert "OVER LIMIT" in text
+                return "\x1b[" in text
+
+            assert colored("always")
+            assert not colored("auto")  # a file is not a terminal
+            assert not colored("never")
+
+            os.environ["NO_COLOR"] = "1"
+            assert not colored("always")
+            # Any non-empty value counts, even "0"
+            os.environ["NO_COLOR"] = "0"
+            assert not colored("always")
+            os.environ["NO_COLOR"] = ""
+            assert colored("always")
+            os.environ["NO_COLOR"] = "1"
+            # CLICOLOR_FORCE=0 is the same as unset
+            os.environ["CLICOLOR_FORCE"] = "0"
+            assert not colored("always")
+            os.environ["CLICOLOR_FORCE"] = "1"
+            assert colored("always")
+            assert not colored("never")
+
+
+def test_show_raw():
+    """Test the KB column next to the human-readable size"""
+    with _data_tree("report.txt") as (_, data, report):
+        (data / "big.bin").write_bytes(b"x" * 2000 * 1024)
+
+        print_disk_usage(str(data), show_raw=True, group_separator=",", output=str(report))
+        lines = report.read_text().splitlines()
+        assert lines[2].split()[:3] == ["Size", "KB", "In"], lines[2]
+        row = next(line for line in lines if line.rstrip().endswith("big.bin"))
+        kb = calculate_directory_sizes(str(data), False)["big.bin"]
+        assert row.split()[2] == f"{kb:,}", row
+
+        print_disk_usage(str(data), show_raw=True, no_grouping=True, output=str(report))
+        row = next(line for line in report.read_text().splitlines() if "big.bin" in line)
+        assert row.split()[2] == str(kb), row
+
+        print_disk_usage(str(data), output=str(report))
+        assert "KB " not in report.read_text().splitlines()[2]
+
+
+def test_exact():
+    """Test the exact byte column and the bytes field of structured output"""
+    with _data_tree("report.json") as (tmpdir, data, report):
+        (data / "sub").mkdir(parents=True)
+        (data / "sub" / "big.bin").write_bytes(b"x" * 3000 * 1024)
+        (data / "small.txt").write_text("x")
+
+        print_disk_usage(str(data), json=True, output=str(report))
+        entries = {e["name"]: e for e in json.loads(report.read_text())["entries"]}
+        for entry in entries.values():
+            assert entry["bytes"] % 512 == 0
+            assert (entry["bytes"] + 1023) // 1024 <= entry["value"]
+        # Still in bytes with --inodes
+        print_disk_usage(str(data), json=True, inodes=True, output=str(report))
+        for entry in json.loads(report.read_text())["entries"]:
+            assert entry["bytes"] == entries[entry["name"]]["bytes"]
+
+        table = Path(tmpdir) / "report.txt"
+        print_disk_usage(str(data), exact=True, group_separator=",", output=str(table))
+        lines = table.read_text().splitlines()
+        header = lines[2]
+        assert header.split()[:2] == ["Size", "Bytes"], header
+        row = next(line for line in lines if line.rstrip().endswith("sub"))
+        exact = f"{entries['sub']['bytes']:,}"
+        assert exact in row, row
+        # Right-aligned under the header
+        assert row.index(exact) + len(exact) == header.index("Bytes") + len("Bytes")
+
+        print_disk_usage(str(data), exact=True, no_grouping=True, output=str(table))
+        assert str(entries["sub"]["bytes"]) in table.read_text()
+
+
+def test_shallow():
+    """Test the approximate scan that reads only immediate children"""
+    with _data_tree("report.json") as (_, data, report):
+        (data / "sub" / "deep").mkdir(parents=True)
+        (data / "sub" / "top.bin").write_bytes(b"x" * 5000)
+        (data / "sub" / "deep" / "hidden.bin").write_bytes(b"x" * 100_000)
+        (data / "file.bin").write_bytes(b"x" * 3000)
+
+        full = calculate_directory_sizes(str(data), False)
+        shallow = calculate_directory_sizes(str(data), False, shallow=True)
+        assert full["sub"] >= 100
+        # Only top.bin and the directory entry of deep are summed
+        deep_len = (data / "sub" / "deep").stat().st_size
+        assert shallow["sub"] == -(-(5000 + deep_len) // 1024), shallow
+        assert shallow["file.bin"] == full["file.bin"]
+
+        inodes = calculate_directory_sizes(str(data), True, shallow=True)
+        assert inodes["sub"] == 3
+
+        print_disk_usage(str(data), json=True, shallow=True, output=str(report))
+        assert json.loads(report.read_text())["approximate"] is True
+        print_disk_usage(str(data), json=True, output=str(report))
+        assert "approximate" not in json.loads(report.read_text())
+
+
+def test_top():
+    """Test --top with a note or an aggregated <other> row for the rest"""
+    with _data_tree() as (_, data, report):
+        for i in range(1, 6):
+            (data / f"f{i}").write_bytes(b"x" * 5000 * i)
+
+        print_disk_usage(str(data), json=True, top=2, output=str(report))
+        result = json.loads(report.read_text())
+        assert [e["name"] for e in result["entries"]] == ["f4", "f5"]
+        assert result["not_shown"]["entries"] == 3
+        assert result["not_shown"]["value"] + sum(e["value"] for e in result["entries"]) == (
+            result["total"]
+        )
+
+        print_disk_usage(str(data), json=True, top=2, aggregate_remainder=True, output=str(report))
+        result = json.loads(report.read_text())
+        # The remainder is the largest row, so it sorts last
+        assert [e["name"] for e in result["entries"]] == ["f4", "f5", "<other>"]
+        assert "not_shown" not in result
+        assert sum(e["value"] for e in result["entries"]) == result["total"]
+        assert round(sum(e["percentage"] for e in result["entries"])) == 100
+
+        print_disk_usage(str(data), top=2, output=str(report))
+        assert "... and 3 more entries not shown" in report.read_text()
+        print_disk_usage(str(data), top=2, aggregate_remainder=True, output=str(report))
+        assert "<other>" in report.read_text()
+        assert "not shown" not in report.read_text()
+
+        # Nothing is left out when there are at most N entries
+        print_disk_usage(str(data), json=True, top=5, aggregate_remainder=True, output=str(report))
+        result = json.loads(report.read_text())
+        assert len(result["entries"]) == 5 and "not_shown" not in result
+
+        for kwargs in [{"top": 0}, {"aggregate_remainder": True}]:
+            try:
+                print_disk_usage(str(data), **kwargs)
+                assert False, "Should have raised ValueError"
+            except ValueError:
+                pass
+
+
+def test_show_root():
+    """Test the row for the whole directory and bars scaled to it"""
+    with _data_tree() as (_, data, report):
+        (data / "small").write_bytes(b"x" * 10_000)
+        (data / "large").write_bytes(b"x" * 30_000)
+
+        print_disk_usage(str(data), show_root_row=True, no_f=True, output=str(report))
+        lines = report.read_text().splitlines()
+        header = next(i for i, line in enumerate(lines) if line.startswith("Size"))
+        rows = [line.split() for line in lines[header + 1 : header + 4]]
+        # The root comes first, whatever the order, with a full bar
+        assert [row[-1] for row in rows] == [".", "small", "large"], rows
+        assert rows[0][2] == "100.00" and rows[0][3] == "#" * 20, rows
+        # The largest entry no longer gets a full bar
+        assert len(rows[2][3]) < 20, rows
+
+        print_disk_usage(str(data), no_f=True, output=str(report))
+        lines = report.read_text().splitlines()
+        assert not any(line.split()[-1:] == ["."] for line in lines), lines
+
+
+def test_mtime_column():
+    """Test the newest modification time per entry, in JSON and the table"""
+    with _data_tree() as (_, data, report):
+        (data / "old" / "deep").mkdir(parents=True)
+        (data / "old" / "a.bin").write_bytes(b"x" * 5000)
+        (data / "old" / "deep" / "b.bin").write_bytes(b"x" * 5000)
+        (data / "file.bin").write_bytes(b"x" * 3000)
+        os.utime(data / "old" / "a.bin", (1_000_000_000, 1_000_000_000))
+        os.utime(data / "old" / "deep" / "b.bin", (1_200_000_000, 1_200_000_000))
+        os.utime(data / "old" / "deep", (1_100_000_000, 1_100_000_000))
+        os.utime(data / "old", (1_100_000_000, 1_100_000_000))
+        os.utime(data / "file.bin", (1_300_000_000, 1_300_000_000))
+
+        print_disk_usage(str(data), json=True, show_mtime=True, output=str(report))
+        entries = {e["name"]: e for e in json.loads(report.read_text())["entries"]}
+        # The newest file anywhere below the entry counts
+        assert entries["old"]["mtime"] == 1_200_000_000, entries
+        assert entries["file.bin"]["mtime"] == 1_300_000_000, entries
+
+        print_disk_usage(str(data), show_mtime=True, output=str(report))
+        table = report.read_text()
+        assert "Modified" in table
+        assert "2008-01-10" in table and "2011-03-13" in table, table
+        print_disk_usage(str(data), output=str(report))
+        assert "Modified" not in report.read_text()
+
+
+def test_sort_time():
+    """Test that --sort time orders entries by the newest file below them"""
+    with _data_tree() as (_, data, report):
+        (data / "big" / "deep").mkdir(parents=True)
+        (data / "big" / "a.bin").write_bytes(b"x" * 90000)
+        (data / "big" / "deep" / "b.bin").write_bytes(b"x" * 5000)
+        (data / "small").mkdir()
+        (data / "small" / "c.bin").write_bytes(b"x" * 3000)
+        (data / "mid.bin").write_bytes(b"x" * 30000)
+        # The newest file of "big" is deep inside it, below an old one
+        os.utime(data / "big" / "a.bin", (1_000_000_000, 1_000_000_000))
+        os.utime(data / "big" / "deep" / "b.bin", (1_300_000_000, 1_300_000_000))
+        os.utime(data / "small" / "c.bin", (1_400_000_000, 1_400_000_000))
+        os.utime(data / "mid.bin", (1_200_000_000, 1_200_000_000))
+        for d in (data / "big" / "deep", data / "big", data / "small"):
+            os.utime(d, (1_000_000_000, 1_000_000_000))
+
+
+        def order(**kwargs):
+            print_disk_usage(str(data), no_f=True, output=str(report), **kwargs)
+            names = ("big", "small", "mid.bin")
+            lines = report.read_text().splitlines()
+            return [line.split()[-1] for line in lines if line.rstrip().endswith(names)]
+
+        assert order() == ["small", "mid.bin", "big"]
+        # Oldest first, as sizes go smallest first
+        assert order(sort="time") == ["mid.bin", "big", "small"]
+
+        print_disk_usage(str(data), json=True, time=True, output=str(report))
+        entries = {e["name"]: e for e in json.loads(report.read_text())["entries"]}
+        assert entries["big"]["mtime"] == 1_300_000_000, entries
+        assert entries["small"]["mtime"] == 1_400_000_000, entries
+        assert entries["mid.bin"]["mtime"] == 1_200_000_000, entries
+
+
+def test_stable_order():
+    """Test that equal-sized entries come out in the same order on every run"""
+    with _data_tree() as (_, data, report):
+        for name in ["delta", "alpha", "echo", "charlie", "bravo", "foxtrot"]:
+            (data / name).write_bytes(b"x" * 4096)
+            (data / (name + "_dir")).mkdir()
+
+        for kwargs in [
+            {},
+            {"json": True},
+            {"format": "du"},
+            {"inodes": True},
+            {"sort": "time"},
+        ]:
+            outputs = set()
+            for _ in range(5):
+                print_disk_usage(str(data), output=str(report), **kwargs)
+                outputs.add(report.read_bytes())
+            assert len(outputs) == 1, kwargs
+
+        print_disk_usage(str(data), json=True, output=str(report))
+        names = [e["name"] for e in json.loads(report.read_text())["entries"]]
+        files = [n for n in names if not n.endswith("_dir")]
+        assert files == sorted(files), names
+
+
+def test_only_dirs():
+    """Test that only directories are shown, optionally counting hidden files"""
+    with _data_tree("report.json") as (tmpdir, data, report):
+        (data / "sub").mkdir(parents=True)
+        (data / "sub" / "inner.bin").write_bytes(b"x" * 8192)
+        (data / "loose.bin").write_bytes(b"x" * 8192)
+        os.symlink("sub", data / "link")
+
+        print_disk_usage(str(data), json=True, output=str(report))
+        full = json.loads(report.read_text())
+
+        print_disk_usage(str(data), json=True, dirs_only_display=True, output=str(report))
+        shown = json.loads(report.read_text())
+        assert sorted(e["name"] for e in shown["entries"]) == ["link", "sub"]
+        assert shown["total"] == sum(e["value"] for e in shown["entries"])
+        assert shown["total"] < full["total"]
+
+        print_disk_usage(
+            str(data), json=True, dirs_only_display=True, total_all=True, output=str(report)
+        )
+        counted = json.loads(report.read_text())
+        assert len(counted["entries"]) == 2
+        assert counted["total"] == full["total"]
+
+        table = Path(tmpdir) / "report.txt"
+        print_disk_usage(str(data), dirs_only_display=True, output=str(table))
+        assert "loose.bin" not in table.read_text()
+
+        try:
+            print_disk_usage(str(data), total_all=True, output=str(table))
+            assert False, "total_all without dirs_only_display should fail"
+        except ValueError:
+            pass
+
+
+def test_legend():
+    """Test that bars are colored by share and the legend explains the colors"""
+    with _clean_env():
+        with _data_tree("report.txt") as (_, data, report):
+            (data / "big.bin").write_bytes(b"x" * 409600)
+            (data / "small.txt").write_text("x")
+
+            print_disk_usage(str(data), color="always", legend=True, output=str(report))
+            lines = report.read_text().splitlines()
+            big = next(line for line in lines if line.rstrip().endswith("big.bin"))
+            small = next(line for line in lines if line.rstrip().endswith("small.txt"))
+            assert "\x1b[31m#" in big, big
+            assert "\x1b[32m#" in small, small
+            legend = next(line for line in lines if line.startswith("Colors:"))
+            assert "< 1%" in legend and "1-10%" in legend and "> 10%" in legend
+
+            # No legend without the flag, nor without color
+            print_disk_usage(str(data), color="always", output=str(report))
+            assert "Colors:" not in report.read_text()
+            print_disk_usage(str(data), color="never", legend=True, output=str(report))
+            text = report.read_text()
+            assert "Colors:" not in text
+            assert "\x1b[" not in text
+
+
+def test_cumulative_percentages():
+    """Test the running percentage column and JSON field"""
+    with _data_tree("report.json") as (tmpdir, data, report):
+        for name, kb in (("a.bin", 8), ("b.bin", 16), ("c.bin", 40)):
+            (data / name).write_bytes(os.urandom(kb * 1024))
+
+        print_disk_usage(str(data), json=True, cumulative=True, output=str(report))
+        entries = json.loads(report.read_text())["entries"]
+        running = 0.0
+        for entry in entries:
+            running += entry["percentage"]
+            assert abs(entry["cumulative_percentage"] - running) < 0.02
+        assert entries[-1]["cumulative_percentage"] == 100.0
+
+        print_disk_usage(str(data), json=True, output=str(report))
+        assert "cumulative_percentage" not in json.loads(report.read_text())["entries"][0]
+
+        table = Path(tmpdir) / "report.txt"
+        print_disk_usage(str(data), cumulative=True, output=str(table))
+        assert "Cum %" in table.read_text()
+        assert "100.00" in table.read_text().splitlines()[-3]
+
+
+def test_cumulative_histogram():
+    """Test the Pareto-style cumulative histogram and its agreement with Cum %"""
+    with _data_tree("report.txt") as (_, data, table):
+        for name, kb in (("a.bin", 20), ("b.bin", 20), ("c.bin", 160)):
+            (data / name).write_bytes(os.urandom(kb * 1024))
+
+        def bars(**kwargs):
+            print_disk_usage(str(data), output=str(table), **kwargs)
+            lines = table.read_text().splitlines()
+            return {line.split()[-1]: line.split()[3] for line in lines if ".bin" in line}
+
+        assert bars() == {"a.bin": "###", "b.bin": "###", "c.bin": "#" * 20}
+        # Running down the listed order: a.bin is 10% of the total, with
+        # b.bin 20%, with c.bin 100%
+        assert bars(histogram_style="cumulative") == {
+            "a.bin": "##",
+            "b.bin": "####",
+            "c.bin": "#" * 20,
+        }
+        assert "Histogram (cum.)" in table.read_text()
+
+        # The bars and the Cum % column show the same running share
+        print_disk_usage(str(data), output=str(table), histogram_style="cumulative", cumulative=True)
+        rows = [line.split() for line in table.read_text().splitlines() if ".bin" in line]
+        assert [row[3] for row in rows] == ["10.00", "20.00", "100.00"]
+        assert [row[4] for row in rows] == ["##", "####", "#" * 20]
+
+
+def test_min_mark_percent():
+    """Test hiding the histogram bars of negligible entries"""
+    with _data_tree("report.txt") as (_, data, table):
+        (data / "big.bin").write_bytes(os.urandom(400 * 1024))
+        (data / "tiny.bin").write_bytes(os.urandom(4 * 1024))
+
+        def rows(**kwargs):
+            print_disk_usage(str(data), output=str(table), **kwargs)
+            lines = table.read_text().splitlines()
+            return {line.split()[-1]: line.split() for line in lines if ".bin" in line}
+
+        # About 1%: one mark by default, none below a 2% threshold
+        assert rows()["tiny.bin"][3] == "#"
+        hidden = rows(min_mark_percent=2.0)
+        assert "#" not in hidden["tiny.bin"]
+        assert hidden["big.bin"][3] == "#" * 20
+        assert rows(min_mark_percent=0.5)["tiny.bin"][3] == "#"
+
+        try:
+            print_disk_usage(str(data), min_mark_percent=150.0)
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+        try:
+            print_disk_usage(str(data), histogram_style="pareto")
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_directory_total_kb():
+    """Test that the total is the sum of the per-entry values"""
+    with _data_tree() as (_, data, _):
+        (data / "sub" / "deeper").mkdir(parents=True)
+        (data / "sub" / "deeper" / "a").write_bytes(b"x" * 8192)
+        (data / "sub" / "b").write_bytes(b"x" * 4096)
+        (data / "top.txt").write_text("hello")
+
+        total = directory_total_kb(str(data))
+        assert isinstance(total, int)
+        assert total == sum(calculate_directory_sizes(str(data), False).values())
+        assert total >= 12
+        assert directory_total_kb(str(data), use_inodes=True) == 5
+
+        # A plain file is its own total
+        top = data / "top.txt"
+        assert directory_total_kb(str(top)) == calculate_directory_sizes(str(top), False)["top.txt"]
+
+        try:
+            directory_total_kb(str(data / "missing"))
+            assert False, "Should have raised FileNotFoundError"
+        except FileNotFoundError:
+            pass
+
+
+def test_scan_async():
+    """Test the asyncio scan: results, callbacks on the loop, cancellation"""
+    import asyncio
+
+    async def scan(data):
+        for i in range(3000):
+            (data / f"file{i:04}.txt").write_text("x" * i)
+
+        assert await scan_async(str(data)) == calculate_directory_sizes(str(data), False)
+        inodes = await scan_async(str(data), True, exclude=["file1*"])
+        assert inodes == calculate_directory_sizes(str(data), True, exclude=["file1*"])
+        assert len(inodes) == 2000
+
+        # Callbacks run on the event loop thread, coroutine functions too
+        loop_thread = threading.get_ident()
+        progress = asyncio.Queue()
+        threads = set()
+
+        async def on_progress(current, total, name):
+            threads.add(threading.get_ident())
+            progress.put_nowait((current, total, name))
+
+        await scan_async(str(data), progress_callback=on_progress)
+        for _ in range(3):
+            await asyncio.sleep(0)
+        assert progress.qsize() == 3000
+        assert threads == {loop_thread}
+
+        # Cancelling the task stops the walk
+        calls = []
+        first = asyncio.Event()
+
+        def on_entry(*args):
+            calls.append(args)
+            first.set()
+
+        task = asyncio.ensure_future(scan_async(str(data), progress_callback=on_entry))
+        await first.wait()
+        task.cancel()
+        try:
+            await task
+            assert False, "Should have raised CancelledError"
+        except asyncio.CancelledError:
+            pass
+        await asyncio.sleep(0.3)
+        assert len(calls) < 3000, len(calls)
+
+        try:
+            await scan_async(str(data / "missing"))
+            assert False, "Should have raised FileNotFoundError"
+        except FileNotFoundError:
+            pass
+
+    with _data_tree() as (_, data, _):
+        asyncio.run(scan(data))
+
+    # There must be a running event loop
+    try:
+        scan_async(".")
+        assert False, "Should have raised RuntimeError"
+    except RuntimeError:
+        pass
+
+
+def test_start_scan():
+    """Test the polled background scan: results, progress, cancellation"""
+    import concurrent.futures
+
+    with _data_tree() as (_, data, _):
+        for i in range(20):
+            (data / f"file{i:02}.txt").write_text("x" * 1000 * i)
+
+        scan = start_scan(str(data), exclude=["file1*"])
+        sizes = scan.result(timeout=30)
+        assert sizes == calculate_directory_sizes(str(data), False, exclude=["file1*"])
+        assert scan.done() and scan.poll() == 1.0
+        assert scan.result() == sizes
+        assert start_scan(str(data), True).result() == calculate_directory_sizes(str(data), True)
+
+        # The callbacks hold the scan until it is cancelled
+        started = threading.Event()
+        gate = threading.Event()
+        calls = []
+
+        def on_entry(*args):
+            calls.append(args)
+            started.set()
+            gate.wait(10)
+
+        scan = start_scan(str(data), progress_callback=on_entry)
+        assert started.wait(10)
+        assert 0.0 < scan.poll() <= 1.0 and not scan.done()
+        try:
+            scan.result(timeout=0.05)
+            assert False, "Should have raised TimeoutError"
+        except TimeoutError:
+            pass
+        scan.cancel()
+        gate.set()
+        try:
+            scan.result()
+            assert False, "Should have raised CancelledError"
+        except concurrent.futures.CancelledError:
+            pass
+        assert calls[0][1] == 20, calls
+
+        try:
+            start_scan(str(data / "missing")).result()
+            assert False, "Should have raised FileNotFoundError"
+        except FileNotFoundError:
+            pass
+        try:
+            start_scan(str(data)).result(timeout=-1)
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_inode_capacity():
+    """Test the filesystem inode totals and their use with inodes=True"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        total, free = inode_capacity(tmpdir)
+        st = os.statvfs(tmpdir)
+        assert total == st.f_files
+        assert free <= total
+        try:
+            inode_capacity(str(Path(tmpdir) / "missing"))
+            assert False, "Should have raised FileNotFoundError"
+        except FileNotFoundError as e:
+            assert e.errno == errno.ENOENT
+
+        data = Path(tmpdir) / "data"
+        (data / "sub").mkdir(parents=True)
+        (data / "sub" / "file.txt").write_text("x")
+        report = Path(tmpdir) / "report"
+        print_disk_usage(str(data), inodes=True, json=True, output=str(report))
+        result = json.loads(report.read_text())
+        assert result["filesystem_inodes"]["total"] == total
+        print_disk_usage(str(data), json=True, output=str(report))
+        assert "filesystem_inodes" not in json.loads(report.read_text())
+
+        print_disk_usage(str(data), inodes=True, output=str(report))
+        if total > 0:
+            assert f"% of {group_digits(total)}" in report.read_text()
+
+
+def test_free_space():
+    """Test the filesystem space footer and JSON field"""
+    with _data_tree() as (_, data, report):
+        (data / "file.txt").write_text("x" * 5000)
+
+        print_disk_usage(str(data), show_free_space=True, output=str(report))
+        text = report.read_text()
+        # A header line, above the table
+        assert text.startswith("Filesystem: ") and " total, " in text and " free" in text, text
+        print_disk_usage(str(data), output=str(report))
+        assert "Filesystem: " not in report.read_text()
+
+        print_disk_usage(str(data), show_free_space=True, json=True, output=str(report))
+        space = json.loads(report.read_text())["filesystem_space"]
+        st = os.statvfs(data)
+        assert space["total"] == st.f_blocks * st.f_frsize
+        assert space["used"] <= space["total"]
+        assert space["free"] <= space["total"]
+        assert space["device"] is None or isinstance(space["device"], str)
+
+        total, used, free = disk_usage(str(data))
+        assert total == space["total"] and used <= total and free <= total
+        try:
+            disk_usage(str(data / "missing"))
+            assert False, "Should have raised FileNotFoundError"
+        except FileNotFoundError:
+            pass
+        print_disk_usage(str(data), json=True, output=str(report))
+        assert "filesystem_space" not in json.loads(report.read_text())
+
+
+def test_filtered_count():
+    """Test the note on entries hidden by filters"""
+    with _data_tree() as (_, data, report):
+        (data / "src").mkdir(parents=True)
+        (data / ".git").mkdir()
+        (data / "big.bin").write_bytes(b"x" * 100_000)
+        (data / "small.txt").write_text("x" * 100)
+        (data / "empty").touch()
+
+        print_disk_usage(str(data), output=str(report))
+        assert "hidden by filters" not in report.read_text()
+        print_disk_usage(str(data), json=True, output=str(report))
+        assert json.loads(report.read_text())["filtered"] == 0
+
+        # Excluded by two globs, and empty
+        print_disk_usage(
+            str(data), exclude=[".git", "small*"], skip_empty=True, output=str(report)
+        )
+        assert "(3 entries hidden by filters)" in report.read_text()
+        print_disk_usage(str(data), exclude=[".git"], output=str(report))
+        assert "(1 entry hidden by filters)" in report.read_text()
+
+        # Files left out of the rows by --only-dirs count too
+        print_disk_usage(str(data), dirs_only_display=True, json=True, output=str(report))
+        assert json.loads(report.read_text())["filtered"] == 3
+        print_disk_usage(str(data), exclude=["src"], format="markdown", output=str(report))
+        assert "- Hidden by filters: 1 entry" in report.read_text()
+
+        # Everything filtered out is not an empty directory
+        print_disk_usage(str(data), exclude=["*"], output=str(report))
+        text = report.read_text()
+        assert "is empty" not in text and "(5 entries hidden by filters)" in text, text
+
+
+def test_scanner():
+    """Test the reusable Scanner: results, dedup across scans, threads"""
+    with _data_tree() as (_, data, _):
+        for name in ["alpha", "beta", "gamma", "delta"]:
+            (data / name / "sub").mkdir(parents=True)
+            (data / name / "sub" / "file.bin").write_bytes(b"x" * 10_000)
+            (data / name / "skip.tmp").write_bytes(b"x" * 50_000)
+        (data / "top.txt").write_text("x" * 3000)
+
+        scanner = Scanner()
+        assert scanner.scan(str(data)) == calculate_directory_sizes(str(data), False)
+        assert scanner.tree_size(str(data)) == directory_total_kb(str(data))
+        inodes = Scanner(use_inodes=True, exclude=["*.tmp"])
+        assert inodes.scan(str(data)) == calculate_directory_sizes(
+            str(data), True, exclude=["*.tmp"]
+        )
+        # sub and sub/file.bin
+        assert inodes.tree_size(str(data / "alpha")) == 2
+        stats = inodes.stats()
+        assert stats["scans"] == 2 and stats["errors"] == 0
+        assert stats["seen_files"] is None and stats["seconds"] >= 0
+
+        # With dedup, a file is counted by the first scan that meets it
+        (data / "alpha" / "link.bin").hardlink_to(data / "beta" / "sub" / "file.bin")
+        plain = Scanner().scan(str(data))
+        dedup = Scanner(dedup=True)
+        assert dedup.scan(str(data)) != plain
+        assert sum(dedup.scan(str(data)).values()) == 0
+        # tree_size leaves out the scanned directory's own blocks
+        dedup = Scanner(dedup=True, include_dir_overhead=False)
+        files_only = calculate_directory_sizes(str(data), False, include_dir_overhead=False)
+        first = dedup.tree_size(str(data / "alpha"))
+        assert first == files_only["alpha"]
+        assert dedup.tree_size(str(data / "beta")) < files_only["beta"]
+        # alpha's two files and the link, then beta's skip.tmp
+        assert dedup.stats()["seen_files"] == 4
+
+        # One scanner shared by several threads
+        shared = Scanner(threads=2, dedup=True)
+        assert shared.stats()["threads"] == 2
+        results = {}
+
+        def worker(name):
+            results[name] = shared.tree_size(str(data / name))
+
+        workers = [
+            threading.Thread(target=worker, args=(name,))
+            for name in ["alpha", "beta", "gamma", "delta"]
+        ]
+        for w in workers:
+            w.start()
+        for w in workers:
+            w.join()
+        assert shared.stats()["scans"] == 4
+        # Eight files and the four sub directories
+        assert shared.stats()["seen_files"] == 12
+        # The hard-linked file counts once, in alpha or in beta
+        linked = Scanner().tree_size(str(data / "beta" / "sub"))
+        assert sum(results.values()) == sum(
+            Scanner().tree_size(str(data / name)) for name in results
+        ) - linked, results
+
+        try:
+            Scanner(threads=0)
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+        try:
+            Scanner().scan(str(data / "missing"))
+            assert False, "Should have raised FileNotFoundError"
+        except FileNotFoundError:
+            pass
+
+
+def test_calculate_both():
+    """Test that sizes and inode counts come from one scan"""
+    with _data_tree("report.json") as (tmpdir, data, report):
+        (data / "sub").mkdir(parents=True)
+        (data / "sub" / "a").write_bytes(b"x" * 8192)
+        (data / "sub" / "b").write_bytes(b"x" * 4096)
+        (data / "top.txt").write_text("hello")
+
+        both = calculate_both(str(data))
+        sizes = calculate_directory_sizes(str(data), False)
+        inodes = calculate_directory_sizes(str(data), True)
+        assert both == {name: (sizes[name], inodes[name]) for name in sizes}
+        assert both["sub"][1] == 3
+
+        print_disk_usage(str(data), json=True, both=True, output=str(report))
+        doc = json.loads(report.read_text())
+        assert {e["name"]: (e["value"], e["inodes"]) for e in doc["entries"]} == {
+            "sub": both["sub"],
+            "top.txt": both["top.txt"],
+        }
+        assert {e["name"]: e["type"] for e in doc["entries"]} == {"sub": "/", "top.txt": ""}
+        assert doc["total_inodes"] == 4
+
+        table = Path(tmpdir) / "report.txt"
+        print_disk_usage(str(data), both=True, output=str(table))
+        text = table.read_text()
+        assert "inodes" in text.splitlines()[2]
+        assert "Total inodes: 4" in text
+
+        try:
+            print_disk_usage(str(data), both=True, inodes=True, output=str(table))
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_markdown_and_html_reports():
+    """Test the Markdown and HTML report formats"""
+    with _data_tree() as (_, data, report):
+        (data / "pipe|name.txt").write_bytes(b"x" * 8192)
+        (data / "<tag>.txt").write_bytes(b"x" * 4096)
+
+        print_disk_usage(str(data), format="markdown", output=str(report))
+        text = report.read_text()
+        assert text.startswith("## Disk usage of ")
+        assert "- Errors: 0" in text
+        assert "| Size | Inodes | In % | Type | Name |" in text
+        assert "pipe\\|name.txt" in text
+        rows = [line for line in text.splitlines() if line.startswith("| ")]
+        # Every row has exactly five cells despite the pipe in the name
+        for row in rows:
+            assert row.replace("\\|", "").count("|") == 6, row
+        print_disk_usage(str(data), format="markdown", no_f=True, output=str(report))
+        assert "| Size | Inodes | In % | Name |" in report.read_text()
+
+        print_disk_usage(str(data), format="html", output=str(report))
+        html = report.read_text()
+        assert html.startswith("<!DOCTYPE html>")
+        assert "&lt;tag&gt;.txt" in html and "<tag>" not in html
+        assert "<script>" in html and 'class="bar"' in html
+        assert html.count("<tr>") == 3
+
+        try:
+            print_disk_usage(str(data), format="pdf", output=str(report))
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_treemap():
+    """Test the SVG treemap export"""
+    with _data_tree() as (tmpdir, data, _):
+        (data / "big" / "nested" / "deeper").mkdir(parents=True)
+        (data / "big" / "nested" / "deeper" / "hidden.bin").write_bytes(b"x" * 65536)
+        (data / "big" / "large.bin").write_bytes(b"x" * 262144)
+        (data / "a&b.txt").write_bytes(b"x" * 16384)
+        svg_path = Path(tmpdir) / "map.svg"
+
+        print_disk_usage(str(data), output=os.devnull, treemap=str(svg_path))
+        svg = svg_path.read_text()
+        assert svg.startswith("<svg ") and svg.rstrip().endswith("</svg>")
+        assert svg.count("<rect") == svg.count("<title>")
+        assert f"<title>{data / 'big' / 'large.bin'}\n" in svg
+        assert "256.0 KB (262" in svg and "144 bytes)</title>" in svg
+        assert "a&amp;b.txt" in svg and "a&b" not in svg
+        # Three levels by default, so the fourth is folded into its parent
+        assert "deeper" in svg and "hidden.bin" not in svg
+
+        print_disk_usage(str(data), output=os.devnull, treemap=str(svg_path), treemap_depth=1)
+        svg = svg_path.read_text()
+        assert ">big<" in svg and "large.bin" not in svg
+
+        # The treemap shows what the table counts, filters included
+        print_disk_usage(
+            str(data), output=os.devnull, treemap=str(svg_path), exclude=["nested"], include=["*.bin"]
+        )
+        svg = svg_path.read_text()
+        assert "large.bin" in svg and "nested" not in svg and "a&amp;b.txt" not in svg
+
+        try:
+            print_disk_usage(str(data), treemap=str(svg_path), treemap_depth=0)
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_include_dir_overhead():
+    """Test counting the blocks of directories themselves"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        tree = Path(tmpdir) / "tree"
+        (tree / "a" / "b").mkdir(parents=True)
+        (tree / "a" / "file.bin").write_bytes(b"x" * 8192)
+
+        plain = calculate_directory_sizes(str(tmpdir), False, include_dir_overhead=False)
+        # Counted by default
+        with_dirs = calculate_directory_sizes(str(tmpdir), False)
+        assert with_dirs == calculate_directory_sizes(
+            str(tmpdir), False, include_dir_overhead=True
+        )
+        # Each directory's 512-byte blocks, in KB rounded up
+        dirs = (tree, tree / "a", tree / "a" / "b")
+        overhead = sum((os.stat(d).st_blocks * 512 + 1023) // 1024 for d in dirs)
+        assert with_dirs["tree"] == plain["tree"] + overhead, (plain, with_dirs)
+
+        # Inode counts are unaffected
+        assert calculate_directory_sizes(
+            str(tmpdir), True, include_dir_overhead=False
+        ) == calculate_directory_sizes(str(tmpdir), True)
+
+        # Thousands of empty directories add up, and the total agrees with du
+        many = Path(tmpdir) / "many"
+        for i in range(2000):
+            (many / f"d{i // 100}" / f"e{i}").mkdir(parents=True)
+        dirs_kb = calculate_directory_sizes(str(tmpdir), False)["many"]
+        assert calculate_directory_sizes(str(tmpdir), False, include_dir_overhead=False)["many"] == 0
+        assert dirs_kb == sum(
+            (os.stat(root).st_blocks * 512 + 1023) // 1024 for root, _, _ in os.walk(many)
+        )
+        du = subprocess.run(["du", "-sk", str(many)], capture_output=True, text=True)
+        if du.returncode == 0:
+            assert int(du.stdout.split()[0]) == dirs_kb, (du.stdout, dirs_kb)
+
+
+def test_du_format():
+    """Test du-compatible and NUL-delimited output"""
+    with _data_tree() as (_, data, report):
+        (data / "sub").mkdir(parents=True)
+        (data / "sub" / "f.bin").write_bytes(b"x" * 16384)
+        (data / "odd\nname").write_bytes(b"x" * 4096)
+
+        # File contents only, for round numbers
+        print_disk_usage(str(data), format="du", include_dir_overhead=False, output=str(report))
+        lines = report.read_text().splitlines()
+        assert f"16\t{data / 'sub'}" in lines, lines
+        assert lines[-1] == f"20\t{data}", lines
+
+        print_disk_usage(
+            str(data), format="du", null=True, include_dir_overhead=False, output=str(report)
+        )
+        records = report.read_bytes().split(b"\0")
+        assert records[-1] == b""
+        sizes = {path: int(size) for size, path in (r.split(b"\t", 1) for r in records[:-1])}
+        assert sizes == {
+            str(data / "sub").encode(): 16,
+            str(data / "odd\nname").encode(): 4,
+            str(data).encode(): 20,
+        }, sizes
+
+        try:
+            print_disk_usage(str(data), null=True, output=str(report))
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_max_name_width():
+    """Test truncating long names"""
+    with _clean_env("LC_ALL", "LC_CTYPE", "LANG"):
+        os.environ["LANG"] = "C"
+        with _data_tree("report.txt") as (_, data, report):
+            (data / "a_directory_with_a_long_name").mkdir(parents=True)
+            (data / "short.txt").write_bytes(b"x" * 4096)
+
+            print_disk_usage(str(data), output=str(report), max_name_width=12)
+            text = report.read_text()
+            assert "a_directo..." in text and "long_name" not in text
+            assert "short.txt" in text
+
+            # A file is not a terminal, so nothing is shortened by default
+            print_disk_usage(str(data), output=str(report))
+            assert "a_directory_with_a_long_name" in report.read_text()
+            print_disk_usage(str(data), output=str(report), max_name_width=0)
+            assert "a_directory_with_a_long_name" in report.read_text()
+
 
 def test_nonexistent_directory():
     """Test that nonexistent directory raises error"""
@@ -87,6 +2123,191 @@ def test_nonexistent_directory():
         assert "not found" in str(e).lower()
 
 
+def test_nonexistent_directory_errno():
+    """Test that missing paths raise FileNotFoundError carrying an errno"""
+    try:
+        calculate_directory_sizes("/nonexistent/path/that/does/not/exist", False)
+        assert False, "Should have raised an error"
+    except FileNotFoundError as e:
+        assert e.errno == errno.ENOENT
+
+
+def test_regular_file_argument():
+    """Test that a regular file is reported as a single entry"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        file_path = Path(tmpdir) / "file.bin"
+        file_path.write_bytes(b"x" * 4096)
+
+        sizes = calculate_directory_sizes(str(file_path), False)
+        expected_kb = (os.stat(file_path).st_blocks * 512 + 1023) // 1024
+        assert sizes == {"file.bin": expected_kb}
+
+        inodes = calculate_directory_sizes(str(file_path), True)
+        assert inodes == {"file.bin": 1}
+
+        # Filters apply to the file itself, as they would inside a directory
+        assert calculate_directory_sizes(str(file_path), False, min_size_kb=1024) == {}
+        assert calculate_directory_sizes(str(file_path), False, include=["*.txt"]) == {
+            "file.bin": 0
+        }
+        report = Path(tmpdir) / "report.json"
+        limits = Path(tmpdir) / "limits.toml"
+        limits.write_text('"*.bin" = "1K"\n')
+        print_disk_usage(
+            str(file_path), json=True, limits=str(limits), output=str(report)
+        )
+        (entry,) = json.loads(report.read_text())["entries"]
+        assert entry["over_limit"] is True
+
+        result = subprocess.run(
+            [sys.executable, "-m", "dustr", str(file_path)],
+            capture_output=True,
+            text=True,
+            check=True,
+        )
+        assert result.stdout.count("\n") == 1
+        assert "file.bin" in result.stdout
+
+
+def test_limits_file():
+    """Test --limits annotations in JSON output and --strict-limits exit status"""
+    with _data_tree("report.json") as (tmpdir, data, report):
+        big = data / "project-big"
+        small = data / "project-small"
+        big.mkdir(parents=True)
+        small.mkdir()
+        (big / "blob.bin").write_bytes(b"x" * 200_000)
+        (small / "note.txt").write_text("hi")
+        (data / "other").mkdir()
+
+        limits = Path(tmpdir) / "limits.toml"
+        limits.write_text('[limits]\n"project-*" = "100K"  # per project\n')
+
+        print_disk_usage(str(data), json=True, limits=str(limits), output=str(report))
+        entries = {e["name"]: e for e in json.loads(report.read_text())["entries"]}
+        assert entries["project-big"]["limit_bytes"] == 102400
+        assert entries["project-big"]["over_limit"] is True
+        assert entries["project-small"]["over_limit"] is False
+        assert entries["other"]["limit_bytes"] is None
+
+        # Integers are kilobytes too
+        limits.write_text("[limits]\nother = 2048\n")
+        print_disk_usage(str(data), json=True, limits=str(limits), output=str(report))
+        entries = {e["name"]: e for e in json.loads(report.read_text())["entries"]}
+        assert entries["other"]["limit_bytes"] == 2048 * 1024
+        limits.write_text('[limits]\n"project-*" = "100K"  # per project\n')
+
+        # A library caller gets an exception, not an exit
+        try:
+            print_disk_usage(str(data), limits=str(limits), strict_limits=True, output=str(report))
+            assert False, "Should have raised LimitsExceeded"
+        except LimitsExceeded as e:
+            assert e.count == 1
+            assert "1 entry is over the size limit" in str(e)
+        try:
+            print_disk_usage(str(data), strict_limits=True, output=str(report))
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+        cmd = [sys.executable, "-m", "dustr", "--limits", str(limits), str(data)]
+        result = subprocess.run(cmd, capture_output=True, text=True, check=True)
+        assert "OVER LIMIT" in result.stdout
+        assert "Over limit:" in result.stdout
+
+        result = subprocess.run(cmd + ["--strict-limits"], capture_output=True, text=True)
+        assert result.returncode != 0
+        assert "over the size limit" in result.stderr
+
+
+def test_avg_file_size():
+    """Test the average file size reported with avg=True"""
+    with _data_tree("report.json") as (_, data, report):
+        many = data / "many"
+        many.mkdir(parents=True)
+        for i in range(4):
+            (many / f"f{i}").write_bytes(b"x" * 8192)
+        (data / "empty").mkdir()
+        (data / "single.bin").write_bytes(b"x" * 16384)
+
+        print_disk_usage(str(data), json=True, avg=True, output=str(report))
+        entries = {e["name"]: e for e in json.loads(report.read_text())["entries"]}
+        assert entries["many"]["avg_file_kb"] == entries["many"]["value"] / 4
+        assert entries["single.bin"]["avg_file_kb"] == entries["single.bin"]["value"]
+        assert entries["empty"]["avg_file_kb"] is None
+
+
+def test_prune_known():
+    """Test that known build/cache directories are aggregated, not listed"""
+    with _data_tree("report.json") as (_, data, report):
+        project = data / "project"
+        (project / "node_modules" / "dep").mkdir(parents=True)
+        (project / "node_modules" / "dep" / "index.js").write_bytes(b"x" * 65536)
+        (project / "main.js").write_bytes(b"x" * 4096)
+        # Hidden known directories below the top level are pruned too
+        (project / ".venv" / "lib").mkdir(parents=True)
+        (project / ".venv" / "lib" / "site.py").write_bytes(b"x" * 16384)
+        (data / "__pycache__").mkdir()
+        (data / "__pycache__" / "mod.pyc").write_bytes(b"x" * 8192)
+        (data / "keep_me").mkdir()
+        (data / "keep_me" / "a").write_bytes(b"x" * 4096)
+
+        def run(**kwargs):
+            print_disk_usage(str(data), json=True, prune_known=True, output=str(report), **kwargs)
+            return json.loads(report.read_text())
+
+        full = calculate_directory_sizes(str(data), False)
+        doc = run()
+        names = {e["name"] for e in doc["entries"]}
+        assert names == {"project", "keep_me"}
+        assert doc["pruned"]["directories"] == 3
+        project_entry = next(e for e in doc["entries"] if e["name"] == "project")
+        venv_kb = calculate_directory_sizes(str(project), False)[".venv"]
+        pruned_kb = full["project"] - project_entry["value"] + full["__pycache__"] + venv_kb
+        assert doc["pruned"]["bytes"] == pruned_kb * 1024
+        # Keeping hidden entries for --find-empty must not lose .venv's size
+        assert run(find_empty=True)["pruned"] == doc["pruned"]
+
+        doc = run(prune_remove=["__pycache__"], prune_add=["keep_me"])
+        assert {e["name"] for e in doc["entries"]} == {"project", "__pycache__"}
+        assert doc["pruned"]["directories"] == 3
+
+        doc = run(prune_skip=True)
+        assert doc["pruned"] == {"directories": 3, "bytes": None}
+
+
+def test_size_units():
+    """Test --si, --binary, --block-size and --unit output units"""
+    with _data_tree("report.txt") as (_, data, report):
+        (data / "big.bin").write_bytes(os.urandom(3 * 1024 * 1024))
+
+        def total(**kwargs):
+            print_disk_usage(str(data), output=str(report), **kwargs)
+            return report.read_text().strip().splitlines()[-1]
+
+        assert total(binary=True).endswith(" MiB")
+        assert total(si=True).endswith(" MB")
+        assert total(block_size="M") == "Total directory size: 3M"
+        assert total(block_size="K").endswith("K")
+        assert total(block_size="1M") == "Total directory size: 3"
+        assert total(unit="K") == "Total directory size: 3072.0 KB"
+        assert total(unit="t", precision=3) == "Total directory size: 0.000 TB"
+        assert total(unit="G", binary=True, precision=4) == "Total directory size: 0.0029 GiB"
+
+        for kwargs in (
+            {"block_size": "3Q"},
+            {"si": True, "binary": True},
+            {"si": True, "block_size": "M"},
+            {"unit": "M", "block_size": "M"},
+            {"unit": "X"},
+        ):
+            try:
+                print_disk_usage(str(data), output=str(report), **kwargs)
+                assert False, f"Should have raised ValueError for {kwargs}"
+            except ValueError:
+                pass
+
+
 def test_permission_denied():
     """Test handling of permission denied errors"""
     # This test is platform-dependent and might not work everywhere
@@ -100,13 +2321,581 @@ def test_permission_denied():
             try:
                 calculate_directory_sizes(str(protected), False)
                 assert False, "Should have raised permission error"
-            except PermissionError:
-                pass
+            except PermissionError as e:
+                assert e.errno == errno.EACCES
             finally:
                 # Restore permissions for cleanup
                 os.chmod(protected, 0o755)
 
 
+def test_sizes_for():
+    """Test sizing a list of paths, with per-path errors, in input order"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        root = Path(tmpdir)
+        (root / "b").mkdir()
+        (root / "b" / "one.txt").write_text("x" * 5000)
+        (root / "b" / "two.txt").write_text("x")
+        (root / "a.txt").write_text("x" * 10000)
+        paths = [str(root / "b"), str(root / "missing"), str(root / "a.txt")]
+
+        sizes = sizes_for(paths)
+        assert list(sizes) == paths
+        assert sizes[paths[0]]["files"] == 2
+        assert sizes[paths[0]]["inodes"] == 2
+        assert sizes[paths[0]]["size_kb"] == directory_total_kb(paths[0])
+        assert sizes[paths[2]]["bytes"] >= 10000
+        assert sizes[paths[2]]["files"] == 1
+        error = sizes[paths[1]]["error"]
+        assert isinstance(error, FileNotFoundError)
+        assert error.errno == errno.ENOENT
+
+        assert sizes_for([str(root / "b")], exclude=["two.txt"])[paths[0]]["files"] == 1
+        assert sizes_for([]) == {}
+
+        result = subprocess.run(
+            [sys.executable, "-m", "dustr", "--no-config", "--files0-from", "-"],
+            input="\0".join(paths) + "\0",
+            capture_output=True,
+            text=True,
+        )
+        assert result.returncode == 1, result.stderr
+        lines = result.stdout.splitlines()
+        assert [line.split("\t")[1] for line in lines] == [paths[0], paths[2]]
+        assert lines[1].split("\t")[0] == str(sizes[paths[2]]["size_kb"])
+        assert "missing" in result.stderr
+        assert "1 of 3 paths could not be sized" in result.stderr
+
+
+def test_strict():
+    """Test that strict mode fails on the first unreadable path"""
+    with _data_tree() as (_, data, _):
+        (data / "open").mkdir(parents=True)
+        (data / "open" / "file.txt").write_text("x" * 5000)
+        assert calculate_directory_sizes(str(data), False, strict=True) == (
+            calculate_directory_sizes(str(data), False)
+        )
+
+        locked = data / "private" / "locked"
+        locked.mkdir(parents=True)
+        (locked / "secret.txt").write_text("x")
+        os.chmod(locked, 0o000)
+        try:
+            # Without strict the unreadable directory is only warned about
+            assert "open" in calculate_directory_sizes(str(data), False)
+            # As root, everything is readable
+            if not os.access(locked, os.R_OK):
+                for scan in [
+                    lambda: calculate_directory_sizes(str(data), False, strict=True),
+                    lambda: print_disk_usage(str(data), strict=True),
+                ]:
+                    try:
+                        scan()
+                        assert False, "Should have raised PermissionError"
+                    except PermissionError as e:
+                        assert e.errno == errno.EACCES
+                        assert str(locked) in str(e), str(e)
+        finally:
+            os.chmod(locked, 0o755)
+
+
+def test_filter():
+    """Test the Python entry filter: counting, pruning and exceptions"""
+    with _data_tree() as (_, data, _):
+        (data / "src" / "build" / "deep").mkdir(parents=True)
+        (data / "src" / "main.rs").write_text("x" * 5000)
+        (data / "src" / "notes.log").write_text("x" * 5000)
+        (data / "src" / "build" / "deep" / "out.o").write_text("x")
+        (data / "skipped.log").write_text("x")
+        (data / "top.txt").write_text("x" * 10000)
+
+        seen = []
+
+        def keep(entry):
+            assert isinstance(entry, EntryInfo)
+            seen.append(entry)
+            return entry.path.endswith(".rs") or entry.is_dir and entry.uid == os.getuid()
+
+        def no_build(entry):
+            return not entry.path.endswith(".log") and Path(entry.path).name != "build"
+
+        assert calculate_directory_sizes(str(data), True, filter=lambda entry: True) == (
+            calculate_directory_sizes(str(data), True)
+        )
+        # Rejected top-level entries are not listed, rejected directories not entered
+        sizes = calculate_directory_sizes(str(data), True, filter=no_build)
+        assert sizes == {"src": 2, "top.txt": 1}, sizes
+        sizes = calculate_directory_sizes(str(data), True, filter=keep)
+        assert sizes == {"src": 4}, sizes
+        by_path = {entry.path: entry for entry in seen}
+        assert len(by_path) == len(seen), "every entry is offered once"
+        main = by_path[str(data / "src" / "main.rs")]
+        assert not main.is_dir and main.size == 5000
+        assert main.mtime == int(os.stat(data / "src" / "main.rs").st_mtime)
+        assert by_path[str(data / "src")].is_dir
+        assert "main.rs" in repr(main)
+
+        def fail(entry):
+            raise RuntimeError(entry.path)
+
+        try:
+            calculate_directory_sizes(str(data), True, filter=fail)
+            assert False, "Should have raised RuntimeError"
+        except RuntimeError:
+            pass
+
+
+def test_detect_cycles():
+    """Test that a directory reached again through a bind mount is skipped"""
+    with _data_tree() as (_, data, _):
+        (data / "a" / "sub" / "loop").mkdir(parents=True)
+        (data / "a" / "big.bin").write_bytes(os.urandom(100 * 1024))
+        # The skipped mount point's own blocks would differ
+        def sizes(**kwargs):
+            return calculate_directory_sizes(str(data), False, include_dir_overhead=False, **kwargs)
+
+        plain = sizes()
+        assert sizes(detect_cycles=True) == plain
+
+        # Bind mounts need root (and mount support); skip the cycle otherwise
+        loop = data / "a" / "sub" / "loop"
+        mount = subprocess.run(["mount", "--bind", str(data), str(loop)], capture_output=True)
+        if mount.returncode != 0:
+            return
+        try:
+            assert sizes()["a"] > plain["a"]
+            assert sizes(detect_cycles=True) == plain
+        finally:
+            subprocess.run(["umount", str(loop)])
+
+
+def test_compare():
+    """Test comparing two scans, including across a pickle round trip"""
+    import pickle
+
+    with _data_tree() as (_, data, _):
+        (data / "logs").mkdir(parents=True)
+        (data / "logs" / "app.log").write_text("x" * 5000)
+        (data / "old.txt").write_text("x")
+        (data / "same.txt").write_text("x")
+        before = pickle.loads(pickle.dumps(calculate_directory_sizes(str(data), False)))
+
+        (data / "logs" / "app.log").write_text("x" * 50000)
+        (data / "old.txt").unlink()
+        (data / "new.txt").write_text("x")
+        after = calculate_directory_sizes(str(data), False)
+
+        changes = compare(before, after)
+        assert list(changes)[0] == "logs"
+        assert changes["logs"]["delta"] == after["logs"] - before["logs"] > 0
+        assert changes["old.txt"] == {"old": before["old.txt"], "new": None, "delta": -4}
+        assert changes["new.txt"] == {"old": None, "new": 4, "delta": 4}
+        assert "same.txt" not in changes
+        assert compare(after, after) == {}
+
+
+def test_on_error():
+    """Test the per-path error callback: it continues, aborts or raises"""
+    with _data_tree() as (_, data, _):
+        (data / "open").mkdir(parents=True)
+        (data / "open" / "file.txt").write_text("x" * 5000)
+        errors = []
+        on_error = lambda path, err, message: errors.append((path, err, message))
+        sizes = calculate_directory_sizes(str(data), False, on_error=on_error)
+        assert sizes == calculate_directory_sizes(str(data), False)
+        assert errors == []
+
+        locked = data / "locked"
+        locked.mkdir()
+        os.chmod(locked, 0o000)
+        try:
+            # As root, everything is readable
+            if not os.access(locked, os.R_OK):
+                sizes = calculate_directory_sizes(str(data), False, on_error=on_error)
+                assert "open" in sizes
+                assert errors == [(str(locked), errno.EACCES, errors[0][2])], errors
+                assert str(locked) in errors[0][2]
+
+                try:
+                    calculate_directory_sizes(
+                        str(data), False, on_error=lambda path, err, message: False
+                    )
+                    assert False, "Should have raised PermissionError"
+                except PermissionError as e:
+                    assert e.errno == errno.EACCES
+
+                def fail(path, err, message):
+                    raise RuntimeError(path)
+
+                try:
+                    calculate_directory_sizes(str(data), False, on_error=fail)
+                    assert False, "Should have raised RuntimeError"
+                except RuntimeError as e:
+                    assert str(e) == str(locked)
+
+                # strict aborts even when the callback would go on
+                errors.clear()
+                try:
+                    calculate_directory_sizes(str(data), False, strict=True, on_error=on_error)
+                    assert False, "Should have raised PermissionError"
+                except PermissionError:
+                    assert len(errors) == 1
+        finally:
+            os.chmod(locked, 0o755)
+
+
+def test_logging():
+    """Test that diagnostics go through the dustr logger"""
+    records = []
+
+    class Collect(logging.Handler):
+        def emit(self, record):
+            records.append(record)
+
+    logger = logging.getLogger("dustr")
+    handler = Collect()
+    logger.addHandler(handler)
+    saved_level = logger.level
+    with _data_tree() as (_, data, _):
+        protected = data / "protected"
+        protected.mkdir()
+        (protected / "inner").mkdir()
+        os.chmod(protected, 0o000)
+        try:
+            logger.setLevel(logging.DEBUG)
+            calculate_directory_sizes(str(data), False)
+            debug = [r.getMessage() for r in records if r.levelno == logging.DEBUG]
+            assert any("Scanning" in m and "protected" in m for m in debug), debug
+
+            if not os.access(protected, os.R_OK):
+                warnings = [r.getMessage() for r in records if r.levelno == logging.WARNING]
+                assert any("protected" in m for m in warnings), warnings
+
+                # The CLI prints warnings unless --quiet
+                cmd = [sys.executable, "-m", "dustr", str(data)]
+                result = subprocess.run(cmd, capture_output=True, text=True)
+                assert "dustr: warning:" in result.stderr, result.stderr
+                result = subprocess.run(cmd[:3] + ["-q"] + cmd[3:], capture_output=True, text=True)
+                assert "warning" not in result.stderr, result.stderr
+
+            records.clear()
+            logger.setLevel(logging.WARNING)
+            calculate_directory_sizes(str(data), False)
+            assert all(r.levelno >= logging.WARNING for r in records)
+        finally:
+            os.chmod(protected, 0o755)
+            logger.removeHandler(handler)
+            logger.setLevel(saved_level)
+
+
+def test_timeout():
+    """Test that a scan past its time budget raises TimeoutError"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        for i in range(200):
+            (Path(tmpdir) / f"file{i:03}.txt").write_text("x")
+
+        # Slow every entry down so the one-second budget runs out
+        def slow(current, total, name):
+            time.sleep(1.1)
+
+        try:
+            calculate_directory_sizes(tmpdir, False, timeout_secs=1, progress_callback=slow)
+            assert False, "Should have raised TimeoutError"
+        except TimeoutError as e:
+            assert isinstance(e, ScanTruncated)
+            assert "timeout after 1s" in str(e)
+            assert e.reason == "timeout after 1s"
+            assert 0 < len(e.partial_results) < 200
+            name = next(iter(e.partial_results))
+            assert repr(name) in str(e)
+
+        # A generous budget changes nothing
+        assert calculate_directory_sizes(tmpdir, False, timeout_secs=60) == (
+            calculate_directory_sizes(tmpdir, False)
+        )
+
+        try:
+            calculate_directory_sizes(tmpdir, False, timeout_secs=0)
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+
+def test_entries_deleted_during_scan():
+    """Test that entries deleted mid-scan are dropped rather than listed as empty"""
+    script = """
+import shutil, sys
+from pathlib import Path
+from dustr._dustr import calculate_directory_sizes
+
+data = Path(sys.argv[1])
+
+def rotate(current, total, name):
+    # After the first entry, delete all the others, like log rotation
+    if current == 1:
+        for p in data.iterdir():
+            if p.name != name:
+                shutil.rmtree(p) if p.is_dir() else p.unlink()
+
+print(len(calculate_directory_sizes(str(data), True, progress_callback=rotate)))
+"""
+    with _data_tree("report.json") as (_, data, report):
+        for i in range(5):
+            (data / f"dir{i}").mkdir(parents=True)
+            (data / f"dir{i}" / "log").write_text("x")
+            (data / f"file{i}.log").write_text("x")
+
+        # One worker thread, so the entries are walked one after the other
+        env = dict(os.environ, RAYON_NUM_THREADS="1")
+        result = subprocess.run(
+            [sys.executable, "-c", script, str(data)], capture_output=True, text=True, env=env
+        )
+        assert result.returncode == 0, result.stderr
+        assert result.stdout.strip() == "1", result.stdout
+
+        print_disk_usage(str(data), json=True, output=str(report))
+        assert json.loads(report.read_text())["races_skipped"] == 0
+
+
+def test_broken_pipe():
+    """Test that a reader closing the output early ends dustr quietly"""
+    with tempfile.TemporaryDirectory() as tmpdir:
+        # Enough rows to overflow the pipe buffer
+        for i in range(3000):
+            (Path(tmpdir) / f"file{i:04}.txt").write_text("x")
+        cmd = [sys.executable, "-m", "dustr", tmpdir]
+
+        # Like `dustr | head -1`
+        proc = subprocess.Popen(cmd, stdout=subprocess.PIPE, stderr=subprocess.PIPE, text=True)
+        assert proc.stdout.readline().startswith("Statistics of directory")
+        proc.stdout.close()
+        stderr = proc.stderr.read()
+        assert proc.wait(timeout=60) == 141, stderr
+        assert "Traceback" not in stderr, stderr
+        assert "Broken pipe" not in stderr, stderr
+
+        # A closed stderr only loses the progress bar
+        proc = subprocess.Popen(cmd, stdout=subprocess.PIPE, stderr=subprocess.PIPE, text=True)
+        proc.stderr.close()
+        stdout = proc.stdout.read()
+        assert proc.wait(timeout=60) == 0
+        assert "file2999.txt" in stdout
+
+
+def test_config_defaults():
+    """Test defaults from the config file and DUSTR_OPTS, and --dump-config"""
+    with _data_tree() as (tmpdir, data, _):
+        (data / "a.txt").write_text("x" * 5000)
+        (data / "b.txt").write_text("x")
+        (data / "skip.log").write_text("x")
+        config = Path(tmpdir) / ".config" / "dustr" / "config.toml"
+        config.parent.mkdir(parents=True)
+        config.write_text('sort = "name"\nexclude = ["*.log"]\nnoF = true\n')
+
+        env = {k: v for k, v in os.environ.items() if not k.startswith(("DUSTR_", "XDG_"))}
+        env["HOME"] = tmpdir
+
+        def run(*args, **extra):
+            return subprocess.run(
+                [sys.executable, "-m", "dustr", *args],
+                capture_output=True,
+                text=True,
+                env={**env, **extra},
+            )
+
+        def names(stdout):
+            lines = stdout.splitlines()
+            return [line.split()[-1] for line in lines[3 : lines.index("", 3)]]
+
+        assert names(run(str(data)).stdout) == ["a.txt", "b.txt"]
+        # The command line wins over DUSTR_OPTS, which wins over the file
+        assert names(run(str(data), DUSTR_OPTS="--sort size").stdout) == ["b.txt", "a.txt"]
+        assert names(run("--sort", "name", str(data), DUSTR_OPTS="--sort size").stdout) == [
+            "a.txt",
+            "b.txt",
+        ]
+        assert "skip.log" in run("--no-config", str(data)).stdout
+
+        dump = run("--dump-config", "--precision", "2", DUSTR_OPTS="-g").stdout
+        assert f"# Config file: {config}" in dump, dump
+        assert "# DUSTR_OPTS: -g" in dump
+        for line in ['sort = "name"', 'exclude = ["*.log"]', "noF = true", "precision = 2"]:
+            assert line in dump.splitlines(), dump
+        assert run("--no-config", "--dump-config").stdout == "# Config file: none\n"
+
+        # A conflicting default gives way instead of failing
+        result = run(str(data), "--binary", DUSTR_OPTS="--si --no-progress")
+        assert result.returncode == 0 and result.stderr == "", result.stderr
+        assert "KiB" in result.stdout, result.stdout
+        assert run(str(data), "--json", DUSTR_OPTS="--format html").stdout.startswith("{")
+
+        config.write_text('sort = "name"\n\nsort = "bogus"\n')
+        stderr = run(str(data)).stderr
+        assert f"{config}:3: key 'sort': invalid value 'bogus'" in stderr, stderr
+
+        other = Path(tmpdir) / "other.toml"
+        other.write_text("json = true\n")
+        assert run(str(data), DUSTR_CONFIG=str(other)).stdout.startswith("{")
+        stderr = run(str(data), DUSTR_CONFIG=str(Path(tmpdir) / "missing.toml")).stderr
+        assert "Cannot read config file" in stderr, stderr
+
+
+def test_completions():
+    """Test the generated bash completion script"""
+    proc = subprocess.run(
+        [sys.executable, "-m", "dustr", "--no-config", "completions", "bash"],
+        capture_output=True,
+        text=True,
+    )
+    assert proc.returncode == 0, proc.stderr
+    script = proc.stdout
+    assert subprocess.run(["bash", "-n"], input=script, text=True).returncode == 0
+
+    with tempfile.TemporaryDirectory() as tmpdir:
+        (Path(tmpdir) / "subdir").mkdir()
+        (Path(tmpdir) / "file.txt").write_text("x")
+
+        def complete(*words):
+            driver = (
+                script
+                + f"COMP_WORDS=(dustr {' '.join(words)}); COMP_CWORD={len(words)}\n"
+                + '_dustr; printf "%s\\n" "${COMPREPLY[@]}"\n'
+            )
+            out = subprocess.run(
+                ["bash", "-c", driver], capture_output=True, text=True, cwd=tmpdir
+            ).stdout
+            return sorted(out.split())
+
+        assert complete("--sort", "''") == ["name", "size", "time"]
+        assert complete("--format", "m") == ["markdown"]
+        assert complete("--exclude", "''") == ["subdir"]
+        assert complete("completions", "''") == ["bash", "fish", "zsh"]
+        assert "--inodes" in complete("--ino")
+        assert complete("''") == ["completions", "subdir"]
+
+    for shell in ["zsh", "fish"]:
+        proc = subprocess.run(
+            [sys.executable, "-m", "dustr", "completions", shell], capture_output=True, text=True
+        )
+        assert "--exclude" in proc.stdout or "-l exclude" in proc.stdout, proc.stderr
+
+
+def test_skip_empty():
+    """Test that skip_empty leaves zero-byte files out of listings and counts"""
+    with _data_tree("report.json") as (_, data, report):
+        (data / "sub").mkdir(parents=True)
+        (data / "empty.txt").touch()
+        (data / "full.txt").write_text("x")
+        (data / "sub" / "placeholder").touch()
+        (data / "sub" / "real").write_text("x")
+
+        inodes = calculate_directory_sizes(str(data), True)
+        assert inodes == {"empty.txt": 1, "full.txt": 1, "sub": 3}
+        inodes = calculate_directory_sizes(str(data), True, skip_empty=True)
+        assert inodes == {"full.txt": 1, "sub": 2}
+        sizes = calculate_directory_sizes(str(data), False, skip_empty=True)
+        assert set(sizes) == {"full.txt", "sub"}
+
+        print_disk_usage(str(data), json=True, inodes=True, skip_empty=True, output=str(report))
+        doc = json.loads(report.read_text())
+        assert {e["name"]: e["value"] for e in doc["entries"]} == {"full.txt": 1, "sub": 2}
+
+
+def test_max_files():
+    """Test that --max-files truncates the scan and reports what was gathered"""
+    with _data_tree() as (_, data, _):
+        for i in range(20):
+            sub = data / f"dir{i:02}"
+            sub.mkdir(parents=True)
+            for j in range(10):
+                (sub / f"f{j}").write_text("x")
+        full = calculate_directory_sizes(str(data), False)
+
+        try:
+            calculate_directory_sizes(str(data), False, max_files=5, check_interval=1)
+            assert False, "Should have raised ScanTruncated"
+        except ScanTruncated as e:
+            assert e.reason == "more than 5 files"
+            assert len(e.partial_results) < 20
+            assert all(full[name] == value for name, value in e.partial_results.items())
+
+        partial = calculate_directory_sizes(
+            str(data), False, max_files=5, check_interval=1, partial=True
+        )
+        assert len(partial) < 20
+        assert all(full[name] == value for name, value in partial.items())
+
+        # Enough budget changes nothing
+        assert calculate_directory_sizes(str(data), False, max_files=200) == full
+
+        try:
+            calculate_directory_sizes(str(data), False, max_files=0)
+            assert False, "Should have raised ValueError"
+        except ValueError:
+            pass
+
+        # The CLI prints the partial report and exits with status 3
+        cmd = [sys.executable, "-m", "dustr", "--max-files", "5", "--check-interval", "1", str(data)]
+        result = subprocess.run(cmd, capture_output=True, text=True)
+        assert result.returncode == 3, result
+        assert "Scan truncated (more than 5 files)" in result.stdout
+        assert "Scan truncated (more than 5 files)" in result.stderr
+
+        result = subprocess.run(cmd[:3] + ["--json"] + cmd[3:], capture_output=True, text=True)
+        assert result.returncode == 3, result
+        doc = json.loads(result.stdout)
+        assert doc["truncated"] == "more than 5 files"
+        assert len(doc["entries"]) < 20
+
+
+def test_verbose_trace():
+    """Test the directory trace of verbose=2 and the per-entry timings"""
+    records = []
+
+    class Collect(logging.Handler):
+        def emit(self, record):
+            records.append(record)
+
+    logger = logging.getLogger("dustr")
+    handler = Collect()
+    logger.addHandler(handler)
+    saved_level = logger.level
+    with _data_tree("report.json") as (_, data, report):
+        (data / "big" / "inner").mkdir(parents=True)
+        for i in range(5):
+            (data / "big" / "inner" / f"f{i}").write_text("x")
+        (data / "small.txt").write_text("x")
+        try:
+            logger.setLevel(1)
+            calculate_directory_sizes(str(data), False, verbose=2)
+            trace = [r.getMessage() for r in records if r.levelno == 5]
+            assert any("Entering" in m and "inner" in m for m in trace), trace
+            assert any("files)" in m for m in trace), trace
+            debug = [r.getMessage() for r in records if r.levelno == logging.DEBUG]
+            assert any(m.startswith("Scanned big in") and "files/s" in m for m in debug), debug
+
+            # Without verbose there is neither trace nor timing
+            records.clear()
+            calculate_directory_sizes(str(data), False)
+            assert not any(r.levelno == 5 for r in records)
+            assert not any(r.getMessage().startswith("Scanned") for r in records)
+        finally:
+            logger.removeHandler(handler)
+            logger.setLevel(saved_level)
+
+        print_disk_usage(str(data), json=True, verbose=1, output=str(report))
+        entries = json.loads(report.read_text())["entries"]
+        assert all(e["scan_seconds"] >= 0 for e in entries)
+        print_disk_usage(str(data), json=True, output=str(report))
+        entries = json.loads(report.read_text())["entries"]
+        assert all("scan_seconds" not in e for e in entries)
+
+        # The CLI keeps the trace on stderr
+        cmd = [sys.executable, "-m", "dustr", "-vv", str(data)]
+        result = subprocess.run(cmd, capture_output=True, text=True)
+        assert "dustr: trace: Entering" in result.stderr, result.stderr
+        assert "Entering" not in result.stdout
+
+
 def test_cross_mounts():
     """Test that cross_mounts parameter is accepted and results match on same fs"""
     with tempfile.TemporaryDirectory() as tmpdir:
@@ -364,9 +3153,90 @@ def test_bench_cli_deep_tree(benchmark, tmp_path):
 if __name__ == "__main__":
     test_calculate_directory_sizes()
     test_calculate_directory_sizes_inodes()
+    test_dirs_only()
     test_get_file_type_indicator()
+    test_version()
+    test_broken_symlinks()
+    test_count_symlink_size()
+    test_age_filters()
+    test_streaming()
+    test_type_breakdown()
+    test_check_interval()
+    test_watch_interval()
+    test_exclude_from()
+    test_size_range()
+    test_include_filters()
+    test_find_duplicates()
+    test_dump_tree_json()
+    test_find_empty_dirs()
+    test_group_digits()
+    test_log_scale_histogram()
+    test_progress_callback()
+    test_walk_callback()
+    test_bar_styles()
+    test_no_color()
+    test_shallow()
+    test_top()
+    test_mtime_column()
+    test_sort_time()
+    test_stable_order()
+    test_only_dirs()
+    test_legend()
+    test_exact()
+    test_show_raw()
+    test_cumulative_percentages()
+    test_cumulative_histogram()
+    test_min_mark_percent()
+    test_directory_total_kb()
+    test_calculate_both()
+    test_scan_async()
+    test_start_scan()
+    test_scanner()
+    test_inode_capacity()
+    test_markdown_and_html_reports()
+    test_treemap()
+    test_include_dir_overhead()
+    test_du_format()
+    test_max_name_width()
     test_nonexistent_directory()
+    test_nonexistent_directory_errno()
+    test_regular_file_argument()
+    test_limits_file()
+    test_avg_file_size()
+    test_prune_known()
+    test_size_units()
     test_permission_denied()
+    test_strict()
+    test_on_error()
+    test_filter()
+    test_compare()
+    test_detect_cycles()
+    test_sizes_for()
+    test_free_space()
+    test_show_root()
+    test_size_histogram()
+    test_duplicates_report()
+    test_find_empty()
+    test_quiet_errors()
+    test_usage_by_depth()
+    test_inode_hogs()
+    test_find_junk()
+    test_estimate_scan()
+    test_file_size_stats()
+    test_recent_files()
+    test_timing()
+    test_xattrs()
+    test_highlight()
+    test_filtered_count()
+    test_logging()
+    test_timeout()
+    test_entries_deleted_during_scan()
+    test_broken_pipe()
+    test_skip_empty()
+    test_config_defaults()
+    test_completions()
+    test_max_files()
+    test_verbose_trace()
     test_cross_mounts()
     test_verbose()
     test_disk_usage_vs_apparent_size()